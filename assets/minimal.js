@@ -0,0 +1,37 @@
+// Fallback script of the pages when the webpack bundle is not installed,
+// only the table filter of web/js/table.js without jquery
+document.addEventListener('DOMContentLoaded', function() {
+  document.querySelectorAll('.filterable').forEach(function(panel) {
+    const inputs = panel.querySelectorAll('.filters input');
+    const rows = function() {
+      return panel.querySelectorAll('.table tbody tr');
+    };
+    const button = panel.querySelector('.btn-filter');
+    if (button) {
+      button.addEventListener('click', function() {
+        const disabled = inputs.length > 0 && inputs[0].disabled;
+        inputs.forEach(function(input) {
+          input.disabled = !disabled;
+          if (!disabled) input.value = '';
+        });
+        if (disabled && inputs.length > 0) {
+          inputs[0].focus();
+        } else {
+          rows().forEach(function(row) { row.style.display = ''; });
+        }
+      });
+    }
+    inputs.forEach(function(input) {
+      const th = input.closest('th');
+      const column = Array.prototype.indexOf.call(th.parentNode.children, th);
+      input.addEventListener('keyup', function() {
+        const text = input.value.toLowerCase();
+        rows().forEach(function(row) {
+          const cell = row.querySelectorAll('td')[column];
+          const value = cell ? cell.textContent.toLowerCase() : '';
+          row.style.display = value.indexOf(text) === -1 ? 'none' : '';
+        });
+      });
+    });
+  });
+});
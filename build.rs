@@ -0,0 +1,40 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Exports the git commit and the build time for the `/version` endpoint
+fn main() {
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // Rebuild after a commit or a checkout, not after every edit
+    let head = Path::new(".git/HEAD");
+    if head.exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Ok(content) = std::fs::read_to_string(head) {
+            if let Some(reference) = content.trim().strip_prefix("ref: ") {
+                println!("cargo:rerun-if-changed=.git/{}", reference);
+            }
+        }
+    }
+
+    let commit = Command::new("git")
+        .args(&["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=GIT_COMMIT={}", commit.trim());
+    }
+
+    // Reproducible builds give the time instead of the clock
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+}
@@ -0,0 +1,11 @@
+-- Need to support old syntax without `drop column`
+begin transaction;
+
+create table update_log_old (time integer primary key, status integer, message text, last_modified integer default 0);
+insert into update_log_old (time, status, message, last_modified)
+    select time, status, message, last_modified from update_log;
+
+drop table update_log;
+alter table update_log_old rename to update_log;
+
+commit;
@@ -0,0 +1,3 @@
+alter table update_log add column generator_name text default '';
+alter table update_log add column generator_url text default '';
+alter table update_log add column feed_date text default '';
@@ -0,0 +1,30 @@
+-- Need to support old syntax without `drop column`
+begin transaction;
+
+create table programs_old (
+    id integer primary key autoincrement,
+    channel integer,
+    begin integer,
+    end integer,
+    title text,
+    description text
+);
+insert into programs_old (id, channel, begin, end, title, description)
+    select id, channel, begin, end, title, description from programs;
+drop table programs;
+alter table programs_old rename to programs;
+create index channel on programs (channel);
+create index channel_begin on programs (channel, begin);
+create index channel_end on programs (channel, end);
+
+drop table programs1;
+create table programs1 (
+    id integer primary key autoincrement,
+    channel integer,
+    begin integer,
+    end integer,
+    title text,
+    description text
+);
+
+commit;
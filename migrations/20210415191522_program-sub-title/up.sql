@@ -0,0 +1,2 @@
+alter table programs add column sub_title text;
+alter table programs1 add column sub_title text;
@@ -0,0 +1,10 @@
+-- Need to support old syntax without `drop column`
+begin transaction;
+
+create table channels_old
+    (id integer primary key, alias text unique, name text, icon_url text);
+insert into channels_old (id, alias, name, icon_url) select id, alias, name, icon_url from channels;
+drop table channels;
+alter table channels_old rename to channels;
+
+commit;
@@ -0,0 +1 @@
+alter table channels add column last_seen integer default 0;
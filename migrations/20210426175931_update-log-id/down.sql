@@ -0,0 +1,21 @@
+-- Only the latest status of each second is kept
+begin transaction;
+
+create table update_log_old (
+    time integer primary key,
+    status integer,
+    message text,
+    last_modified integer default 0,
+    generator_name text default '',
+    generator_url text default '',
+    feed_date text default ''
+);
+insert or replace into update_log_old
+    (time, status, message, last_modified, generator_name, generator_url, feed_date)
+    select time, status, message, last_modified, generator_name, generator_url, feed_date
+    from update_log order by id;
+
+drop table update_log;
+alter table update_log_old rename to update_log;
+
+commit;
@@ -0,0 +1,3 @@
+-- Json array of program categories
+alter table programs add column category text;
+alter table programs1 add column category text;
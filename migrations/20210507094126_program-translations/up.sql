@@ -0,0 +1,3 @@
+-- Json object of program titles and descriptions by language
+alter table programs add column translations text;
+alter table programs1 add column translations text;
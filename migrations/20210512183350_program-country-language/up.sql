@@ -0,0 +1,5 @@
+-- Json array of production countries and the spoken language
+alter table programs add column country text;
+alter table programs add column language text;
+alter table programs1 add column country text;
+alter table programs1 add column language text;
@@ -0,0 +1,3 @@
+-- Category picked for compact display by the configured priority
+alter table programs add column primary_category text;
+alter table programs1 add column primary_category text;
@@ -0,0 +1,46 @@
+-- Need to support old syntax without `drop column`
+begin transaction;
+
+create table programs_old (
+    id integer primary key autoincrement,
+    channel integer,
+    begin integer,
+    end integer,
+    title text,
+    description text,
+    sub_title text,
+    category text,
+    translations text,
+    country text,
+    language text,
+    primary_category text
+);
+insert into programs_old
+    (id, channel, begin, end, title, description, sub_title, category, translations,
+    country, language, primary_category)
+    select id, channel, begin, end, title, description, sub_title, category, translations,
+    country, language, primary_category
+    from programs;
+drop table programs;
+alter table programs_old rename to programs;
+create index channel on programs (channel);
+create index channel_begin on programs (channel, begin);
+create index channel_end on programs (channel, end);
+
+drop table programs1;
+create table programs1 (
+    id integer primary key autoincrement,
+    channel integer,
+    begin integer,
+    end integer,
+    title text,
+    description text,
+    sub_title text,
+    category text,
+    translations text,
+    country text,
+    language text,
+    primary_category text
+);
+
+commit;
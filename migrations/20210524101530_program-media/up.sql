@@ -0,0 +1,3 @@
+-- Audio and video properties as a json object
+alter table programs add column media text;
+alter table programs1 add column media text;
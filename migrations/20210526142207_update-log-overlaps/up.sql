@@ -0,0 +1,2 @@
+-- Number of overlapping programs in the loaded feed
+alter table update_log add column overlaps integer default 0;
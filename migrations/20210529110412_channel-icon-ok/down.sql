@@ -0,0 +1,11 @@
+-- Need to support old syntax without `drop column`
+begin transaction;
+
+create table channels_old
+    (id integer primary key, alias text unique, name text, icon_url text, last_seen integer default 0);
+insert into channels_old (id, alias, name, icon_url, last_seen)
+    select id, alias, name, icon_url, last_seen from channels;
+drop table channels;
+alter table channels_old rename to channels;
+
+commit;
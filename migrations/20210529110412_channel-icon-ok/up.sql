@@ -0,0 +1,3 @@
+-- Result of the last probe of the icon url, null until it is probed
+alter table channels add column icon_ok integer;
+alter table channels add column icon_checked integer default 0;
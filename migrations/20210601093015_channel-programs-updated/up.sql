@@ -0,0 +1,2 @@
+-- Time the programs of the channel last changed, zero until a feed changes them
+alter table channels add column programs_updated integer default 0;
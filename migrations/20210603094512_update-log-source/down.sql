@@ -0,0 +1,26 @@
+-- Need to support old syntax without `drop column`
+begin transaction;
+
+create table update_log_old (
+    id integer primary key autoincrement,
+    time integer,
+    status integer,
+    message text,
+    last_modified integer default 0,
+    generator_name text default '',
+    generator_url text default '',
+    feed_date text default '',
+    overlaps integer default 0
+);
+insert into update_log_old
+    (id, time, status, message, last_modified, generator_name, generator_url, feed_date,
+    overlaps)
+    select id, time, status, message, last_modified, generator_name, generator_url, feed_date,
+    overlaps
+    from update_log;
+
+drop table update_log;
+alter table update_log_old rename to update_log;
+create index update_log_time on update_log (time);
+
+commit;
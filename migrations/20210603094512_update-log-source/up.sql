@@ -0,0 +1,2 @@
+-- Which feed url the update loaded, the primary or the fallback one
+alter table update_log add column source text default 'primary';
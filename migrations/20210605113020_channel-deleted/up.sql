@@ -0,0 +1,2 @@
+-- Channels removed by the purge are kept with their id and alias until they reappear
+alter table channels add column deleted integer default 0;
@@ -0,0 +1,630 @@
+use crate::db::{EffectivePragmas, Remap, StoredProgram};
+use crate::epg::{ChannelInfo, ScheduleCheck};
+use crate::name_match::{MatcherStats, VecMatcher};
+use crate::query::{GapsQuery, Query};
+use crate::utils::{bad_request, error_with_status, get_parameter, server_error};
+use crate::{feed_reader, EpgSqlServer};
+use chrono::prelude::*;
+use iron::mime::Mime;
+use iron::prelude::*;
+use iron::status;
+use iron::BeforeMiddleware;
+use multipart::server::iron::Intercept;
+use multipart::server::Entries;
+use router::Router;
+use serde_derive::Serialize;
+use std::fmt;
+use std::io::BufRead;
+use std::str;
+use urlencoded::UrlEncodedQuery;
+
+/// Diagnostic endpoints for operators
+pub struct AdminModel {}
+
+/// Default similarity of channel names reported as duplicates
+const DUPLICATE_THRESHOLD: f32 = 0.8;
+/// Upper limit for the number of reported duplicate pairs
+const MAX_DUPLICATES: usize = 1000;
+/// Most similar names checked for each channel
+const DUPLICATE_CANDIDATES: usize = 10;
+/// Rounding error of similarities, identical names may score slightly below 1
+const SIMILARITY_EPSILON: f32 = 1e-5;
+/// Default number of channels reported by `/ending_soon`
+const ENDING_SOON_LIMIT: i64 = 50;
+/// Upper limit for the total size of feeds uploaded to `/import`
+const MAX_IMPORT_SIZE: u64 = 256 * 1024 * 1024;
+
+fn json<T: serde::Serialize>(value: &T) -> IronResult<Response> {
+    let out = serde_json::to_string(value).map_err(|e| server_error(Box::new(e)))?;
+    Ok(Response::with((
+        status::Ok,
+        "application/json".parse::<Mime>().unwrap(),
+        out,
+    )))
+}
+
+/// Admits only requests with `Authorization: Bearer <token>` of the configured admin token,
+/// everyone is refused when no token is configured
+struct AdminAuth;
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Admin token required")
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+#[derive(Debug)]
+struct AdminDisabled;
+
+impl fmt::Display for AdminDisabled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Admin endpoints are disabled without an admin token")
+    }
+}
+
+impl std::error::Error for AdminDisabled {}
+
+/// Compares the whole of both values so the time taken does not reveal the matching prefix
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl BeforeMiddleware for AdminAuth {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let settings = data.settings();
+        let token = match &settings.admin_token {
+            Some(token) => token,
+            None => return Err(error_with_status(AdminDisabled, status::Forbidden)),
+        };
+        let given = req
+            .headers
+            .get_raw("Authorization")
+            .and_then(|values| values.first())
+            .and_then(|value| str::from_utf8(value).ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match given {
+            Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => Ok(()),
+            _ => Err(error_with_status(Unauthorized, status::Unauthorized)),
+        }
+    }
+}
+
+impl AdminModel {
+    pub fn new() -> Chain {
+        let mut router = Router::new();
+        router.get("/gaps", AdminModel::find_gaps, "admin_gaps");
+        router.get(
+            "/matcher_stats",
+            AdminModel::matcher_stats,
+            "admin_matcher_stats",
+        );
+        router.get("/no_icon", AdminModel::no_icon, "admin_no_icon");
+        router.get("/dead_icons", AdminModel::dead_icons, "admin_dead_icons");
+        router.get(
+            "/deleted_channels",
+            AdminModel::deleted_channels,
+            "admin_deleted_channels",
+        );
+        router.get("/ending_soon", AdminModel::ending_soon, "admin_ending_soon");
+        router.get("/duplicates", AdminModel::duplicates, "admin_duplicates");
+        router.get("/program", AdminModel::program, "admin_program");
+        router.get("/pragmas", AdminModel::pragmas, "admin_pragmas");
+        router.post("/finalize", AdminModel::finalize, "admin_finalize");
+        router.post("/import", AdminModel::import, "admin_import");
+        router.post("/reindex", AdminModel::reindex, "admin_reindex");
+        router.post("/reload", AdminModel::reload, "admin_reload");
+        router.post("/remap", AdminModel::remap, "admin_remap");
+        router.post("/undelete", AdminModel::undelete, "admin_undelete");
+        let mut chain = Chain::new(router);
+        chain.link_before(AdminAuth);
+        // Uploads are read only from admitted requests
+        chain.link_before(Intercept::default().file_size_limit(MAX_IMPORT_SIZE));
+        chain
+    }
+
+    /// Merge the feed parts loaded in incremental mode
+    fn finalize(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        if !data.settings().incremental_load {
+            return Ok(Response::with((
+                status::Conflict,
+                "Incremental load is not enabled",
+            )));
+        }
+        #[derive(Serialize)]
+        struct Data {
+            data: i64,
+        }
+        json(&Data {
+            data: data.finalize_update().map_err(server_error)?,
+        })
+    }
+
+    /// Load xmltv files uploaded as `feed` parts of a multipart form together,
+    /// gzipped files are accepted as well
+    fn import(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let parts = match req
+            .extensions
+            .get::<Entries>()
+            .and_then(|entries| entries.fields.get("feed"))
+        {
+            Some(parts) => parts,
+            None => return Ok(Response::with((status::BadRequest, "Missing feed files"))),
+        };
+        // The intercept limits each file, several of them may still exceed the limit
+        if parts.iter().map(|part| part.data.size()).sum::<u64>() > MAX_IMPORT_SIZE {
+            return Ok(Response::with((
+                status::PayloadTooLarge,
+                format!("Feeds exceed {} bytes", MAX_IMPORT_SIZE),
+            )));
+        }
+        let mut feeds: Vec<(String, Box<dyn BufRead + '_>)> = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            let name = part
+                .headers
+                .filename
+                .clone()
+                .unwrap_or_else(|| format!("feed {}", i + 1));
+            let reader = part
+                .data
+                .readable()
+                .and_then(feed_reader)
+                .map_err(bad_request)?;
+            feeds.push((name, reader));
+        }
+        #[derive(Serialize)]
+        struct Data<T> {
+            data: T,
+        }
+        json(&Data {
+            data: data.import_feeds(feeds).map_err(server_error)?,
+        })
+    }
+
+    /// Rebuild database indexes after bulk imports or corruption
+    fn reindex(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let elapsed = data.rebuild_indexes().map_err(server_error)?;
+        #[derive(Serialize)]
+        struct Reindex {
+            elapsed_ms: u64,
+        }
+        #[derive(Serialize)]
+        struct Data {
+            data: Reindex,
+        }
+        json(&Data {
+            data: Reindex {
+                elapsed_ms: elapsed.as_millis() as u64,
+            },
+        })
+    }
+
+    /// Give a channel the new alias of its provider keeping the id,
+    /// a channel which already has the alias is merged only with `merge=1`
+    fn remap(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let params = req.get_ref::<UrlEncodedQuery>().map_err(bad_request)?;
+        let (old_alias, new_alias) = match (
+            get_parameter(&params, "old_alias"),
+            get_parameter(&params, "new_alias"),
+        ) {
+            (Some(old), Some(new)) if !new.trim().is_empty() => (old, new.trim()),
+            _ => return Ok(Response::with((status::BadRequest, "Missing parameters"))),
+        };
+        let merge = get_parameter(&params, "merge").map_or(false, |v| v == "1");
+        #[derive(Serialize)]
+        struct Data {
+            data: Remap,
+        }
+        match data
+            .remap_alias(old_alias, new_alias, merge)
+            .map_err(server_error)?
+        {
+            Remap::NotFound => Ok(Response::with((status::NotFound, "Channel not found"))),
+            Remap::Conflict => Ok(Response::with((
+                status::Conflict,
+                "Another channel has the alias, use merge=1 to merge it",
+            ))),
+            remap => json(&Data { data: remap }),
+        }
+    }
+
+    /// Restore a channel deleted by a load
+    fn undelete(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let params = req.get_ref::<UrlEncodedQuery>().map_err(bad_request)?;
+        let id = match get_parameter(&params, "id").and_then(|id| id.parse::<i64>().ok()) {
+            Some(id) => id,
+            None => return Ok(Response::with((status::BadRequest, "Missing parameters"))),
+        };
+        #[derive(Serialize)]
+        struct Data {
+            data: i64,
+        }
+        if data.undelete_channel(id).map_err(server_error)? {
+            json(&Data { data: id })
+        } else {
+            Ok(Response::with((status::NotFound, "Channel not found")))
+        }
+    }
+
+    /// Re-read the config file, responds with changed settings which need a restart
+    fn reload(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        #[derive(Serialize)]
+        struct Data {
+            restart_required: Vec<&'static str>,
+        }
+        match data.reload_settings() {
+            Ok(kept) => json(&Data {
+                restart_required: kept,
+            }),
+            Err(e) => Ok(Response::with((status::BadRequest, e))),
+        }
+    }
+
+    fn no_icon(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let channels = data.get_channels(false, None).map_err(server_error)?;
+        #[derive(Serialize)]
+        struct Item {
+            id: i64,
+            alias: String,
+            name: String,
+        }
+        #[derive(Serialize)]
+        struct Data {
+            data: Vec<Item>,
+        }
+        json(&Data {
+            data: without_icon(channels)
+                .map(|(id, c)| Item {
+                    id,
+                    alias: c.alias,
+                    name: c.name,
+                })
+                .collect(),
+        })
+    }
+
+    /// Channels which icon url failed the last probe, see `--icon-probe-interval`
+    fn dead_icons(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let channels = data
+            .db
+            .get_dead_icons()
+            .map_err(|e| server_error(Box::new(e)))?;
+        #[derive(Serialize)]
+        struct Item {
+            id: i64,
+            alias: String,
+            name: String,
+            icon_url: String,
+        }
+        #[derive(Serialize)]
+        struct Data {
+            data: Vec<Item>,
+        }
+        json(&Data {
+            data: channels
+                .into_iter()
+                .map(|(id, c)| Item {
+                    id,
+                    alias: c.alias,
+                    name: c.name,
+                    icon_url: c.icon_url,
+                })
+                .collect(),
+        })
+    }
+
+    /// Channels deleted by loads, they keep their ids when they reappear in a feed
+    fn deleted_channels(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let channels = data
+            .db
+            .get_deleted_channels()
+            .map_err(|e| server_error(Box::new(e)))?;
+        #[derive(Serialize)]
+        struct Item {
+            id: i64,
+            alias: String,
+            name: String,
+        }
+        #[derive(Serialize)]
+        struct Data {
+            data: Vec<Item>,
+        }
+        json(&Data {
+            data: channels
+                .into_iter()
+                .map(|(id, c)| Item {
+                    id,
+                    alias: c.alias,
+                    name: c.name,
+                })
+                .collect(),
+        })
+    }
+
+    /// Pairs of channels with similar names, candidates for alias mappings
+    fn duplicates(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let params = req.get_ref::<UrlEncodedQuery>().map_err(bad_request)?;
+        let threshold = match get_parameter(&params, "threshold") {
+            Some(v) => v.parse::<f32>().map_err(bad_request)?,
+            None => DUPLICATE_THRESHOLD,
+        };
+        if !(0.0..=1.0).contains(&threshold) {
+            return Ok(Response::with((
+                status::BadRequest,
+                "Threshold must be between 0 and 1",
+            )));
+        }
+        let limit = match get_parameter(&params, "limit") {
+            Some(v) => v.parse::<usize>().map_err(bad_request)?,
+            None => 100,
+        };
+        let limit = limit.min(MAX_DUPLICATES);
+
+        #[derive(Serialize)]
+        struct Item<'a> {
+            id: i64,
+            alias: &'a str,
+            name: &'a str,
+        }
+        #[derive(Serialize)]
+        struct Pair<'a> {
+            first: Item<'a>,
+            second: Item<'a>,
+            sim: f32,
+        }
+        #[derive(Serialize)]
+        struct Data<'a> {
+            data: Vec<Pair<'a>>,
+        }
+        let _permit = data.acquire_match_permit()?;
+        let mut corpus = data.load_corpus().map_err(server_error)?;
+        let corpus = match corpus.as_mut() {
+            Some(corpus) => corpus,
+            None => return json(&Data { data: Vec::new() }),
+        };
+        let pairs = duplicate_pairs(&mut corpus.matcher, threshold, limit);
+        let item = |index: usize| {
+            let (id, channel) = &corpus.channels[index];
+            Item {
+                id: *id,
+                alias: &channel.alias,
+                name: &channel.name,
+            }
+        };
+        json(&Data {
+            data: pairs
+                .into_iter()
+                .map(|(i, j, sim)| Pair {
+                    first: item(i),
+                    second: item(j),
+                    sim,
+                })
+                .collect(),
+        })
+    }
+
+    /// Sqlite options in effect on database connections
+    fn pragmas(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        #[derive(Serialize)]
+        struct Data {
+            data: EffectivePragmas,
+        }
+        json(&Data {
+            data: data
+                .db
+                .effective_pragmas()
+                .map_err(|e| server_error(Box::new(e)))?,
+        })
+    }
+
+    fn matcher_stats(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let corpus = data.load_corpus().map_err(server_error)?;
+        #[derive(Serialize)]
+        struct Data {
+            data: Option<MatcherStats>,
+        }
+        json(&Data {
+            data: corpus.as_ref().map(|corpus| corpus.matcher.stats()),
+        })
+    }
+
+    /// Channels whose guide data runs out first, `end` is null for channels without programs
+    fn ending_soon(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let limit = match req
+            .get_ref::<UrlEncodedQuery>()
+            .ok()
+            .and_then(|params| get_parameter(params, "limit"))
+        {
+            Some(v) => v.parse::<i64>().map_err(bad_request)?,
+            None => ENDING_SOON_LIMIT,
+        };
+        let channels = data
+            .db
+            .channels_ending_soonest(limit)
+            .map_err(|e| server_error(Box::new(e)))?;
+        #[derive(Serialize)]
+        struct Item {
+            id: i64,
+            alias: String,
+            name: String,
+            end: Option<i64>,
+        }
+        #[derive(Serialize)]
+        struct Data {
+            data: Vec<Item>,
+        }
+        json(&Data {
+            data: channels
+                .into_iter()
+                .map(|(id, c, end)| Item {
+                    id,
+                    alias: c.alias,
+                    name: c.name,
+                    end,
+                })
+                .collect(),
+        })
+    }
+
+    /// Stored row of a program with its times formatted in UTC and the sizes of its texts
+    fn program(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let params = req.get_ref::<UrlEncodedQuery>().map_err(bad_request)?;
+
+        let (id, begin) = match (
+            get_parameter(&params, "id"),
+            get_parameter(&params, "begin"),
+        ) {
+            (Some(id), Some(begin)) => (
+                id.parse::<i64>().map_err(bad_request)?,
+                begin.parse::<i64>().map_err(bad_request)?,
+            ),
+            _ => return Ok(Response::with((status::BadRequest, "Missing parameters"))),
+        };
+        let program = match data
+            .db
+            .get_program(id, begin)
+            .map_err(|e| server_error(Box::new(e)))?
+        {
+            Some(program) => program,
+            None => return Ok(Response::with((status::NotFound, "Program not found"))),
+        };
+
+        #[derive(Serialize)]
+        struct Item {
+            #[serde(flatten)]
+            program: StoredProgram,
+            begin_utc: String,
+            end_utc: String,
+            title_bytes: usize,
+            description_bytes: Option<usize>,
+        }
+        #[derive(Serialize)]
+        struct Data {
+            data: Item,
+        }
+        let utc = |t| Utc.timestamp(t, 0).to_rfc3339();
+        json(&Data {
+            data: Item {
+                begin_utc: utc(program.begin),
+                end_utc: utc(program.end),
+                title_bytes: program.title.len(),
+                description_bytes: program.description.as_ref().map(String::len),
+                program,
+            },
+        })
+    }
+
+    fn find_gaps(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let GapsQuery { id, from, to } = GapsQuery::parse(
+            Query::new(req.get_ref::<UrlEncodedQuery>().ok()),
+            data.settings().max_query_span_days,
+        )?;
+
+        #[derive(Serialize)]
+        struct Data {
+            data: ScheduleCheck,
+        }
+        let check = data
+            .db
+            .find_gaps(id, from, to)
+            .map_err(|e| server_error(Box::new(e)))?;
+        json(&Data { data: check })
+    }
+}
+
+/// Indexes of texts with similarity at least `threshold`, most similar first.
+/// Each pair is reported once with the smaller index first.
+fn duplicate_pairs(
+    matcher: &mut VecMatcher,
+    threshold: f32,
+    limit: usize,
+) -> Vec<(usize, usize, f32)> {
+    let threshold = threshold - SIMILARITY_EPSILON;
+    let mut pairs = Vec::new();
+    for i in 0..matcher.stats().texts {
+        let text = matcher.get_text(i).to_string();
+        for (j, sim) in matcher.search(&text, threshold, DUPLICATE_CANDIDATES + 1) {
+            // The text itself and pairs found from the other side
+            if j > i {
+                pairs.push((i, j, sim.min(1.0)));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal));
+    pairs.truncate(limit);
+    pairs
+}
+
+/// Channels which need a logo to be found
+fn without_icon(channels: Vec<(i64, ChannelInfo)>) -> impl Iterator<Item = (i64, ChannelInfo)> {
+    channels
+        .into_iter()
+        .filter(|(_, c)| c.icon_url.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicates() {
+        let names = ["BBC One", "CNN", "BBC One", "Euronews", "CNN"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>();
+        let mut matcher = VecMatcher::new(&names, 2);
+        let pairs = duplicate_pairs(&mut matcher, 0.99, 10)
+            .into_iter()
+            .map(|(i, j, _)| (i, j))
+            .collect::<Vec<_>>();
+        assert_eq!(pairs.len(), 2);
+        assert!(pairs.contains(&(0, 2)));
+        assert!(pairs.contains(&(1, 4)));
+        assert_eq!(duplicate_pairs(&mut matcher, 0.99, 1).len(), 1);
+
+        // Identical names reach the highest threshold
+        let pairs = duplicate_pairs(&mut matcher, 1.0, 10);
+        assert_eq!(pairs.len(), 2);
+        assert!(pairs
+            .iter()
+            .all(|&(_, _, sim)| 1.0 - sim < SIMILARITY_EPSILON));
+    }
+
+    #[test]
+    fn no_icon() {
+        let channel = |alias: &str, icon_url: &str| ChannelInfo {
+            alias: alias.to_string(),
+            icon_url: icon_url.to_string(),
+            ..ChannelInfo::new()
+        };
+        let channels = vec![
+            (1, channel("cnn", "http://logo/cnn.png")),
+            (2, channel("bbc", "")),
+            (3, channel("mtv", " ")),
+        ];
+        assert_eq!(
+            without_icon(channels).map(|(id, _)| id).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+    }
+}
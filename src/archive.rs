@@ -0,0 +1,263 @@
+use crate::EpgSqlServer;
+use chrono::prelude::*;
+use filetime::FileTime;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How often the archive directory is maintained
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Where loaded feeds are archived and how long they are kept
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchivePolicy {
+    pub dir: PathBuf,
+    /// Archived feeds older than this are gzip compressed, recent ones stay plain xml
+    pub recompress_after: Option<Duration>,
+    /// Archived feeds older than this are removed
+    pub keep: Option<Duration>,
+}
+
+/// Reader which copies everything read to the archive file.
+/// Failing to archive is only logged, the feed is loaded anyway.
+pub struct ArchiveReader<R> {
+    inner: R,
+    path: PathBuf,
+    /// Dropped after the first failed write
+    file: Option<fs::File>,
+}
+
+impl<R> ArchiveReader<R> {
+    fn fail(&mut self, e: io::Error) {
+        eprintln!("Failed to archive feed into {}: {}", self.path.display(), e);
+        if self.file.take().is_some() {
+            // Incomplete copy of the feed is not worth keeping
+            fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+impl<R: Read> Read for ArchiveReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(file) = &mut self.file {
+            if let Err(e) = file.write_all(&buf[..n]) {
+                self.fail(e);
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl ArchivePolicy {
+    /// Archive the feed read by the reader in a file named after the time
+    pub fn archive<R: Read>(&self, reader: R, time: DateTime<Utc>) -> ArchiveReader<R> {
+        let name = format!("feed-{}.xml", time.format("%Y%m%dT%H%M%S"));
+        let mut archive = ArchiveReader {
+            inner: reader,
+            path: self.dir.join(name),
+            file: None,
+        };
+        match fs::create_dir_all(&self.dir).and_then(|_| fs::File::create(&archive.path)) {
+            Ok(file) => archive.file = Some(file),
+            Err(e) => archive.fail(e),
+        }
+        archive
+    }
+
+    /// Compress and remove archived feeds by their age,
+    /// returns the number of compressed and removed files.
+    /// A missing directory has nothing archived yet, failures of single files are only logged.
+    pub fn maintain(&self, now: SystemTime) -> io::Result<(usize, usize)> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((0, 0)),
+            Err(e) => return Err(e),
+        };
+        let (mut compressed, mut removed) = (0, 0);
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    eprintln!("Failed to list feed archive {}: {}", self.dir.display(), e);
+                    continue;
+                }
+            };
+            match self.maintain_feed(&path, now) {
+                Ok((c, r)) => {
+                    compressed += c;
+                    removed += r;
+                }
+                Err(e) => eprintln!("Failed to maintain archived feed {}: {}", path.display(), e),
+            }
+        }
+        Ok((compressed, removed))
+    }
+
+    /// Compress or remove one archived feed, returns whether it was compressed and removed
+    fn maintain_feed(&self, path: &Path, now: SystemTime) -> io::Result<(usize, usize)> {
+        let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+        if !name.starts_with("feed-") {
+            return Ok((0, 0));
+        }
+        let modified = fs::metadata(path)?.modified()?;
+        let age = now.duration_since(modified).unwrap_or_default();
+        if self.keep.map_or(false, |keep| age > keep) {
+            fs::remove_file(path)?;
+            Ok((0, 1))
+        } else if name.ends_with(".xml") && self.recompress_after.map_or(false, |after| age > after)
+        {
+            compress(path, modified)?;
+            Ok((1, 0))
+        } else {
+            Ok((0, 0))
+        }
+    }
+}
+
+/// Replace the file with its `.gz`, which keeps the modification time,
+/// so the age of an archived feed does not restart
+fn compress(path: &Path, modified: SystemTime) -> io::Result<()> {
+    let mut gz_path = path.as_os_str().to_owned();
+    gz_path.push(".gz");
+    let write = || -> io::Result<()> {
+        let mut encoder = GzEncoder::new(fs::File::create(&gz_path)?, Compression::best());
+        io::copy(&mut fs::File::open(path)?, &mut encoder)?;
+        encoder.finish()?;
+        filetime::set_file_mtime(&gz_path, FileTime::from_system_time(modified))
+    };
+    if let Err(e) = write() {
+        // Partial copy must not replace the feed later
+        fs::remove_file(&gz_path).ok();
+        return Err(e);
+    }
+    fs::remove_file(path)
+}
+
+/// Maintain the feed archive in the background when it is configured
+pub fn spawn(app: Arc<EpgSqlServer>) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        let policy = app.settings().feed_archive.clone();
+        if let Some(policy) = policy {
+            match policy.maintain(SystemTime::now()) {
+                Ok((0, 0)) => {}
+                Ok((compressed, removed)) => println!(
+                    "Compressed {} and removed {} archived feeds",
+                    compressed, removed
+                ),
+                Err(e) => eprintln!("Failed to maintain feed archive: {}", e),
+            }
+        }
+        thread::sleep(MAINTENANCE_INTERVAL);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[test]
+    fn maintain() {
+        let dir = std::env::temp_dir().join("test_archive_maintain");
+        fs::remove_dir_all(&dir).ok();
+        let policy = ArchivePolicy {
+            dir: dir.clone(),
+            recompress_after: Some(2 * DAY),
+            keep: Some(10 * DAY),
+        };
+        let now = SystemTime::now();
+        let mut reader = policy.archive(&b"<tv></tv>"[..], Utc::now());
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+        let feeds: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(feeds.len(), 1);
+        let feed = &feeds[0];
+        assert_eq!(fs::read(feed).unwrap(), b"<tv></tv>");
+        let gz = PathBuf::from(format!("{}.gz", feed.display()));
+        let age = |days: u32| {
+            filetime::set_file_mtime(feed, FileTime::from_system_time(now - days * DAY)).unwrap()
+        };
+
+        // Recent feeds stay as they are
+        age(1);
+        assert_eq!(policy.maintain(now).unwrap(), (0, 0));
+        assert!(feed.exists());
+
+        // Compressed after the grace period, the age is kept
+        age(3);
+        assert_eq!(policy.maintain(now).unwrap(), (1, 0));
+        assert!(!feed.exists());
+        let mut xml = String::new();
+        GzDecoder::new(fs::File::open(&gz).unwrap())
+            .read_to_string(&mut xml)
+            .unwrap();
+        assert_eq!(xml, "<tv></tv>");
+        assert_eq!(
+            FileTime::from_last_modification_time(&fs::metadata(&gz).unwrap()).unix_seconds(),
+            FileTime::from_system_time(now - 3 * DAY).unix_seconds()
+        );
+        assert_eq!(policy.maintain(now).unwrap(), (0, 0));
+
+        // Removed once older than the retention
+        assert_eq!(policy.maintain(now + 8 * DAY).unwrap(), (0, 1));
+        assert!(!gz.exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn maintain_failures() {
+        let dir = std::env::temp_dir().join("test_archive_maintain_failures");
+        fs::remove_dir_all(&dir).ok();
+        let policy = ArchivePolicy {
+            dir: dir.clone(),
+            recompress_after: Some(2 * DAY),
+            keep: None,
+        };
+        let now = SystemTime::now();
+
+        // Nothing is archived before the first load
+        assert_eq!(policy.maintain(now).unwrap(), (0, 0));
+
+        // A feed which can not be compressed does not stop the others
+        fs::create_dir_all(dir.join("feed-broken.xml")).unwrap();
+        fs::write(dir.join("feed-ok.xml"), b"<tv></tv>").unwrap();
+        for name in &["feed-broken.xml", "feed-ok.xml"] {
+            let old = FileTime::from_system_time(now - 3 * DAY);
+            filetime::set_file_mtime(dir.join(name), old).unwrap();
+        }
+        assert_eq!(policy.maintain(now).unwrap(), (1, 0));
+        assert!(dir.join("feed-ok.xml.gz").exists());
+        assert!(dir.join("feed-broken.xml").exists());
+        assert!(!dir.join("feed-broken.xml.gz").exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn archive_failure() {
+        // The archive directory can not be created inside a file
+        let file = std::env::temp_dir().join("test_archive_failure");
+        fs::write(&file, b"").unwrap();
+        let policy = ArchivePolicy {
+            dir: file.join("archive"),
+            recompress_after: None,
+            keep: None,
+        };
+        let mut xml = String::new();
+        policy
+            .archive(&b"<tv></tv>"[..], Utc::now())
+            .read_to_string(&mut xml)
+            .unwrap();
+        assert_eq!(xml, "<tv></tv>");
+        fs::remove_file(&file).ok();
+    }
+}
@@ -0,0 +1,98 @@
+use iron::mime::Mime;
+use iron::prelude::*;
+use iron::status;
+use iron::Handler;
+use staticfile::Static;
+use std::path::{Component, Path, PathBuf};
+
+/// Minimal replacements of the webpack bundle compiled into the binary: name, content type
+/// and content. Pages stay usable in images which ship only the executable.
+const EMBEDDED: &[(&str, &str, &str)] = &[
+    (
+        "bundle.min.css",
+        "text/css; charset=utf-8",
+        include_str!("../assets/minimal.css"),
+    ),
+    (
+        "bundle.min.js",
+        "application/javascript; charset=utf-8",
+        include_str!("../assets/minimal.js"),
+    ),
+];
+
+fn embedded(name: &str) -> Option<(&'static str, &'static str)> {
+    EMBEDDED
+        .iter()
+        .find(|(n, _, _)| *n == name)
+        .map(|(_, mime, content)| (*mime, *content))
+}
+
+/// Path of the requested file in the directory, `None` when it escapes the directory
+fn file_path(dir: &Path, segments: &[&str]) -> Option<PathBuf> {
+    let relative: PathBuf = segments.iter().filter(|s| !s.is_empty()).collect();
+    if relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        Some(dir.join(relative))
+    } else {
+        None
+    }
+}
+
+/// Static files served from the directory, or the embedded ones when they are missing on disk
+pub struct Assets {
+    dir: PathBuf,
+    files: Static,
+}
+
+impl Assets {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            files: Static::new(dir.as_ref()),
+        }
+    }
+}
+
+impl Handler for Assets {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let path = req.url.path();
+        let on_disk = file_path(&self.dir, &path).map_or(false, |p| p.is_file());
+        if !on_disk {
+            if let [name] = path.as_slice() {
+                if let Some((mime, content)) = embedded(name) {
+                    return Ok(Response::with((
+                        status::Ok,
+                        mime.parse::<Mime>().unwrap(),
+                        content,
+                    )));
+                }
+            }
+        }
+        self.files.handle(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback() {
+        assert!(embedded("bundle.min.css").unwrap().1.contains(".table"));
+        assert!(embedded("bundle.min.js").is_some());
+        assert_eq!(embedded("index.html"), None);
+
+        let dir = Path::new("static");
+        assert_eq!(
+            file_path(dir, &["bundle.min.css"]),
+            Some(PathBuf::from("static/bundle.min.css"))
+        );
+        assert_eq!(
+            file_path(dir, &["fonts", "fa.woff2"]),
+            Some(PathBuf::from("static/fonts/fa.woff2"))
+        );
+        assert_eq!(file_path(dir, &["..", "Cargo.toml"]), None);
+    }
+}
@@ -1,28 +1,393 @@
-use crate::epg::{ChannelInfo, EpgNow, Program};
-use crate::update_status::UpdateStatus;
+use crate::epg::{
+    check_schedule, Audio, Channel, ChannelInfo, EpgNow, Program, ScheduleCheck, Snapshot, Video,
+};
+use crate::mapping::{merge_by_priority, AliasMapping, ChannelSeeds};
+use crate::update_status::{FeedSource, UpdateStatus};
+use crate::xmltv::FeedInfo;
 use crate::xmltv::XmltvItem;
 use crate::xmltv::XmltvReader;
 use chrono::prelude::*;
 use error_chain::ChainedError;
 use failure::Fail;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ValueRef};
 use rusqlite::{types::ToSql, OptionalExtension};
 use rusqlite::{Connection, Result, NO_PARAMS};
+use serde_derive::{Deserialize, Serialize};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
-use std::io::BufRead;
-use std::path::PathBuf;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::{fmt, fs};
 
+/// Default limit for the number of programs per channel returned by `get_at`
+pub const MAX_PROGRAMS_AT: i64 = 24;
+
+/// Limit for the number of programs of a channel exported by `get_channel_full`
+pub const MAX_CHANNEL_PROGRAMS: i64 = 10_000;
+
+/// Outcome of loading xmltv feed into the database
+#[derive(Debug, Default)]
+pub struct LoadSummary {
+    pub feed: FeedInfo,
+    pub channels: usize,
+    pub programs: usize,
+    /// Programs skipped because their channel is unknown
+    pub skipped: usize,
+    /// Programs dropped because they begin too far in the future
+    pub too_far: usize,
+    /// Programs skipped because their channel is not in the allowlist
+    pub not_allowed: usize,
+    /// Programs which begin before the previous program of their channel ends
+    pub overlaps: usize,
+    /// Programs merged into the preceding part of the same show
+    pub coalesced: usize,
+    /// Programs dropped because another program of their channel begins at the same time
+    pub duplicates: usize,
+    /// Parser error which stopped loading
+    pub error: Option<String>,
+}
+
+impl fmt::Display for LoadSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} channels, {} programs, {} skipped programs",
+            self.channels, self.programs, self.skipped
+        )?;
+        if self.too_far > 0 {
+            write!(f, ", {} programs too far ahead", self.too_far)?;
+        }
+        if self.not_allowed > 0 {
+            write!(f, ", {} programs of channels not allowed", self.not_allowed)?;
+        }
+        if self.overlaps > 0 {
+            write!(f, ", {} overlapping programs", self.overlaps)?;
+        }
+        if self.coalesced > 0 {
+            write!(f, ", {} coalesced programs", self.coalesced)?;
+        }
+        if self.duplicates > 0 {
+            write!(f, ", {} programs with duplicate begin", self.duplicates)?;
+        }
+        if let Some(e) = &self.error {
+            write!(f, ", error: {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+/// Descriptions converted between plain and compressed storage
+#[derive(Debug, Default, PartialEq)]
+pub struct DescriptionStats {
+    pub rows: usize,
+    /// Size of the converted descriptions as text
+    pub text_bytes: usize,
+    /// Size of the converted descriptions compressed
+    pub compressed_bytes: usize,
+}
+
+impl fmt::Display for DescriptionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} descriptions, {} bytes as text and {} bytes compressed",
+            self.rows, self.text_bytes, self.compressed_bytes
+        )
+    }
+}
+
+/// Conditions under which a channel is considered gone after a feed load,
+/// channel is removed only when both of them hold
+#[derive(Debug, Clone)]
+pub struct PurgePolicy {
+    /// Channels with fewer programs in the database are candidates for removal
+    pub min_programs: i64,
+    /// Channels which had programs in a feed within this period are kept
+    pub grace: chrono::Duration,
+}
+
+impl Default for PurgePolicy {
+    fn default() -> Self {
+        Self {
+            min_programs: 1,
+            grace: chrono::Duration::zero(),
+        }
+    }
+}
+
+/// When sqlite waits for data to reach the disk, values of `pragma synchronous`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Synchronous::Off => "off",
+            Synchronous::Normal => "normal",
+            Synchronous::Full => "full",
+            Synchronous::Extra => "extra",
+        }
+    }
+
+    /// Level reported by sqlite as a number
+    fn from_level(level: i64) -> Option<Self> {
+        match level {
+            0 => Some(Synchronous::Off),
+            1 => Some(Synchronous::Normal),
+            2 => Some(Synchronous::Full),
+            3 => Some(Synchronous::Extra),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for Synchronous {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(Synchronous::Off),
+            "normal" => Ok(Synchronous::Normal),
+            "full" => Ok(Synchronous::Full),
+            "extra" => Ok(Synchronous::Extra),
+            _ => Err(format!("unknown synchronous mode {}", s)),
+        }
+    }
+}
+
+/// Which of the programs of a channel with the same begin time `load_xmltv` keeps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicateBegins {
+    /// The one which ends last, the earlier one in the feed on a tie
+    Longer,
+    First,
+    Last,
+}
+
+impl std::str::FromStr for DuplicateBegins {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "longer" => Ok(DuplicateBegins::Longer),
+            "first" => Ok(DuplicateBegins::First),
+            "last" => Ok(DuplicateBegins::Last),
+            _ => Err(format!("unknown duplicate begins policy {}", s)),
+        }
+    }
+}
+
+/// Sqlite tuning applied to every connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pragmas {
+    /// Pages of the page cache, negative values are kibibytes like in sqlite
+    pub cache_size: i64,
+    /// Bytes of the database file mapped into memory, zero disables mapping
+    pub mmap_size: i64,
+    pub synchronous: Synchronous,
+}
+
+impl Default for Pragmas {
+    fn default() -> Self {
+        Self {
+            cache_size: 10000,
+            mmap_size: 0,
+            synchronous: Synchronous::Full,
+        }
+    }
+}
+
+impl Pragmas {
+    fn apply(&self, conn: &Connection) -> Result<()> {
+        conn.execute_batch(&format!(
+            "pragma cache_size={}; pragma mmap_size={}; pragma synchronous={}",
+            self.cache_size,
+            self.mmap_size,
+            self.synchronous.as_str()
+        ))
+    }
+}
+
+/// Pragma values in effect on a connection, as reported by sqlite
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EffectivePragmas {
+    pub cache_size: i64,
+    pub mmap_size: i64,
+    pub synchronous: Option<&'static str>,
+    pub journal_mode: String,
+    pub page_size: i64,
+}
+
+/// Outcome of changing the alias of a channel
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Remap {
+    /// Alias of the channel changed, its programs stay
+    Renamed,
+    /// Channel of the new alias was merged into the renamed one
+    Merged {
+        /// Programs moved from the merged channel
+        programs: usize,
+    },
+    /// There is no channel with the old alias
+    NotFound,
+    /// Another channel has the new alias and merging was not requested
+    Conflict,
+}
+
+/// Overview of the database contents
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DbStats {
+    pub channels: i64,
+    pub programs: i64,
+    /// Size of the database and its write-ahead log in bytes
+    pub file_size: u64,
+    /// Begin of the earliest program
+    pub oldest_program: Option<i64>,
+    /// End of the latest program
+    pub newest_program: Option<i64>,
+    /// Time of the last update attempt
+    pub last_update: Option<i64>,
+}
+
+/// Schema of a database and its migrations, for diagnostics
+#[derive(Debug, PartialEq)]
+pub struct DbSchema {
+    /// Statements which created the tables, indexes and triggers, in the order of creation
+    pub sql: Vec<String>,
+    /// Tags of the applied migrations in the order of application
+    pub applied: Vec<String>,
+    /// Tags of migrations of this version which are not applied
+    pub pending: Vec<&'static str>,
+}
+
+impl fmt::Display for DbSchema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for sql in &self.sql {
+            writeln!(f, "{};", sql)?;
+        }
+        writeln!(f, "-- Applied migrations:")?;
+        for tag in &self.applied {
+            writeln!(f, "--   {}", tag)?;
+        }
+        writeln!(f, "-- Pending migrations:")?;
+        for tag in &self.pending {
+            writeln!(f, "--   {}", tag)?;
+        }
+        Ok(())
+    }
+}
+
+macro_rules! migration {
+    ($tag:expr) => {
+        (
+            $tag,
+            include_str!(concat!("../migrations/", $tag, "/up.sql")),
+            include_str!(concat!("../migrations/", $tag, "/down.sql")),
+        )
+    };
+}
+
+/// Schema migrations in the order of application, each is a tag with up and down scripts
+const MIGRATIONS: &[(&str, &str, &str)] = &[
+    migration!("20190325100907_channel-alias"),
+    migration!("20210221123809_update-log"),
+    migration!("20210316201302_last-modified-log"),
+    migration!("20210402183045_feed-info-log"),
+    migration!("20210415191522_program-sub-title"),
+    migration!("20210421204810_channel-last-seen"),
+    migration!("20210426175931_update-log-id"),
+    migration!("20210503120417_program-category"),
+    migration!("20210507094126_program-translations"),
+    migration!("20210512183350_program-country-language"),
+    migration!("20210521093412_program-primary-category"),
+    migration!("20210524101530_program-media"),
+    migration!("20210526142207_update-log-overlaps"),
+    migration!("20210529110412_channel-icon-ok"),
+    migration!("20210601093015_channel-programs-updated"),
+    migration!("20210603094512_update-log-source"),
+    migration!("20210605113020_channel-deleted"),
+];
+
+#[derive(Clone)]
 pub struct ProgramsDatabase {
     file: String,
+    /// SQLCipher key of an encrypted database
+    key: Option<String>,
+    max_programs_at: i64,
+    alias_mapping: AliasMapping,
+    purge_policy: PurgePolicy,
+    channel_seeds: ChannelSeeds,
+    /// Maximum number of characters of stored descriptions, zero drops them
+    description_limit: Option<usize>,
+    /// Programs which begin later than this number of days from now are not loaded
+    max_days_ahead: Option<i64>,
+    /// Loads accumulate in `programs1` until `finalize_load`
+    incremental: bool,
+    /// Contiguous parts of the same show are merged into one program
+    coalesce: bool,
+    /// Only one of the programs of a channel with the same begin is kept
+    duplicate_begins: Option<DuplicateBegins>,
+    /// Descriptions are stored deflated, see `store_descriptions`
+    compress_descriptions: bool,
+    /// Only programs of channels with these feed or canonical aliases are loaded
+    channel_allowlist: Option<HashSet<String>>,
+    pragmas: Pragmas,
+    /// Held by writes, shared by the clones of the database, see `lock_writer`
+    writer: Arc<Mutex<()>>,
 }
 
 impl ProgramsDatabase {
     pub fn open(file: &str) -> Result<Self> {
-        let conn = Connection::open(&file)?;
+        Self::open_with_key(file, None)
+    }
+
+    /// Open a database encrypted with the key, without a key it is the same as `open`.
+    /// A database left with a broken write-ahead log after a crash is recovered once.
+    pub fn open_with_key(file: &str, key: Option<&str>) -> Result<Self> {
+        Self::open_with_pragmas(file, key, Pragmas::default())
+    }
+
+    /// Open a database with the tuning applied to all its connections
+    pub fn open_with_pragmas(file: &str, key: Option<&str>, pragmas: Pragmas) -> Result<Self> {
+        match Self::open_once(file, key, &pragmas) {
+            Err(e) if is_shm_failure(&e) && Path::new(&shm_file(file)).exists() => {
+                eprintln!("Failed to open database {}: {}", file, e);
+                recover_shm(file, key);
+                Self::open_once(file, key, &pragmas)
+            }
+            result => result,
+        }
+    }
+
+    fn open_once(file: &str, key: Option<&str>, pragmas: &Pragmas) -> Result<Self> {
+        let db = Self {
+            file: file.to_string(),
+            key: key.map(str::to_string),
+            max_programs_at: MAX_PROGRAMS_AT,
+            alias_mapping: AliasMapping::default(),
+            purge_policy: PurgePolicy::default(),
+            channel_seeds: ChannelSeeds::default(),
+            description_limit: None,
+            max_days_ahead: None,
+            incremental: false,
+            coalesce: false,
+            duplicate_begins: None,
+            compress_descriptions: false,
+            channel_allowlist: None,
+            pragmas: pragmas.clone(),
+            writer: Arc::new(Mutex::new(())),
+        };
+        let mut conn = db.connect()?;
         conn.execute_batch("pragma journal_mode=WAL")?;
-        conn.execute_batch("pragma cache_size=10000")?;
         conn.execute(
             "create table if not exists channels \
              (id integer primary key, alias text unique, name text, icon_url text)",
@@ -50,9 +415,11 @@ impl ProgramsDatabase {
              )",
             NO_PARAMS,
         )?;
-        let db = Self {
-            file: file.to_string(),
-        };
+        if db.key.is_some() {
+            run_keyed_migrations(&mut conn)?;
+            return Ok(db);
+        }
+        drop(conn);
 
         #[derive(Debug)]
         struct MigrantError {
@@ -97,19 +464,16 @@ impl ProgramsDatabase {
         let mut config = migrant_lib::Config::with_settings(&settings);
         config.setup()?;
         config.use_cli_compatible_tags(true);
-        macro_rules! make_migration {
-            ($tag:expr) => {
-                migrant_lib::EmbeddedMigration::with_tag($tag)
-                    .up(include_str!(concat!("../migrations/", $tag, "/up.sql")))
-                    .down(include_str!(concat!("../migrations/", $tag, "/down.sql")))
+        let migrations = MIGRATIONS
+            .iter()
+            .map(|&(tag, up, down)| {
+                migrant_lib::EmbeddedMigration::with_tag(tag)
+                    .up(up)
+                    .down(down)
                     .boxed()
-            };
-        }
-        config.use_migrations(&[
-            make_migration!("20190325100907_channel-alias"),
-            make_migration!("20210221123809_update-log"),
-            make_migration!("20210316201302_last-modified-log"),
-        ])?;
+            })
+            .collect::<Vec<_>>();
+        config.use_migrations(&migrations)?;
         let config = config.reload()?;
         migrant_lib::list(&config)?;
         println!("Applying migrations ...");
@@ -122,30 +486,81 @@ impl ProgramsDatabase {
         Ok(())
     }
 
-    pub fn load_xmltv<R: BufRead>(&self, xmltv: XmltvReader<R>) -> Result<()> {
-        let mut conn = Connection::open(&self.file)?;
+    /// Load xmltv data into the database.
+    /// With `dry_run` the feed is parsed and counted, but nothing is written.
+    /// In incremental mode programs are only staged, see `finalize_load`.
+    pub fn load_xmltv<R: BufRead>(
+        &self,
+        xmltv: XmltvReader<R>,
+        dry_run: bool,
+    ) -> Result<LoadSummary> {
+        let _writer = self.lock_writer();
+        let mut conn = self.connect()?;
 
-        // Make sure that temporary storage is clean
-        conn.execute("drop index if exists p1_channel", NO_PARAMS)?;
-        conn.execute("delete from programs1", NO_PARAMS)?;
+        if !dry_run {
+            // Make sure that temporary storage is clean
+            conn.execute("drop index if exists p1_channel", NO_PARAMS)?;
+            if !self.incremental {
+                conn.execute("delete from programs1", NO_PARAMS)?;
+            }
+            // Seeded channels could be purged since the last load
+            apply_channel_seeds(&conn, &self.channel_seeds)?;
+        }
+        // Ids reserved by seeds are never assigned to other channels
+        let reserved: HashSet<i64> = self.channel_seeds.iter().map(|&(id, _)| id).collect();
 
-        let mut ids: HashMap<String, i64> = self
-            .get_channels()?
-            .into_iter()
-            .map(|(id, info)| (info.alias, id))
-            .collect();
+        // Deleted channels which reappear get their old ids back
+        let mut ids = self.get_channel_ids()?;
+
+        let mapping = &self.alias_mapping;
+        // Priority of the feed channel which provided metadata for a merged channel
+        let mut merged_meta: HashMap<String, usize> = HashMap::new();
+        // Programs of merged channels are resolved after all of them are parsed
+        let mut merged_programs: HashMap<i64, Vec<(usize, Program)>> = HashMap::new();
+
+        let horizon = self
+            .max_days_ahead
+            .map(|days| (Utc::now() + chrono::Duration::days(days)).timestamp());
 
-        let mut ins_c = 0;
-        let mut ins_p = 0;
+        let mut summary = LoadSummary::default();
         let mut result = Ok(());
         println!("Parsing XMLTV entries into database ...");
         // Convert xmltv into sql table
         {
             let tx = conn.transaction()?;
+            // Rows staged by earlier incremental loads end here
+            let staged_before: i64 = tx.query_row(
+                "select coalesce(max(id), 0) from programs1",
+                NO_PARAMS,
+                |row| row.get(0),
+            )?;
             for item in xmltv {
                 match item {
+                    Ok(XmltvItem::Header(info)) => {
+                        println!(
+                            "Feed generated by '{}' at '{}'",
+                            info.generator_name, info.date
+                        );
+                        summary.feed = info;
+                    }
                     Ok(XmltvItem::Channel(channel)) => {
-                        match ids.entry(channel.alias) {
+                        let canonical = mapping.canonical(&channel.alias).to_string();
+                        if let Some(priority) = mapping.priority(&channel.alias) {
+                            match merged_meta.entry(canonical.clone()) {
+                                Entry::Occupied(mut entry) => {
+                                    if *entry.get() < priority {
+                                        // Keep metadata from the higher priority channel
+                                        summary.channels += 1;
+                                        continue;
+                                    }
+                                    entry.insert(priority);
+                                }
+                                Entry::Vacant(entry) => {
+                                    entry.insert(priority);
+                                }
+                            }
+                        }
+                        match ids.entry(canonical) {
                             Entry::Occupied(entry) => {
                                 // Chanel with this alias already exists
                                 let &id = entry.get();
@@ -159,7 +574,12 @@ impl ProgramsDatabase {
                             }
                             Entry::Vacant(entry) => {
                                 // First try use alias as an integer id
-                                if let Ok(id) = entry.key().parse::<i64>() {
+                                let alias_id = entry
+                                    .key()
+                                    .parse::<i64>()
+                                    .ok()
+                                    .filter(|id| !reserved.contains(id));
+                                if let Some(id) = alias_id {
                                     update_channel(
                                         &tx,
                                         id,
@@ -180,63 +600,448 @@ impl ProgramsDatabase {
                                 }
                             }
                         }
-                        ins_c += 1;
+                        summary.channels += 1;
                     }
-                    Ok(XmltvItem::Program((alias, program))) => {
-                        if let Some(&id) = ids.get(&alias) {
-                            insert_program(&tx, id, &program)?;
-                            ins_p += 1;
+                    Ok(XmltvItem::Program((alias, mut program))) => {
+                        if horizon.map_or(false, |horizon| program.begin > horizon) {
+                            summary.too_far += 1;
+                            continue;
+                        }
+                        if let Some(allowlist) = &self.channel_allowlist {
+                            if !allowlist.contains(&alias)
+                                && !allowlist.contains(mapping.canonical(&alias))
+                            {
+                                summary.not_allowed += 1;
+                                continue;
+                            }
+                        }
+                        if let Some(limit) = self.description_limit {
+                            limit_descriptions(&mut program, limit);
+                        }
+                        if let Some(&id) = ids.get(mapping.canonical(&alias)) {
+                            if let Some(priority) = mapping.priority(&alias) {
+                                merged_programs
+                                    .entry(id)
+                                    .or_insert_with(Vec::new)
+                                    .push((priority, program));
+                            } else {
+                                insert_program(&tx, id, &program)?;
+                                summary.programs += 1;
+                            }
                         } else {
                             eprintln!("Skip program for unknown channel {}", alias);
+                            summary.skipped += 1;
                         }
                     }
                     Err(e) => {
                         // Process all parsed items and return Error in the end
+                        summary.error = Some(e.to_string());
                         result = Err(rusqlite::Error::UserFunctionError(Box::new(e.compat())));
                         break;
                     }
                 }
             }
+            if summary.channels == 0 && !dry_run {
+                // Taken for a broken feed, the purge after the merge would delete all channels.
+                // The transaction is rolled back, so the stored data stays as it was.
+                result?;
+                return Err(rusqlite::Error::UserFunctionError(
+                    "Feed has no channels, the stored data is kept".into(),
+                ));
+            }
+            for (id, programs) in merged_programs {
+                for program in merge_by_priority(programs) {
+                    insert_program(&tx, id, &program)?;
+                    summary.programs += 1;
+                }
+            }
+            if self.incremental {
+                let replaced = unstage_replaced(&tx, staged_before)?;
+                if replaced > 0 {
+                    println!("Replaced {} programs staged by earlier loads", replaced);
+                }
+            }
+            if let Some(policy) = self.duplicate_begins {
+                summary.duplicates = drop_duplicate_begins(&tx, policy)?;
+            }
+            if self.coalesce {
+                summary.coalesced = coalesce_programs(&tx)?;
+            }
+            if self.compress_descriptions {
+                let t = std::time::Instant::now();
+                let stats = store_descriptions(&tx, "programs1", true)?;
+                println!("Compressed {} in {:?}", stats, t.elapsed());
+            }
+            summary.overlaps = count_overlaps(&tx)?;
+            if dry_run {
+                tx.rollback()?;
+                println!("Dry run parsed {}", summary);
+                return Ok(summary);
+            }
             tx.commit()?;
         }
 
         println!(
             "Loaded {} channels and {} programs into sql database",
-            ins_c, ins_p
+            summary.channels, summary.programs
         );
+        if summary.overlaps > 0 {
+            println!("Feed has {} overlapping programs", summary.overlaps);
+        }
+        if summary.coalesced > 0 {
+            println!(
+                "Coalesced {} programs into their predecessors",
+                summary.coalesced
+            );
+        }
+        if summary.duplicates > 0 {
+            println!(
+                "Dropped {} programs beginning at the same time as another one",
+                summary.duplicates
+            );
+        }
+        if summary.not_allowed > 0 {
+            println!(
+                "Skipped {} programs of channels not in the allowlist",
+                summary.not_allowed
+            );
+        }
+        if summary.too_far > 0 {
+            println!(
+                "Dropped {} programs beginning more than {} days ahead",
+                summary.too_far,
+                self.max_days_ahead.unwrap_or_default()
+            );
+        }
+        if self.incremental {
+            println!("Programs are staged until the load is finalized");
+            return result.map(|_| summary);
+        }
+        self.merge_staged(&mut conn)?;
+        result.map(|_| summary)
+    }
 
+    /// Merge programs staged by incremental loads into the database,
+    /// returns the number of merged programs.
+    ///
+    /// Channels of the feed parts are merged by priority within each part only.
+    pub fn finalize_load(&self) -> Result<i64> {
+        let _writer = self.lock_writer();
+        self.merge_all_staged()
+    }
+
+    /// Same as `finalize_load` while the writer lock is held
+    fn merge_all_staged(&self) -> Result<i64> {
+        let mut conn = self.connect()?;
+        let staged = conn.query_row("select count(*) from programs1", NO_PARAMS, |row| {
+            row.get(0)
+        })?;
+        conn.execute("drop index if exists p1_channel", NO_PARAMS)?;
+        self.merge_staged(&mut conn)?;
+        Ok(staged)
+    }
+
+    /// Merge programs left in `programs1` by a load which was interrupted before its merge,
+    /// returns their number. Incremental loads keep staged programs until `finalize_load`.
+    pub fn recover_staged(&self) -> Result<i64> {
+        if self.incremental {
+            return Ok(0);
+        }
+        let _writer = self.lock_writer();
+        let staged: i64 =
+            self.connect()?
+                .query_row("select count(*) from programs1", NO_PARAMS, |row| {
+                    row.get(0)
+                })?;
+        if staged == 0 {
+            return Ok(0);
+        }
+        self.merge_all_staged()
+    }
+
+    /// Recreate the program indexes and rebuild all other indexes of the database
+    pub fn rebuild_indexes(&self) -> Result<()> {
+        let _writer = self.lock_writer();
+        let mut conn = self.connect()?;
+        let tx = conn.transaction()?;
+        drop_indexes(&tx)?;
+        create_indexes(&tx)?;
+        tx.execute_batch("reindex")?;
+        tx.commit()
+    }
+
+    /// Replace programs with the ones staged in `programs1` and clean up afterwards.
+    ///
+    /// All steps commit at once, a merge which fails or is killed leaves the stored
+    /// programs as they were and the staged ones for `recover_staged`.
+    fn merge_staged(&self, conn: &mut Connection) -> Result<()> {
+        let now = Utc::now();
+        let tx = conn.transaction()?;
         // Clear old epg entries from the database
-        let time = Utc::now().naive_utc() - chrono::Duration::days(20);
-        self.delete_before(time.timestamp())?;
+        let time = now.naive_utc() - chrono::Duration::days(20);
+        delete_programs_before(&tx, time.timestamp())?;
+        // Remember which channels are still present in the feed
+        mark_channels_seen(&tx, now.timestamp())?;
         // Merge new programs data into database
-        append_programs(&mut conn)?;
+        merge_programs(&tx, now.timestamp())?;
         // Clean up obsolete channels
-        clear_channels(&mut conn)?;
-        result
+        clear_channels(&tx, &self.purge_policy, now.timestamp())?;
+        tx.commit()
+    }
+
+    /// Load xmltv data into a copy of the database, then atomically replace the live file.
+    ///
+    /// Readers open a new connection for each query, so they see either the old
+    /// or the new file, but never wait for `append_programs`. The copy is made by
+    /// `vacuum into`, which needs SQLite 3.27 or newer. It lives in the same directory,
+    /// so `rename` does not cross filesystems.
+    ///
+    /// Other writes of this database and its clones wait from the copy until the swap,
+    /// otherwise they would be lost with the old file. The write-ahead log of the old
+    /// file is checkpointed and the copy leaves WAL mode before the swap, so the new file
+    /// is never opened together with stale WAL frames.
+    pub fn load_xmltv_staged<R: BufRead>(&self, xmltv: XmltvReader<R>) -> Result<LoadSummary> {
+        let _writer = self.lock_writer();
+        let io_error = |e: std::io::Error| rusqlite::Error::UserFunctionError(Box::new(e));
+        let staging = self.staging_copy(format!("{}.staging", self.file));
+        if Path::new(&staging.file).exists() {
+            // Leftover of an interrupted load
+            fs::remove_file(&staging.file).map_err(io_error)?;
+        }
+        println!("Copying database into {}", staging.file);
+        self.connect()?
+            .execute("vacuum into ?1", &[&staging.file])?;
+
+        let summary = match staging.load_xmltv(xmltv, false) {
+            Ok(summary) => summary,
+            Err(e) => {
+                fs::remove_file(&staging.file).ok();
+                return Err(e);
+            }
+        };
+        staging
+            .connect()?
+            .execute_batch("pragma journal_mode=DELETE")?;
+        self.connect()?
+            .execute_batch("pragma wal_checkpoint(TRUNCATE)")?;
+        fs::rename(&staging.file, &self.file).map_err(io_error)?;
+        self.connect()?.execute_batch("pragma journal_mode=WAL")?;
+        println!("Swapped staging database into {}", self.file);
+        Ok(summary)
+    }
+
+    /// Database with the same settings stored in another file
+    fn staging_copy(&self, file: String) -> Self {
+        Self {
+            file,
+            key: self.key.clone(),
+            max_programs_at: self.max_programs_at,
+            alias_mapping: self.alias_mapping.clone(),
+            purge_policy: self.purge_policy.clone(),
+            channel_seeds: self.channel_seeds.clone(),
+            description_limit: self.description_limit,
+            max_days_ahead: self.max_days_ahead,
+            incremental: self.incremental,
+            coalesce: self.coalesce,
+            duplicate_begins: self.duplicate_begins,
+            compress_descriptions: self.compress_descriptions,
+            channel_allowlist: self.channel_allowlist.clone(),
+            pragmas: self.pragmas.clone(),
+            writer: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Serializes writes of the clones with `load_xmltv_staged`, which replaces the file
+    fn lock_writer(&self) -> MutexGuard<'_, ()> {
+        // Nothing is guarded, a panic of another writer leaves no broken state
+        self.writer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Open a connection, the key of an encrypted database goes before any other statement
+    fn connect(&self) -> Result<Connection> {
+        let conn = open_connection(&self.file, self.key.as_deref())?;
+        self.pragmas.apply(&conn)?;
+        Ok(conn)
+    }
+
+    /// Pragma values of a new connection
+    pub fn effective_pragmas(&self) -> Result<EffectivePragmas> {
+        let conn = self.connect()?;
+        let pragma = |name: &str| format!("pragma {}", name);
+        Ok(EffectivePragmas {
+            cache_size: conn.query_row(&pragma("cache_size"), NO_PARAMS, |row| row.get(0))?,
+            mmap_size: conn.query_row(&pragma("mmap_size"), NO_PARAMS, |row| row.get(0))?,
+            synchronous: Synchronous::from_level(conn.query_row(
+                &pragma("synchronous"),
+                NO_PARAMS,
+                |row| row.get(0),
+            )?)
+            .map(Synchronous::as_str),
+            journal_mode: conn.query_row(&pragma("journal_mode"), NO_PARAMS, |row| row.get(0))?,
+            page_size: conn.query_row(&pragma("page_size"), NO_PARAMS, |row| row.get(0))?,
+        })
     }
 
     pub fn get_channels(&self) -> Result<Vec<(i64, ChannelInfo)>> {
-        let conn = Connection::open(&self.file)?;
-        let mut stmt = conn.prepare("select id, alias, name, icon_url from channels")?;
+        let conn = self.connect()?;
+        let mut stmt =
+            conn.prepare("select id, alias, name, icon_url from channels where deleted = 0")?;
         let it = stmt
-            .query_map(NO_PARAMS, |row| {
-                Ok((
-                    row.get::<_, i64>(0)?,
-                    ChannelInfo {
-                        alias: row.get(1)?,
-                        name: row.get(2)?,
-                        icon_url: row.get(3)?,
-                    },
-                ))
-            })?
+            .query_map(NO_PARAMS, channel_from_row)?
+            .filter_map(|item| item.ok());
+        Ok(it.collect::<Vec<_>>())
+    }
+
+    /// Ids of all channels by alias, deleted ones included
+    fn get_channel_ids(&self) -> Result<HashMap<String, i64>> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare("select alias, id from channels")?;
+        let it = stmt.query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        it.collect()
+    }
+
+    /// Channels removed by the purge, they are restored when a feed has them again
+    pub fn get_deleted_channels(&self) -> Result<Vec<(i64, ChannelInfo)>> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(
+            "select id, alias, name, icon_url from channels where deleted != 0 order by name",
+        )?;
+        let it = stmt.query_map(NO_PARAMS, channel_from_row)?;
+        it.collect()
+    }
+
+    /// Restore a deleted channel, returns whether there was one with the id.
+    /// It is purged again by the next load unless it gets enough programs or is seen in a feed.
+    pub fn undelete_channel(&self, id: i64) -> Result<bool> {
+        let _writer = self.lock_writer();
+        let conn = self.connect()?;
+        let count = conn.execute(
+            "update channels set deleted = 0 where id = ?1 and deleted != 0",
+            &[&id],
+        )?;
+        Ok(count > 0)
+    }
+
+    /// Time the programs of the channel last changed, `None` when it is not known
+    pub fn get_programs_updated(&self, id: i64) -> Result<Option<i64>> {
+        let conn = self.connect()?;
+        let updated = conn
+            .query_row(
+                "select programs_updated from channels where id = ?1",
+                &[&id],
+                |row| row.get::<_, Option<i64>>(0),
+            )
+            .optional()?
+            .flatten();
+        Ok(updated.filter(|&t| t > 0))
+    }
+
+    /// Channels which have a program on air at the time
+    pub fn get_active_channels(&self, time: i64) -> Result<Vec<(i64, ChannelInfo)>> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(
+            "select id, alias, name, icon_url from channels where deleted = 0 and exists
+             (select 1 from programs where programs.channel = channels.id
+              and programs.begin <= ?1 and programs.end > ?1)",
+        )?;
+        let it = stmt
+            .query_map(&[&time], channel_from_row)?
             .filter_map(|item| item.ok());
         Ok(it.collect::<Vec<_>>())
     }
 
+    /// Channels with their programs on air at the time, `None` when nothing is
+    pub fn get_channels_with_now(
+        &self,
+        time: i64,
+    ) -> Result<Vec<(i64, ChannelInfo, Option<Program>)>> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(&format!(
+            "select channels.id, alias, name, icon_url, {}
+             from channels left join programs on programs.id =
+             (select id from programs where programs.channel = channels.id
+              and programs.begin <= ?1 and programs.end > ?1
+              order by programs.begin desc limit 1)
+             where deleted = 0",
+            PROGRAM_COLUMNS
+        ))?;
+        let it = stmt.query_map(&[&time], |row| {
+            let (id, channel) = channel_from_row(row)?;
+            let now = match row.get::<_, Option<i64>>(4)? {
+                Some(_) => Some(program_from_row(row, 4)?),
+                None => None,
+            };
+            Ok((id, channel, now))
+        })?;
+        it.collect()
+    }
+
+    /// Channels with an icon url which was not probed since the time, least recently probed first
+    pub fn get_icons_to_probe(
+        &self,
+        checked_before: i64,
+        limit: i64,
+    ) -> Result<Vec<(i64, String)>> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(
+            "select id, icon_url from channels
+             where deleted = 0 and icon_url != '' and coalesce(icon_checked, 0) < ?1
+             order by icon_checked limit ?2",
+        )?;
+        let it = stmt.query_map(&[&checked_before, &limit], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        it.collect()
+    }
+
+    /// Record the probe of the icon, it is ignored when the channel got another icon meanwhile
+    pub fn set_icon_status(&self, id: i64, icon_url: &str, ok: bool, time: i64) -> Result<()> {
+        let _writer = self.lock_writer();
+        let conn = self.connect()?;
+        conn.execute(
+            "update channels set icon_ok = ?1, icon_checked = ?2 where id = ?3 and icon_url = ?4",
+            rusqlite::params![ok, time, id, icon_url],
+        )?;
+        Ok(())
+    }
+
+    /// Channels which icon url did not respond with success on the last probe
+    pub fn get_dead_icons(&self) -> Result<Vec<(i64, ChannelInfo)>> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(
+            "select id, alias, name, icon_url from channels
+             where deleted = 0 and icon_url != '' and icon_ok = 0 order by name",
+        )?;
+        let it = stmt.query_map(NO_PARAMS, channel_from_row)?;
+        it.collect()
+    }
+
+    /// Channels by the end of their last program, the ones whose guide runs out first
+    /// come first and channels without programs before all others
+    pub fn channels_ending_soonest(
+        &self,
+        limit: i64,
+    ) -> Result<Vec<(i64, ChannelInfo, Option<i64>)>> {
+        let conn = self.connect()?;
+        // The maximum of each channel is a lookup in the `channel_end` index
+        let mut stmt = conn.prepare(
+            "select id, alias, name, icon_url,
+             (select max(end) from programs where programs.channel = channels.id) as last_end
+             from channels where deleted = 0 order by last_end, name limit ?1",
+        )?;
+        let it = stmt.query_map(&[&limit], |row| {
+            let (id, channel) = channel_from_row(row)?;
+            Ok((id, channel, row.get(4)?))
+        })?;
+        it.collect()
+    }
+
     pub fn get_channel_by_alias(&self, alias: &str) -> Result<Option<(i64, ChannelInfo)>> {
-        let conn = Connection::open(&self.file)?;
+        let conn = self.connect()?;
         conn.query_row(
-            "select id, alias, name, icon_url from channels where alias = ?1",
+            "select id, alias, name, icon_url from channels where alias = ?1 and deleted = 0",
             rusqlite::params![alias],
             |row| {
                 Ok((
@@ -252,119 +1057,848 @@ impl ProgramsDatabase {
         .optional()
     }
 
-    pub fn get_at(&self, timestamp: i64, count: i64) -> Result<HashMap<i64, EpgNow>> {
-        let conn = Connection::open(&self.file)?;
+    /// Channel with all its stored programs ordered by begin time,
+    /// at most `MAX_CHANNEL_PROGRAMS` earliest programs are returned
+    pub fn get_channel_full(&self, id: i64) -> Result<Option<Channel>> {
+        let conn = self.connect()?;
+        let channel = conn
+            .query_row(
+                "select alias, name, icon_url from channels where id = ?1 and deleted = 0",
+                &[&id],
+                |row| {
+                    Ok(Channel {
+                        id,
+                        alias: row.get(0)?,
+                        name: row.get(1)?,
+                        icon_url: row.get(2)?,
+                        programs: Vec::new(),
+                    })
+                },
+            )
+            .optional()?;
+        let mut channel = match channel {
+            Some(channel) => channel,
+            None => return Ok(None),
+        };
         let mut stmt = conn.prepare(
-            "select
-                channels.id,
-                programs.begin, programs.end, programs.title, programs.description
-             from channels
-             join programs on programs.id in
-             (select programs.id from programs where
-              programs.channel=channels.id AND programs.end > ?1 order by programs.end limit ?2)",
+            "select programs.begin, programs.end, programs.title, programs.description,
+             programs.sub_title, programs.category, programs.translations,
+             programs.country, programs.language, programs.primary_category,
+             programs.media
+             from programs where programs.channel = ?1
+             order by programs.begin limit ?2",
         )?;
+        channel.programs = stmt
+            .query_map(&[&id, &MAX_CHANNEL_PROGRAMS], |row| {
+                program_from_row(row, 0)
+            })?
+            .filter_map(|item| item.ok())
+            .collect();
+        Ok(Some(channel))
+    }
 
-        let mut hash: HashMap<i64, EpgNow> = HashMap::new();
+    /// Set the upper limit for `count` accepted by `get_at`
+    pub fn set_max_programs_at(&mut self, count: i64) {
+        self.max_programs_at = count.max(1);
+    }
 
-        let it = stmt.query_map(&[&timestamp, &count], |row| {
-            let id: i64 = row.get(0)?;
-            let program = Program {
-                begin: row.get(1)?,
-                end: row.get(2)?,
-                title: row.get(3)?,
-                description: row.get(4)?,
-            };
-            Ok((id, program))
-        })?;
+    /// Set the number of characters of descriptions kept by `load_xmltv`,
+    /// zero stores no descriptions and `None` keeps them whole
+    pub fn set_description_limit(&mut self, limit: Option<usize>) {
+        self.description_limit = limit;
+    }
 
-        for (id, program) in it.filter_map(|item| item.ok()) {
-            hash.entry(id)
-                .or_insert(EpgNow {
-                    channel_id: id,
-                    programs: Vec::new(),
-                })
-                .programs
-                .push(program);
-        }
-        Ok(hash)
+    /// Set the number of days ahead beyond which `load_xmltv` drops programs as bogus
+    pub fn set_max_days_ahead(&mut self, days: Option<i64>) {
+        self.max_days_ahead = days;
     }
 
-    pub fn get_range(&self, id: i64, from: i64, to: i64) -> Result<Vec<Program>> {
-        let conn = Connection::open(&self.file)?;
-        let mut stmt = conn.prepare(
-            "select programs.begin, programs.end, programs.title, programs.description
-         from programs where
-         programs.channel = ?1 and programs.begin >= ?2 and programs.begin < ?3",
-        )?;
-        let it = stmt
-            .query_map(&[&id, &from, &to], |row| {
-                Ok(Program {
-                    begin: row.get(0)?,
-                    end: row.get(1)?,
-                    title: row.get(2)?,
-                    description: row.get(3)?,
-                })
+    /// Keep programs of `load_xmltv` staged until `finalize_load`,
+    /// for feeds which are split into several parts
+    pub fn set_incremental(&mut self, incremental: bool) {
+        self.incremental = incremental;
+    }
+
+    /// Merge back-to-back programs with the same title, see `coalesce_programs`
+    pub fn set_coalesce(&mut self, coalesce: bool) {
+        self.coalesce = coalesce;
+    }
+
+    /// Keep one of the programs of a channel which begin at the same time,
+    /// all of them without a policy
+    pub fn set_duplicate_begins(&mut self, policy: Option<DuplicateBegins>) {
+        self.duplicate_begins = policy;
+    }
+
+    /// Store descriptions of loaded programs compressed,
+    /// `convert_descriptions` brings the stored ones to the same form
+    pub fn set_compress_descriptions(&mut self, compress: bool) {
+        self.compress_descriptions = compress;
+    }
+
+    /// Load programs only of the channels with the aliases, all of them without an allowlist.
+    /// Channels themselves are still stored, so playlists can be matched against them.
+    pub fn set_channel_allowlist(&mut self, aliases: Option<HashSet<String>>) {
+        self.channel_allowlist = aliases;
+    }
+
+    /// Compress plain descriptions of stored programs or restore compressed ones
+    /// as configured by `set_compress_descriptions`
+    pub fn convert_descriptions(&self) -> Result<DescriptionStats> {
+        let _writer = self.lock_writer();
+        let mut conn = self.connect()?;
+        let tx = conn.transaction()?;
+        let stats = store_descriptions(&tx, "programs", self.compress_descriptions)?;
+        tx.commit()?;
+        Ok(stats)
+    }
+
+    /// Set mapping used to merge feed channels in `load_xmltv`
+    pub fn set_alias_mapping(&mut self, mapping: AliasMapping) {
+        self.alias_mapping = mapping;
+    }
+
+    /// Set channels with fixed ids, they are applied by `seed_channels` and `load_xmltv`
+    pub fn set_channel_seeds(&mut self, seeds: ChannelSeeds) {
+        self.channel_seeds = seeds;
+    }
+
+    /// Make sure that seeded channels exist with their fixed ids
+    pub fn seed_channels(&self) -> Result<()> {
+        let _writer = self.lock_writer();
+        let mut conn = self.connect()?;
+        let tx = conn.transaction()?;
+        apply_channel_seeds(&tx, &self.channel_seeds)?;
+        tx.commit()
+    }
+
+    /// Set conditions for removing channels after `load_xmltv`
+    pub fn set_purge_policy(&mut self, policy: PurgePolicy) {
+        self.purge_policy = policy;
+    }
+
+    /// Get current and next programs for all channels,
+    /// `count` must be positive and is clamped to the configured maximum
+    pub fn get_at(&self, timestamp: i64, count: i64) -> Result<HashMap<i64, EpgNow>> {
+        self.get_at_with_lookback(timestamp, count, 0)
+    }
+
+    /// Same as `get_at`, but also with up to `lookback` programs of each channel
+    /// which ended by the time, programs of a channel are ordered by begin
+    pub fn get_at_with_lookback(
+        &self,
+        timestamp: i64,
+        count: i64,
+        lookback: i64,
+    ) -> Result<HashMap<i64, EpgNow>> {
+        if count <= 0 {
+            return Err(rusqlite::Error::UserFunctionError(
+                format!("Programs count must be positive, got {}", count).into(),
+            ));
+        }
+        if lookback < 0 {
+            return Err(rusqlite::Error::UserFunctionError(
+                format!("Lookback must not be negative, got {}", lookback).into(),
+            ));
+        }
+        let count = count.min(self.max_programs_at);
+        let lookback = lookback.min(self.max_programs_at);
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(
+            "select
+                channels.id,
+                programs.begin, programs.end, programs.title, programs.description,
+                programs.sub_title, programs.category, programs.translations,
+                programs.country, programs.language, programs.primary_category,
+                programs.media
+             from channels
+             join programs on programs.id in
+             (select id from (select programs.id from programs where
+              programs.channel=channels.id AND programs.end > ?1 order by programs.end limit ?2)
+              union all
+              select id from (select programs.id from programs where
+              programs.channel=channels.id AND programs.end <= ?1
+              order by programs.end desc limit ?3))
+             where channels.deleted = 0",
+        )?;
+
+        let mut hash: HashMap<i64, EpgNow> = HashMap::new();
+
+        let it = stmt.query_map(&[&timestamp, &count, &lookback], |row| {
+            let id: i64 = row.get(0)?;
+            let program = program_from_row(row, 1)?;
+            Ok((id, program))
+        })?;
+
+        for (id, program) in it.filter_map(|item| item.ok()) {
+            hash.entry(id)
+                .or_insert(EpgNow {
+                    channel_id: id,
+                    programs: Vec::new(),
+                })
+                .programs
+                .push(program);
+        }
+        if lookback > 0 {
+            for epg in hash.values_mut() {
+                epg.programs.sort_by_key(|p| p.begin);
+            }
+        }
+        Ok(hash)
+    }
+
+    /// Get programs of all channels which overlap with `[from, to)`,
+    /// optionally restricted to the given channels
+    pub fn get_window(
+        &self,
+        from: i64,
+        to: i64,
+        ids: Option<&[i64]>,
+    ) -> Result<HashMap<i64, EpgNow>> {
+        let conn = self.connect()?;
+        // Ids are integers, so they are safe to inline and not limited by the number of variables
+        let filter = ids.map_or_else(String::new, |ids| {
+            let ids = ids.iter().map(i64::to_string).collect::<Vec<_>>();
+            format!("and programs.channel in ({})", ids.join(","))
+        });
+        let mut stmt = conn.prepare(&format!(
+            "select
+                programs.channel,
+                programs.begin, programs.end, programs.title, programs.description,
+                programs.sub_title, programs.category, programs.translations,
+                programs.country, programs.language, programs.primary_category,
+                programs.media
+             from programs where programs.end > ?1 and programs.begin < ?2 {}
+             order by programs.channel, programs.begin",
+            filter
+        ))?;
+
+        let it = stmt.query_map(&[&from, &to], |row| {
+            let id: i64 = row.get(0)?;
+            let program = program_from_row(row, 1)?;
+            Ok((id, program))
+        })?;
+
+        let mut hash: HashMap<i64, EpgNow> = HashMap::new();
+        for (id, program) in it.filter_map(|item| item.ok()) {
+            hash.entry(id)
+                .or_insert(EpgNow {
+                    channel_id: id,
+                    programs: Vec::new(),
+                })
+                .programs
+                .push(program);
+        }
+        Ok(hash)
+    }
+
+    /// Previous, current and next programs of the channels at the time,
+    /// of the requested channels in their order or of all channels with programs by id
+    pub fn get_snapshot(&self, timestamp: i64, ids: Option<&[i64]>) -> Result<Vec<Snapshot>> {
+        let conn = self.connect()?;
+        let filter = ids.map_or_else(String::new, |ids| {
+            let ids = ids.iter().map(i64::to_string).collect::<Vec<_>>();
+            format!("and channels.id in ({})", ids.join(","))
+        });
+        let mut stmt = conn.prepare(&format!(
+            "select
+                channels.id,
+                programs.begin, programs.end, programs.title, programs.description,
+                programs.sub_title, programs.category, programs.translations,
+                programs.country, programs.language, programs.primary_category,
+                programs.media
+             from channels
+             join programs on programs.id in
+             (select id from (select programs.id from programs where
+              programs.channel=channels.id AND programs.end > ?1 order by programs.end limit 2)
+              union all
+              select id from (select programs.id from programs where
+              programs.channel=channels.id AND programs.end <= ?1
+              order by programs.end desc limit 1))
+             where channels.deleted = 0 {}
+             order by channels.id, programs.begin",
+            filter
+        ))?;
+        let it = stmt.query_map(&[&timestamp], |row| {
+            let id: i64 = row.get(0)?;
+            let program = program_from_row(row, 1)?;
+            Ok((id, program))
+        })?;
+
+        let mut programs: BTreeMap<i64, Vec<Program>> = BTreeMap::new();
+        for (id, program) in it.filter_map(|item| item.ok()) {
+            programs.entry(id).or_insert_with(Vec::new).push(program);
+        }
+        Ok(match ids {
+            Some(ids) => ids
+                .iter()
+                .map(|&id| {
+                    let channel = programs.get(&id).cloned().unwrap_or_default();
+                    Snapshot::at(id, channel, timestamp)
+                })
+                .collect(),
+            None => programs
+                .into_iter()
+                .map(|(id, channel)| Snapshot::at(id, channel, timestamp))
+                .collect(),
+        })
+    }
+
+    /// Stored row of the program of the channel which begins at the time
+    pub fn get_program(&self, channel: i64, begin: i64) -> Result<Option<StoredProgram>> {
+        let conn = self.connect()?;
+        conn.query_row(
+            &format!(
+                "select {}, length(cast(description as blob)), typeof(description) = 'blob'
+                 from programs where channel = ?1 and begin = ?2",
+                PROGRAM_COLUMNS
+            ),
+            &[&channel, &begin],
+            |row| {
+                Ok(StoredProgram {
+                    channel,
+                    begin: row.get(0)?,
+                    end: row.get(1)?,
+                    title: row.get(2)?,
+                    description: row.get::<_, Option<StoredDescription>>(3)?.map(|d| d.0),
+                    sub_title: row.get(4)?,
+                    category: row.get(5)?,
+                    translations: row.get(6)?,
+                    country: row.get(7)?,
+                    language: row.get(8)?,
+                    primary_category: row.get(9)?,
+                    media: row.get(10)?,
+                    description_stored_bytes: row.get(11)?,
+                    description_compressed: row.get(12)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    pub fn get_range(&self, id: i64, from: i64, to: i64) -> Result<Vec<Program>> {
+        Ok(self
+            .get_range_with_ids(id, from, to)?
+            .into_iter()
+            .map(|(_, program)| program)
+            .collect())
+    }
+
+    /// Same as `get_range`, each program with its row id
+    pub fn get_range_with_ids(&self, id: i64, from: i64, to: i64) -> Result<Vec<(i64, Program)>> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(
+            "select programs.id,
+         programs.begin, programs.end, programs.title, programs.description,
+         programs.sub_title, programs.category, programs.translations,
+         programs.country, programs.language, programs.primary_category,
+         programs.media
+         from programs where
+         programs.channel = ?1 and programs.begin >= ?2 and programs.begin < ?3",
+        )?;
+        let it = stmt
+            .query_map(&[&id, &from, &to], |row| {
+                Ok((row.get(0)?, program_from_row(row, 1)?))
             })?
             .filter_map(|item| item.ok());
         Ok(it.collect::<Vec<_>>())
     }
 
+    /// Channel and program with the row id, ids are assigned anew when a feed is merged
+    pub fn get_program_by_id(&self, pid: i64) -> Result<Option<(i64, Program)>> {
+        let conn = self.connect()?;
+        conn.query_row(
+            "select programs.channel,
+             programs.begin, programs.end, programs.title, programs.description,
+             programs.sub_title, programs.category, programs.translations,
+             programs.country, programs.language, programs.primary_category,
+             programs.media
+             from programs where programs.id = ?1",
+            &[&pid],
+            |row| Ok((row.get(0)?, program_from_row(row, 1)?)),
+        )
+        .optional()
+    }
+
+    /// Number of programs of the channel which begin within `[from, to)`
+    /// by the hour of day in the timezone
+    pub fn get_hourly_histogram<Tz: TimeZone>(
+        &self,
+        id: i64,
+        from: i64,
+        to: i64,
+        tz: &Tz,
+    ) -> Result<[i64; 24]> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(
+            "select begin from programs where channel = ?1 and begin >= ?2 and begin < ?3",
+        )?;
+        let mut rows = stmt.query(&[&id, &from, &to])?;
+        let mut buckets = [0; 24];
+        while let Some(row) = rows.next()? {
+            let begin: i64 = row.get(0)?;
+            buckets[tz.timestamp(begin, 0).hour() as usize] += 1;
+        }
+        Ok(buckets)
+    }
+
+    /// Get programs which overlap with `[from, to)` ordered by begin time
+    pub fn get_overlapping(&self, id: i64, from: i64, to: i64) -> Result<Vec<Program>> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(
+            "select programs.begin, programs.end, programs.title, programs.description,
+         programs.sub_title, programs.category, programs.translations,
+         programs.country, programs.language, programs.primary_category,
+         programs.media
+         from programs where
+         programs.channel = ?1 and programs.end > ?2 and programs.begin < ?3
+         order by programs.begin",
+        )?;
+        let it = stmt
+            .query_map(&[&id, &from, &to], |row| program_from_row(row, 0))?
+            .filter_map(|item| item.ok());
+        Ok(it.collect::<Vec<_>>())
+    }
+
+    /// Programs with the text in the title, produced in the country and spoken in the language
+    /// along with their channel ids, filters are optional, the result is ordered by begin time
+    pub fn search_programs(
+        &self,
+        text: Option<&str>,
+        country: Option<&str>,
+        language: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<(i64, Program)>> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(
+            "select programs.channel,
+                programs.begin, programs.end, programs.title, programs.description,
+                programs.sub_title, programs.category, programs.translations,
+                programs.country, programs.language, programs.primary_category,
+                programs.media
+             from programs where
+             (?1 is null or instr(lower(programs.title), lower(?1)) > 0) and
+             (?2 is null or instr(upper(programs.country), upper('\"' || ?2 || '\"')) > 0) and
+             (?3 is null or lower(programs.language) = lower(?3))
+             order by programs.begin limit ?4",
+        )?;
+        let it = stmt
+            .query_map(&[&text as &dyn ToSql, &country, &language, &limit], |row| {
+                Ok((row.get(0)?, program_from_row(row, 1)?))
+            })?
+            .filter_map(|item| item.ok());
+        Ok(it.collect())
+    }
+
+    /// Find time intervals without programs and overlapping programs of the channel
+    pub fn find_gaps(&self, id: i64, from: i64, to: i64) -> Result<ScheduleCheck> {
+        let programs = self.get_overlapping(id, from, to)?;
+        Ok(check_schedule(&programs, from, to))
+    }
+
+    /// Give the channel of `old_alias` the new alias keeping its id. When another channel
+    /// already has the new alias it is merged when `merge` is set: its programs which end
+    /// after `now` move to the renamed channel and replace the ones from their first begin on.
+    pub fn remap_alias(
+        &self,
+        old_alias: &str,
+        new_alias: &str,
+        merge: bool,
+        now: i64,
+    ) -> Result<Remap> {
+        let _writer = self.lock_writer();
+        let mut conn = self.connect()?;
+        let tx = conn.transaction()?;
+        let id_of = |alias: &str| {
+            tx.query_row(
+                "select id from channels where alias = ?1",
+                rusqlite::params![alias],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+        };
+        let id = match id_of(old_alias)? {
+            Some(id) => id,
+            None => return Ok(Remap::NotFound),
+        };
+        let result = match id_of(new_alias)? {
+            Some(other) if other == id => Remap::Renamed,
+            Some(_) if !merge => return Ok(Remap::Conflict),
+            Some(other) => {
+                let first: Option<i64> = tx.query_row(
+                    "select min(begin) from programs where channel = ?1 and end > ?2",
+                    &[&other, &now],
+                    |row| row.get(0),
+                )?;
+                if let Some(first) = first {
+                    tx.execute(
+                        "delete from programs where channel = ?1 and begin >= ?2",
+                        &[&id, &first],
+                    )?;
+                }
+                let programs = tx.execute(
+                    "update programs set channel = ?1 where channel = ?2 and end > ?3",
+                    &[&id, &other, &now],
+                )?;
+                tx.execute("delete from programs where channel = ?1", &[&other])?;
+                tx.execute(
+                    "update channels set programs_updated = ?1 where id = ?2",
+                    &[&now, &id],
+                )?;
+                // Frees the alias before it is taken by the renamed channel
+                tx.execute("delete from channels where id = ?1", &[&other])?;
+                Remap::Merged { programs }
+            }
+            None => Remap::Renamed,
+        };
+        tx.execute(
+            "update channels set alias = ?1 where id = ?2",
+            rusqlite::params![new_alias, id],
+        )?;
+        tx.commit()?;
+        println!(
+            "Remapped channel {} from {} to {}",
+            id, old_alias, new_alias
+        );
+        Ok(result)
+    }
+
     pub fn delete_before(&self, timestamp: i64) -> Result<()> {
-        println!("Removing programs before t={} from sqlite ...", timestamp);
-        let conn = Connection::open(&self.file)?;
-        let count = conn.execute(
-            "delete from programs where programs.end < ?1",
-            &[&timestamp],
+        let _writer = self.lock_writer();
+        delete_programs_before(&self.connect()?, timestamp)
+    }
+
+    pub fn get_db_stats(&self) -> Result<DbStats> {
+        let conn = self.connect()?;
+        let channels = conn.query_row(
+            "select count(*) from channels where deleted = 0",
+            NO_PARAMS,
+            |row| row.get(0),
         )?;
-        println!("Deleted {} rows.", count);
-        Ok(())
+        let (programs, oldest_program, newest_program) = conn.query_row(
+            "select count(*), min(begin), max(end) from programs",
+            NO_PARAMS,
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        let last_update = conn.query_row("select max(time) from update_log", NO_PARAMS, |row| {
+            row.get(0)
+        })?;
+        let file_size = [self.file.clone(), format!("{}-wal", self.file)]
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|m| m.len())
+            .sum();
+        Ok(DbStats {
+            channels,
+            programs,
+            file_size,
+            oldest_program,
+            newest_program,
+            last_update,
+        })
     }
 
     pub fn get_last_update(&self) -> Result<Option<UpdateStatus>> {
-        let conn = Connection::open(&self.file)?;
+        let conn = self.connect()?;
         conn.query_row(
-            "select time, status, message, last_modified from update_log order by time desc limit 1",
+            "select time, status, message, last_modified, \
+             generator_name, generator_url, feed_date, overlaps, source \
+             from update_log order by id desc limit 1",
             NO_PARAMS,
             |row| {
                 let t = Utc.timestamp(row.get(0)?, 0);
                 let modified = Utc.timestamp(row.get(3)?, 0);
-                match row.get(1)? {
-                    0 => Ok(UpdateStatus::new_ok(t, modified)),
-                    1 => Ok(UpdateStatus::new_fail(t, row.get(2)?)),
-                    _ => Err(rusqlite::Error::UserFunctionError(
-                        "Bad status value".into(),
-                    )),
-                }
+                let mut status = match row.get(1)? {
+                    0 => UpdateStatus::new_ok(t, modified),
+                    1 => UpdateStatus::new_fail(t, row.get(2)?),
+                    _ => {
+                        return Err(rusqlite::Error::UserFunctionError(
+                            "Bad status value".into(),
+                        ))
+                    }
+                };
+                status.feed = FeedInfo {
+                    generator_name: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                    generator_url: row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+                    date: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+                };
+                status.overlaps = row.get::<_, Option<i64>>(7)?.unwrap_or_default();
+                status.source = row.get::<_, Option<String>>(8)?.unwrap_or_default();
+                Ok(status)
             },
         )
         .optional()
     }
 
+    /// Modification time of the feed of the last succeeded update from the source
+    pub fn get_last_modified(&self, source: FeedSource) -> Result<Option<DateTime<Utc>>> {
+        let conn = self.connect()?;
+        conn.query_row(
+            "select last_modified from update_log where status = 0 and source = ?1 \
+             order by id desc limit 1",
+            &[source.as_str()],
+            |row| Ok(Utc.timestamp(row.get(0)?, 0)),
+        )
+        .optional()
+    }
+
+    /// Whether any feed update succeeded, so missing programs are not just loading yet
+    pub fn has_loaded_feed(&self) -> Result<bool> {
+        let conn = self.connect()?;
+        conn.query_row(
+            "select exists(select 1 from update_log where status = 0)",
+            NO_PARAMS,
+            |row| row.get(0),
+        )
+    }
+
     pub fn insert_update_status(&self, entry: UpdateStatus) -> Result<()> {
-        let conn = Connection::open(&self.file)?;
-        if let Some(t) = conn
-            .query_row(
-                "select time from update_log where time=?1",
-                rusqlite::params![entry.time.timestamp()],
-                |row| row.get(0),
-            )
-            .optional()?
-        {
-            eprintln!("Overriding previous entry at {}", Utc.timestamp(t, 0));
-        }
+        let _writer = self.lock_writer();
+        let conn = self.connect()?;
         conn.execute(
-            "insert or replace into update_log (time, status, message, last_modified) values (?1, ?2, ?3, ?4)",
+            "insert into update_log \
+             (time, status, message, last_modified, generator_name, generator_url, feed_date, \
+             overlaps, source) \
+             values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
             rusqlite::params![
                 entry.time.timestamp(),
                 (if entry.succeed { 0 } else { 1 }),
                 entry.message,
                 entry.last_modified.timestamp(),
+                entry.feed.generator_name,
+                entry.feed.generator_url,
+                entry.feed.date,
+                entry.overlaps,
+                entry.source,
             ],
         )?;
         Ok(())
     }
 }
 
+/// Number of staged programs which begin before an earlier program of their channel ends
+fn count_overlaps(conn: &Connection) -> Result<usize> {
+    let mut stmt =
+        conn.prepare("select channel, begin, end from programs1 order by channel, begin, end")?;
+    let mut rows = stmt.query(NO_PARAMS)?;
+    let mut count = 0;
+    // Channel and the latest end of its programs so far
+    let mut last: Option<(i64, i64)> = None;
+    while let Some(row) = rows.next()? {
+        let (channel, begin, end): (i64, i64, i64) = (row.get(0)?, row.get(1)?, row.get(2)?);
+        match &mut last {
+            Some((id, latest_end)) if *id == channel => {
+                if begin < *latest_end {
+                    count += 1;
+                }
+                *latest_end = (*latest_end).max(end);
+            }
+            _ => last = Some((channel, end)),
+        }
+    }
+    Ok(count)
+}
+
+/// Remove programs staged up to the row `last_id` which begin within the range of their channel
+/// staged again after it, so a feed part loaded twice before the merge is not merged twice
+fn unstage_replaced(conn: &Connection, last_id: i64) -> Result<usize> {
+    conn.execute(
+        "delete from programs1 where id <= ?1 and exists
+         (select 1 from (select channel, min(begin) as begin, max(end) as end
+                         from programs1 where id > ?1 group by channel) as part
+          where part.channel = programs1.channel
+          and programs1.begin >= part.begin and programs1.begin < part.end)",
+        &[&last_id],
+    )
+}
+
+/// Remove staged programs when another program of their channel begins at the same time
+/// and is preferred by the policy, returns the number of removed programs.
+/// Rows are staged in the order of the feed.
+fn drop_duplicate_begins(conn: &Connection, policy: DuplicateBegins) -> Result<usize> {
+    let preferred = match policy {
+        DuplicateBegins::Longer => {
+            "p.end > programs1.end or (p.end = programs1.end and p.id < programs1.id)"
+        }
+        DuplicateBegins::First => "p.id < programs1.id",
+        DuplicateBegins::Last => "p.id > programs1.id",
+    };
+    conn.execute(
+        &format!(
+            "delete from programs1 where exists (select 1 from programs1 p \
+             where p.channel = programs1.channel and p.begin = programs1.begin and ({}))",
+            preferred
+        ),
+        NO_PARAMS,
+    )
+}
+
+/// Merge staged programs of a channel which continue the previous one with the same
+/// title and sub-title, returns the number of removed programs. Parts with different
+/// descriptions stay apart, a missing description is taken from a later part.
+fn coalesce_programs(conn: &Connection) -> Result<usize> {
+    /// First part of a show and where its last merged part ends
+    struct Run {
+        rowid: i64,
+        channel: i64,
+        end: i64,
+        title: String,
+        sub_title: Option<String>,
+        description: Option<String>,
+        merged: Vec<i64>,
+    }
+    let mut stmt = conn.prepare(
+        "select rowid, channel, begin, end, title, sub_title, description from programs1 \
+         order by channel, begin, end",
+    )?;
+    let mut rows = stmt.query(NO_PARAMS)?;
+    let mut runs = Vec::new();
+    let mut current: Option<Run> = None;
+    while let Some(row) = rows.next()? {
+        let (rowid, channel, begin, end): (i64, i64, i64, i64) =
+            (row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?);
+        let title: String = row.get(4)?;
+        let sub_title: Option<String> = row.get(5)?;
+        // Parts staged by earlier incremental loads may be compressed
+        let description = row.get::<_, Option<StoredDescription>>(6)?.map(|d| d.0);
+        if let Some(run) = &mut current {
+            let continues = run.channel == channel
+                && run.end == begin
+                && run.title == title
+                && run.sub_title == sub_title;
+            let same_description = match (&run.description, &description) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            };
+            if continues && same_description {
+                run.end = end;
+                run.merged.push(rowid);
+                if run.description.is_none() {
+                    run.description = description;
+                }
+                continue;
+            }
+        }
+        if let Some(run) = current.take() {
+            if !run.merged.is_empty() {
+                runs.push(run);
+            }
+        }
+        current = Some(Run {
+            rowid,
+            channel,
+            end,
+            title,
+            sub_title,
+            description,
+            merged: Vec::new(),
+        });
+    }
+    runs.extend(current.filter(|run| !run.merged.is_empty()));
+
+    let mut removed = 0;
+    for run in runs {
+        conn.execute(
+            "update programs1 set end = ?1, description = ?2 where rowid = ?3",
+            rusqlite::params![run.end, run.description, run.rowid],
+        )?;
+        for rowid in run.merged {
+            removed += conn.execute("delete from programs1 where rowid = ?1", &[&rowid])?;
+        }
+    }
+    Ok(removed)
+}
+
+/// Program row with the values as they are stored, for diagnostics
+#[derive(Debug, Serialize)]
+pub struct StoredProgram {
+    pub channel: i64,
+    pub begin: i64,
+    pub end: i64,
+    pub title: String,
+    /// Text of the description, compressed ones are inflated
+    pub description: Option<String>,
+    /// Size of the stored value, smaller than the text when it is compressed
+    pub description_stored_bytes: Option<i64>,
+    pub description_compressed: bool,
+    pub sub_title: Option<String>,
+    pub category: Option<String>,
+    pub translations: Option<String>,
+    pub country: Option<String>,
+    pub language: Option<String>,
+    pub primary_category: Option<String>,
+    pub media: Option<String>,
+}
+
+/// Description column value, compressed descriptions are deflated blobs
+/// and plain ones are text, so both kinds of rows can be mixed in a table
+struct StoredDescription(String);
+
+impl FromSql for StoredDescription {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Blob(data) => {
+                let mut text = String::new();
+                DeflateDecoder::new(data)
+                    .read_to_string(&mut text)
+                    .map_err(|e| FromSqlError::Other(Box::new(e)))?;
+                Ok(StoredDescription(text))
+            }
+            _ => String::column_result(value).map(StoredDescription),
+        }
+    }
+}
+
+fn deflate(text: &str) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(text.as_bytes())?;
+    encoder.finish()
+}
+
+/// Convert descriptions of the table to compressed or plain storage.
+/// Descriptions which do not get smaller, usually the short ones, stay plain.
+fn store_descriptions(conn: &Connection, table: &str, compress: bool) -> Result<DescriptionStats> {
+    let kind = if compress { "text" } else { "blob" };
+    let rows = {
+        let mut stmt = conn.prepare(&format!(
+            "select rowid, description, length(description) from {} \
+             where typeof(description) = ?1",
+            table
+        ))?;
+        let rows = stmt.query_map(&[kind], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, StoredDescription>(1)?.0,
+                row.get::<_, i64>(2)? as usize,
+            ))
+        })?;
+        rows.collect::<Result<Vec<_>>>()?
+    };
+    let mut update = conn.prepare(&format!(
+        "update {} set description = ?1 where rowid = ?2",
+        table
+    ))?;
+    let mut stats = DescriptionStats::default();
+    for (rowid, text, stored_len) in rows {
+        let compressed_len = if compress {
+            let data =
+                deflate(&text).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+            if data.len() >= text.len() {
+                continue;
+            }
+            update.execute(rusqlite::params![data, rowid])?;
+            data.len()
+        } else {
+            update.execute(rusqlite::params![text, rowid])?;
+            stored_len
+        };
+        stats.rows += 1;
+        stats.text_bytes += text.len();
+        stats.compressed_bytes += compressed_len;
+    }
+    Ok(stats)
+}
+
 /// Insert channel into the database return assigned id
 fn insert_channel(conn: &Connection, alias: &str, name: &str, icon_url: &str) -> Result<i64> {
     let mut stmt =
@@ -377,7 +1911,8 @@ fn insert_channel(conn: &Connection, alias: &str, name: &str, icon_url: &str) ->
     Ok(row_id)
 }
 
-/// Insert or replace channel data in the database
+/// Insert or replace channel data in the database, `last_seen` time is preserved
+/// and so is the icon probe unless the icon url changed
 fn update_channel(
     conn: &Connection,
     id: i64,
@@ -386,7 +1921,12 @@ fn update_channel(
     icon_url: &str,
 ) -> Result<()> {
     let mut stmt = conn.prepare_cached(
-        "insert or replace into channels (id, alias, name, icon_url) values (?1, ?2, ?3, ?4)",
+        "insert or replace into channels
+         (id, alias, name, icon_url, last_seen, icon_ok, icon_checked, programs_updated)
+         values (?1, ?2, ?3, ?4, coalesce((select last_seen from channels where id=?1), 0),
+         (select icon_ok from channels where id=?1 and icon_url=?4),
+         coalesce((select icon_checked from channels where id=?1 and icon_url=?4), 0),
+         coalesce((select programs_updated from channels where id=?1), 0))",
     )?;
     let row_id = stmt.insert(&[
         &id,
@@ -398,17 +1938,128 @@ fn update_channel(
     Ok(())
 }
 
+/// Read channel selected as `id, alias, name, icon_url`
+fn channel_from_row(row: &rusqlite::Row) -> Result<(i64, ChannelInfo)> {
+    Ok((
+        row.get(0)?,
+        ChannelInfo {
+            alias: row.get(1)?,
+            name: row.get(2)?,
+            icon_url: row.get(3)?,
+        },
+    ))
+}
+
+/// Read program selected as
+/// `begin, end, title, description, sub_title, category, translations, country, language,
+/// primary_category, media` starting at `offset`
+fn program_from_row(row: &rusqlite::Row, offset: usize) -> Result<Program> {
+    let media: Media = row
+        .get::<_, Option<String>>(offset + 10)?
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+    Ok(Program {
+        begin: row.get(offset)?,
+        end: row.get(offset + 1)?,
+        title: row.get(offset + 2)?,
+        description: row
+            .get::<_, Option<StoredDescription>>(offset + 3)?
+            .map(|d| d.0)
+            .unwrap_or_default(),
+        sub_title: row.get(offset + 4)?,
+        categories: row
+            .get::<_, Option<String>>(offset + 5)?
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default(),
+        translations: row
+            .get::<_, Option<String>>(offset + 6)?
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default(),
+        countries: row
+            .get::<_, Option<String>>(offset + 7)?
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default(),
+        language: row.get(offset + 8)?,
+        primary_category: row.get(offset + 9)?,
+        video: media.video,
+        audio: media.audio,
+        ..Program::new()
+    })
+}
+
+/// Audio and video properties of a program stored as a json object
+#[derive(Serialize, Deserialize, Default)]
+struct Media {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    video: Option<Video>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    audio: Option<Audio>,
+}
+
+/// `NULL` when the program has neither audio nor video properties
+fn media_to_sql(program: &Program) -> Option<String> {
+    if program.video.is_none() && program.audio.is_none() {
+        return None;
+    }
+    let media = Media {
+        video: program.video.clone(),
+        audio: program.audio.clone(),
+    };
+    serde_json::to_string(&media).ok()
+}
+
+/// Categories and countries are stored as a json array, `NULL` when there are none
+fn categories_to_sql(categories: &[String]) -> Option<String> {
+    if categories.is_empty() {
+        None
+    } else {
+        serde_json::to_string(categories).ok()
+    }
+}
+
+/// Cut descriptions of the program and its translations to `limit` characters
+fn limit_descriptions(program: &mut Program, limit: usize) {
+    let truncate = |s: &mut String| {
+        if let Some((index, _)) = s.char_indices().nth(limit) {
+            s.truncate(index);
+        }
+    };
+    truncate(&mut program.description);
+    for translation in program.translations.values_mut() {
+        truncate(&mut translation.description);
+    }
+}
+
 fn insert_program(conn: &Connection, channel_id: i64, program: &Program) -> Result<()> {
     let mut stmt = conn.prepare_cached(
-        "insert into programs1 (channel, begin, end, title, description) \
-         values (?1, ?2, ?3, ?4, ?5)",
+        "insert into programs1 \
+         (channel, begin, end, title, description, sub_title, category, translations, \
+         country, language, primary_category, media) \
+         values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
     )?;
+    // Missing descriptions take no space
+    let description = Some(program.description.as_str()).filter(|s| !s.is_empty());
+    let category = categories_to_sql(&program.categories);
+    let country = categories_to_sql(&program.countries);
+    let translations = if program.translations.is_empty() {
+        None
+    } else {
+        serde_json::to_string(&program.translations).ok()
+    };
+    let media = media_to_sql(program);
     stmt.execute(&[
         &channel_id,
         &program.begin,
         &program.end,
         &program.title as &dyn ToSql,
-        &program.description as &dyn ToSql,
+        &description as &dyn ToSql,
+        &program.sub_title as &dyn ToSql,
+        &category as &dyn ToSql,
+        &translations as &dyn ToSql,
+        &country as &dyn ToSql,
+        &program.language as &dyn ToSql,
+        &program.primary_category as &dyn ToSql,
+        &media as &dyn ToSql,
     ])?;
     Ok(())
 }
@@ -434,11 +2085,35 @@ fn drop_indexes(conn: &Connection) -> Result<()> {
     Ok(())
 }
 
-fn append_programs(conn: &mut Connection) -> Result<()> {
-    conn.execute("create index p1_channel on programs1 (channel)", NO_PARAMS)?;
+/// Columns of a program which are compared to tell whether a channel changed
+const PROGRAM_COLUMNS: &str = "\"begin\", \"end\", title, description, sub_title, category, \
+     translations, country, language, primary_category, media";
+
+fn delete_programs_before(conn: &Connection, timestamp: i64) -> Result<()> {
+    println!("Removing programs before t={} from sqlite ...", timestamp);
+    let count = conn.execute(
+        "delete from programs where programs.end < ?1",
+        &[&timestamp],
+    )?;
+    println!("Deleted {} rows.", count);
+    Ok(())
+}
+
+/// Replace programs of the staged channels in a transaction, see `merge_programs`
+fn append_programs(conn: &mut Connection, now: i64) -> Result<()> {
+    let tx = conn.transaction()?;
+    merge_programs(&tx, now)?;
+    tx.commit()
+}
+
+/// Replace programs of the staged channels and empty `programs1`, channels which programs
+/// differ from the stored ones get `programs_updated` set to now.
+/// Runs within the caller's transaction, so the staged programs stay until it commits.
+fn merge_programs(tx: &Connection, now: i64) -> Result<()> {
+    tx.execute("create index p1_channel on programs1 (channel)", NO_PARAMS)?;
 
     let channels = {
-        let mut stmt = conn.prepare("select distinct p1.channel from programs1 p1")?;
+        let mut stmt = tx.prepare("select distinct p1.channel from programs1 p1")?;
         let it = stmt
             .query_map(NO_PARAMS, |row| {
                 let c: Result<i64> = row.get(0);
@@ -450,13 +2125,36 @@ fn append_programs(conn: &mut Connection) -> Result<()> {
     {
         // Remove programs from database, which times conflict with new data
         let mut total = 0;
-        let tx = conn.transaction()?;
+        let mut changed = Vec::new();
         {
-            let mut stmt = tx.prepare(
-                "delete from programs where programs.channel=?1 and
-                 programs.begin >= (select min(p1.begin) from programs1 p1 where p1.channel=?1)",
-            )?;
-            for id in channels.iter() {
+            // Stored programs which would be replaced and the staged ones are the same sets
+            let mut stmt = tx.prepare(&format!(
+                "with replaced as (select {0} from programs where channel = ?1 and
+                 begin >= (select min(begin) from programs1 where channel = ?1)),
+                 staged as (select {0} from programs1 where channel = ?1)
+                 select exists (select * from replaced except select * from staged)
+                 or exists (select * from staged except select * from replaced)",
+                PROGRAM_COLUMNS
+            ))?;
+            for &id in channels.iter() {
+                if stmt.query_row(&[&id], |row| row.get(0))? {
+                    changed.push(id);
+                }
+            }
+        }
+        {
+            let mut stmt = tx.prepare("update channels set programs_updated = ?1 where id = ?2")?;
+            for id in changed.iter() {
+                stmt.execute(&[&now, id])?;
+            }
+        }
+        println!("Programs changed on {} channels", changed.len());
+        {
+            let mut stmt = tx.prepare(
+                "delete from programs where programs.channel=?1 and
+                 programs.begin >= (select min(p1.begin) from programs1 p1 where p1.channel=?1)",
+            )?;
+            for id in channels.iter() {
                 let count = stmt.execute(&[&id])?;
                 total += count;
             }
@@ -467,29 +2165,236 @@ fn append_programs(conn: &mut Connection) -> Result<()> {
         drop_indexes(&tx)?;
         // Copy new data into the database
         total = tx.execute(
-            "insert into programs (channel, begin, end, title, description)
-             select channel, \"begin\", \"end\", title, description from programs1",
+            "insert into programs
+             (channel, begin, end, title, description, sub_title, category, translations,
+             country, language, primary_category, media)
+             select channel, \"begin\", \"end\", title, description, sub_title, category,
+             translations, country, language, primary_category, media from programs1",
             NO_PARAMS,
         )?;
         create_indexes(&tx)?;
         println!("Inserted {} new programs", total);
+    }
 
-        tx.commit()?;
+    tx.execute("delete from programs1", NO_PARAMS)?;
+    Ok(())
+}
+
+/// Move seeded channels with their programs to the fixed ids
+fn apply_channel_seeds(conn: &Connection, seeds: &ChannelSeeds) -> Result<()> {
+    let max_seed = seeds.iter().map(|&(id, _)| id).max().unwrap_or(0);
+    for (id, channel) in seeds.iter() {
+        let occupant: Option<String> = conn
+            .query_row("select alias from channels where id=?1", &[id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        match occupant {
+            Some(alias) if alias == channel.alias => {
+                // Seeded channels are never left deleted
+                conn.execute("update channels set deleted = 0 where id=?1", &[id])?;
+                continue;
+            }
+            Some(alias) => {
+                // Above all seeds, so a later seed does not take the id again
+                let free: i64 = conn.query_row(
+                    "select max(coalesce(max(id), 0), ?1) + 1 from channels",
+                    &[&max_seed],
+                    |row| row.get(0),
+                )?;
+                eprintln!(
+                    "Channel '{}' moves from id {} to {} for seeded channel '{}'",
+                    alias, id, free, channel.alias
+                );
+                conn.execute("update channels set id=?1 where id=?2", &[&free, id])?;
+                for table in &["programs", "programs1"] {
+                    conn.execute(
+                        &format!("update {} set channel=?1 where channel=?2", table),
+                        &[&free, id],
+                    )?;
+                }
+            }
+            None => {}
+        }
+        let previous: Option<i64> = conn
+            .query_row(
+                "select id from channels where alias=?1",
+                &[&channel.alias],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(previous) = previous {
+            conn.execute(
+                "update programs set channel=?1 where channel=?2",
+                &[id, &previous],
+            )?;
+            conn.execute("delete from channels where id=?1", &[&previous])?;
+        }
+        update_channel(conn, *id, &channel.alias, &channel.name, &channel.icon_url)?;
+    }
+    Ok(())
+}
+
+fn open_connection(file: &str, key: Option<&str>) -> Result<Connection> {
+    let conn = Connection::open(file)?;
+    #[cfg(feature = "sqlcipher")]
+    {
+        if let Some(key) = key {
+            conn.pragma_update(None, "key", &key)?;
+        }
+    }
+    #[cfg(not(feature = "sqlcipher"))]
+    let _ = key;
+    Ok(conn)
+}
+
+/// Shared memory index of the write-ahead log
+fn shm_file(file: &str) -> String {
+    format!("{}-shm", file)
+}
+
+/// Errors of opening a database which could be caused by an unreadable index of its
+/// write-ahead log. A corrupt database or a wrong key are not, they must stop the server.
+fn is_shm_failure(e: &rusqlite::Error) -> bool {
+    use rusqlite::ErrorCode::*;
+    match e {
+        rusqlite::Error::SqliteFailure(e, _) => matches!(e.code, CannotOpen | SystemIOFailure),
+        _ => false,
+    }
+}
+
+/// Bring the database up to date with its write-ahead log, when even that fails
+/// the index of the log is moved aside. SQLite rebuilds the index from the log,
+/// which is never touched, so committed transactions are kept.
+fn recover_shm(file: &str, key: Option<&str>) {
+    match open_connection(file, key)
+        .and_then(|conn| conn.execute_batch("pragma wal_checkpoint(TRUNCATE)"))
+    {
+        Ok(()) => {
+            println!("Recovered database {} by checkpoint", file);
+            return;
+        }
+        Err(e) => eprintln!("Checkpoint of {} failed: {}", file, e),
+    }
+    let path = shm_file(file);
+    let aside = format!("{}.{}", path, Utc::now().timestamp());
+    match fs::rename(&path, &aside) {
+        Ok(()) => println!("Moved stale {} to {}", path, aside),
+        Err(e) => eprintln!("Failed to move {} aside: {}", path, e),
+    }
+}
+
+/// Schema of an existing database, nothing is created or migrated.
+/// Migrations are read from the migrant table, which works for encrypted databases as well.
+pub fn read_schema(file: &str, key: Option<&str>) -> Result<DbSchema> {
+    if !Path::new(file).is_file() {
+        return Err(rusqlite::Error::InvalidPath(PathBuf::from(file)));
+    }
+    let conn = open_connection(file, key)?;
+    let mut stmt =
+        conn.prepare("select sql from sqlite_master where sql is not null order by rowid")?;
+    let sql = stmt
+        .query_map(NO_PARAMS, |row| row.get(0))?
+        .collect::<Result<Vec<String>>>()?;
+    let migrated: bool = conn.query_row(
+        "select exists(select 1 from sqlite_master where name = '__migrant_migrations')",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+    let applied = if migrated {
+        let mut stmt = conn.prepare("select tag from __migrant_migrations order by rowid")?;
+        let tags = stmt.query_map(NO_PARAMS, |row| row.get(0))?;
+        tags.collect::<Result<Vec<String>>>()?
+    } else {
+        Vec::new()
+    };
+    let pending = MIGRATIONS
+        .iter()
+        .map(|&(tag, _, _)| tag)
+        .filter(|tag| !applied.iter().any(|t| t == tag))
+        .collect();
+    Ok(DbSchema {
+        sql,
+        applied,
+        pending,
+    })
+}
+
+/// Apply pending migrations over an encrypted connection which migrant is not able to open,
+/// applied tags are recorded in the migrant table so both ways stay interchangeable
+fn run_keyed_migrations(conn: &mut Connection) -> Result<()> {
+    conn.execute(
+        "create table if not exists __migrant_migrations (tag text unique)",
+        NO_PARAMS,
+    )?;
+    for &(tag, up, _) in MIGRATIONS {
+        let applied: bool = conn.query_row(
+            "select exists(select 1 from __migrant_migrations where tag = ?1)",
+            &[tag],
+            |row| row.get(0),
+        )?;
+        if !applied {
+            println!("Applying migration {}", tag);
+            // A failed migration leaves neither its changes nor its tag
+            let tx = conn.transaction()?;
+            tx.execute_batch(&without_transaction(up))?;
+            tx.execute("insert into __migrant_migrations (tag) values (?1)", &[tag])?;
+            tx.commit()?;
+        }
     }
+    Ok(())
+}
+
+/// Script of a migration without its own `begin` and `commit` statements, which migrant
+/// needs but which can not be nested in the transaction of `run_keyed_migrations`.
+/// Statements are compared by their words ignoring case and comment lines, the `end`
+/// of a trigger is kept.
+fn without_transaction(up: &str) -> String {
+    up.split(';')
+        .filter(|statement| {
+            let words = statement
+                .lines()
+                .filter(|line| !line.trim_start().starts_with("--"))
+                .flat_map(str::split_whitespace)
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>();
+            let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+            let is_begin = words.len() <= 3
+                && words.first() == Some(&"begin")
+                && words[1..]
+                    .iter()
+                    .all(|w| ["deferred", "immediate", "exclusive", "transaction"].contains(w));
+            let is_commit = matches!(
+                words.as_slice(),
+                ["commit"] | ["commit", "transaction"] | ["end", "transaction"]
+            );
+            !is_begin && !is_commit
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
 
-    conn.execute("delete from programs1", NO_PARAMS)?;
+/// Update `last_seen` time of channels which have programs in the temporary storage
+fn mark_channels_seen(conn: &Connection, timestamp: i64) -> Result<()> {
+    conn.execute(
+        "update channels set last_seen=?1 where id in (select distinct channel from programs1)",
+        &[&timestamp],
+    )?;
     Ok(())
 }
 
-/// Remove channels with no programs
-fn clear_channels(conn: &Connection) -> Result<()> {
+/// Mark channels with too few programs which were not seen in feeds for a while deleted,
+/// their rows stay so that the ids are kept when the channels reappear
+fn clear_channels(conn: &Connection, policy: &PurgePolicy, now: i64) -> Result<()> {
     println!("Clearing channels without epg data");
+    let seen_before = now - policy.grace.num_seconds();
     let count = conn.execute(
-        "delete from channels where \
-         (select count(id) from programs where programs.channel=channels.id)=0",
-        NO_PARAMS,
+        "update channels set deleted = 1 where deleted = 0 and \
+         (select count(id) from programs where programs.channel=channels.id) < ?1 and \
+         coalesce(channels.last_seen, 0) < ?2",
+        &[&policy.min_programs, &seen_before],
     )?;
-    println!("Removed {} rows.", count);
+    println!("Deleted {} channels.", count);
     Ok(())
 }
 
@@ -499,6 +2404,7 @@ mod tests {
     use crate::db::*;
     use crate::epg::ChannelInfo;
     use crate::epg::Program;
+    use crate::xmltv::test_feed;
     use rusqlite::Connection;
     use serial_test::serial;
     use std::fs;
@@ -565,18 +2471,21 @@ mod tests {
                 end: 20,
                 title: String::from("a"),
                 description: String::new(),
+                ..Program::new()
             },
             Program {
                 begin: 20,
                 end: 25,
                 title: String::from("b"),
                 description: String::new(),
+                ..Program::new()
             },
             Program {
                 begin: 25,
                 end: 40,
                 title: String::from("c"),
                 description: String::new(),
+                ..Program::new()
             },
         ] {
             insert_program(&conn, 1, &program).unwrap();
@@ -587,23 +2496,26 @@ mod tests {
                 end: 17,
                 title: String::from("x"),
                 description: String::new(),
+                ..Program::new()
             },
             Program {
                 begin: 17,
                 end: 30,
                 title: String::from("y"),
                 description: String::new(),
+                ..Program::new()
             },
             Program {
                 begin: 30,
                 end: 50,
                 title: String::from("z"),
                 description: String::new(),
+                ..Program::new()
             },
         ] {
             insert_program(&conn, 2, &program).unwrap();
         }
-        append_programs(&mut conn).unwrap();
+        append_programs(&mut conn, 0).unwrap();
 
         let t = 10;
         let result = db.get_at(t, 2).unwrap();
@@ -625,23 +2537,1774 @@ mod tests {
 
     #[test]
     #[serial]
-    fn test_update_log() {
+    fn test_categories() {
         let db = open_db();
-        assert_eq!(db.get_last_update().unwrap(), None);
+        let format = |hours: i64| {
+            (Utc::now() + chrono::Duration::hours(hours))
+                .format("%Y%m%d%H0000 +0000")
+                .to_string()
+        };
+        let data = format!(
+            r#"<tv>
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <programme start="{}" stop="{}" channel="ch1">
+            <title>Movie</title><category>Drama</category><category>Comedy</category></programme>
+            <programme start="{}" stop="{}" channel="ch1"><title>News</title></programme>
+            </tv>"#,
+            format(0),
+            format(1),
+            format(1),
+            format(2)
+        );
+        db.load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        let (id, _) = db.get_channel_by_alias("ch1").unwrap().unwrap();
+        let categories = || {
+            let mut programs = db.get_range(id, 0, i64::max_value()).unwrap();
+            programs.sort_by_key(|p| p.begin);
+            programs
+                .into_iter()
+                .map(|p| p.categories)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            categories(),
+            vec![vec!["Drama".to_string(), "Comedy".to_string()], vec![]]
+        );
 
-        let day = Utc.ymd(2021, 2, 21);
+        // Rows stored before categories were known have none
+        let conn = Connection::open(&db.file).unwrap();
+        conn.execute("update programs set category = null", NO_PARAMS)
+            .unwrap();
+        assert_eq!(categories(), vec![Vec::<String>::new(), vec![]]);
+    }
 
-        let st1 = UpdateStatus::new_ok(day.and_hms(10, 10, 0), day.and_hms(0, 0, 5));
-        db.insert_update_status(st1.clone()).unwrap();
-        assert_eq!(db.get_last_update().unwrap(), Some(st1));
+    #[test]
+    #[serial]
+    fn test_empty_feed_keeps_data() {
+        let db = open_db();
+        let now = Utc::now();
+        let data = test_feed(&["ch1"], now, now + chrono::Duration::hours(1));
+        db.load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        let stats = db.get_db_stats().unwrap();
+        assert_eq!((stats.channels, stats.programs), (1, 1));
 
-        let st2 = UpdateStatus::new_fail(day.and_hms(10, 15, 0), "failure message".to_owned());
-        db.insert_update_status(st2.clone()).unwrap();
-        assert_eq!(db.get_last_update().unwrap(), Some(st2));
+        for empty in &[
+            "<tv></tv>",
+            "<tv><programme start=\"20210101000000\" channel=\"ch1\"/></tv>",
+        ] {
+            let e = db
+                .load_xmltv(XmltvReader::new(empty.as_bytes()), false)
+                .unwrap_err();
+            assert!(e.to_string().contains("no channels"));
+            let stats = db.get_db_stats().unwrap();
+            assert_eq!((stats.channels, stats.programs), (1, 1));
+            assert_eq!(db.get_channel_by_alias("ch1").unwrap().unwrap().0, 1);
+        }
 
-        let st3 =
-            UpdateStatus::new_fail(day.and_hms(10, 15, 0), "another failure message".to_owned());
-        db.insert_update_status(st3.clone()).unwrap();
-        assert_eq!(db.get_last_update().unwrap(), Some(st3));
+        // Dry runs only report the counts
+        let summary = db
+            .load_xmltv(XmltvReader::new("<tv></tv>".as_bytes()), true)
+            .unwrap();
+        assert_eq!(summary.channels, 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_read_schema() {
+        let _db = open_db();
+        let schema = read_schema("test.db", None).unwrap();
+        assert!(schema.sql.iter().any(|sql| sql.contains("programs1")));
+        let tags = MIGRATIONS
+            .iter()
+            .map(|&(tag, _, _)| tag)
+            .collect::<Vec<_>>();
+        assert_eq!(schema.applied, tags);
+        assert!(schema.pending.is_empty());
+        let dump = schema.to_string();
+        assert!(dump.contains("-- Applied migrations:\n--   20190325100907_channel-alias\n"));
+
+        // A missing database is not created
+        assert!(read_schema("missing.db", None).is_err());
+        assert!(!Path::new("missing.db").exists());
+    }
+
+    #[test]
+    #[serial]
+    fn test_deleted_channel_reappears() {
+        let db = open_db();
+        let format = |t: DateTime<Utc>| t.format("%Y%m%d%H%M%S +0000").to_string();
+        let now = Utc::now();
+        let feed = |with_programs: &[&str], without_programs: &[&str]| {
+            let mut xml = String::from("<tv>");
+            for alias in with_programs.iter().chain(without_programs) {
+                xml += &format!(
+                    r#"<channel id="{0}"><display-name>{0}</display-name></channel>"#,
+                    alias
+                );
+            }
+            for alias in with_programs {
+                xml += &format!(
+                    r#"<programme start="{}" stop="{}" channel="{}"><title>News</title></programme>"#,
+                    format(now),
+                    format(now + chrono::Duration::hours(1)),
+                    alias
+                );
+            }
+            xml + "</tv>"
+        };
+        let load = |xml: String| {
+            db.load_xmltv(XmltvReader::new(xml.as_bytes()), false)
+                .unwrap();
+        };
+        let aliases = |channels: Vec<(i64, ChannelInfo)>| {
+            channels
+                .into_iter()
+                .map(|(id, c)| (c.alias, id))
+                .collect::<HashMap<_, _>>()
+        };
+
+        // A channel without programs is purged, but keeps its row
+        load(feed(&["ch1"], &["ch2"]));
+        let deleted = aliases(db.get_deleted_channels().unwrap());
+        assert_eq!(deleted.keys().collect::<Vec<_>>(), vec!["ch2"]);
+        assert!(!aliases(db.get_channels().unwrap()).contains_key("ch2"));
+        assert_eq!(db.get_channel_by_alias("ch2").unwrap(), None);
+
+        // Its id is not taken by a new channel and it returns with the same id
+        load(feed(&["ch1", "ch3", "ch2"], &[]));
+        let channels = aliases(db.get_channels().unwrap());
+        assert_eq!(channels["ch2"], deleted["ch2"]);
+        assert_ne!(channels["ch3"], deleted["ch2"]);
+        assert!(db.get_deleted_channels().unwrap().is_empty());
+
+        // Undeleted by the admin
+        load(feed(&["ch1"], &["ch4"]));
+        let id = aliases(db.get_deleted_channels().unwrap())["ch4"];
+        assert!(db.undelete_channel(id).unwrap());
+        assert!(!db.undelete_channel(id).unwrap());
+        assert_eq!(db.get_channel_by_alias("ch4").unwrap().unwrap().0, id);
+    }
+
+    #[test]
+    #[serial]
+    fn test_clear_channels() {
+        let db = open_db();
+        let mut conn = Connection::open(&db.file).unwrap();
+        let now = 1_000_000_000;
+        let day = 24 * 60 * 60;
+        // (programs count, last seen) of channels 1..
+        let matrix = [
+            (3, now - 2 * day), // enough programs
+            (1, now - 60 * 60), // sparse, but seen recently
+            (1, now - 2 * day), // sparse and gone
+            (0, 0),             // never seen
+            (0, now - 60),      // no programs, but seen recently
+        ];
+        for (i, &(count, last_seen)) in matrix.iter().enumerate() {
+            let id = i as i64 + 1;
+            let channel = ChannelInfo {
+                alias: format!("c{}", id),
+                ..ChannelInfo::new()
+            };
+            update_channel_info(&conn, id, &channel).unwrap();
+            conn.execute(
+                "update channels set last_seen=?1 where id=?2",
+                &[&last_seen, &id],
+            )
+            .unwrap();
+            for begin in 0..count {
+                let program = Program {
+                    begin: now + begin * 60,
+                    end: now + (begin + 1) * 60,
+                    ..Program::new()
+                };
+                insert_program(&conn, id, &program).unwrap();
+            }
+        }
+        append_programs(&mut conn, 0).unwrap();
+
+        let policy = PurgePolicy {
+            min_programs: 2,
+            grace: chrono::Duration::days(1),
+        };
+        clear_channels(&conn, &policy, now).unwrap();
+        let ids = db
+            .get_channels()
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 5]);
+
+        // Default policy removes only channels without programs
+        clear_channels(&conn, &PurgePolicy::default(), now).unwrap();
+        let ids = db
+            .get_channels()
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_dry_run() {
+        let db = open_db();
+        let data = r#"<tv>
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <programme start="20210101100000 +0000" stop="20210101110000 +0000" channel="ch1">
+              <title>News</title>
+            </programme>
+            <programme start="20210101110000 +0000" stop="20210101120000 +0000" channel="ch2">
+              <title>Sport</title>
+            </programme>
+            </tv>"#;
+        let summary = db
+            .load_xmltv(XmltvReader::new(data.as_bytes()), true)
+            .unwrap();
+        assert_eq!(summary.channels, 1);
+        assert_eq!(summary.programs, 1);
+        assert_eq!(summary.skipped, 1);
+        assert!(summary.error.is_none());
+        assert!(db.get_channels().unwrap().is_empty());
+
+        let summary = db
+            .load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        assert_eq!(summary.programs, 1);
+        assert_eq!(db.get_channels().unwrap().len(), 1);
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_window() {
+        let db = open_db();
+        let mut conn = Connection::open(&db.file).unwrap();
+        for id in 1..=2 {
+            for i in 0..10 {
+                let program = Program {
+                    begin: i * 10,
+                    end: (i + 1) * 10,
+                    title: format!("p{}", i),
+                    description: String::new(),
+                    ..Program::new()
+                };
+                insert_program(&conn, id, &program).unwrap();
+            }
+        }
+        append_programs(&mut conn, 0).unwrap();
+
+        let result = db.get_window(25, 50, None).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(
+            result[&1]
+                .programs
+                .iter()
+                .map(|p| p.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["p2", "p3", "p4"]
+        );
+
+        let result = db.get_window(25, 50, Some(&[2])).unwrap();
+        assert_eq!(result.keys().collect::<Vec<_>>(), vec![&2]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_program_ids() {
+        let db = open_db();
+        let mut conn = Connection::open(&db.file).unwrap();
+        for (begin, title) in &[(0, "a"), (10, "b")] {
+            let program = Program {
+                begin: *begin,
+                end: begin + 10,
+                title: title.to_string(),
+                description: String::new(),
+                ..Program::new()
+            };
+            insert_program(&conn, 3, &program).unwrap();
+        }
+        append_programs(&mut conn, 0).unwrap();
+
+        let programs = db.get_range_with_ids(3, 0, 100).unwrap();
+        assert_eq!(programs.len(), 2);
+        assert_ne!(programs[0].0, programs[1].0);
+        for (pid, program) in &programs {
+            assert_eq!(
+                db.get_program_by_id(*pid).unwrap(),
+                Some((3, program.clone()))
+            );
+        }
+        let unknown = programs.iter().map(|(pid, _)| pid).max().unwrap() + 1;
+        assert_eq!(db.get_program_by_id(unknown).unwrap(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_snapshot() {
+        let db = open_db();
+        let mut conn = Connection::open(&db.file).unwrap();
+        let program = |begin: i64, end: i64, title: &str| Program {
+            begin,
+            end,
+            title: title.to_string(),
+            description: String::new(),
+            ..Program::new()
+        };
+        for id in 1..=2 {
+            let info = ChannelInfo {
+                alias: format!("c{}", id),
+                name: format!("ch{}", id),
+                icon_url: String::new(),
+            };
+            update_channel_info(&conn, id, &info).unwrap();
+        }
+        for (begin, end, title) in &[(0, 10, "a"), (10, 20, "b"), (20, 30, "c"), (30, 40, "d")] {
+            insert_program(&conn, 1, &program(*begin, *end, title)).unwrap();
+        }
+        insert_program(&conn, 2, &program(0, 10, "x")).unwrap();
+        insert_program(&conn, 2, &program(40, 50, "y")).unwrap();
+        append_programs(&mut conn, 0).unwrap();
+
+        let titles = |time, ids: Option<&[i64]>| {
+            let title = |p: &Option<Program>| p.as_ref().map(|p| p.title.clone());
+            db.get_snapshot(time, ids)
+                .unwrap()
+                .iter()
+                .map(|s| {
+                    (
+                        s.channel_id,
+                        title(&s.previous),
+                        title(&s.now),
+                        title(&s.next),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let s = |t: &str| Some(t.to_string());
+
+        // Right before and at the boundary of two programs
+        assert_eq!(
+            titles(9, None),
+            vec![(1, None, s("a"), s("b")), (2, None, s("x"), s("y"))]
+        );
+        assert_eq!(
+            titles(10, None),
+            vec![(1, s("a"), s("b"), s("c")), (2, s("x"), None, s("y"))]
+        );
+        // Nothing after the last program, unknown channels are empty
+        assert_eq!(
+            titles(45, Some(&[2, 1, 3])),
+            vec![
+                (2, s("x"), s("y"), None),
+                (1, s("d"), None, None),
+                (3, None, None, None)
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_channels_with_now() {
+        let db = open_db();
+        let mut conn = Connection::open(&db.file).unwrap();
+        for id in 1..=3 {
+            let info = ChannelInfo {
+                alias: format!("c{}", id),
+                name: format!("ch{}", id),
+                icon_url: String::new(),
+            };
+            update_channel_info(&conn, id, &info).unwrap();
+        }
+        let program = |begin: i64, end: i64, title: &str| Program {
+            begin,
+            end,
+            title: title.to_string(),
+            ..Program::new()
+        };
+        insert_program(&conn, 1, &program(0, 10, "a")).unwrap();
+        insert_program(&conn, 1, &program(10, 20, "b")).unwrap();
+        insert_program(&conn, 2, &program(15, 30, "x")).unwrap();
+        append_programs(&mut conn, 0).unwrap();
+
+        let titles = |time| {
+            let mut channels = db
+                .get_channels_with_now(time)
+                .unwrap()
+                .into_iter()
+                .map(|(id, c, now)| (id, c.alias, now.map(|p| p.title)))
+                .collect::<Vec<_>>();
+            channels.sort();
+            channels
+        };
+        let s = |t: &str| Some(t.to_string());
+        assert_eq!(
+            titles(5),
+            vec![
+                (1, "c1".to_string(), s("a")),
+                (2, "c2".to_string(), None),
+                (3, "c3".to_string(), None)
+            ]
+        );
+        // The program which begins at the time is on air, the one which ends is not
+        assert_eq!(
+            titles(15),
+            vec![
+                (1, "c1".to_string(), s("b")),
+                (2, "c2".to_string(), s("x")),
+                (3, "c3".to_string(), None)
+            ]
+        );
+        assert_eq!(
+            titles(20),
+            vec![
+                (1, "c1".to_string(), None),
+                (2, "c2".to_string(), s("x")),
+                (3, "c3".to_string(), None)
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_at_count() {
+        let mut db = open_db();
+        db.set_max_programs_at(3);
+        let mut conn = Connection::open(&db.file).unwrap();
+
+        update_channel_info(
+            &conn,
+            1,
+            &ChannelInfo {
+                alias: "c1".to_string(),
+                name: "ch1".to_string(),
+                icon_url: String::new(),
+            },
+        )
+        .unwrap();
+        for i in 0..10 {
+            let program = Program {
+                begin: i * 10,
+                end: (i + 1) * 10,
+                title: format!("p{}", i),
+                description: String::new(),
+                ..Program::new()
+            };
+            insert_program(&conn, 1, &program).unwrap();
+        }
+        append_programs(&mut conn, 0).unwrap();
+
+        assert_eq!(db.get_at(5, 2).unwrap()[&1].programs.len(), 2);
+        assert_eq!(db.get_at(5, 1000).unwrap()[&1].programs.len(), 3);
+        assert!(db.get_at(5, 0).is_err());
+        assert!(db.get_at(5, -1).is_err());
+
+        let titles = |lookback| {
+            db.get_at_with_lookback(35, 2, lookback).unwrap()[&1]
+                .programs
+                .iter()
+                .map(|p| p.title.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(titles(0), vec!["p3", "p4"]);
+        assert_eq!(titles(1), vec!["p2", "p3", "p4"]);
+        assert_eq!(titles(2), vec!["p1", "p2", "p3", "p4"]);
+        assert_eq!(
+            db.get_at_with_lookback(5, 1, 1).unwrap()[&1].programs.len(),
+            1
+        );
+        assert!(db.get_at_with_lookback(5, 1, -1).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_alias_mapping() {
+        let mut db = open_db();
+        db.set_alias_mapping(AliasMapping::parse("cnn: cnn, cnn-intl".as_bytes()).unwrap());
+
+        let data = r#"<tv>
+            <channel id="cnn"><display-name>CNN</display-name></channel>
+            <channel id="cnn-intl"><display-name>CNN International</display-name></channel>
+            <programme start="20210101100000 +0000" stop="20210101110000 +0000" channel="cnn">
+              <title>News</title>
+            </programme>
+            <programme start="20210101103000 +0000" stop="20210101113000 +0000" channel="cnn-intl">
+              <title>World</title>
+            </programme>
+            <programme start="20210101110000 +0000" stop="20210101120000 +0000" channel="cnn-intl">
+              <title>Business</title>
+            </programme>
+            </tv>"#;
+        db.load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+
+        let channels = db.get_channels().unwrap();
+        assert_eq!(channels.len(), 1);
+        let (id, channel) = &channels[0];
+        assert_eq!(channel.alias, "cnn");
+        assert_eq!(channel.name, "CNN");
+
+        let mut programs = db.get_range(*id, 0, i64::max_value()).unwrap();
+        programs.sort_by_key(|p| p.begin);
+        assert_eq!(
+            programs
+                .iter()
+                .map(|p| p.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["News", "Business"]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_channel_seeds() {
+        let mut db = open_db();
+        let feed = |aliases: &[&str]| {
+            let mut data = String::from("<tv>");
+            for alias in aliases {
+                data += &format!(
+                    r#"<channel id="{0}"><display-name>{0}</display-name></channel>"#,
+                    alias
+                );
+            }
+            for alias in aliases {
+                data += &format!(
+                    r#"<programme start="20210101100000 +0000" stop="20210101110000 +0000" channel="{}">
+                       <title>News</title></programme>"#,
+                    alias
+                );
+            }
+            data + "</tv>"
+        };
+        let ids = |db: &ProgramsDatabase| {
+            let mut ids = db
+                .get_channels()
+                .unwrap()
+                .into_iter()
+                .map(|(id, c)| (c.alias, id))
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+
+        // Channel loaded before it was seeded is moved to the fixed id
+        db.load_xmltv(XmltvReader::new(feed(&["b"]).as_bytes()), false)
+            .unwrap();
+        db.set_channel_seeds(ChannelSeeds::parse("b: 10\n3: 20".as_bytes()).unwrap());
+        db.seed_channels().unwrap();
+
+        db.load_xmltv(XmltvReader::new(feed(&["c", "b", "20"]).as_bytes()), false)
+            .unwrap();
+        let first = ids(&db);
+        assert!(first.contains(&("b".to_string(), 10)));
+        assert!(!first.iter().any(|(alias, id)| alias == "20" && *id == 20));
+        assert_eq!(db.get_range(10, 0, i64::max_value()).unwrap().len(), 1);
+
+        db.load_xmltv(XmltvReader::new(feed(&["20", "c", "b"]).as_bytes()), false)
+            .unwrap();
+        assert_eq!(ids(&db), first);
+    }
+
+    #[test]
+    #[serial]
+    fn test_channel_seed_moves_occupant() {
+        let mut db = open_db();
+        let data = r#"<tv>
+            <channel id="a"><display-name>A</display-name></channel>
+            <programme start="20210101100000 +0000" stop="20210101110000 +0000" channel="a">
+              <title>News</title>
+            </programme>
+            </tv>"#;
+        db.load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        let id = db.get_channels().unwrap()[0].0;
+        db.set_channel_seeds(ChannelSeeds::parse(format!("s: {}", id).as_bytes()).unwrap());
+        db.seed_channels().unwrap();
+
+        let channels = db.get_channels().unwrap();
+        let moved = channels.iter().find(|(_, c)| c.alias == "a").unwrap().0;
+        assert!(moved > id);
+        assert_eq!(db.get_range(moved, 0, i64::max_value()).unwrap().len(), 1);
+        assert!(channels.iter().any(|(i, c)| *i == id && c.alias == "s"));
+        assert_eq!(db.get_range(id, 0, i64::max_value()).unwrap(), vec![]);
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    #[serial]
+    fn test_encrypted() {
+        let file = "test_encrypted.db";
+        if Path::new(file).exists() {
+            fs::remove_file(file).unwrap();
+        }
+        let db = ProgramsDatabase::open_with_key(file, Some("secret")).unwrap();
+        let conn = db.connect().unwrap();
+        insert_channel(&conn, "ch1", "Channel 1", "").unwrap();
+        drop(conn);
+
+        let db = ProgramsDatabase::open_with_key(file, Some("secret")).unwrap();
+        assert_eq!(db.get_channels().unwrap().len(), 1);
+        assert!(ProgramsDatabase::open_with_key(file, Some("wrong")).is_err());
+        assert!(ProgramsDatabase::open(file).is_err());
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_staged_load() {
+        let db = open_db();
+        let feed = |title: &str| {
+            format!(
+                r#"<tv>
+                <channel id="ch1"><display-name>Channel 1</display-name></channel>
+                <programme start="20210101100000 +0000" stop="20210101110000 +0000" channel="ch1">
+                  <title>{}</title>
+                </programme>
+                </tv>"#,
+                title
+            )
+        };
+        db.load_xmltv_staged(XmltvReader::new(feed("News").as_bytes()))
+            .unwrap();
+        db.load_xmltv_staged(XmltvReader::new(feed("Sport").as_bytes()))
+            .unwrap();
+        assert!(!Path::new("test.db.staging").exists());
+
+        let channels = db.get_channels().unwrap();
+        assert_eq!(channels.len(), 1);
+        let programs = db.get_range(channels[0].0, 0, i64::max_value()).unwrap();
+        assert_eq!(
+            programs
+                .iter()
+                .map(|p| p.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Sport"]
+        );
+        // Swapped file is still a valid migrated database
+        ProgramsDatabase::open("test.db").unwrap();
+
+        // A write made while the copy is loaded ends up in the swapped file
+        struct Feed<'a> {
+            data: &'a [u8],
+            on_read: Option<Box<dyn FnOnce() + 'a>>,
+        }
+        impl std::io::Read for Feed<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if let Some(f) = self.on_read.take() {
+                    f();
+                }
+                std::io::Read::read(&mut self.data, buf)
+            }
+        }
+        let id = channels[0].0;
+        let writer = std::cell::RefCell::new(None);
+        let start_writer = || {
+            let db = db.clone();
+            let (started, start) = std::sync::mpsc::channel();
+            *writer.borrow_mut() = Some(std::thread::spawn(move || {
+                started.send(()).unwrap();
+                db.set_icon_status(id, "", true, 1234).unwrap()
+            }));
+            start.recv().unwrap();
+            // The write waits for the swap
+            assert!(db.writer.try_lock().is_err());
+        };
+        let data = feed("Movie");
+        let reader = std::io::BufReader::new(Feed {
+            data: data.as_bytes(),
+            on_read: Some(Box::new(start_writer)),
+        });
+        db.load_xmltv_staged(XmltvReader::new(reader)).unwrap();
+        writer.into_inner().unwrap().join().unwrap();
+        let checked: i64 = Connection::open("test.db")
+            .unwrap()
+            .query_row(
+                "select icon_checked from channels where id = ?1",
+                &[&id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(checked, 1234);
+    }
+
+    #[test]
+    #[serial]
+    fn test_channel_full() {
+        let db = open_db();
+        let data = r#"<tv>
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <programme start="20210101110000 +0000" stop="20210101113000 +0000" channel="ch1">
+              <title>Sport</title>
+            </programme>
+            <programme start="20210101100000 +0000" stop="20210101110000 +0000" channel="ch1">
+              <title>News</title>
+            </programme>
+            </tv>"#;
+        db.load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        let (id, _) = db.get_channel_by_alias("ch1").unwrap().unwrap();
+
+        let channel = db.get_channel_full(id).unwrap().unwrap();
+        assert_eq!(channel.id, id);
+        assert_eq!(channel.alias, "ch1");
+        assert_eq!(channel.name, "Channel 1");
+        assert_eq!(
+            channel
+                .programs
+                .iter()
+                .map(|p| p.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["News", "Sport"]
+        );
+        assert!(db.get_channel_full(id + 1).unwrap().is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn test_pragmas() {
+        open_db();
+        let pragmas = Pragmas {
+            cache_size: -2048,
+            mmap_size: 0,
+            synchronous: Synchronous::Normal,
+        };
+        let db = ProgramsDatabase::open_with_pragmas("test.db", None, pragmas).unwrap();
+        let effective = db.effective_pragmas().unwrap();
+        assert_eq!(effective.cache_size, -2048);
+        assert_eq!(effective.synchronous, Some("normal"));
+        assert_eq!(effective.journal_mode, "wal");
+        assert_eq!("EXTRA".parse::<Synchronous>(), Ok(Synchronous::Extra));
+        assert!("fast".parse::<Synchronous>().is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_remap_alias() {
+        let db = open_db();
+        let mut conn = Connection::open(&db.file).unwrap();
+        for (id, alias) in &[(1, "old"), (2, "new"), (3, "other")] {
+            let channel = ChannelInfo {
+                alias: alias.to_string(),
+                ..ChannelInfo::new()
+            };
+            update_channel_info(&conn, *id, &channel).unwrap();
+        }
+        // Channel 1 has stale programs, the provider moved on to channel 2
+        for &(id, begin, title) in &[(1, 0, "a"), (1, 100, "b"), (2, 0, "c"), (2, 100, "d")] {
+            let program = Program {
+                begin,
+                end: begin + 100,
+                title: title.to_string(),
+                ..Program::new()
+            };
+            insert_program(&conn, id, &program).unwrap();
+        }
+        append_programs(&mut conn, 0).unwrap();
+
+        assert_eq!(
+            db.remap_alias("missing", "x", false, 150).unwrap(),
+            Remap::NotFound
+        );
+        assert_eq!(
+            db.remap_alias("old", "new", false, 150).unwrap(),
+            Remap::Conflict
+        );
+        assert_eq!(
+            db.remap_alias("other", "renamed", false, 150).unwrap(),
+            Remap::Renamed
+        );
+        assert_eq!(db.get_channel_by_alias("renamed").unwrap().unwrap().0, 3);
+
+        assert_eq!(
+            db.remap_alias("old", "new", true, 150).unwrap(),
+            Remap::Merged { programs: 1 }
+        );
+        assert_eq!(db.get_channel_by_alias("new").unwrap().unwrap().0, 1);
+        assert_eq!(db.get_channel_by_alias("old").unwrap(), None);
+        let titles = db
+            .get_range(1, 0, 1000)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.title)
+            .collect::<Vec<_>>();
+        assert_eq!(titles, vec!["a", "d"]);
+        assert_eq!(db.get_range(2, 0, 1000).unwrap(), vec![]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_overlaps() {
+        let db = open_db();
+        let data = r#"<tv>
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <channel id="ch2"><display-name>Channel 2</display-name></channel>
+            <programme start="20210101100000 +0000" stop="20210101120000 +0000" channel="ch1">
+              <title>Movie</title>
+            </programme>
+            <programme start="20210101110000 +0000" stop="20210101113000 +0000" channel="ch1">
+              <title>News</title>
+            </programme>
+            <programme start="20210101113000 +0000" stop="20210101130000 +0000" channel="ch1">
+              <title>Sport</title>
+            </programme>
+            <programme start="20210101120000 +0000" stop="20210101130000 +0000" channel="ch2">
+              <title>Weather</title>
+            </programme>
+            <programme start="20210101100000 +0000" stop="20210101120000 +0000" channel="ch2">
+              <title>Music</title>
+            </programme>
+            </tv>"#;
+        let summary = db
+            .load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        // News and Sport begin before Movie ends, programs of ch2 only touch
+        assert_eq!(summary.overlaps, 2);
+        assert!(summary.to_string().contains("2 overlapping programs"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_db_stats() {
+        let db = open_db();
+        let stats = db.get_db_stats().unwrap();
+        assert_eq!((stats.channels, stats.programs), (0, 0));
+        assert_eq!(stats.oldest_program, None);
+        assert_eq!(stats.last_update, None);
+
+        let data = r#"<tv>
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <programme start="20210101100000 +0000" stop="20210101110000 +0000" channel="ch1">
+              <title>News</title>
+            </programme>
+            <programme start="20210101110000 +0000" stop="20210101113000 +0000" channel="ch1">
+              <title>Sport</title>
+            </programme>
+            </tv>"#;
+        db.load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        db.insert_update_status(UpdateStatus::new_ok(
+            Utc.timestamp(1609500000, 0),
+            Utc.timestamp(0, 0),
+        ))
+        .unwrap();
+
+        let stats = db.get_db_stats().unwrap();
+        assert_eq!((stats.channels, stats.programs), (1, 2));
+        assert_eq!(stats.oldest_program, Some(1609495200));
+        assert_eq!(stats.newest_program, Some(1609500600));
+        assert_eq!(stats.last_update, Some(1609500000));
+        assert!(stats.file_size > 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_description_limit() {
+        let feed = (0..200).fold(
+            r#"<tv><channel id="ch1"><display-name>Channel 1</display-name></channel>"#.to_string(),
+            |feed, i| {
+                feed + &format!(
+                    r#"<programme start="{}" stop="{}" channel="ch1">
+                    <title>Program {}</title><desc>{}</desc></programme>"#,
+                    Utc.timestamp(1609459200 + i * 3600, 0)
+                        .format("%Y%m%d%H%M%S +0000"),
+                    Utc.timestamp(1609459200 + (i + 1) * 3600, 0)
+                        .format("%Y%m%d%H%M%S +0000"),
+                    i,
+                    "Сюжет фильма. ".repeat(100)
+                )
+            },
+        ) + "</tv>";
+        let load = |limit| {
+            let mut db = open_db();
+            db.set_description_limit(limit);
+            db.load_xmltv(XmltvReader::new(feed.as_bytes()), false)
+                .unwrap();
+            let id = db.get_channels().unwrap()[0].0;
+            let programs = db.get_range(id, 0, i64::max_value()).unwrap();
+            assert_eq!(programs.len(), 200);
+            (programs, db.get_db_stats().unwrap().file_size)
+        };
+
+        let (programs, full_size) = load(None);
+        assert_eq!(programs[0].description.chars().count(), 1400);
+        let (programs, _) = load(Some(5));
+        assert_eq!(programs[0].description, "Сюжет");
+        let (programs, dropped_size) = load(Some(0));
+        assert!(programs.iter().all(|p| p.description.is_empty()));
+        assert!(dropped_size < full_size / 2);
+    }
+
+    #[test]
+    #[serial]
+    fn test_compress_descriptions() {
+        let now = Utc::now().timestamp();
+        let feed = (0..3).fold(
+            r#"<tv><channel id="ch1"><display-name>Channel 1</display-name></channel>"#.to_string(),
+            |feed, i| {
+                feed + &format!(
+                    r#"<programme start="{}" stop="{}" channel="ch1">
+                    <title>Program {}</title><desc>{}</desc></programme>"#,
+                    Utc.timestamp(now + i * 3600, 0)
+                        .format("%Y%m%d%H%M%S +0000"),
+                    Utc.timestamp(now + (i + 1) * 3600, 0)
+                        .format("%Y%m%d%H%M%S +0000"),
+                    i,
+                    if i == 0 {
+                        "Short".to_string()
+                    } else {
+                        "Сюжет фильма. ".repeat(100)
+                    }
+                )
+            },
+        ) + "</tv>";
+        let types = |db: &ProgramsDatabase| {
+            let conn = db.connect().unwrap();
+            let mut stmt = conn
+                .prepare("select typeof(description) from programs order by begin")
+                .unwrap();
+            let mut types = Vec::new();
+            for t in stmt.query_map(NO_PARAMS, |row| row.get(0)).unwrap() {
+                types.push(t.unwrap());
+            }
+            types
+        };
+        let mut db = open_db();
+        db.set_compress_descriptions(true);
+        db.load_xmltv(XmltvReader::new(feed.as_bytes()), false)
+            .unwrap();
+        // Short descriptions do not get smaller
+        assert_eq!(types(&db), vec!["text", "blob", "blob"]);
+        let id = db.get_channels().unwrap()[0].0;
+        let programs = db.get_range(id, 0, i64::max_value()).unwrap();
+        assert_eq!(programs[0].description, "Short");
+        assert_eq!(programs[1].description, "Сюжет фильма. ".repeat(100));
+        assert_eq!(db.convert_descriptions().unwrap().rows, 0);
+
+        db.set_compress_descriptions(false);
+        let stats = db.convert_descriptions().unwrap();
+        assert_eq!(stats.rows, 2);
+        assert_eq!(stats.text_bytes, 2 * "Сюжет фильма. ".repeat(100).len());
+        assert!(stats.compressed_bytes < stats.text_bytes / 10);
+        assert_eq!(types(&db), vec!["text", "text", "text"]);
+        assert_eq!(db.get_range(id, 0, i64::max_value()).unwrap(), programs);
+    }
+
+    #[test]
+    #[serial]
+    fn test_stale_wal() {
+        let db = open_db();
+        let conn = db.connect().unwrap();
+        insert_channel(&conn, "ch1", "Channel 1", "").unwrap();
+        drop(conn);
+        drop(db);
+
+        // Index of the log which can not be opened as after an unclean shutdown
+        fs::create_dir("test.db-shm").unwrap();
+        assert!(ProgramsDatabase::open_once("test.db", None, &Pragmas::default()).is_err());
+
+        let db = ProgramsDatabase::open("test.db").unwrap();
+        assert_eq!(db.get_channels().unwrap().len(), 1);
+        assert!(!Path::new("test.db-shm").is_dir());
+        for entry in fs::read_dir(".").unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if name.starts_with("test.db-shm.") {
+                fs::remove_dir(path).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_keyed_migrations() {
+        let tables = |conn: &Connection| {
+            let mut stmt = conn
+                .prepare("select name from sqlite_master where type = 'table' order by name")
+                .unwrap();
+            let names = stmt.query_map(NO_PARAMS, |row| row.get(0)).unwrap();
+            names.collect::<Result<Vec<String>>>().unwrap()
+        };
+        let tags = |conn: &Connection| -> i64 {
+            conn.query_row(
+                "select count(*) from __migrant_migrations",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+
+        // The first migration copies a column which is missing
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("create table channels (id integer primary key, name text)")
+            .unwrap();
+        assert!(run_keyed_migrations(&mut conn).is_err());
+        assert_eq!(tables(&conn), vec!["__migrant_migrations", "channels"]);
+        assert_eq!(tags(&conn), 0);
+
+        // Tables as created by `open_once`, including a migration with its own transaction
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "create table channels
+                 (id integer primary key, alias text unique, name text, icon_url text);
+             create table programs (id integer primary key autoincrement, channel integer,
+                 begin integer, end integer, title text, description text);
+             create table programs1 (id integer primary key autoincrement, channel integer,
+                 begin integer, end integer, title text, description text);",
+        )
+        .unwrap();
+        run_keyed_migrations(&mut conn).unwrap();
+        assert_eq!(tags(&conn), MIGRATIONS.len() as i64);
+        run_keyed_migrations(&mut conn).unwrap();
+        assert_eq!(tags(&conn), MIGRATIONS.len() as i64);
+    }
+
+    #[test]
+    fn test_without_transaction() {
+        let up = "-- Log ids\nBEGIN;\ncreate table t (id integer);\n\
+                  create trigger t_log after insert on t begin\n  delete from t;\nend;\n\
+                  Commit Transaction ;\n";
+        assert_eq!(
+            without_transaction(up),
+            "\ncreate table t (id integer);\n\
+             create trigger t_log after insert on t begin\n  delete from t;\nend;\n"
+        );
+        let up = "begin immediate transaction;\nalter table t add column x;\nend transaction;";
+        assert_eq!(without_transaction(up), "\nalter table t add column x;");
+    }
+
+    #[test]
+    fn test_corrupt_db_keeps_wal() {
+        let file = "test_corrupt.db";
+        let wal = format!("{}-wal", file);
+        fs::write(file, vec![0x55; 4096]).unwrap();
+        fs::write(&wal, b"log").unwrap();
+        assert!(ProgramsDatabase::open(file).is_err());
+        assert_eq!(fs::read(&wal).unwrap(), b"log");
+        fs::remove_file(file).ok();
+        fs::remove_file(&wal).ok();
+        fs::remove_file(format!("{}-shm", file)).ok();
+    }
+
+    #[test]
+    #[serial]
+    fn test_search_programs() {
+        let db = open_db();
+        let data = r#"<tv>
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <programme start="20210101100000 +0000" stop="20210101120000 +0000" channel="ch1">
+              <title>Amelie</title>
+              <language>fr</language>
+              <country>FR</country>
+              <country>DE</country>
+            </programme>
+            <programme start="20210101120000 +0000" stop="20210101140000 +0000" channel="ch1">
+              <title>Amadeus</title>
+              <language>en</language>
+              <country>US</country>
+            </programme>
+            </tv>"#;
+        db.load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        let titles = |text, country, language| {
+            db.search_programs(text, country, language, 10)
+                .unwrap()
+                .into_iter()
+                .map(|(_, p)| p.title)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(titles(Some("am"), None, None), vec!["Amelie", "Amadeus"]);
+        assert_eq!(titles(None, Some("de"), None), vec!["Amelie"]);
+        assert_eq!(titles(None, Some("D"), None), Vec::<String>::new());
+        assert_eq!(titles(Some("am"), None, Some("EN")), vec!["Amadeus"]);
+
+        let (_, program) = db
+            .search_programs(None, Some("FR"), None, 10)
+            .unwrap()
+            .remove(0);
+        assert_eq!(program.countries, vec!["FR", "DE"]);
+        assert_eq!(program.language.as_deref(), Some("fr"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_program_media() {
+        let db = open_db();
+        let data = r#"<tv>
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <programme start="20210101100000 +0000" stop="20210101120000 +0000" channel="ch1">
+              <title>Concert</title>
+              <video><aspect>16:9</aspect><quality>HDTV</quality></video>
+              <audio><stereo>dolby digital</stereo></audio>
+            </programme>
+            <programme start="20210101120000 +0000" stop="20210101140000 +0000" channel="ch1">
+              <title>News</title>
+            </programme>
+            </tv>"#;
+        db.load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        let programs = db.search_programs(None, None, None, 10).unwrap();
+        let video = programs[0].1.video.as_ref().unwrap();
+        assert_eq!(video.aspect.as_deref(), Some("16:9"));
+        assert_eq!(video.quality.as_deref(), Some("HDTV"));
+        assert_eq!(video.present, None);
+        let audio = programs[0].1.audio.as_ref().unwrap();
+        assert_eq!(audio.stereo.as_deref(), Some("dolby digital"));
+        assert_eq!(programs[1].1.video, None);
+        assert_eq!(programs[1].1.audio, None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_active_channels() {
+        let db = open_db();
+        let data = r#"<tv>
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <channel id="ch2"><display-name>Channel 2</display-name></channel>
+            <channel id="ch3"><display-name>Channel 3</display-name></channel>
+            <programme start="20210101100000 +0000" stop="20210101110000 +0000" channel="ch1">
+              <title>News</title>
+            </programme>
+            <programme start="20210101090000 +0000" stop="20210101100000 +0000" channel="ch2">
+              <title>Morning</title>
+            </programme>
+            <programme start="20210101110000 +0000" stop="20210101120000 +0000" channel="ch2">
+              <title>Sport</title>
+            </programme>
+            <programme start="20210101080000 +0000" stop="20210101130000 +0000" channel="ch3">
+              <title>Marathon</title>
+            </programme>
+            </tv>"#;
+        db.load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        let active = |time| {
+            let mut aliases = db
+                .get_active_channels(time)
+                .unwrap()
+                .into_iter()
+                .map(|(_, c)| c.alias)
+                .collect::<Vec<_>>();
+            aliases.sort();
+            aliases
+        };
+        // 2021-01-01 10:00, the program of ch2 ends just then
+        assert_eq!(active(1609495200), vec!["ch1", "ch3"]);
+        assert_eq!(active(1609498800), vec!["ch2", "ch3"]);
+        assert!(active(1609520400).is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn test_synthesized_alias() {
+        let db = open_db();
+        let data = r#"<tv>
+            <channel><display-name>BBC One</display-name></channel>
+            <channel><display-name>BBC-One</display-name></channel>
+            <programme start="20210101100000 +0000" stop="20210101110000 +0000" channel="BBC One">
+              <title>News</title>
+            </programme>
+            <programme start="20210101100000 +0000" stop="20210101110000 +0000" channel="BBC-One">
+              <title>Sport</title>
+            </programme>
+            </tv>"#;
+        let summary = db
+            .load_xmltv(
+                XmltvReader::new(data.as_bytes()).with_synthesized_aliases(true),
+                false,
+            )
+            .unwrap();
+        assert_eq!((summary.programs, summary.skipped), (2, 0));
+        let (id, channel) = db.get_channel_by_alias("bbc-one").unwrap().unwrap();
+        assert_eq!(channel.name, "BBC One");
+        assert_eq!(
+            db.get_range(id, 0, i64::max_value()).unwrap()[0].title,
+            "News"
+        );
+        // Names with the same slug are told apart
+        let (id, channel) = db.get_channel_by_alias("bbc-one-2").unwrap().unwrap();
+        assert_eq!(channel.name, "BBC-One");
+        assert_eq!(
+            db.get_range(id, 0, i64::max_value()).unwrap()[0].title,
+            "Sport"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_max_days_ahead() {
+        let mut db = open_db();
+        db.set_max_days_ahead(Some(30));
+        let format = |t: DateTime<Utc>| t.format("%Y%m%d%H%M%S +0000").to_string();
+        let now = Utc::now();
+        let data = format!(
+            r#"<tv>
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <programme start="{}" stop="{}" channel="ch1"><title>Soon</title></programme>
+            <programme start="21000101100000 +0000" stop="21000101110000 +0000" channel="ch1">
+              <title>Far</title>
+            </programme>
+            </tv>"#,
+            format(now + chrono::Duration::days(2)),
+            format(now + chrono::Duration::days(2) + chrono::Duration::hours(1)),
+        );
+        let summary = db
+            .load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        assert_eq!((summary.programs, summary.too_far), (1, 1));
+        let id = db.get_channels().unwrap()[0].0;
+        let programs = db.get_range(id, 0, i64::max_value()).unwrap();
+        assert_eq!(
+            programs
+                .iter()
+                .map(|p| p.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Soon"]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_channel_allowlist() {
+        let mut db = open_db();
+        db.set_channel_allowlist(Some(
+            ["bbc1", "disc"].iter().map(|s| s.to_string()).collect(),
+        ));
+        let now = Utc::now();
+        let data = test_feed(
+            &["bbc1", "disc", "cnn"],
+            now,
+            now + chrono::Duration::hours(1),
+        );
+        let summary = db
+            .load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        assert_eq!(
+            (summary.channels, summary.programs, summary.not_allowed),
+            (3, 2, 1)
+        );
+        let mut loaded = db
+            .get_channels()
+            .unwrap()
+            .into_iter()
+            .filter(|(id, _)| !db.get_range(*id, 0, i64::max_value()).unwrap().is_empty())
+            .map(|(_, c)| c.alias)
+            .collect::<Vec<_>>();
+        loaded.sort();
+        assert_eq!(loaded, vec!["bbc1", "disc"]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_incremental_load() {
+        let mut db = open_db();
+        db.set_incremental(true);
+        let part = |alias: &str, title: &str| {
+            format!(
+                r#"<tv>
+                <channel id="{0}"><display-name>{0}</display-name></channel>
+                <programme start="20210101100000 +0000" stop="20210101110000 +0000" channel="{0}">
+                  <title>{1}</title>
+                </programme>
+                </tv>"#,
+                alias, title
+            )
+        };
+        db.load_xmltv(XmltvReader::new(part("ch1", "News").as_bytes()), false)
+            .unwrap();
+        db.load_xmltv(XmltvReader::new(part("ch2", "Sport").as_bytes()), false)
+            .unwrap();
+        assert_eq!(db.get_db_stats().unwrap().programs, 0);
+        // A part loaded again replaces what it staged before
+        db.load_xmltv(XmltvReader::new(part("ch1", "Weather").as_bytes()), false)
+            .unwrap();
+
+        assert_eq!(db.finalize_load().unwrap(), 2);
+        let mut titles = db
+            .get_channels()
+            .unwrap()
+            .into_iter()
+            .flat_map(|(id, _)| db.get_range(id, 0, i64::max_value()).unwrap())
+            .map(|p| p.title)
+            .collect::<Vec<_>>();
+        titles.sort();
+        assert_eq!(titles, vec!["Sport", "Weather"]);
+        // Nothing is left to merge twice
+        assert_eq!(db.finalize_load().unwrap(), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_incremental_disjoint_parts() {
+        let mut db = open_db();
+        db.set_incremental(true);
+        let part = |day: u32, title: &str| {
+            format!(
+                r#"<tv>
+                <channel id="ch1"><display-name>ch1</display-name></channel>
+                <programme start="202101{0:02}100000 +0000" stop="202101{0:02}110000 +0000" channel="ch1">
+                  <title>{1}</title>
+                </programme>
+                </tv>"#,
+                day, title
+            )
+        };
+        db.load_xmltv(XmltvReader::new(part(1, "News").as_bytes()), false)
+            .unwrap();
+        // Another day of the channel is kept with the first one
+        db.load_xmltv(XmltvReader::new(part(2, "Sport").as_bytes()), false)
+            .unwrap();
+        // The first day loaded again replaces only itself
+        db.load_xmltv(XmltvReader::new(part(1, "Weather").as_bytes()), false)
+            .unwrap();
+
+        assert_eq!(db.finalize_load().unwrap(), 2);
+        let mut titles = db
+            .get_channels()
+            .unwrap()
+            .into_iter()
+            .flat_map(|(id, _)| db.get_range(id, 0, i64::max_value()).unwrap())
+            .map(|p| p.title)
+            .collect::<Vec<_>>();
+        titles.sort();
+        assert_eq!(titles, vec!["Sport", "Weather"]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_interrupted_merge() {
+        let mut db = open_db();
+        let conn = Connection::open(&db.file).unwrap();
+        update_channel(&conn, 1, "ch1", "Channel 1", "").unwrap();
+        let old = Program {
+            begin: 0,
+            end: 3600,
+            title: "Old news".to_string(),
+            ..Program::new()
+        };
+        insert_program(&conn, 1, &old).unwrap();
+        let mut conn = conn;
+        append_programs(&mut conn, 0).unwrap();
+
+        // Staged by a load which was killed before its merge
+        db.set_incremental(true);
+        let now = Utc::now();
+        let feed = test_feed(&["ch1"], now, now + chrono::Duration::hours(1));
+        db.load_xmltv(XmltvReader::new(feed.as_bytes()), false)
+            .unwrap();
+        assert_eq!(db.recover_staged().unwrap(), 0);
+        db.set_incremental(false);
+
+        let titles = || {
+            db.get_range(1, 0, i64::max_value())
+                .unwrap()
+                .into_iter()
+                .map(|p| p.title)
+                .collect::<Vec<_>>()
+        };
+        let staged = || -> i64 {
+            conn.query_row("select count(*) from programs1", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        // A merge which fails halfway changes nothing, not even the pruning of old programs
+        conn.execute_batch(
+            "create trigger fail_insert before insert on programs
+             begin select raise(abort, 'disk full'); end",
+        )
+        .unwrap();
+        assert!(db.recover_staged().is_err());
+        assert_eq!(titles(), vec!["Old news"]);
+        assert_eq!(staged(), 1);
+
+        conn.execute_batch("drop trigger fail_insert").unwrap();
+        assert_eq!(db.recover_staged().unwrap(), 1);
+        assert_eq!(titles(), vec!["News"]);
+        assert_eq!(staged(), 0);
+        assert_eq!(db.recover_staged().unwrap(), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_hourly_histogram() {
+        let db = open_db();
+        let data = r#"<tv>
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <programme start="20210101100000 +0000" stop="20210101104500 +0000" channel="ch1">
+              <title>News</title>
+            </programme>
+            <programme start="20210101104500 +0000" stop="20210101110000 +0000" channel="ch1">
+              <title>Weather</title>
+            </programme>
+            <programme start="20210101233000 +0000" stop="20210102003000 +0000" channel="ch1">
+              <title>Movie</title>
+            </programme>
+            </tv>"#;
+        db.load_xmltv(XmltvReader::new(data.as_bytes()), false)
+            .unwrap();
+        let id = db.get_channels().unwrap()[0].0;
+
+        let histogram = db
+            .get_hourly_histogram(id, 0, i64::max_value(), &Utc)
+            .unwrap();
+        assert_eq!((histogram[10], histogram[23]), (2, 1));
+        assert_eq!(histogram.iter().sum::<i64>(), 3);
+
+        let histogram = db
+            .get_hourly_histogram(id, 0, i64::max_value(), &FixedOffset::east(2 * 3600))
+            .unwrap();
+        assert_eq!((histogram[12], histogram[1]), (2, 1));
+
+        let histogram = db
+            .get_hourly_histogram(id, 0, 1609495200 + 3600, &Utc)
+            .unwrap();
+        assert_eq!(histogram.iter().sum::<i64>(), 2);
+    }
+
+    #[test]
+    #[serial]
+    fn test_update_log() {
+        let db = open_db();
+        assert_eq!(db.get_last_update().unwrap(), None);
+
+        let day = Utc.ymd(2021, 2, 21);
+
+        let mut st1 = UpdateStatus::new_ok(day.and_hms(10, 10, 0), day.and_hms(0, 0, 5));
+        st1.feed = FeedInfo {
+            generator_name: "generator".to_owned(),
+            generator_url: "http://generator.org".to_owned(),
+            date: "20210221".to_owned(),
+        };
+        st1.overlaps = 2;
+        db.insert_update_status(st1.clone()).unwrap();
+        assert_eq!(db.get_last_update().unwrap(), Some(st1));
+
+        let st2 = UpdateStatus::new_fail(day.and_hms(10, 15, 0), "failure message".to_owned());
+        db.insert_update_status(st2.clone()).unwrap();
+        assert_eq!(db.get_last_update().unwrap(), Some(st2));
+
+        let st3 =
+            UpdateStatus::new_fail(day.and_hms(10, 15, 0), "another failure message".to_owned());
+        db.insert_update_status(st3.clone()).unwrap();
+        assert_eq!(db.get_last_update().unwrap(), Some(st3));
+
+        // Statuses logged within the same second are all kept
+        let conn = Connection::open(&db.file).unwrap();
+        let count: i64 = conn
+            .query_row("select count(*) from update_log", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 3);
+
+        // Modification times are remembered per feed url
+        let mut st4 = UpdateStatus::new_ok(day.and_hms(10, 20, 0), day.and_hms(0, 0, 9));
+        st4.source = FeedSource::Fallback.as_str().to_string();
+        db.insert_update_status(st4.clone()).unwrap();
+        assert_eq!(db.get_last_update().unwrap(), Some(st4));
+        assert_eq!(
+            db.get_last_modified(FeedSource::Primary).unwrap(),
+            Some(day.and_hms(0, 0, 5))
+        );
+        assert_eq!(
+            db.get_last_modified(FeedSource::Fallback).unwrap(),
+            Some(day.and_hms(0, 0, 9))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_drop_duplicate_begins() {
+        let db = open_db();
+        let conn = Connection::open(&db.file).unwrap();
+        let program = |begin: i64, end: i64, title: &str| Program {
+            begin,
+            end,
+            title: title.to_string(),
+            description: String::new(),
+            ..Program::new()
+        };
+        let titles = |policy| {
+            conn.execute("delete from programs1", NO_PARAMS).unwrap();
+            insert_program(&conn, 1, &program(0, 30, "Short")).unwrap();
+            insert_program(&conn, 1, &program(0, 60, "Long")).unwrap();
+            insert_program(&conn, 1, &program(0, 30, "Repeat")).unwrap();
+            insert_program(&conn, 1, &program(60, 90, "Later")).unwrap();
+            insert_program(&conn, 2, &program(0, 30, "Other")).unwrap();
+            let removed = drop_duplicate_begins(&conn, policy).unwrap();
+            let mut stmt = conn
+                .prepare("select title from programs1 order by channel, begin")
+                .unwrap();
+            let titles = stmt
+                .query_map(NO_PARAMS, |row| row.get::<_, String>(0))
+                .unwrap()
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            (removed, titles)
+        };
+        assert_eq!(
+            titles(DuplicateBegins::Longer),
+            (2, vec!["Long".into(), "Later".into(), "Other".into()])
+        );
+        assert_eq!(
+            titles(DuplicateBegins::First),
+            (2, vec!["Short".into(), "Later".into(), "Other".into()])
+        );
+        assert_eq!(
+            titles(DuplicateBegins::Last),
+            (2, vec!["Repeat".into(), "Later".into(), "Other".into()])
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_coalesce_programs() {
+        let db = open_db();
+        let conn = Connection::open(&db.file).unwrap();
+        let program = |begin: i64, end: i64, title: &str, description: &str| Program {
+            begin,
+            end,
+            title: title.to_string(),
+            description: description.to_string(),
+            ..Program::new()
+        };
+        // Movie split into four blocks, one of them with the description
+        for (i, description) in ["", "", "Plot", "Plot"].iter().enumerate() {
+            let begin = i as i64 * 30;
+            insert_program(&conn, 1, &program(begin, begin + 30, "Movie", description)).unwrap();
+        }
+        // Same title after a gap and with another description
+        insert_program(&conn, 1, &program(130, 160, "Movie", "")).unwrap();
+        insert_program(&conn, 2, &program(0, 30, "News", "Morning")).unwrap();
+        insert_program(&conn, 2, &program(30, 60, "News", "Noon")).unwrap();
+        insert_program(&conn, 3, &program(0, 30, "Series", "")).unwrap();
+        insert_program(
+            &conn,
+            3,
+            &Program {
+                sub_title: Some("Episode 2".to_string()),
+                ..program(30, 60, "Series", "")
+            },
+        )
+        .unwrap();
+
+        assert_eq!(coalesce_programs(&conn).unwrap(), 3);
+        let mut stmt = conn
+            .prepare(
+                "select channel, begin, end, description from programs1 order by channel, begin",
+            )
+            .unwrap();
+        let rows = stmt
+            .query_map(NO_PARAMS, |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let plot = Some("Plot".to_string());
+        assert_eq!(
+            rows,
+            vec![
+                (1, 0, 120, plot),
+                (1, 130, 160, None),
+                (2, 0, 30, Some("Morning".to_string())),
+                (2, 30, 60, Some("Noon".to_string())),
+                (3, 0, 30, None),
+                (3, 30, 60, None),
+            ]
+        );
+        assert_eq!(coalesce_programs(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_icon_status() {
+        let db = open_db();
+        let conn = Connection::open(&db.file).unwrap();
+        update_channel(&conn, 1, "a", "A", "http://a.png").unwrap();
+        update_channel(&conn, 2, "b", "B", "").unwrap();
+
+        assert_eq!(
+            db.get_icons_to_probe(100, 10).unwrap(),
+            vec![(1, "http://a.png".to_string())]
+        );
+        db.set_icon_status(1, "http://a.png", false, 100).unwrap();
+        assert!(db.get_icons_to_probe(100, 10).unwrap().is_empty());
+        assert_eq!(db.get_icons_to_probe(101, 10).unwrap().len(), 1);
+        let dead = db.get_dead_icons().unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].1.name, "A");
+
+        // The probe is kept while the icon stays the same
+        update_channel(&conn, 1, "a", "A", "http://a.png").unwrap();
+        assert_eq!(db.get_dead_icons().unwrap().len(), 1);
+
+        update_channel(&conn, 1, "a", "A", "http://a2.png").unwrap();
+        assert!(db.get_dead_icons().unwrap().is_empty());
+        assert_eq!(db.get_icons_to_probe(100, 10).unwrap().len(), 1);
+        // Outdated probe of the previous icon
+        db.set_icon_status(1, "http://a.png", false, 100).unwrap();
+        assert!(db.get_dead_icons().unwrap().is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn test_has_loaded_feed() {
+        let db = open_db();
+        assert!(!db.has_loaded_feed().unwrap());
+
+        let day = Utc.ymd(2021, 2, 21);
+        let failed = UpdateStatus::new_fail(day.and_hms(10, 0, 0), "timeout".to_owned());
+        db.insert_update_status(failed.clone()).unwrap();
+        assert!(!db.has_loaded_feed().unwrap());
+
+        let ok = UpdateStatus::new_ok(day.and_hms(11, 0, 0), day.and_hms(0, 0, 5));
+        db.insert_update_status(ok).unwrap();
+        db.insert_update_status(failed).unwrap();
+        assert!(db.has_loaded_feed().unwrap());
+    }
+
+    #[test]
+    #[serial]
+    fn test_programs_updated() {
+        let db = open_db();
+        let mut conn = Connection::open(&db.file).unwrap();
+        update_channel(&conn, 1, "a", "A", "").unwrap();
+        update_channel(&conn, 2, "b", "B", "").unwrap();
+        assert_eq!(db.get_programs_updated(1).unwrap(), None);
+        let stage = |conn: &Connection, titles: &[&str]| {
+            for (channel, title) in titles.iter().enumerate() {
+                for begin in &[100, 200] {
+                    let program = Program {
+                        begin: *begin,
+                        end: begin + 100,
+                        title: title.to_string(),
+                        ..Program::new()
+                    };
+                    insert_program(conn, channel as i64 + 1, &program).unwrap();
+                }
+            }
+        };
+        stage(&conn, &["News", "Sport"]);
+        append_programs(&mut conn, 1000).unwrap();
+        assert_eq!(db.get_programs_updated(1).unwrap(), Some(1000));
+        assert_eq!(db.get_programs_updated(2).unwrap(), Some(1000));
+
+        // The same programs again leave the channel unchanged
+        conn.execute("drop index p1_channel", NO_PARAMS).unwrap();
+        stage(&conn, &["News", "Movie"]);
+        append_programs(&mut conn, 2000).unwrap();
+        assert_eq!(db.get_programs_updated(1).unwrap(), Some(1000));
+        assert_eq!(db.get_programs_updated(2).unwrap(), Some(2000));
+        assert_eq!(db.get_range(2, 0, 1000).unwrap()[0].title, "Movie");
+        assert_eq!(db.get_programs_updated(3).unwrap(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_program() {
+        let db = open_db();
+        let conn = Connection::open(&db.file).unwrap();
+        update_channel(&conn, 1, "ch1", "Channel 1", "").unwrap();
+        let description = "Ещё один длинный выпуск новостей. ".repeat(20);
+        let program = Program {
+            begin: 3600,
+            end: 7200,
+            title: "Новости".to_string(),
+            description: description.clone(),
+            ..Program::new()
+        };
+        insert_program(&conn, 1, &program).unwrap();
+        store_descriptions(&conn, "programs1", true).unwrap();
+        let mut conn = conn;
+        append_programs(&mut conn, 0).unwrap();
+
+        let stored = db.get_program(1, 3600).unwrap().unwrap();
+        assert_eq!((stored.channel, stored.begin, stored.end), (1, 3600, 7200));
+        assert_eq!(stored.title, "Новости");
+        assert_eq!(stored.description, Some(description.clone()));
+        assert!(stored.description_compressed);
+        assert!(stored.description_stored_bytes.unwrap() < description.len() as i64);
+        assert!(db.get_program(1, 0).unwrap().is_none());
+        assert!(db.get_program(2, 3600).unwrap().is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn test_channels_ending_soonest() {
+        let db = open_db();
+        let conn = Connection::open(&db.file).unwrap();
+        for (id, alias, hours) in &[
+            (1, "week", 168),
+            (2, "day", 24),
+            (3, "empty", 0),
+            (4, "hour", 1),
+        ] {
+            update_channel(&conn, *id, alias, alias, "").unwrap();
+            for begin in (0..*hours).map(|h| h * 3600) {
+                let program = Program {
+                    begin,
+                    end: begin + 3600,
+                    title: "News".to_string(),
+                    ..Program::new()
+                };
+                insert_program(&conn, *id, &program).unwrap();
+            }
+        }
+        let mut conn = conn;
+        append_programs(&mut conn, 0).unwrap();
+
+        let ending = |limit| {
+            db.channels_ending_soonest(limit)
+                .unwrap()
+                .into_iter()
+                .map(|(_, c, end)| (c.alias, end))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ending(10),
+            vec![
+                ("empty".to_string(), None),
+                ("hour".to_string(), Some(3600)),
+                ("day".to_string(), Some(24 * 3600)),
+                ("week".to_string(), Some(168 * 3600)),
+            ]
+        );
+        assert_eq!(ending(2).len(), 2);
+    }
+
+    #[test]
+    #[serial]
+    fn test_rebuild_indexes() {
+        let db = open_db();
+        let conn = Connection::open(&db.file).unwrap();
+        for begin in &[0, 10, 20] {
+            let program = Program {
+                begin: *begin,
+                end: begin + 10,
+                title: "News".to_string(),
+                ..Program::new()
+            };
+            insert_program(&conn, 1, &program).unwrap();
+        }
+        db.rebuild_indexes().unwrap();
+        db.rebuild_indexes().unwrap();
+
+        let indexes: i64 = conn
+            .query_row(
+                "select count(*) from sqlite_master where type = 'index'
+                 and name in ('channel', 'channel_begin', 'channel_end')",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(indexes, 3);
+        assert_eq!(db.get_range(1, 0, 30).unwrap().len(), 3);
     }
 }
@@ -1,6 +1,7 @@
 use chrono::prelude::*;
 use serde_derive::{Deserialize, Serialize};
-use std::fmt;
+use std::collections::{BTreeMap, HashSet};
+use std::{fmt, str};
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Program {
@@ -8,6 +9,66 @@ pub struct Program {
     pub end: i64,
     pub title: String,
     pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub_title: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+    /// One of `categories` for compact display
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_category: Option<String>,
+    /// Production countries
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub countries: Vec<String>,
+    /// Spoken language
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Picture details of the `<video>` element
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video: Option<Video>,
+    /// Sound details of the `<audio>` element
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio: Option<Audio>,
+    /// Title and description by language, used to localize the program
+    #[serde(skip)]
+    pub translations: BTreeMap<String, Translation>,
+    /// Begin or end was cut to the requested time window
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub clamped: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Picture properties of the broadcast, like `HDTV` quality and `16:9` aspect
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct Video {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub present: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub colour: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aspect: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+}
+
+/// Sound properties of the broadcast, `stereo` is like `mono`, `stereo` or `dolby digital`
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct Audio {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub present: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stereo: Option<String>,
+}
+
+/// Program text in one language
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct Translation {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub title: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
 }
 
 impl Program {
@@ -17,28 +78,497 @@ impl Program {
             end: 0,
             title: String::new(),
             description: String::new(),
+            sub_title: None,
+            categories: Vec::new(),
+            primary_category: None,
+            countries: Vec::new(),
+            language: None,
+            video: None,
+            audio: None,
+            translations: BTreeMap::new(),
+            clamped: false,
+        }
+    }
+
+    /// Cut the program to the visible part of `[from, to)`
+    pub fn clamp(&mut self, from: i64, to: i64) {
+        if self.begin < from {
+            self.begin = from;
+            self.clamped = true;
+        }
+        if self.end > to {
+            self.end = to;
+            self.clamped = true;
+        }
+    }
+
+    /// Replace title and description with the first available of the preferred languages,
+    /// a language like `en-US` also matches translation to `en`
+    pub fn localize(&mut self, languages: &[String]) {
+        let primary = |lang: &str| lang.split('-').next().unwrap_or("").to_string();
+        let found = languages.iter().find_map(|lang| {
+            self.translations.get(lang).or_else(|| {
+                self.translations
+                    .iter()
+                    .find(|(l, _)| primary(l) == primary(lang))
+                    .map(|(_, t)| t)
+            })
+        });
+        if let Some(translation) = found.cloned() {
+            if !translation.title.is_empty() {
+                self.title = translation.title;
+            }
+            if !translation.description.is_empty() {
+                self.description = translation.description;
+            }
         }
     }
+
+    /// Pick the primary category as the one listed first in `priority`, compared
+    /// case-insensitively, and fall back to the first category of the program
+    pub fn select_primary_category(&mut self, priority: &[String]) {
+        let rank = |category: &String| {
+            priority
+                .iter()
+                .position(|p| p.eq_ignore_ascii_case(category))
+        };
+        self.primary_category = self
+            .categories
+            .iter()
+            .filter_map(|c| rank(c).map(|r| (r, c)))
+            .min_by_key(|&(r, _)| r)
+            .map(|(_, c)| c)
+            .or_else(|| self.categories.first())
+            .cloned();
+    }
+
+    /// Begin and end times formatted as `HH:MM` in the given timezone
+    pub fn times_in<Tz: TimeZone>(&self, tz: &Tz) -> (String, String)
+    where
+        Tz::Offset: fmt::Display,
+    {
+        let format = |t| tz.timestamp(t, 0).format("%H:%M").to_string();
+        (format(self.begin), format(self.end))
+    }
 }
 
 impl fmt::Display for Program {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}-{}|{}",
-            Utc.timestamp(self.begin, 0).format("%H:%M"),
-            Utc.timestamp(self.end, 0).format("%H:%M"),
-            self.title
-        )
+        let (begin, end) = self.times_in(&Utc);
+        write!(f, "{}-{}|{}", begin, end, self.title)
     }
 }
 
+/// Time interval of a channel schedule not covered by any program
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Gap {
+    pub begin: i64,
+    pub end: i64,
+}
+
+/// Coverage problems found in a channel schedule
+#[derive(Serialize, Debug, Default)]
+pub struct ScheduleCheck {
+    pub gaps: Vec<Gap>,
+    /// Pairs of programs where the second one starts before the first one ends
+    pub overlaps: Vec<(Program, Program)>,
+}
+
+/// Scan programs ordered by begin time and find gaps and overlaps within `[from, to)`
+pub fn check_schedule(programs: &[Program], from: i64, to: i64) -> ScheduleCheck {
+    let mut result = ScheduleCheck::default();
+    // End of the covered time and the program which covers it
+    let mut covered = from;
+    let mut last: Option<&Program> = None;
+    for p in programs {
+        if p.begin > covered && covered < to {
+            result.gaps.push(Gap {
+                begin: covered,
+                end: p.begin.min(to),
+            });
+        }
+        if let Some(prev) = last {
+            if p.begin < prev.end {
+                result.overlaps.push((prev.clone(), p.clone()));
+            }
+        }
+        if last.map_or(true, |prev| p.end > prev.end) {
+            last = Some(p);
+        }
+        covered = covered.max(p.end);
+    }
+    if covered < to {
+        result.gaps.push(Gap {
+            begin: covered,
+            end: to,
+        });
+    }
+    result
+}
+
 #[derive(Serialize, Debug)]
 pub struct EpgNow {
     pub channel_id: i64,
     pub programs: Vec<Program>,
 }
 
+/// Program in the wire format which does not follow changes of `Program`.
+///
+/// Fields always come in this order and absent values are `null`:
+/// `begin, end, title, sub_title, description, categories, countries, language, clamped`.
+/// New fields may only be appended to the end.
+#[derive(Serialize, Debug)]
+pub struct StableProgram<'a> {
+    pub begin: i64,
+    pub end: i64,
+    pub title: &'a str,
+    pub sub_title: Option<&'a str>,
+    pub description: &'a str,
+    pub categories: &'a [String],
+    pub countries: &'a [String],
+    pub language: Option<&'a str>,
+    pub clamped: bool,
+}
+
+impl<'a> From<&'a Program> for StableProgram<'a> {
+    fn from(p: &'a Program) -> Self {
+        Self {
+            begin: p.begin,
+            end: p.end,
+            title: &p.title,
+            sub_title: p.sub_title.as_deref(),
+            description: &p.description,
+            categories: &p.categories,
+            countries: &p.countries,
+            language: p.language.as_deref(),
+            clamped: p.clamped,
+        }
+    }
+}
+
+/// `EpgNow` with programs in the stable wire format
+#[derive(Serialize, Debug)]
+pub struct StableEpgNow<'a> {
+    pub channel_id: i64,
+    pub programs: Vec<StableProgram<'a>>,
+}
+
+impl<'a> From<&'a EpgNow> for StableEpgNow<'a> {
+    fn from(e: &'a EpgNow) -> Self {
+        Self {
+            channel_id: e.channel_id,
+            programs: e.programs.iter().map(StableProgram::from).collect(),
+        }
+    }
+}
+
+/// Names of the program fields which may be requested with the `fields` parameter
+pub const PROGRAM_FIELDS: &[&str] = &[
+    "begin",
+    "end",
+    "title",
+    "description",
+    "sub_title",
+    "categories",
+    "primary_category",
+    "countries",
+    "language",
+    "video",
+    "audio",
+    "clamped",
+];
+
+/// Program fields requested by the client, parsed from `begin,end,title`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramFields(HashSet<String>);
+
+impl str::FromStr for ProgramFields {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut fields = HashSet::new();
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if !PROGRAM_FIELDS.contains(&name) {
+                return Err(format!("Unknown field {}", name));
+            }
+            fields.insert(name.to_string());
+        }
+        if fields.is_empty() {
+            return Err("No fields requested".to_string());
+        }
+        Ok(ProgramFields(fields))
+    }
+}
+
+/// Program serialized with only the requested fields, absent values are skipped as usual
+#[derive(Debug)]
+pub struct ProjectedProgram<'a> {
+    pub program: &'a Program,
+    pub fields: &'a ProgramFields,
+}
+
+impl serde::Serialize for ProjectedProgram<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let names = PROGRAM_FIELDS.iter().copied();
+        let names = names.filter(|name| self.fields.0.contains(*name));
+        serialize_program_fields(serializer, self.program, names, false)
+    }
+}
+
+/// Names of the program fields in alphabetical order
+const SORTED_PROGRAM_FIELDS: &[&str] = &[
+    "audio",
+    "begin",
+    "categories",
+    "clamped",
+    "countries",
+    "description",
+    "end",
+    "language",
+    "primary_category",
+    "sub_title",
+    "title",
+    "video",
+];
+
+/// Serialize the fields of the program in the order of `names`, absent values are skipped
+/// like the derived serialization does. `sorted` orders the keys of `video` as well.
+fn serialize_program_fields<'a, S, I>(
+    serializer: S,
+    p: &Program,
+    names: I,
+    sorted: bool,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    I: Iterator<Item = &'a str>,
+{
+    use serde::ser::SerializeMap;
+    let mut map = serializer.serialize_map(None)?;
+    for name in names {
+        match name {
+            "begin" => map.serialize_entry(name, &p.begin)?,
+            "end" => map.serialize_entry(name, &p.end)?,
+            "title" => map.serialize_entry(name, &p.title)?,
+            "description" => map.serialize_entry(name, &p.description)?,
+            "sub_title" if p.sub_title.is_some() => map.serialize_entry(name, &p.sub_title)?,
+            "categories" if !p.categories.is_empty() => map.serialize_entry(name, &p.categories)?,
+            "primary_category" if p.primary_category.is_some() => {
+                map.serialize_entry(name, &p.primary_category)?
+            }
+            "countries" if !p.countries.is_empty() => map.serialize_entry(name, &p.countries)?,
+            "language" if p.language.is_some() => map.serialize_entry(name, &p.language)?,
+            "video" => match &p.video {
+                Some(video) if sorted => map.serialize_entry(name, &SortedVideo(video))?,
+                Some(video) => map.serialize_entry(name, video)?,
+                None => {}
+            },
+            "audio" if p.audio.is_some() => map.serialize_entry(name, &p.audio)?,
+            "clamped" if p.clamped => map.serialize_entry(name, &p.clamped)?,
+            _ => {}
+        }
+    }
+    map.end()
+}
+
+/// `Video` with keys in alphabetical order
+struct SortedVideo<'a>(&'a Video);
+
+impl serde::Serialize for SortedVideo<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let v = self.0;
+        let mut map = serializer.serialize_map(None)?;
+        if let Some(aspect) = &v.aspect {
+            map.serialize_entry("aspect", aspect)?;
+        }
+        if let Some(colour) = &v.colour {
+            map.serialize_entry("colour", colour)?;
+        }
+        if let Some(present) = &v.present {
+            map.serialize_entry("present", present)?;
+        }
+        if let Some(quality) = &v.quality {
+            map.serialize_entry("quality", quality)?;
+        }
+        map.end()
+    }
+}
+
+/// `EpgNow` serialized with keys in alphabetical order, the way `serde_json::Value` does
+#[derive(Debug)]
+pub struct SortedEpgNow<'a>(pub &'a EpgNow);
+
+impl serde::Serialize for SortedEpgNow<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        struct Programs<'a>(&'a [Program]);
+        impl serde::Serialize for Programs<'_> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.0.iter().map(SortedProgram))
+            }
+        }
+        struct SortedProgram<'a>(&'a Program);
+        impl serde::Serialize for SortedProgram<'_> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let names = SORTED_PROGRAM_FIELDS.iter().copied();
+                serialize_program_fields(serializer, self.0, names, true)
+            }
+        }
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("channel_id", &self.0.channel_id)?;
+        map.serialize_entry("programs", &Programs(&self.0.programs))?;
+        map.end()
+    }
+}
+
+/// `EpgNow` with projected programs
+#[derive(Serialize, Debug)]
+pub struct ProjectedEpgNow<'a> {
+    pub channel_id: i64,
+    pub programs: Vec<ProjectedProgram<'a>>,
+}
+
+impl<'a> ProjectedEpgNow<'a> {
+    pub fn new(e: &'a EpgNow, fields: &'a ProgramFields) -> Self {
+        Self {
+            channel_id: e.channel_id,
+            programs: e
+                .programs
+                .iter()
+                .map(|program| ProjectedProgram { program, fields })
+                .collect(),
+        }
+    }
+}
+
+/// Program with its database id, which identifies it only until the next feed update
+#[derive(Serialize, Debug)]
+pub struct ProgramWithId {
+    pub id: i64,
+    #[serde(flatten)]
+    pub program: Program,
+}
+
+/// Program fields shown in the compact now and next view
+#[derive(Serialize, Debug)]
+pub struct ProgramBrief<'a> {
+    pub begin: i64,
+    pub end: i64,
+    pub title: &'a str,
+    pub primary_category: Option<&'a str>,
+}
+
+impl<'a> From<&'a Program> for ProgramBrief<'a> {
+    fn from(p: &'a Program) -> Self {
+        Self {
+            begin: p.begin,
+            end: p.end,
+            title: &p.title,
+            primary_category: p.primary_category.as_deref(),
+        }
+    }
+}
+
+/// Program airing at some time and the one after it
+#[derive(Serialize, Debug)]
+pub struct NowNext<'a> {
+    pub channel_id: i64,
+    pub now: Option<ProgramBrief<'a>>,
+    pub next: Option<ProgramBrief<'a>>,
+}
+
+impl<'a> NowNext<'a> {
+    /// Split programs ending after `time` into the current and the next one
+    pub fn at(epg: &'a EpgNow, time: i64) -> Self {
+        let mut programs = epg.programs.iter().filter(|p| p.end > time);
+        let first = programs.next();
+        let (now, next) = match first {
+            Some(p) if p.begin <= time => (Some(p), programs.next()),
+            _ => (None, first),
+        };
+        Self {
+            channel_id: epg.channel_id,
+            now: now.map(ProgramBrief::from),
+            next: next.map(ProgramBrief::from),
+        }
+    }
+}
+
+/// Programs of a channel around some time: the last one which ended, the airing one
+/// and the one after it, each may be missing
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Snapshot {
+    pub channel_id: i64,
+    pub previous: Option<Program>,
+    pub now: Option<Program>,
+    pub next: Option<Program>,
+}
+
+impl Snapshot {
+    /// Split programs of the channel ordered by begin around `time`
+    pub fn at(channel_id: i64, programs: Vec<Program>, time: i64) -> Self {
+        let mut snapshot = Self {
+            channel_id,
+            previous: None,
+            now: None,
+            next: None,
+        };
+        for p in programs {
+            if p.end <= time {
+                snapshot.previous = Some(p);
+            } else if p.begin <= time && snapshot.now.is_none() {
+                snapshot.now = Some(p);
+            } else if p.begin > time && snapshot.next.is_none() {
+                snapshot.next = Some(p);
+            }
+        }
+        snapshot
+    }
+}
+
+/// Program placed in a guide grid, times are seconds from the start of the window
+#[derive(Serialize, Debug)]
+pub struct GridCell<'a> {
+    pub start_offset: i64,
+    pub duration: i64,
+    /// The program begins before or ends after the window
+    pub clamped: bool,
+    pub title: &'a str,
+    pub primary_category: Option<&'a str>,
+}
+
+/// Programs of a channel cut to the window `[from, to)` of a guide grid,
+/// `to - from` must not overflow as `GridQuery` checks
+#[derive(Serialize, Debug)]
+pub struct GridRow<'a> {
+    pub channel_id: i64,
+    pub programs: Vec<GridCell<'a>>,
+}
+
+impl<'a> GridRow<'a> {
+    pub fn new(epg: &'a EpgNow, from: i64, to: i64) -> Self {
+        let programs = epg
+            .programs
+            .iter()
+            .filter(|p| p.end > from && p.begin < to)
+            .map(|p| {
+                let (begin, end) = (p.begin.max(from), p.end.min(to));
+                GridCell {
+                    start_offset: begin - from,
+                    duration: end - begin,
+                    clamped: begin != p.begin || end != p.end,
+                    title: &p.title,
+                    primary_category: p.primary_category.as_deref(),
+                }
+            })
+            .collect();
+        Self {
+            channel_id: epg.channel_id,
+            programs,
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Debug)]
 pub struct ChannelInfo {
     pub alias: String,
@@ -58,8 +588,9 @@ impl ChannelInfo {
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Channel {
-    #[serde(rename = "_id")]
     pub id: i64,
+    #[serde(default)]
+    pub alias: String,
     pub name: String,
     pub icon_url: String,
     pub programs: Vec<Program>,
@@ -146,10 +677,15 @@ impl Channel {
 mod tests {
     use crate::epg::Channel;
     use crate::epg::Program;
+    use crate::epg::{
+        check_schedule, Audio, EpgNow, Gap, GridRow, NowNext, ProgramFields, ProjectedProgram,
+        SortedEpgNow, StableProgram, Translation, Video, PROGRAM_FIELDS, SORTED_PROGRAM_FIELDS,
+    };
 
     fn sample_channel() -> Channel {
         Channel {
             id: 0,
+            alias: String::new(),
             name: String::new(),
             icon_url: String::new(),
             programs: vec![
@@ -158,18 +694,21 @@ mod tests {
                     end: 20,
                     title: String::from("a"),
                     description: String::new(),
+                    ..Program::new()
                 },
                 Program {
                     begin: 20,
                     end: 25,
                     title: String::from("b"),
                     description: String::new(),
+                    ..Program::new()
                 },
                 Program {
                     begin: 25,
                     end: 40,
                     title: String::from("c"),
                     description: String::new(),
+                    ..Program::new()
                 },
             ],
         }
@@ -209,6 +748,7 @@ mod tests {
                 end: 50,
                 title: String::from("x"),
                 description: String::new(),
+                ..Program::new()
             });
             assert_eq!(channel.programs[3].title, "x")
         }
@@ -219,6 +759,7 @@ mod tests {
                 end: 10,
                 title: String::from("x"),
                 description: String::new(),
+                ..Program::new()
             });
             assert_eq!(channel.programs[0].title, "x")
         }
@@ -235,12 +776,14 @@ mod tests {
                         end: 5,
                         title: String::from("x"),
                         description: String::new(),
+                        ..Program::new()
                     },
                     Program {
                         begin: 5,
                         end: 10,
                         title: String::from("y"),
                         description: String::new(),
+                        ..Program::new()
                     },
                 ],
                 0,
@@ -263,12 +806,14 @@ mod tests {
                         end: 11,
                         title: String::from("x"),
                         description: String::new(),
+                        ..Program::new()
                     },
                     Program {
                         begin: 10,
                         end: 12,
                         title: String::from("y"),
                         description: String::new(),
+                        ..Program::new()
                     },
                 ],
                 0,
@@ -291,12 +836,14 @@ mod tests {
                         end: 5,
                         title: String::from("x"),
                         description: String::new(),
+                        ..Program::new()
                     },
                     Program {
                         begin: 5,
                         end: 10,
                         title: String::from("y"),
                         description: String::new(),
+                        ..Program::new()
                     },
                 ],
                 3,
@@ -312,6 +859,255 @@ mod tests {
         }
     }
 
+    #[test]
+    fn schedule_gaps() {
+        let program = |begin, end, title: &str| Program {
+            begin,
+            end,
+            title: title.to_string(),
+            description: String::new(),
+            ..Program::new()
+        };
+        let programs = vec![
+            program(5, 20, "a"),
+            program(25, 40, "b"),
+            program(30, 35, "c"),
+            program(38, 50, "d"),
+        ];
+        let check = check_schedule(&programs, 10, 60);
+        assert_eq!(
+            check.gaps,
+            vec![Gap { begin: 20, end: 25 }, Gap { begin: 50, end: 60 }]
+        );
+        assert_eq!(
+            check
+                .overlaps
+                .iter()
+                .map(|(a, b)| (a.title.as_str(), b.title.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("b", "c"), ("b", "d")]
+        );
+
+        let check = check_schedule(&[], 10, 60);
+        assert_eq!(check.gaps, vec![Gap { begin: 10, end: 60 }]);
+        assert!(check.overlaps.is_empty());
+    }
+
+    #[test]
+    fn clamp() {
+        let program = Program {
+            begin: 5,
+            end: 20,
+            ..Program::new()
+        };
+        let mut p = program.clone();
+        p.clamp(10, 30);
+        assert_eq!((p.begin, p.end, p.clamped), (10, 20, true));
+
+        let mut p = program.clone();
+        p.clamp(0, 15);
+        assert_eq!((p.begin, p.end, p.clamped), (5, 15, true));
+
+        let mut p = program.clone();
+        p.clamp(0, 30);
+        assert_eq!(p, program);
+        assert!(!serde_json::to_string(&p).unwrap().contains("clamped"));
+    }
+
+    #[test]
+    fn stable_program() {
+        let program = Program {
+            begin: 0,
+            end: 60,
+            title: "News".to_string(),
+            countries: vec!["FR".to_string()],
+            ..Program::new()
+        };
+        assert_eq!(
+            serde_json::to_string(&StableProgram::from(&program)).unwrap(),
+            concat!(
+                r#"{"begin":0,"end":60,"title":"News","sub_title":null,"description":"","#,
+                r#""categories":[],"countries":["FR"],"language":null,"clamped":false}"#
+            )
+        );
+    }
+
+    #[test]
+    fn projected_program() {
+        let program = Program {
+            begin: 0,
+            end: 60,
+            title: "News".to_string(),
+            description: "Daily news".to_string(),
+            ..Program::new()
+        };
+        let fields = "begin, title,sub_title".parse::<ProgramFields>().unwrap();
+        assert_eq!(
+            serde_json::to_string(&ProjectedProgram {
+                program: &program,
+                fields: &fields
+            })
+            .unwrap(),
+            r#"{"begin":0,"title":"News"}"#
+        );
+        assert!("title,rating".parse::<ProgramFields>().is_err());
+        assert!("".parse::<ProgramFields>().is_err());
+    }
+
+    #[test]
+    fn sorted_epg_now() {
+        let mut names = PROGRAM_FIELDS.to_vec();
+        names.sort_unstable();
+        assert_eq!(names, SORTED_PROGRAM_FIELDS);
+
+        let full = Program {
+            begin: 0,
+            end: 60,
+            title: "News".to_string(),
+            description: "Daily news".to_string(),
+            sub_title: Some("Morning".to_string()),
+            categories: vec!["news".to_string()],
+            primary_category: Some("news".to_string()),
+            countries: vec!["FR".to_string()],
+            language: Some("fr".to_string()),
+            video: Some(Video {
+                present: Some(true),
+                colour: Some(true),
+                aspect: Some("16:9".to_string()),
+                quality: Some("HDTV".to_string()),
+            }),
+            audio: Some(Audio {
+                present: Some(true),
+                stereo: Some("stereo".to_string()),
+            }),
+            clamped: true,
+            ..Program::new()
+        };
+        let epg = EpgNow {
+            channel_id: 5,
+            programs: vec![full, Program::new()],
+        };
+        assert_eq!(
+            serde_json::to_string(&SortedEpgNow(&epg)).unwrap(),
+            serde_json::to_value(&epg).unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn primary_category() {
+        let mut program = Program {
+            categories: vec!["Drama".to_string(), "movie".to_string()],
+            ..Program::new()
+        };
+        let priority = vec!["Movie".to_string(), "Drama".to_string()];
+        program.select_primary_category(&priority);
+        assert_eq!(program.primary_category.as_deref(), Some("movie"));
+
+        program.select_primary_category(&["Sport".to_string()]);
+        assert_eq!(program.primary_category.as_deref(), Some("Drama"));
+
+        program.categories.clear();
+        program.select_primary_category(&priority);
+        assert_eq!(program.primary_category, None);
+    }
+
+    #[test]
+    fn now_next() {
+        let epg = EpgNow {
+            channel_id: 1,
+            programs: sample_channel().programs,
+        };
+        let titles = |n: NowNext| {
+            (
+                n.now.map(|p| p.title.to_string()),
+                n.next.map(|p| p.title.to_string()),
+            )
+        };
+        assert_eq!(
+            titles(NowNext::at(&epg, 15)),
+            (Some("a".to_string()), Some("b".to_string()))
+        );
+        assert_eq!(titles(NowNext::at(&epg, 5)), (None, Some("a".to_string())));
+        assert_eq!(titles(NowNext::at(&epg, 30)), (Some("c".to_string()), None));
+    }
+
+    #[test]
+    fn snapshot_overlap() {
+        let program = |begin, end, title: &str| Program {
+            begin,
+            end,
+            title: title.to_string(),
+            description: String::new(),
+            ..Program::new()
+        };
+        let programs = vec![
+            program(0, 10, "a"),
+            program(10, 30, "b"),
+            program(15, 25, "c"),
+            program(30, 40, "d"),
+        ];
+        let snapshot = Snapshot::at(1, programs, 20);
+        let title = |p: Option<Program>| p.map(|p| p.title);
+        assert_eq!(title(snapshot.previous), Some("a".to_string()));
+        assert_eq!(title(snapshot.now), Some("b".to_string()));
+        // The overlapping program already started, so it is not the next one
+        assert_eq!(title(snapshot.next), Some("d".to_string()));
+    }
+
+    #[test]
+    fn grid_row() {
+        let epg = EpgNow {
+            channel_id: 1,
+            programs: sample_channel().programs,
+        };
+        let cells = |from, to| {
+            GridRow::new(&epg, from, to)
+                .programs
+                .into_iter()
+                .map(|c| (c.title, c.start_offset, c.duration, c.clamped))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            cells(15, 30),
+            vec![("a", 0, 5, true), ("b", 5, 5, false), ("c", 10, 5, true)]
+        );
+        assert_eq!(
+            cells(10, 40),
+            vec![
+                ("a", 0, 10, false),
+                ("b", 10, 5, false),
+                ("c", 15, 15, false)
+            ]
+        );
+        assert_eq!(cells(40, 50), vec![]);
+    }
+
+    #[test]
+    fn localize() {
+        let mut program = Program {
+            title: "Nachrichten".to_string(),
+            description: "Aktuelles".to_string(),
+            ..Program::new()
+        };
+        program.translations.insert(
+            "en".to_string(),
+            Translation {
+                title: "News".to_string(),
+                description: String::new(),
+            },
+        );
+
+        let mut p = program.clone();
+        p.localize(&["fr".to_string()]);
+        assert_eq!(p.title, "Nachrichten");
+
+        let mut p = program.clone();
+        p.localize(&["fr".to_string(), "en-gb".to_string()]);
+        assert_eq!(p.title, "News");
+        // Missing translated description keeps the original one
+        assert_eq!(p.description, "Aktuelles");
+    }
+
     //    #[test]
     //    fn channel_programs_range() {
     //        panic!("Make this test fail");
@@ -0,0 +1,77 @@
+use crate::EpgSqlServer;
+use chrono::prelude::*;
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Pause between requests, so icon hosts are not hammered
+const REQUEST_DELAY: Duration = Duration::from_secs(1);
+/// How long an icon host may take to respond before the icon counts as dead
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of icons read from the database at once
+const BATCH_SIZE: i64 = 100;
+
+/// Whether the icon url responds with success. Hosts which do not allow `HEAD`
+/// are asked with `GET`, the body is not read.
+fn probe(client: &Client, url: &str) -> bool {
+    match client.head(url).send() {
+        Ok(response)
+            if response.status() == StatusCode::METHOD_NOT_ALLOWED
+                || response.status() == StatusCode::NOT_IMPLEMENTED =>
+        {
+            client
+                .get(url)
+                .send()
+                .map_or(false, |response| response.status().is_success())
+        }
+        Ok(response) => response.status().is_success(),
+        Err(e) => {
+            eprintln!("Failed to probe icon {}: {}", url, e);
+            false
+        }
+    }
+}
+
+/// Probe icons which were not probed within the interval, returns the number of dead ones
+fn probe_icons(app: &EpgSqlServer, client: &Client, interval: Duration) -> rusqlite::Result<usize> {
+    let started = Utc::now().timestamp();
+    let checked_before = started - interval.as_secs() as i64;
+    let mut dead = 0;
+    loop {
+        // Probed icons get the current time, so every batch brings new ones
+        let icons = app.db.get_icons_to_probe(checked_before, BATCH_SIZE)?;
+        if icons.is_empty() {
+            return Ok(dead);
+        }
+        for (id, url) in icons {
+            let ok = probe(client, &url);
+            if !ok {
+                dead += 1;
+            }
+            app.db
+                .set_icon_status(id, &url, ok, Utc::now().timestamp())?;
+            thread::sleep(REQUEST_DELAY);
+        }
+    }
+}
+
+/// Check channel icons in the background, each icon is probed once per interval
+pub fn spawn(
+    app: Arc<EpgSqlServer>,
+    interval: Duration,
+) -> reqwest::Result<thread::JoinHandle<()>> {
+    let client = Client::builder()
+        .user_agent(app.settings().user_agent.as_str())
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+    Ok(thread::spawn(move || loop {
+        match probe_icons(&app, &client, interval) {
+            Ok(dead) if dead > 0 => println!("Found {} dead channel icons", dead),
+            Ok(_) => {}
+            Err(e) => eprintln!("Failed to probe channel icons: {}", e),
+        }
+        thread::sleep(interval);
+    }))
+}
@@ -0,0 +1,249 @@
+use chrono::Datelike;
+use std::str::FromStr;
+
+/// Names of days and months of a language and how a full date is written with them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Locale {
+    pub code: &'static str,
+    /// Monday first
+    weekdays: [&'static str; 7],
+    /// Month names in the form they take after the day number
+    months: [&'static str; 12],
+    /// `{weekday}`, `{month}`, `{year}` and the day as `{d}` or zero padded `{dd}`
+    pattern: &'static str,
+}
+
+const ENGLISH: Locale = Locale {
+    code: "en",
+    weekdays: [
+        "Monday",
+        "Tuesday",
+        "Wednesday",
+        "Thursday",
+        "Friday",
+        "Saturday",
+        "Sunday",
+    ],
+    months: [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ],
+    pattern: "{weekday}, {dd} {month} {year}",
+};
+
+const LOCALES: &[Locale] = &[
+    ENGLISH,
+    Locale {
+        code: "de",
+        weekdays: [
+            "Montag",
+            "Dienstag",
+            "Mittwoch",
+            "Donnerstag",
+            "Freitag",
+            "Samstag",
+            "Sonntag",
+        ],
+        months: [
+            "Januar",
+            "Februar",
+            "März",
+            "April",
+            "Mai",
+            "Juni",
+            "Juli",
+            "August",
+            "September",
+            "Oktober",
+            "November",
+            "Dezember",
+        ],
+        pattern: "{weekday}, {d}. {month} {year}",
+    },
+    Locale {
+        code: "fr",
+        weekdays: [
+            "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi", "dimanche",
+        ],
+        months: [
+            "janvier",
+            "février",
+            "mars",
+            "avril",
+            "mai",
+            "juin",
+            "juillet",
+            "août",
+            "septembre",
+            "octobre",
+            "novembre",
+            "décembre",
+        ],
+        pattern: "{weekday} {d} {month} {year}",
+    },
+    Locale {
+        code: "es",
+        weekdays: [
+            "lunes",
+            "martes",
+            "miércoles",
+            "jueves",
+            "viernes",
+            "sábado",
+            "domingo",
+        ],
+        months: [
+            "enero",
+            "febrero",
+            "marzo",
+            "abril",
+            "mayo",
+            "junio",
+            "julio",
+            "agosto",
+            "septiembre",
+            "octubre",
+            "noviembre",
+            "diciembre",
+        ],
+        pattern: "{weekday}, {d} de {month} de {year}",
+    },
+    Locale {
+        code: "it",
+        weekdays: [
+            "lunedì",
+            "martedì",
+            "mercoledì",
+            "giovedì",
+            "venerdì",
+            "sabato",
+            "domenica",
+        ],
+        months: [
+            "gennaio",
+            "febbraio",
+            "marzo",
+            "aprile",
+            "maggio",
+            "giugno",
+            "luglio",
+            "agosto",
+            "settembre",
+            "ottobre",
+            "novembre",
+            "dicembre",
+        ],
+        pattern: "{weekday} {d} {month} {year}",
+    },
+    Locale {
+        code: "ru",
+        weekdays: [
+            "понедельник",
+            "вторник",
+            "среда",
+            "четверг",
+            "пятница",
+            "суббота",
+            "воскресенье",
+        ],
+        months: [
+            "января",
+            "февраля",
+            "марта",
+            "апреля",
+            "мая",
+            "июня",
+            "июля",
+            "августа",
+            "сентября",
+            "октября",
+            "ноября",
+            "декабря",
+        ],
+        pattern: "{weekday}, {d} {month} {year}",
+    },
+];
+
+impl Locale {
+    /// Locale of a language tag like `de` or `de-AT`, regional variants share the names
+    pub fn find(tag: &str) -> Option<Self> {
+        let language = tag.split(|c| c == '-' || c == '_').next()?.to_lowercase();
+        LOCALES.iter().find(|l| l.code == language).copied()
+    }
+
+    /// Locale of the first of the preferred languages which has one, the fallback otherwise
+    pub fn preferred(languages: &[String], fallback: Self) -> Self {
+        languages
+            .iter()
+            .find_map(|lang| Self::find(lang))
+            .unwrap_or(fallback)
+    }
+
+    /// Weekday, day, month and year of the date
+    pub fn format_date<D: Datelike>(&self, date: &D) -> String {
+        self.pattern
+            .replace(
+                "{weekday}",
+                self.weekdays[date.weekday().num_days_from_monday() as usize],
+            )
+            .replace("{dd}", &format!("{:02}", date.day()))
+            .replace("{d}", &date.day().to_string())
+            .replace("{month}", self.months[date.month0() as usize])
+            .replace("{year}", &date.year().to_string())
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        ENGLISH
+    }
+}
+
+impl FromStr for Locale {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        Self::find(s).ok_or_else(|| {
+            let codes = LOCALES.iter().map(|l| l.code).collect::<Vec<_>>();
+            format!("unknown locale {}, expected one of {}", s, codes.join(", "))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn format_date() {
+        let date = NaiveDate::from_ymd(2021, 5, 2);
+        assert_eq!(
+            Locale::default().format_date(&date),
+            date.format("%A, %d %B %Y").to_string()
+        );
+        let de = Locale::find("de-AT").unwrap();
+        assert_eq!(de.format_date(&date), "Sonntag, 2. Mai 2021");
+        let ru = "ru".parse::<Locale>().unwrap();
+        assert_eq!(ru.format_date(&date), "воскресенье, 2 мая 2021");
+        assert_eq!(
+            Locale::find("es_MX").unwrap().format_date(&date),
+            "domingo, 2 de mayo de 2021"
+        );
+        assert_eq!(Locale::find("ja"), None);
+        assert!("xx".parse::<Locale>().is_err());
+
+        let languages = vec!["ja".to_string(), "fr-ca".to_string()];
+        assert_eq!(Locale::preferred(&languages, de).code, "fr");
+        assert_eq!(Locale::preferred(&languages[..1], de).code, "de");
+    }
+}
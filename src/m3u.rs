@@ -48,6 +48,15 @@ impl Entry {
             .map_or("", |cap| cap.get(1).unwrap().as_str())
     }
 
+    /// Name of the channel in the guide which is often cleaner than the display name
+    pub fn tvg_name(&self) -> &str {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r#"tvg-name="([^"]*)""#).unwrap();
+        }
+        RE.captures(self.info())
+            .map_or("", |cap| cap.get(1).unwrap().as_str())
+    }
+
     pub fn set_tvg_id(&mut self, tvg_id: &str) {
         lazy_static! {
             static ref RE: Regex = Regex::new(r#"tvg-id="([^"]*)""#).unwrap();
@@ -118,10 +127,16 @@ pub struct Playlist<R: BufRead> {
     current: Entry,
     line_number: u32,
     buf: String,
+    /// `#EXTM3U` line with its attributes like `url-tvg`
+    header: String,
+    /// Comment lines between the header and the first entry
+    comments: Vec<String>,
 }
 
 enum State {
     Header,
+    /// Between the header and the first entry, where comments are kept
+    Comments,
     Body,
 }
 
@@ -175,8 +190,21 @@ impl<R: BufRead> Playlist<R> {
             current: Entry::default(),
             buf: String::new(),
             line_number: 0,
+            header: EXTM3U.to_string(),
+            comments: Vec::new(),
         }
     }
+
+    /// Header line as read, plain `#EXTM3U` until the header is parsed
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    /// Comments at the top of the file read so far
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
     fn make_error(&self, kind: ErrorKind) -> Option<Result<Entry, Error>> {
         Some(Err(Error::M3UError((self.line_number, kind))))
     }
@@ -206,13 +234,22 @@ impl<R: BufRead> Iterator for Playlist<R> {
             match self.st {
                 Header => {
                     if self.buf.starts_with(EXTM3U) {
-                        self.st = Body;
+                        self.header = self.buf.clone();
+                        self.st = Comments;
                     } else {
                         return self.make_error(InvalidHeader);
                     }
                 }
-                Body => {
+                Comments
+                    if self.buf.starts_with('#')
+                        && !self.buf.starts_with(EXTINF)
+                        && !self.buf.starts_with(EXTGRP) =>
+                {
+                    self.comments.push(self.buf.clone());
+                }
+                Comments | Body => {
                     if self.buf.starts_with(EXTINF) {
+                        self.st = Body;
                         if !self.current.info.is_empty() {
                             return self.make_error(ExpectedUrl);
                         }
@@ -246,9 +283,18 @@ pub struct PlaylistWriter {
 
 impl PlaylistWriter {
     pub fn new() -> Self {
+        Self::with_header(EXTM3U, &[])
+    }
+
+    /// Start with the header and comments of a parsed playlist
+    pub fn with_header(header: &str, comments: &[String]) -> Self {
         let mut storage = String::new();
-        storage.push_str(EXTM3U);
+        storage.push_str(header);
         storage.push('\n');
+        for comment in comments {
+            storage.push_str(comment);
+            storage.push('\n');
+        }
         Self { storage: storage }
     }
 
@@ -337,6 +383,25 @@ mod test {
         assert_eq!(String::from(writer), data.replace("\n\n", "\n"));
     }
 
+    #[test]
+    fn header_comments() {
+        let data = indoc!(
+            r#"#EXTM3U url-tvg="http://epg.org/guide.xml" x-tvg-url="http://epg.org/guide.xml"
+        # Provider playlist
+        #EXTINF:0,Foo
+        http://url.com/foo/bar/20.m3u8
+        "#
+        );
+        let mut playlist = Playlist::open(data.as_bytes());
+        let entries = playlist.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(playlist.comments(), &["# Provider playlist".to_string()]);
+
+        let mut writer = PlaylistWriter::with_header(playlist.header(), playlist.comments());
+        writer.push(&entries[0]);
+        assert_eq!(String::from(writer), data);
+    }
+
     #[test]
     fn bad_header() {
         let data = indoc!(
@@ -405,4 +470,21 @@ mod test {
         entry.set_tvg_id("ch");
         assert_eq!(entry.info, "#EXTINF:0 tvg-id=\"ch\",Channel");
     }
+
+    #[test]
+    fn tvg_name() {
+        let data = indoc!(
+            r#"#EXTM3U
+        #EXTINF:0 tvg-name="Foo Bar" tvg-id="fb",Foobar HD
+        http://iptv.com/1.m3u8
+        #EXTINF:0,Channel
+        http://iptv.com/2.m3u8
+        "#
+        );
+        let playlist = Playlist::open(data.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(playlist[0].tvg_name(), "Foo Bar");
+        assert_eq!(playlist[1].tvg_name(), "");
+    }
 }
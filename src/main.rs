@@ -6,14 +6,14 @@ use flate2::bufread::GzDecoder;
 use hyperx::header::HttpDate;
 use iron::prelude::*;
 use iron::status;
+use iron::Handler;
 use mount::Mount;
-use multipart::server::iron::Intercept;
 use playlist::PlaylistModel;
-use reqwest::header::{CONTENT_TYPE, LAST_MODIFIED};
+use reqwest::header::{CONTENT_TYPE, LAST_MODIFIED, RETRY_AFTER};
+use reqwest::StatusCode;
 use router::Router;
 use serde::Serializer;
 use serde_derive::Serialize;
-use staticfile::Static;
 use std::collections::HashMap;
 use std::error::Error;
 use std::io::{BufRead, BufReader};
@@ -21,34 +21,65 @@ use std::panic;
 use std::path::Path;
 use std::str;
 use std::str::FromStr;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time;
 use std::{
     cell::Cell,
+    ffi::OsString,
     time::{Instant, SystemTime, UNIX_EPOCH},
 };
 use urlencoded::UrlEncodedQuery;
 
+mod admin;
+mod archive;
+mod assets;
 mod db;
 mod epg;
+mod feed_auth;
+mod icon_probe;
+mod locale;
 mod m3u;
+mod mapping;
 mod name_match;
+mod now_push;
 mod playlist;
+mod query;
+mod settings;
+mod throttle;
 mod update_status;
 mod utils;
 mod xmltv;
 
-use crate::update_status::UpdateStatus;
-use db::ProgramsDatabase;
-use epg::{ChannelInfo, EpgNow, Program};
-use utils::{bad_request, error_with_status, get_parameter, server_error};
-use xmltv::XmltvReader;
+use crate::update_status::{FeedSource, UpdateStatus};
+use admin::AdminModel;
+use archive::ArchivePolicy;
+use assets::Assets;
+use db::{LoadSummary, Pragmas, ProgramsDatabase, PurgePolicy, Remap, Synchronous};
+use epg::{
+    ChannelInfo, EpgNow, GridRow, NowNext, Program, ProgramFields, ProgramWithId, ProjectedEpgNow,
+    ProjectedProgram, Snapshot, SortedEpgNow, StableEpgNow, StableProgram,
+};
+use locale::Locale;
+use mapping::{AliasMapping, ChannelDurations, ChannelGroups, ChannelSeeds};
+use name_match::{MatchOptions, Metric, VecMatcher};
+use query::{EpgDayQuery, EpgListQuery, GridQuery, NowNextQuery, Query, QueryError};
+use settings::{RootMode, Settings};
+use throttle::{Permit, Semaphore};
+use utils::{
+    accepts_media_type, bad_request, box_error_with_status, error_with_status, get_parameter,
+    normalize_base_path, palette_index, parse_accept_language, parse_duration, parse_retry_after,
+    server_error, write_csv_record, ServerTiming,
+};
+use xmltv::{FeedInfo, XmltvReader, XmltvWriter};
 
 struct LiveCache {
-    data: HashMap<i64, EpgNow>,
+    data: Arc<HashMap<i64, EpgNow>>,
     begin: i64,
     end: i64,
+    /// Incremented by each refresh, so an outdated one does not replace newer data
+    generation: u64,
 }
 
 struct IteratorAdapter<I>(Cell<Option<I>>)
@@ -84,17 +115,108 @@ where
     }
 }
 
+/// `/epg_list` snapshot serialized directly into the response stream
+struct EpgListBody {
+    data: Arc<HashMap<i64, EpgNow>>,
+    ids: Option<Vec<i64>>,
+    /// Write programs in the stable wire format
+    stable: bool,
+    /// Write only these program fields
+    fields: Option<ProgramFields>,
+}
+
+fn write_data<W, I>(writer: W, items: I) -> serde_json::Result<()>
+where
+    W: std::io::Write,
+    I: Iterator,
+    I::Item: serde::Serialize,
+{
+    #[derive(Serialize)]
+    #[serde(bound = "")]
+    struct Data<I>
+    where
+        I: Iterator,
+        I::Item: serde::Serialize,
+    {
+        data: IteratorAdapter<I>,
+    }
+    serde_json::to_writer(
+        writer,
+        &Data {
+            data: IteratorAdapter::new(items),
+        },
+    )
+}
+
+impl EpgListBody {
+    fn write_json<W: std::io::Write>(&self, writer: W) -> serde_json::Result<()> {
+        let channels: Box<dyn Iterator<Item = &EpgNow> + '_> = match &self.ids {
+            Some(ids) => Box::new(ids.iter().filter_map(move |id| self.data.get(id))),
+            None => Box::new(self.data.values()),
+        };
+        if self.stable {
+            write_data(writer, channels.map(StableEpgNow::from))
+        } else if let Some(fields) = &self.fields {
+            write_data(writer, channels.map(|e| ProjectedEpgNow::new(e, fields)))
+        } else {
+            // Channels used to be serialized through `serde_json::Value`, keep the key order
+            write_data(writer, channels.map(SortedEpgNow))
+        }
+    }
+}
+
+/// Items written as newline delimited json, every line is a complete object
+struct NdjsonBody<T>(Vec<T>);
+
+fn write_ndjson<W, I>(mut writer: W, items: I) -> serde_json::Result<()>
+where
+    W: std::io::Write,
+    I: Iterator,
+    I::Item: serde::Serialize,
+{
+    for item in items {
+        serde_json::to_writer(&mut writer, &item)?;
+        writer.write_all(b"\n").map_err(serde_json::Error::io)?;
+    }
+    Ok(())
+}
+
+impl<T: serde::Serialize + Send> iron::response::WriteBody for NdjsonBody<T> {
+    fn write_body(&mut self, res: &mut dyn std::io::Write) -> std::io::Result<()> {
+        write_ndjson(res, self.0.iter()).map_err(|e| e.into())
+    }
+}
+
+/// Newline delimited json body with its content type, which replaces the json one
+/// of `json_response` and `epg_response`
+fn ndjson_body<T>(items: Vec<T>) -> (iron::mime::Mime, Box<dyn iron::response::WriteBody>)
+where
+    T: serde::Serialize + Send + 'static,
+{
+    (
+        "application/x-ndjson".parse().unwrap(),
+        Box::new(NdjsonBody(items)),
+    )
+}
+
+impl iron::response::WriteBody for EpgListBody {
+    fn write_body(&mut self, res: &mut dyn std::io::Write) -> std::io::Result<()> {
+        self.write_json(res).map_err(|e| e.into())
+    }
+}
+
 impl LiveCache {
     fn new() -> Self {
         LiveCache {
-            data: HashMap::new(),
+            data: Arc::new(HashMap::new()),
             begin: 0,
             end: 0,
+            generation: 0,
         }
     }
 
     fn set_data(&mut self, data: HashMap<i64, EpgNow>, t: i64) {
-        self.data = data;
+        self.data = Arc::new(data);
         self.recalculate(t);
     }
 
@@ -122,75 +244,426 @@ impl LiveCache {
         (self.begin <= t && t <= self.end) && !self.data.is_empty()
     }
 
-    fn to_json(&self, ids: Option<&[i64]>) -> Result<String, serde_json::Error> {
-        serde_json::to_string(&match ids {
-            Some(ids) => serde_json::json!({
-                 "data": IteratorAdapter::new(
-                    ids.iter().filter_map(|id| self.data.get(id)),
-                )
-            }),
-            None => serde_json::json!({ "data": IteratorAdapter::new(
-               self.data.values()),
-            }),
-        })
+    /// Shares the cached data with the response body, which serializes it later
+    fn snapshot(&self, ids: Option<&[i64]>) -> EpgListBody {
+        EpgListBody {
+            data: self.data.clone(),
+            ids: ids.map(|ids| ids.to_vec()),
+            stable: false,
+            fields: None,
+        }
     }
 
     fn clear(&mut self) {
         self.begin = 0;
         self.end = 0;
-        self.data.clear();
+        self.data = Arc::new(HashMap::new());
     }
 }
 
+/// Channel names indexed for similarity search
+struct ChannelCorpus {
+    channels: Vec<(i64, ChannelInfo)>,
+    matcher: VecMatcher,
+}
+
 struct EpgSqlServer {
-    cache: RwLock<LiveCache>,
+    /// Shared with the refresh in the background, see `invalidate_cache`
+    cache: Arc<RwLock<LiveCache>>,
+    /// Built on first search and dropped after each update
+    corpus: Mutex<Option<ChannelCorpus>>,
+    /// Limits concurrent playlist matching which is heavy on CPU
+    match_limiter: Semaphore,
     db: ProgramsDatabase,
+    /// Replaced as a whole when the config is reloaded
+    settings: RwLock<Arc<Settings>>,
+    /// Set once a feed was loaded, empty programs before mean the server is warming up
+    ready: AtomicBool,
+}
+
+/// Apply options of the settings which are used by the database
+fn configure_db(db: &mut ProgramsDatabase, settings: &Settings) {
+    db.set_max_programs_at(settings.max_programs_at);
+    db.set_alias_mapping(settings.alias_mapping.clone());
+    db.set_purge_policy(settings.purge_policy.clone());
+    db.set_description_limit(settings.description_limit);
+    db.set_max_days_ahead(settings.max_days_ahead);
+    db.set_incremental(settings.incremental_load);
+    db.set_coalesce(settings.coalesce_programs);
+    db.set_duplicate_begins(settings.duplicate_begins);
+    db.set_compress_descriptions(settings.compress_descriptions);
+    db.set_channel_seeds(settings.channel_seeds.clone());
 }
 
 type ServerResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
 
 impl EpgSqlServer {
-    fn new(file: &str) -> Self {
+    /// Upper limit for the number of aliases in one `/resolve` request
+    const MAX_RESOLVE_ALIASES: usize = 1000;
+    /// How long a playlist matching request waits for its turn
+    const MATCH_QUEUE_TIMEOUT: time::Duration = time::Duration::from_secs(15);
+    /// Upper limit for the number of programs found by `/search`
+    const MAX_SEARCH_PROGRAMS: i64 = 500;
+
+    fn new(file: &str, key: Option<&str>, settings: Settings) -> Self {
+        let mut db = ProgramsDatabase::open_with_pragmas(&file, key, settings.db_pragmas.clone())
+            .expect("Failed to open database");
+        match db.effective_pragmas() {
+            Ok(pragmas) => println!("Database pragmas {:?}", pragmas),
+            Err(e) => eprintln!("Failed to read database pragmas: {}", e),
+        }
+        configure_db(&mut db, &settings);
+        db.seed_channels().expect("Failed to seed channels");
+        let ready = db.has_loaded_feed().unwrap_or_else(|e| {
+            eprintln!("Failed to read update log: {}", e);
+            false
+        });
         Self {
-            cache: RwLock::new(LiveCache::new()),
-            db: ProgramsDatabase::open(&file).expect("Failed to open database"),
+            cache: Arc::new(RwLock::new(LiveCache::new())),
+            corpus: Mutex::new(None),
+            match_limiter: Semaphore::new(settings.max_match_concurrency),
+            db,
+            settings: RwLock::new(Arc::new(settings)),
+            ready: AtomicBool::new(ready),
+        }
+    }
+
+    /// Whether a feed was loaded, so empty programs mean nothing is scheduled
+    fn is_initialized(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    /// Current settings, a reload does not affect the returned ones
+    fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap().clone()
+    }
+
+    /// Database with load options of the current settings
+    fn configured_db(&self, settings: &Settings) -> ProgramsDatabase {
+        let mut db = self.db.clone();
+        configure_db(&mut db, settings);
+        db
+    }
+
+    /// Database configured for loading feeds, with the allowlist of the playlist
+    /// matched against the current channels
+    fn loading_db(&self, settings: &Settings) -> ServerResult<ProgramsDatabase> {
+        let mut db = self.configured_db(settings);
+        if let Some(path) = &settings.allowlist_playlist {
+            let file = BufReader::new(std::fs::File::open(path)?);
+            let channels = self
+                .db
+                .get_channels()?
+                .into_iter()
+                .map(|(_, c)| c)
+                .collect::<Vec<_>>();
+            let aliases = playlist::playlist_aliases(file, &channels, settings.match_options)?;
+            println!(
+                "Loading programs of {} channels of the playlist {}",
+                aliases.len(),
+                path.display()
+            );
+            db.set_channel_allowlist(Some(aliases));
+        }
+        Ok(db)
+    }
+
+    /// Re-read the config file and replace the settings,
+    /// returns names of changed settings which need a restart and were kept
+    fn reload_settings(&self) -> Result<Vec<&'static str>, String> {
+        let current = self.settings();
+        let path = current
+            .config_file
+            .as_ref()
+            .ok_or_else(|| "No config file is given".to_string())?;
+        let args = matches_with_config(std::env::args_os(), path)?;
+        let mut settings = parse_settings(&args)?;
+
+        let mut kept = Vec::new();
+        if settings.base_path != current.base_path {
+            kept.push("base-path");
+        }
+        if settings.ws_port != current.ws_port {
+            kept.push("ws-port");
+        }
+        if settings.icon_probe_interval != current.icon_probe_interval {
+            kept.push("icon-probe-interval");
+        }
+        if settings.max_match_concurrency != current.max_match_concurrency {
+            kept.push("max-match-concurrency");
         }
+        if settings.max_programs_at != current.max_programs_at {
+            kept.push("max-programs-at");
+        }
+        if settings.db_pragmas != current.db_pragmas {
+            kept.push("db-pragmas");
+        }
+        settings.base_path = current.base_path.clone();
+        settings.ws_port = current.ws_port;
+        settings.icon_probe_interval = current.icon_probe_interval;
+        settings.max_match_concurrency = current.max_match_concurrency;
+        settings.max_programs_at = current.max_programs_at;
+        settings.db_pragmas = current.db_pragmas.clone();
+        settings.config_file = current.config_file.clone();
+
+        *self.settings.write().unwrap() = Arc::new(settings);
+        // Cached responses may depend on the old settings
+        self.invalidate_cache();
+        *self.corpus.lock().unwrap() = None;
+        println!("Reloaded settings from {}", path);
+        Ok(kept)
     }
 
-    fn update_data<R: BufRead>(&self, xmltv: XmltvReader<R>) -> ServerResult<()> {
+    fn update_data<R: BufRead>(
+        &self,
+        xmltv: XmltvReader<R>,
+        dry_run: bool,
+    ) -> ServerResult<LoadSummary> {
         let t = Instant::now();
+        let settings = self.settings();
+        let db = self.loading_db(&settings)?;
+        let xmltv = configure_reader(xmltv, &settings);
 
         // Load new data
-        self.db.load_xmltv(xmltv)?;
-        self.cache.write().unwrap().clear();
+        let summary = if settings.staging && !dry_run {
+            db.load_xmltv_staged(xmltv)?
+        } else {
+            db.load_xmltv(xmltv, dry_run)?
+        };
+        if !dry_run {
+            self.invalidate_cache();
+            *self.corpus.lock().unwrap() = None;
+            self.ready.store(true, Ordering::Relaxed);
+        }
 
         println!("Database transactions took {:?}", t.elapsed());
-        Ok(())
+        Ok(summary)
+    }
+
+    /// Stage each of the feeds and merge them at once like parts of an incremental load,
+    /// a feed which fails keeps the programs read before the error
+    fn import_feeds<'a>(
+        &self,
+        feeds: Vec<(String, Box<dyn BufRead + 'a>)>,
+    ) -> ServerResult<FeedImport> {
+        let t = Instant::now();
+        let settings = self.settings();
+        let mut db = self.loading_db(&settings)?;
+        db.set_incremental(true);
+        let mut import = FeedImport::default();
+        for (name, reader) in feeds {
+            let xmltv = configure_reader(XmltvReader::new(reader), &settings);
+            import.files.push(match db.load_xmltv(xmltv, false) {
+                Ok(summary) => ImportedFile {
+                    name,
+                    channels: summary.channels,
+                    programs: summary.programs,
+                    skipped: summary.skipped,
+                    error: None,
+                },
+                Err(e) => ImportedFile {
+                    name,
+                    error: Some(e.to_string()),
+                    ..ImportedFile::default()
+                },
+            });
+        }
+        import.merged = db.finalize_load()?;
+        self.invalidate_cache();
+        *self.corpus.lock().unwrap() = None;
+        self.ready.store(true, Ordering::Relaxed);
+        println!(
+            "Imported {} feeds with {} programs in {:?}",
+            import.files.len(),
+            import.merged,
+            t.elapsed()
+        );
+        Ok(import)
+    }
+
+    /// Drop the `/epg_list` snapshot after the database changed. With `serve_stale`
+    /// the old snapshot is served until the new one is read in the background,
+    /// instead of every client waiting for the database at once.
+    fn invalidate_cache(&self) {
+        if !self.settings().serve_stale {
+            self.cache.write().unwrap().clear();
+            return;
+        }
+        let generation = {
+            let mut cache = self.cache.write().unwrap();
+            cache.generation += 1;
+            cache.generation
+        };
+        let (cache, db) = (self.cache.clone(), self.db.clone());
+        thread::spawn(move || {
+            let t = Utc::now().timestamp();
+            let data = db.get_at(t, 2);
+            let mut cache = cache.write().unwrap();
+            if cache.generation != generation {
+                // A later refresh reads newer data
+                return;
+            }
+            match data {
+                Ok(data) => cache.set_data(data, t),
+                Err(e) => {
+                    eprintln!("Failed to refresh cache: {}", e);
+                    cache.clear();
+                }
+            }
+        });
+    }
+
+    /// Change the alias of a channel, see `ProgramsDatabase::remap_alias`
+    fn remap_alias(&self, old_alias: &str, new_alias: &str, merge: bool) -> ServerResult<Remap> {
+        let remap = self
+            .db
+            .remap_alias(old_alias, new_alias, merge, Utc::now().timestamp())?;
+        if let Remap::Renamed | Remap::Merged { .. } = remap {
+            self.invalidate_cache();
+            *self.corpus.lock().unwrap() = None;
+        }
+        Ok(remap)
+    }
+
+    /// Restore a deleted channel, returns whether there was one with the id
+    fn undelete_channel(&self, id: i64) -> ServerResult<bool> {
+        let restored = self.db.undelete_channel(id)?;
+        if restored {
+            self.invalidate_cache();
+            *self.corpus.lock().unwrap() = None;
+        }
+        Ok(restored)
+    }
+
+    /// Merge feed parts staged by incremental loads, returns the number of merged programs
+    fn finalize_update(&self) -> ServerResult<i64> {
+        let t = Instant::now();
+        let merged = self.configured_db(&self.settings()).finalize_load()?;
+        self.invalidate_cache();
+        *self.corpus.lock().unwrap() = None;
+        println!("Finalized {} staged programs in {:?}", merged, t.elapsed());
+        Ok(merged)
+    }
+
+    /// Rebuild database indexes, returns how long it took
+    fn rebuild_indexes(&self) -> ServerResult<time::Duration> {
+        let t = Instant::now();
+        self.db.rebuild_indexes()?;
+        let elapsed = t.elapsed();
+        println!("Rebuilt indexes in {:?}", elapsed);
+        Ok(elapsed)
     }
 
-    fn get_epg_day(&self, id: i64, date: chrono::Date<Utc>) -> ServerResult<Vec<Program>> {
+    /// Programs which begin within the broadcast day of the date, see `broadcast_day`
+    fn get_epg_day<Tz: TimeZone>(
+        &self,
+        id: i64,
+        date: chrono::Date<Tz>,
+    ) -> ServerResult<Vec<Program>>
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        let list = self.get_epg_day_with_ids(id, date)?;
+        Ok(list.into_iter().map(|(_, program)| program).collect())
+    }
+
+    /// Same as `get_epg_day`, each program with its id
+    fn get_epg_day_with_ids<Tz: TimeZone>(
+        &self,
+        id: i64,
+        date: chrono::Date<Tz>,
+    ) -> ServerResult<Vec<(i64, Program)>>
+    where
+        Tz::Offset: std::fmt::Display,
+    {
         println!("get_epg_day {} {}", id, date);
-        let a = date.and_hms(0, 0, 0).timestamp();
-        let b = date.and_hms(23, 59, 59).timestamp();
-        self.db.get_range(id, a, b).map_err(|e| e.into())
+        let (a, b) = broadcast_day(&date, self.settings().broadcast_day_start);
+        self.db.get_range_with_ids(id, a, b).map_err(|e| e.into())
     }
 
     fn get_epg_list(
         &self,
         time: chrono::DateTime<Utc>,
         ids: Option<&[i64]>,
-    ) -> ServerResult<String> {
+        timing: &mut ServerTiming,
+    ) -> ServerResult<EpgListBody> {
         let t = time.timestamp();
         let cache = self.cache.read().unwrap();
         if cache.contains_time(t) {
             println!("Using value from cache");
-            cache.to_json(ids).map_err(|e| e.into())
+            Ok(cache.snapshot(ids))
         } else {
             drop(cache);
             let mut cache = self.cache.write().unwrap();
-            cache.set_data(self.db.get_at(t, 2)?, t);
-            cache.to_json(ids).map_err(|e| e.into())
+            let data = timing.measure("db", || self.db.get_at(t, 2))?;
+            cache.set_data(data, t);
+            Ok(cache.snapshot(ids))
+        }
+    }
+
+    /// Snapshot with up to `lookback` ended programs per channel, bypasses the cache
+    fn get_epg_list_lookback(
+        &self,
+        time: chrono::DateTime<Utc>,
+        ids: Option<&[i64]>,
+        lookback: i64,
+        timing: &mut ServerTiming,
+    ) -> ServerResult<EpgListBody> {
+        let data = timing.measure("db", || {
+            self.db.get_at_with_lookback(time.timestamp(), 2, lookback)
+        })?;
+        Ok(EpgListBody {
+            data: Arc::new(data),
+            ids: ids.map(|ids| ids.to_vec()),
+            stable: false,
+            fields: None,
+        })
+    }
+
+    /// Programs of the channels overlapping with the time window, bypasses the cache
+    fn get_epg_window(
+        &self,
+        time: chrono::DateTime<Utc>,
+        window: chrono::Duration,
+        ids: Option<&[i64]>,
+        clamp: bool,
+        stable: bool,
+        fields: Option<&ProgramFields>,
+        timing: &mut ServerTiming,
+    ) -> ServerResult<String> {
+        let t = time.timestamp();
+        let end = t + window.num_seconds();
+        let mut data = timing.measure("db", || self.db.get_window(t, end, ids))?;
+        if clamp {
+            for program in data.values_mut().flat_map(|e| e.programs.iter_mut()) {
+                program.clamp(t, end);
+            }
         }
+        timing
+            .measure("serialize", || {
+                let list = match ids {
+                    Some(ids) => ids.iter().filter_map(|id| data.remove(id)).collect(),
+                    None => {
+                        let mut list = data.into_iter().map(|(_, e)| e).collect::<Vec<_>>();
+                        list.sort_by_key(|e| e.channel_id);
+                        list
+                    }
+                };
+                if stable {
+                    let mut out = Vec::new();
+                    write_data(&mut out, list.iter().map(StableEpgNow::from))?;
+                    Ok(String::from_utf8(out).unwrap())
+                } else if let Some(fields) = fields {
+                    let list = list
+                        .iter()
+                        .map(|e| ProjectedEpgNow::new(e, fields))
+                        .collect::<Vec<_>>();
+                    serde_json::to_string(&serde_json::json!({ "data": list }))
+                } else {
+                    serde_json::to_string(&serde_json::json!({ "data": list }))
+                }
+            })
+            .map_err(|e| e.into())
     }
 
     fn find_channel(&self, id: i64) -> ServerResult<Option<ChannelInfo>> {
@@ -205,15 +678,76 @@ impl EpgSqlServer {
         self.db.get_channel_by_alias(alias).map_err(|e| e.into())
     }
 
-    fn get_channels(&self) -> ServerResult<Vec<(i64, ChannelInfo)>> {
-        let mut vec = self.db.get_channels()?;
-        vec.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+    /// All channels, `active` leaves only those which air a program now
+    /// and `name_contains` those with the text in their name
+    fn list_channels(
+        &self,
+        active: bool,
+        name_contains: Option<&str>,
+    ) -> rusqlite::Result<Vec<(i64, ChannelInfo)>> {
+        let mut channels = if active {
+            self.db.get_active_channels(Utc::now().timestamp())?
+        } else {
+            self.db.get_channels()?
+        };
+        if let Some(text) = name_contains {
+            retain_name_contains(&mut channels, text);
+        }
+        Ok(channels)
+    }
+
+    fn get_channels(
+        &self,
+        active: bool,
+        name_contains: Option<&str>,
+    ) -> ServerResult<Vec<(i64, ChannelInfo)>> {
+        let mut vec = self.list_channels(active, name_contains)?;
+        sort_channels(&mut vec, &self.settings().pinned_channels);
         Ok(vec)
     }
 
-    fn get_channels_alias(&self) -> ServerResult<HashMap<String, i64>> {
-        self.db
-            .get_channels()
+    /// Channels in the order of `get_channels` with the titles of their programs on air
+    /// in the first available of the languages
+    fn get_channels_with_now(
+        &self,
+        active: bool,
+        name_contains: Option<&str>,
+        languages: &[String],
+    ) -> ServerResult<Vec<(i64, ChannelInfo, Option<String>)>> {
+        let rows = self.db.get_channels_with_now(Utc::now().timestamp())?;
+        let mut titles = HashMap::with_capacity(rows.len());
+        let mut channels = Vec::with_capacity(rows.len());
+        for (id, channel, now) in rows {
+            // Active channels are the ones with a program on air
+            if active && now.is_none() {
+                continue;
+            }
+            let title = now.map(|mut program| {
+                program.localize(languages);
+                program.title
+            });
+            titles.insert(id, title);
+            channels.push((id, channel));
+        }
+        if let Some(text) = name_contains {
+            retain_name_contains(&mut channels, text);
+        }
+        sort_channels(&mut channels, &self.settings().pinned_channels);
+        Ok(channels
+            .into_iter()
+            .map(|(id, channel)| {
+                let title = titles.remove(&id).flatten();
+                (id, channel, title)
+            })
+            .collect())
+    }
+
+    fn get_channels_alias(
+        &self,
+        active: bool,
+        name_contains: Option<&str>,
+    ) -> ServerResult<HashMap<String, i64>> {
+        self.list_channels(active, name_contains)
             .map(|vec| {
                 vec.into_iter()
                     .map(|(id, channel)| (channel.alias, id))
@@ -222,9 +756,12 @@ impl EpgSqlServer {
             .map_err(|e| e.into())
     }
 
-    fn get_channels_name(&self) -> ServerResult<HashMap<String, i64>> {
-        self.db
-            .get_channels()
+    fn get_channels_name(
+        &self,
+        active: bool,
+        name_contains: Option<&str>,
+    ) -> ServerResult<HashMap<String, i64>> {
+        self.list_channels(active, name_contains)
             .map(|vec| {
                 vec.into_iter()
                     .map(|(id, channel)| (channel.name, id))
@@ -232,51 +769,312 @@ impl EpgSqlServer {
             })
             .map_err(|e| e.into())
     }
+
+    /// Export all stored channels and programs as XMLTV document,
+    /// `with_aliases` adds channel alias as an alternative display name
+    fn export_xmltv(&self, with_aliases: bool) -> ServerResult<Vec<u8>> {
+        let info = FeedInfo {
+            generator_name: env!("CARGO_PKG_NAME").to_string(),
+            generator_url: String::new(),
+            date: Utc::now().format("%Y%m%d%H%M%S +0000").to_string(),
+        };
+        let mut writer = XmltvWriter::new(Vec::new(), &info)?;
+        let channels = self.get_channels(false, None)?;
+        for (_, channel) in channels.iter() {
+            writer.write_channel(channel, with_aliases)?;
+        }
+        for (id, channel) in channels.iter() {
+            for program in self
+                .db
+                .get_overlapping(*id, i64::min_value(), i64::max_value())?
+            {
+                writer.write_program(&channel.alias, &program)?;
+            }
+        }
+        Ok(writer.finish()?)
+    }
+
+    /// Find channels with names similar to the query, best matches first
+    fn search_channels(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> ServerResult<Vec<(i64, ChannelInfo, f32)>> {
+        let mut corpus = self.load_corpus()?;
+        let corpus = match corpus.as_mut() {
+            Some(corpus) => corpus,
+            None => return Ok(Vec::new()),
+        };
+        let found = corpus.matcher.search(query, playlist::SIM_POSSIBLE, limit);
+        Ok(found
+            .into_iter()
+            .map(|(index, sim)| {
+                let (id, channel) = &corpus.channels[index];
+                (*id, channel.clone(), sim)
+            })
+            .collect())
+    }
+
+    /// Ids of the channels which alias starts with `prefix`, a trailing `*` is ignored
+    fn find_ids_by_alias_prefix(&self, prefix: &str) -> ServerResult<Vec<i64>> {
+        let prefix = prefix.trim_end_matches('*');
+        let corpus = self.load_corpus()?;
+        Ok(corpus.as_ref().map_or_else(Vec::new, |corpus| {
+            corpus
+                .channels
+                .iter()
+                .filter(|(_, c)| c.alias.starts_with(prefix))
+                .map(|&(id, _)| id)
+                .collect()
+        }))
+    }
+
+    /// Ids of the channels of the configured group in its order, channels which are not
+    /// in the database are left out, `None` for an unknown group
+    fn find_ids_by_group(&self, group: &str) -> ServerResult<Option<Vec<i64>>> {
+        let settings = self.settings();
+        let aliases = match settings.channel_groups.get(group) {
+            Some(aliases) => aliases,
+            None => return Ok(None),
+        };
+        let aliases = aliases.iter().map(String::as_str).collect::<Vec<_>>();
+        let known = self.resolve_aliases(&aliases)?;
+        Ok(Some(
+            aliases
+                .iter()
+                .filter_map(|&alias| known.get(alias).copied().flatten())
+                .collect(),
+        ))
+    }
+
+    /// Ids of the given aliases, unknown aliases are mapped to `None`
+    fn resolve_aliases(&self, aliases: &[&str]) -> ServerResult<HashMap<String, Option<i64>>> {
+        let corpus = self.load_corpus()?;
+        let known = corpus.as_ref().map_or_else(HashMap::new, |corpus| {
+            corpus
+                .channels
+                .iter()
+                .map(|(id, c)| (c.alias.as_str(), *id))
+                .collect::<HashMap<_, _>>()
+        });
+        Ok(aliases
+            .iter()
+            .map(|&alias| (alias.to_string(), known.get(alias).copied()))
+            .collect())
+    }
+
+    /// Turn to run the matcher, `503` when other requests keep it busy for too long
+    fn acquire_match_permit(&self) -> IronResult<Permit> {
+        self.match_limiter
+            .acquire_timeout(Self::MATCH_QUEUE_TIMEOUT)
+            .ok_or_else(|| {
+                box_error_with_status(
+                    Box::<dyn Error + Send + Sync>::from(
+                        "Too many playlists are being matched, try again later",
+                    ),
+                    status::ServiceUnavailable,
+                )
+            })
+    }
+
+    /// Cached channels list, `None` when there are no channels yet
+    fn load_corpus(&self) -> ServerResult<std::sync::MutexGuard<Option<ChannelCorpus>>> {
+        let mut corpus = self.corpus.lock().unwrap();
+        if corpus.is_none() {
+            let channels = self.db.get_channels()?;
+            if channels.is_empty() {
+                return Ok(corpus);
+            }
+            let dataset = channels
+                .iter()
+                .map(|(_, c)| c.name.clone())
+                .collect::<Vec<_>>();
+            let matcher = VecMatcher::with_options(&dataset, 2, self.settings().match_options);
+            *corpus = Some(ChannelCorpus { channels, matcher });
+        }
+        Ok(corpus)
+    }
+}
+
+/// Feed provider responded with an error status
+#[derive(Debug)]
+struct FeedHttpError {
+    status: StatusCode,
+    /// Delay before the next attempt, when it differs from the regular schedule
+    retry_after: Option<time::Duration>,
+}
+
+impl FeedHttpError {
+    /// Backoff after temporary server errors
+    const SERVER_ERROR_DELAY: time::Duration = time::Duration::from_secs(10 * 60);
+    /// Backoff after `429` without `Retry-After`
+    const TOO_MANY_REQUESTS_DELAY: time::Duration = time::Duration::from_secs(30 * 60);
+    /// Shortest backoff, `Retry-After: 0` or a past date must not make the updater spin
+    const MIN_RETRY_DELAY: time::Duration = time::Duration::from_secs(60);
+    /// Longest backoff, the regular update interval, so a far `Retry-After` does not stop updates
+    const MAX_RETRY_DELAY: time::Duration = time::Duration::from_secs(3 * 60 * 60);
+
+    fn new(status: StatusCode, retry_after: Option<&str>) -> Self {
+        let retry_after = if status == StatusCode::TOO_MANY_REQUESTS {
+            Some(
+                retry_after
+                    .and_then(|s| parse_retry_after(s, SystemTime::now()))
+                    .unwrap_or(Self::TOO_MANY_REQUESTS_DELAY)
+                    .max(Self::MIN_RETRY_DELAY)
+                    .min(Self::MAX_RETRY_DELAY),
+            )
+        } else if status.is_server_error() {
+            Some(Self::SERVER_ERROR_DELAY)
+        } else {
+            None
+        };
+        Self {
+            status,
+            retry_after,
+        }
+    }
+
+    /// Error which won't go away without fixing configuration, e.g. wrong url
+    fn is_fatal(&self) -> bool {
+        self.status.is_client_error() && self.status != StatusCode::TOO_MANY_REQUESTS
+    }
+}
+
+impl std::fmt::Display for FeedHttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_fatal() {
+            write!(
+                f,
+                "Feed request rejected with HTTP {}, check the url",
+                self.status
+            )
+        } else {
+            write!(f, "Feed server responded with HTTP {}", self.status)?;
+            if let Some(delay) = self.retry_after {
+                write!(f, ", retry in {} s", delay.as_secs())?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl Error for FeedHttpError {}
+
+/// Whether the feed could not be fetched at all, as opposed to a feed which failed to load
+fn is_download_error(e: &(dyn Error + Send + Sync + 'static)) -> bool {
+    e.is::<FeedHttpError>() || e.is::<reqwest::Error>() || e.is::<std::io::Error>()
 }
 
 struct EpgUpdaterWorker {
     epg_db: Arc<EpgSqlServer>,
     url: String,
-    /// Timestamp of recently parsed xmltv data
+    /// Feed url tried when the primary one fails
+    fallback_url: Option<String>,
+    /// Timestamp of recently parsed xmltv data of the primary url
     last_modified: HttpDate,
+    /// Timestamp of recently parsed xmltv data of the fallback url
+    fallback_modified: HttpDate,
+    /// Consecutive updates which failed to download the primary url
+    primary_failures: u32,
+    /// Provenance of recently parsed xmltv data
+    feed: FeedInfo,
+    /// Overlapping programs of recently parsed xmltv data
+    overlaps: i64,
+    /// Parse feed without writing to the database
+    dry_run: bool,
 }
 
 impl EpgUpdaterWorker {
+    /// Failed downloads of the primary url in a row before the fallback url is tried
+    const FALLBACK_AFTER_FAILURES: u32 = 3;
+
     fn new(epg_db: Arc<EpgSqlServer>, url: String) -> Self {
-        let last_modified: HttpDate = epg_db
-            .db
-            .get_last_update()
-            .unwrap_or_else(|err| {
-                eprintln!("Error in get status {}", err);
-                None
-            })
-            .map_or(UNIX_EPOCH, |st| st.last_modified.into())
-            .into();
+        let last_update = epg_db.db.get_last_update().unwrap_or_else(|err| {
+            eprintln!("Error in get status {}", err);
+            None
+        });
+        let modified = |source| -> HttpDate {
+            epg_db
+                .db
+                .get_last_modified(source)
+                .unwrap_or_else(|err| {
+                    eprintln!("Error in get status {}", err);
+                    None
+                })
+                .map_or(UNIX_EPOCH, SystemTime::from)
+                .into()
+        };
+        let last_modified = modified(FeedSource::Primary);
+        let fallback_modified = modified(FeedSource::Fallback);
         println!("Last update has file modified at {}", last_modified);
         Self {
             epg_db,
             url,
+            fallback_url: None,
             last_modified,
+            fallback_modified,
+            primary_failures: 0,
+            overlaps: last_update.as_ref().map_or(0, |st| st.overlaps),
+            feed: last_update.map(|st| st.feed).unwrap_or_default(),
+            dry_run: false,
         }
     }
 
+    fn with_fallback_url(mut self, url: Option<String>) -> Self {
+        self.fallback_url = url;
+        self
+    }
+
     fn run(mut self) -> thread::JoinHandle<()> {
         use rand::Rng;
         thread::spawn(move || loop {
-            self.update();
-            let minute = rand::thread_rng().gen_range(0..30);
-            thread::sleep(time::Duration::from_secs((3 * 60 + minute) * 60));
+            let delay = self.update().unwrap_or_else(|| {
+                let minute = rand::thread_rng().gen_range(0..30);
+                time::Duration::from_secs((3 * 60 + minute) * 60)
+            });
+            thread::sleep(delay);
         })
     }
 
-    fn update(&mut self) {
+    /// Returns delay before the next attempt when the provider asks to back off
+    fn update(&mut self) -> Option<time::Duration> {
+        let mut retry_after = None;
         // Catch panics, so that `run()` continues to retry even when thread panics
         let st = match panic::catch_unwind(|| self.perform_update()) {
-            Ok(Ok(t)) => {
-                self.last_modified = t;
-                UpdateStatus::new_ok(Utc::now(), SystemTime::from(self.last_modified).into())
+            Ok(Ok((source, t, summary))) => {
+                match source {
+                    FeedSource::Primary => {
+                        self.last_modified = t;
+                        self.primary_failures = 0;
+                    }
+                    FeedSource::Fallback => {
+                        self.fallback_modified = t;
+                        self.primary_failures = self.primary_failures.saturating_add(1);
+                    }
+                }
+                if let Some(summary) = summary {
+                    self.feed = summary.feed;
+                    self.overlaps = summary.overlaps as i64;
+                }
+                let mut st = UpdateStatus::new_ok(Utc::now(), SystemTime::from(t).into());
+                st.feed = self.feed.clone();
+                st.overlaps = self.overlaps;
+                st.source = source.as_str().to_string();
+                st
             }
             Ok(Err(e)) => {
+                // The primary url is reachable when only its feed failed to load
+                self.primary_failures = if is_download_error(&*e) {
+                    self.primary_failures.saturating_add(1)
+                } else {
+                    0
+                };
+                if let Some(http) = e.downcast_ref::<FeedHttpError>() {
+                    if http.is_fatal() {
+                        eprintln!("!!! {} !!!", http);
+                    }
+                    retry_after = http.retry_after;
+                }
                 eprintln!("Failed to update epg {}", e);
                 UpdateStatus::new_fail(Utc::now(), e.to_string())
             }
@@ -289,18 +1087,74 @@ impl EpgUpdaterWorker {
             .db
             .insert_update_status(st)
             .unwrap_or_else(|e| eprintln!("Error in insert status {}", e));
+        retry_after
+    }
+
+    /// Download and parse the feed without writing to the database
+    fn validate(mut self) -> ServerResult<LoadSummary> {
+        self.dry_run = true;
+        self.last_modified = HttpDate::from(UNIX_EPOCH);
+        self.fallback_modified = HttpDate::from(UNIX_EPOCH);
+        let (_, _, summary) = self.perform_update()?;
+        summary.ok_or_else(|| "Feed was not loaded".into())
     }
 
-    fn perform_update(&self) -> ServerResult<HttpDate> {
-        static APP_USER_AGENT: &str =
-            concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+    /// Load new xmltv data when it is available, from the fallback url when the primary
+    /// could not be downloaded several times in a row,
+    /// returns the source, modification time and summary of the loaded feed
+    fn perform_update(&self) -> ServerResult<(FeedSource, HttpDate, Option<LoadSummary>)> {
+        let error = match self.update_from(&self.url, self.last_modified) {
+            Ok((t, summary)) => return Ok((FeedSource::Primary, t, summary)),
+            Err(e) => e,
+        };
+        let fallback = match &self.fallback_url {
+            Some(url) if is_download_error(&*error) => url,
+            _ => return Err(error),
+        };
+        if self.primary_failures + 1 < Self::FALLBACK_AFTER_FAILURES {
+            eprintln!(
+                "Failed to download epg {}, {} failures before trying fallback url",
+                error,
+                Self::FALLBACK_AFTER_FAILURES - self.primary_failures - 1
+            );
+            return Err(error);
+        }
+        eprintln!("Failed to update epg {}, trying fallback url", error);
+        match self.update_from(fallback, self.fallback_modified) {
+            Ok((t, summary)) => Ok((FeedSource::Fallback, t, summary)),
+            Err(e) => {
+                eprintln!("Failed to update epg from fallback url {}", e);
+                Err(error)
+            }
+        }
+    }
 
+    /// Load the feed of the url when it was modified after the time
+    fn update_from(
+        &self,
+        url: &str,
+        last_modified: HttpDate,
+    ) -> ServerResult<(HttpDate, Option<LoadSummary>)> {
         println!("check for new epg");
+        if let Some(path) = local_feed_path(url) {
+            return self.update_from_file(path, last_modified);
+        }
         let client = reqwest::blocking::Client::builder()
-            .user_agent(APP_USER_AGENT)
+            .user_agent(self.epg_db.settings().user_agent.as_str())
             .gzip(true)
             .build()?;
-        let result = client.get(&self.url).send()?;
+        let mut request = client.get(url);
+        if let Some(auth) = &self.epg_db.settings().feed_auth {
+            request = auth.apply(request);
+        }
+        let result = request.send()?;
+        if !result.status().is_success() {
+            let retry_after = result
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok());
+            return Err(Box::new(FeedHttpError::new(result.status(), retry_after)));
+        }
         let t = result
             .headers()
             .get(LAST_MODIFIED)
@@ -308,7 +1162,8 @@ impl EpgUpdaterWorker {
             .and_then(|s| HttpDate::from_str(s).ok())
             .unwrap_or(HttpDate::from(SystemTime::now()));
         println!("last modified {}", t);
-        if t > self.last_modified {
+        let mut summary = None;
+        if t > last_modified {
             println!("loading xmltv");
             let mut zipped = true;
             use mime::Mime;
@@ -323,7 +1178,7 @@ impl EpgUpdaterWorker {
                     (_, mime::XML) => zipped = false,
                     _ => {
                         // hack to support urls with wrong content-type
-                        if self.url.ends_with("xmltv") {
+                        if url.ends_with("xmltv") {
                             println!("url ends with 'xmltv' assuming unzipped xml content");
                             zipped = false;
                         }
@@ -336,12 +1191,104 @@ impl EpgUpdaterWorker {
             } else {
                 Box::new(BufReader::new(GzDecoder::new(buf_reader)))
             };
-            self.epg_db.update_data(XmltvReader::new(reader))?;
+            let reader = self.archived(reader);
+            summary = Some(
+                self.epg_db
+                    .update_data(XmltvReader::new(reader), self.dry_run)?,
+            );
             println!("updated epg data");
         } else {
             println!("already up to date");
         }
-        Ok(t)
+        Ok((t, summary))
+    }
+
+    /// Load the feed from a local file, its modification time is used like `Last-Modified`
+    fn update_from_file(
+        &self,
+        path: &Path,
+        last_modified: HttpDate,
+    ) -> ServerResult<(HttpDate, Option<LoadSummary>)> {
+        let file = std::fs::File::open(path).map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!("Failed to open feed file {}: {}", path.display(), e),
+            )
+        })?;
+        let t = HttpDate::from(file.metadata()?.modified()?);
+        println!("last modified {}", t);
+        if t <= last_modified {
+            println!("already up to date");
+            return Ok((t, None));
+        }
+        println!("loading xmltv");
+        let reader = self.archived(feed_reader(file)?);
+        let summary = self
+            .epg_db
+            .update_data(XmltvReader::new(reader), self.dry_run)?;
+        println!("updated epg data");
+        Ok((t, Some(summary)))
+    }
+
+    /// Copy the feed xml to the archive while it is loaded, dry runs are not archived
+    fn archived(&self, reader: Box<dyn BufRead>) -> Box<dyn BufRead> {
+        match &self.epg_db.settings().feed_archive {
+            Some(policy) if !self.dry_run => {
+                Box::new(BufReader::new(policy.archive(reader, Utc::now())))
+            }
+            _ => reader,
+        }
+    }
+}
+
+/// Reader with the feed options of the settings
+fn configure_reader<R: BufRead>(xmltv: XmltvReader<R>, settings: &Settings) -> XmltvReader<R> {
+    xmltv
+        .with_category_priority(settings.category_priority.clone())
+        .with_title_max_len(settings.program_title_max_len)
+        .with_title_prefix(settings.title_prefix.clone())
+        .with_timezone(settings.feed_tz)
+        .with_default_durations(settings.default_durations.clone())
+        .with_merged_clumps(settings.merge_clumps)
+        .with_synthesized_aliases(settings.allow_empty_alias)
+}
+
+/// Xml of a feed which may be gzipped, told by its magic number
+/// when there is no content type
+fn feed_reader<'a, R: std::io::Read + 'a>(reader: R) -> std::io::Result<Box<dyn BufRead + 'a>> {
+    let mut buf_reader = BufReader::new(reader);
+    let zipped = buf_reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    Ok(if !zipped {
+        Box::new(buf_reader)
+    } else {
+        Box::new(BufReader::new(GzDecoder::new(buf_reader)))
+    })
+}
+
+/// Outcome of one feed of an import
+#[derive(Serialize, Default)]
+struct ImportedFile {
+    name: String,
+    channels: usize,
+    programs: usize,
+    skipped: usize,
+    error: Option<String>,
+}
+
+/// Outcome of importing several feeds at once
+#[derive(Serialize, Default)]
+struct FeedImport {
+    files: Vec<ImportedFile>,
+    /// Programs merged into the database
+    merged: i64,
+}
+
+/// Path of a feed on the local disk given as `file://` url or a plain path
+fn local_feed_path(url: &str) -> Option<&Path> {
+    match url.strip_prefix("file://") {
+        Some(path) => Some(Path::new(path)),
+        None if !url.contains("://") => Some(Path::new(url)),
+        None => None,
     }
 }
 
@@ -349,43 +1296,253 @@ impl iron::typemap::Key for EpgSqlServer {
     type Value = EpgSqlServer;
 }
 
-fn create_router() -> Router {
+/// Build json response, attaching timing report when it is enabled
+fn json_response<B>(server: &EpgSqlServer, body: B, timing: &ServerTiming) -> Response
+where
+    B: iron::modifier::Modifier<Response>,
+{
     use iron::mime::Mime;
+    let mut response = Response::with((
+        status::Ok,
+        "application/json".parse::<Mime>().unwrap(),
+        body,
+    ));
+    if server.settings().server_timing {
+        timing.apply(&mut response);
+    }
+    response
+}
 
-    fn get_epg_day(req: &mut Request) -> IronResult<Response> {
-        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
-        let params = req.get_ref::<UrlEncodedQuery>().map_err(bad_request)?;
+/// Json response with programs, `X-Epg-Ready` tells clients whether empty data means
+/// nothing is scheduled or the server has not loaded a feed yet
+fn epg_response<B>(server: &EpgSqlServer, body: B, timing: &ServerTiming) -> Response
+where
+    B: iron::modifier::Modifier<Response>,
+{
+    let mut response = json_response(server, body, timing);
+    let ready = if server.is_initialized() {
+        "true"
+    } else {
+        "false"
+    };
+    response
+        .headers
+        .set_raw("X-Epg-Ready", vec![ready.as_bytes().to_vec()]);
+    response
+}
 
-        if let (Some(day), Some(id)) = (
-            params.get("day").and_then(|l| l.last()),
-            params.get("id").and_then(|l| l.last()),
-        ) {
-            let id: i64 = id.parse().map_err(bad_request)?;
-
-            let date = NaiveDate::parse_from_str(day, "%Y.%m.%d")
-                .map(|d| Utc.from_utc_date(&d))
-                .map_err(bad_request)?;
-
-            let list = data.get_epg_day(id, date).map_err(server_error)?;
-            #[derive(Serialize)]
-            struct Data {
-                data: Vec<Program>,
-            }
-            let response = Data { data: list };
-            let out = serde_json::to_string(&response)
-                .map_err(|e| error_with_status(e, status::InternalServerError))?;
-            Ok(Response::with((
-                status::Ok,
-                "application/json".parse::<Mime>().unwrap(),
-                out,
-            )))
-        } else {
-            Ok(Response::with((status::BadRequest, "Invalid parameters")))
-        }
+/// Preferred languages of the request: explicit `lang` parameter overrides
+/// `Accept-Language` header, configured default language is the last resort
+fn request_languages(req: &mut Request, settings: &Settings) -> Vec<String> {
+    let mut languages = req
+        .headers
+        .get_raw("Accept-Language")
+        .and_then(|values| values.first())
+        .and_then(|value| str::from_utf8(value).ok())
+        .map_or_else(Vec::new, parse_accept_language);
+    if let Some(lang) = req
+        .get_ref::<UrlEncodedQuery>()
+        .ok()
+        .and_then(|params| get_parameter(params, "lang"))
+    {
+        languages = vec![lang.to_lowercase()];
     }
+    if let Some(lang) = &settings.default_lang {
+        languages.push(lang.to_lowercase());
+    }
+    languages
+}
 
-    fn get_epg_html(req: &mut Request) -> IronResult<Response> {
-        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+/// Keep channels which name contains the text, ignoring case
+fn retain_name_contains(channels: &mut Vec<(i64, ChannelInfo)>, text: &str) {
+    let text = text.to_lowercase();
+    channels.retain(|(_, c)| c.name.to_lowercase().contains(&text));
+}
+
+/// Bounds `[from, to)` of the day which starts at midnight of the date's timezone
+/// shifted by the start of the broadcast day, e.g. 06:00 until 06:00 of the next day.
+/// When a daylight saving change skips midnight the day starts at the first local time after it.
+fn broadcast_day<Tz: TimeZone>(date: &chrono::Date<Tz>, start: chrono::Duration) -> (i64, i64) {
+    let midnight = |date: chrono::Date<Tz>| {
+        let mut local = date.naive_local().and_hms(0, 0, 0);
+        loop {
+            if let Some(t) = date.timezone().from_local_datetime(&local).earliest() {
+                return (t + start).timestamp();
+            }
+            local += chrono::Duration::minutes(1);
+        }
+    };
+    (midnight(date.clone()), midnight(date.succ()))
+}
+
+/// Http date of the unix time
+fn http_date(t: i64) -> String {
+    HttpDate::from(UNIX_EPOCH + time::Duration::from_secs(t.max(0) as u64)).to_string()
+}
+
+/// Whether the copy of the client from the `If-Modified-Since` time is not older
+/// than the modification, dates have whole seconds
+fn is_not_modified(if_modified_since: Option<&str>, modified: i64) -> bool {
+    if_modified_since
+        .and_then(|s| HttpDate::from_str(s).ok())
+        .and_then(|since| SystemTime::from(since).duration_since(UNIX_EPOCH).ok())
+        .map_or(false, |since| since.as_secs() as i64 >= modified)
+}
+
+/// Sort channels by name, pinned aliases come first in their configured order
+fn sort_channels(channels: &mut Vec<(i64, ChannelInfo)>, pinned: &[String]) {
+    channels.sort_by_cached_key(|(_, c)| {
+        let pin = pinned.iter().position(|alias| *alias == c.alias);
+        (pin.unwrap_or(usize::MAX), c.name.clone())
+    });
+}
+
+/// Clients stream newline delimited json with `format=ndjson` or `Accept: application/x-ndjson`
+fn wants_ndjson(req: &mut Request) -> bool {
+    let format = req
+        .get_ref::<UrlEncodedQuery>()
+        .ok()
+        .and_then(|params| get_parameter(params, "format"))
+        .map_or(false, |v| v == "ndjson");
+    format
+        || req
+            .headers
+            .get_raw("Accept")
+            .and_then(|values| values.first())
+            .and_then(|value| str::from_utf8(value).ok())
+            .map_or(false, |value| {
+                accepts_media_type(value, "application/x-ndjson")
+            })
+}
+
+/// Responses of the handler depend on the request headers, e.g. `Accept`,
+/// caches must not serve them to clients which ask for another format or language
+fn vary<H: Handler>(headers: &'static str, handler: H) -> impl Handler {
+    move |req: &mut Request| {
+        let mut response = handler.handle(req)?;
+        response
+            .headers
+            .set_raw("Vary", vec![headers.as_bytes().to_vec()]);
+        Ok(response)
+    }
+}
+
+/// `active=1` and `name_contains` parameters of channel listings
+fn channel_filter(req: &mut Request) -> (bool, Option<String>) {
+    let params = req.get_ref::<UrlEncodedQuery>().ok();
+    let active = params
+        .and_then(|params| get_parameter(params, "active"))
+        .map_or(false, |v| v == "1");
+    let name_contains = params
+        .and_then(|params| get_parameter(params, "name_contains"))
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string);
+    (active, name_contains)
+}
+
+fn create_router() -> Router {
+    fn get_epg_day(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let languages = request_languages(req, &data.settings());
+        let ndjson = wants_ndjson(req);
+        let query = EpgDayQuery::parse(Query::new(req.get_ref::<UrlEncodedQuery>().ok()))?;
+        if ndjson && (query.stable || query.fields.is_some()) {
+            return Err(
+                QueryError::new("format", "ndjson has programs with all their fields").into(),
+            );
+        }
+
+        // Clients polling a channel get `304` until its programs change
+        let updated = data
+            .db
+            .get_programs_updated(query.id)
+            .map_err(|e| server_error(e.into()))?;
+        if let Some(updated) = updated {
+            let since = req
+                .headers
+                .get_raw("If-Modified-Since")
+                .and_then(|values| values.first())
+                .and_then(|value| str::from_utf8(value).ok());
+            if is_not_modified(since, updated) {
+                let mut response = Response::with(status::NotModified);
+                response
+                    .headers
+                    .set_raw("Last-Modified", vec![http_date(updated).into_bytes()]);
+                return Ok(response);
+            }
+        }
+
+        let mut timing = ServerTiming::new();
+        let list = timing
+            .measure("db", || data.get_epg_day_with_ids(query.id, query.day))
+            .map_err(server_error)?;
+        let (from, to) = broadcast_day(&query.day, data.settings().broadcast_day_start);
+        let (pids, mut list): (Vec<i64>, Vec<Program>) = list.into_iter().unzip();
+        for program in list.iter_mut() {
+            program.localize(&languages);
+            if query.clamp {
+                program.clamp(from, to);
+            }
+        }
+        let with_ids = |list: Vec<Program>| {
+            pids.into_iter()
+                .zip(list)
+                .map(|(id, program)| ProgramWithId { id, program })
+                .collect::<Vec<_>>()
+        };
+        if ndjson {
+            let body = if query.with_ids {
+                ndjson_body(with_ids(list))
+            } else {
+                ndjson_body(list)
+            };
+            let mut response = epg_response(&data, body, &timing);
+            if let Some(updated) = updated {
+                response
+                    .headers
+                    .set_raw("Last-Modified", vec![http_date(updated).into_bytes()]);
+            }
+            return Ok(response);
+        }
+        #[derive(Serialize)]
+        struct Data<T> {
+            data: Vec<T>,
+        }
+        let out = timing
+            .measure("serialize", || {
+                if query.stable {
+                    serde_json::to_string(&Data {
+                        data: list.iter().map(StableProgram::from).collect(),
+                    })
+                } else if let Some(fields) = &query.fields {
+                    serde_json::to_string(&Data {
+                        data: list
+                            .iter()
+                            .map(|program| ProjectedProgram { program, fields })
+                            .collect(),
+                    })
+                } else if query.with_ids {
+                    serde_json::to_string(&Data {
+                        data: with_ids(list),
+                    })
+                } else {
+                    serde_json::to_string(&Data { data: list })
+                }
+            })
+            .map_err(|e| error_with_status(e, status::InternalServerError))?;
+        let mut response = epg_response(&data, out, &timing);
+        if let Some(updated) = updated {
+            response
+                .headers
+                .set_raw("Last-Modified", vec![http_date(updated).into_bytes()]);
+        }
+        Ok(response)
+    }
+
+    fn get_epg_html(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let languages = request_languages(req, &data.settings());
         let params = req.get_ref::<UrlEncodedQuery>().map_err(bad_request)?;
         let invalid = || Ok(Response::with((status::BadRequest, "Missing parameters")));
         let not_found = || Ok(Response::with((status::NotFound, "Not found")));
@@ -408,13 +1565,56 @@ fn create_router() -> Router {
             return not_found();
         };
 
+        let tz = match get_parameter(&params, "tz") {
+            Some(v) => match v.parse::<chrono_tz::Tz>() {
+                Ok(tz) => tz,
+                Err(e) => return Ok(Response::with((status::BadRequest, e))),
+            },
+            None => data.settings().display_tz,
+        };
         let day = match get_parameter(&params, "day") {
-            Some(v) => NaiveDate::parse_from_str(v, "%Y.%m.%d")
-                .map(|d| Utc.from_utc_date(&d))
-                .map_err(bad_request)?,
-            None => Utc::now().date(),
+            Some(v) => {
+                let d = NaiveDate::parse_from_str(v, "%Y.%m.%d").map_err(bad_request)?;
+                match tz.from_local_date(&d).earliest() {
+                    Some(day) => day,
+                    None => return invalid(),
+                }
+            }
+            None => Utc::now().with_timezone(&tz).date(),
         };
-        let list = data.get_epg_day(id, day).map_err(server_error)?;
+        // Keep an explicit timezone while paging through days
+        let tz_query = get_parameter(&params, "tz").map_or_else(String::new, |_| {
+            format!(
+                "&tz={}",
+                iron::url::form_urlencoded::byte_serialize(tz.name().as_bytes())
+                    .collect::<String>()
+            )
+        });
+        let mut list = data.get_epg_day(id, day).map_err(server_error)?;
+        for program in list.iter_mut() {
+            program.localize(&languages);
+        }
+        let settings = data.settings();
+        let palette = &settings.category_palette;
+        let programs = list
+            .iter()
+            .map(|p| ProgramRow {
+                times: p.times_in(&tz),
+                class: p
+                    .categories
+                    .first()
+                    .and_then(|c| palette_index(c, palette.len()))
+                    .map_or_else(String::new, |i| format!("category-{}", i)),
+                p,
+            })
+            .collect::<Vec<_>>();
+        struct ProgramRow<'a> {
+            p: &'a Program,
+            /// Begin and end time in the display timezone
+            times: (String, String),
+            /// Css class of the row tinted by the first category
+            class: String,
+        }
         #[derive(Template)]
         #[template(path = "programs.html")]
         struct ChannelsTemplate<'a> {
@@ -422,92 +1622,588 @@ fn create_router() -> Router {
             date: &'a str,
             prev: &'a str,
             next: &'a str,
+            tz_query: &'a str,
             channel: &'a str,
-            programs: &'a [Program],
+            programs: &'a [ProgramRow<'a>],
+            palette: &'a [String],
         }
         Ok(Response::with((
             status::Ok,
             ChannelsTemplate {
                 id,
                 channel: &channel.name,
-                date: &format!("{}", day.format("%A, %d %B %Y")),
+                date: &Locale::preferred(&languages, settings.locale).format_date(&day),
                 next: &format!("{}", (day + chrono::Duration::days(1)).format("%Y.%m.%d")),
                 prev: &format!("{}", (day - chrono::Duration::days(1)).format("%Y.%m.%d")),
-                programs: &list,
+                tz_query: &tz_query,
+                programs: &programs,
+                palette,
             },
         )))
     }
 
     fn get_epg_list(req: &mut Request) -> IronResult<Response> {
         let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
-        let opt_query = req.get_ref::<UrlEncodedQuery>().ok();
-
-        let time = opt_query
-            .and_then(|query| query.get("time"))
-            .and_then(|l| l.last())
-            .and_then(|s| s.parse::<i64>().ok())
-            .and_then(|ts| Some(Utc.timestamp(ts, 0)))
-            .unwrap_or_else(Utc::now);
-
-        let ids = opt_query
-            .and_then(|query| query.get("ids"))
-            .and_then(|l| l.last())
-            .map(|s| {
-                s.split(',')
-                    .map(|id| id.parse::<i64>())
-                    .collect::<Result<Vec<_>, _>>()
-            })
-            .transpose()
-            .map_err(bad_request)?;
+        let query = EpgListQuery::parse(
+            Query::new(req.get_ref::<UrlEncodedQuery>().ok()),
+            data.settings().max_query_span_days,
+        )?;
+        let ids = query.ids;
 
         let t = Instant::now();
 
-        let out = data
-            .get_epg_list(time, ids.as_ref().map(Vec::as_slice))
-            .map_err(server_error)?;
+        let mut timing = ServerTiming::new();
+        // Channel group by alias prefix, combined with `ids` both filters apply
+        let ids = match query.alias_prefix {
+            Some(prefix) => {
+                let group = timing
+                    .measure("channels", || data.find_ids_by_alias_prefix(prefix))
+                    .map_err(server_error)?;
+                Some(match ids {
+                    Some(ids) => ids.into_iter().filter(|id| group.contains(id)).collect(),
+                    None => group,
+                })
+            }
+            None => ids,
+        };
+        let ids = match query.group {
+            Some(group) => {
+                let members = timing
+                    .measure("channels", || data.find_ids_by_group(group))
+                    .map_err(server_error)?;
+                let members = match members {
+                    Some(members) => members,
+                    None => return Ok(Response::with((status::NotFound, "Unknown channel group"))),
+                };
+                Some(match ids {
+                    Some(ids) => ids.into_iter().filter(|id| members.contains(id)).collect(),
+                    None => members,
+                })
+            }
+            None => ids,
+        };
+        let ids = ids.as_ref().map(Vec::as_slice);
+        let out: Box<dyn iron::response::WriteBody> = match query.window {
+            Some(window) => Box::new(
+                data.get_epg_window(
+                    query.time,
+                    window,
+                    ids,
+                    query.clamp,
+                    query.stable,
+                    query.fields.as_ref(),
+                    &mut timing,
+                )
+                .map_err(server_error)?,
+            ),
+            None => {
+                let mut body = if query.lookback > 0 {
+                    data.get_epg_list_lookback(query.time, ids, query.lookback, &mut timing)
+                } else {
+                    data.get_epg_list(query.time, ids, &mut timing)
+                }
+                .map_err(server_error)?;
+                body.stable = query.stable;
+                body.fields = query.fields;
+                if data.settings().server_timing {
+                    // The streamed body would be written only after the timing header
+                    let mut out = Vec::new();
+                    timing
+                        .measure("serialize", || body.write_json(&mut out))
+                        .map_err(|e| server_error(e.into()))?;
+                    Box::new(out)
+                } else {
+                    Box::new(body)
+                }
+            }
+        };
 
         println!("req processed in {:?}", t.elapsed());
-        Ok(Response::with((
-            status::Ok,
-            "application/json".parse::<Mime>().unwrap(),
-            out,
-        )))
+        Ok(epg_response(&data, out, &timing))
     }
 
-    fn get_channel_ids(req: &mut Request) -> IronResult<Response> {
+    fn get_now_next(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let NowNextQuery { time, ids } =
+            NowNextQuery::parse(Query::new(req.get_ref::<UrlEncodedQuery>().ok()))?;
+
+        let mut timing = ServerTiming::new();
+        let body = data
+            .get_epg_list(time, ids.as_ref().map(Vec::as_slice), &mut timing)
+            .map_err(server_error)?;
+        let t = time.timestamp();
+        let channels: Vec<&EpgNow> = match &body.ids {
+            Some(ids) => ids.iter().filter_map(|id| body.data.get(id)).collect(),
+            None => {
+                let mut list = body.data.values().collect::<Vec<_>>();
+                list.sort_by_key(|e| e.channel_id);
+                list
+            }
+        };
+        #[derive(Serialize)]
+        struct Data<'a> {
+            data: Vec<NowNext<'a>>,
+        }
+        let out = timing
+            .measure("serialize", || {
+                serde_json::to_string(&Data {
+                    data: channels.into_iter().map(|e| NowNext::at(e, t)).collect(),
+                })
+            })
+            .map_err(|e| server_error(e.into()))?;
+        Ok(epg_response(&data, out, &timing))
+    }
+
+    /// Previous, current and next program of each channel at the time, missing ones are null
+    fn get_snapshot(req: &mut Request) -> IronResult<Response> {
         let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let NowNextQuery { time, ids } =
+            NowNextQuery::parse(Query::new(req.get_ref::<UrlEncodedQuery>().ok()))?;
+
+        let mut timing = ServerTiming::new();
+        let snapshot = timing
+            .measure("db", || {
+                data.db
+                    .get_snapshot(time.timestamp(), ids.as_ref().map(Vec::as_slice))
+            })
+            .map_err(|e| server_error(e.into()))?;
         #[derive(Serialize)]
         struct Data {
+            data: Vec<Snapshot>,
+        }
+        let out = timing
+            .measure("serialize", || {
+                serde_json::to_string(&Data { data: snapshot })
+            })
+            .map_err(|e| server_error(e.into()))?;
+        Ok(epg_response(&data, out, &timing))
+    }
+
+    /// Programs of the channels laid out in the window for a guide grid,
+    /// channels without programs in the window have empty rows
+    fn get_grid(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let GridQuery { ids, from, to } = GridQuery::parse(
+            Query::new(req.get_ref::<UrlEncodedQuery>().ok()),
+            data.settings().max_query_span_days,
+        )?;
+
+        let mut timing = ServerTiming::new();
+        let epg = timing
+            .measure("db", || data.db.get_window(from, to, Some(&ids)))
+            .map_err(|e| server_error(e.into()))?;
+        #[derive(Serialize)]
+        struct Data<'a> {
+            from: i64,
+            to: i64,
+            data: Vec<GridRow<'a>>,
+        }
+        let rows = ids
+            .iter()
+            .map(|&id| match epg.get(&id) {
+                Some(e) => GridRow::new(e, from, to),
+                None => GridRow {
+                    channel_id: id,
+                    programs: Vec::new(),
+                },
+            })
+            .collect();
+        let out = timing
+            .measure("serialize", || {
+                serde_json::to_string(&Data {
+                    from,
+                    to,
+                    data: rows,
+                })
+            })
+            .map_err(|e| server_error(e.into()))?;
+        Ok(epg_response(&data, out, &timing))
+    }
+
+    fn get_channel_ids(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let (active, name_contains) = channel_filter(req);
+        #[derive(Serialize)]
+        struct Data<'a> {
             data: HashMap<String, i64>,
+            /// Aliases of the listed channels which are pinned to the top
+            pinned: Vec<&'a str>,
         }
-        let out = serde_json::to_string(&Data {
-            data: data.get_channels_alias().map_err(server_error)?,
-        })
-        .unwrap();
-        Ok(Response::with((
-            status::Ok,
-            "application/json".parse::<Mime>().unwrap(),
-            out,
-        )))
+        let mut timing = ServerTiming::new();
+        let channels = timing
+            .measure("db", || {
+                data.get_channels_alias(active, name_contains.as_deref())
+            })
+            .map_err(server_error)?;
+        let settings = data.settings();
+        let pinned = settings
+            .pinned_channels
+            .iter()
+            .map(String::as_str)
+            .filter(|alias| channels.contains_key(*alias))
+            .collect();
+        let out = timing
+            .measure("serialize", || {
+                serde_json::to_string(&Data {
+                    data: channels,
+                    pinned,
+                })
+            })
+            .unwrap();
+        Ok(json_response(&data, out, &timing))
+    }
+
+    /// Channels with the title of the program on air, for pickers showing what is on
+    fn get_channels_now(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let languages = request_languages(req, &data.settings());
+        let (active, name_contains) = channel_filter(req);
+        #[derive(Serialize)]
+        struct Item {
+            id: i64,
+            alias: String,
+            name: String,
+            icon_url: String,
+            /// Title of the program on air, null when there is none
+            now: Option<String>,
+        }
+        #[derive(Serialize)]
+        struct Data {
+            data: Vec<Item>,
+        }
+        let mut timing = ServerTiming::new();
+        let channels = timing
+            .measure("db", || {
+                data.get_channels_with_now(active, name_contains.as_deref(), &languages)
+            })
+            .map_err(server_error)?;
+        let out = timing
+            .measure("serialize", || {
+                serde_json::to_string(&Data {
+                    data: channels
+                        .into_iter()
+                        .map(|(id, c, now)| Item {
+                            id,
+                            alias: c.alias,
+                            name: c.name,
+                            icon_url: c.icon_url,
+                            now,
+                        })
+                        .collect(),
+                })
+            })
+            .unwrap();
+        Ok(json_response(&data, out, &timing))
+    }
+
+    fn resolve_aliases(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let params = req.get_ref::<UrlEncodedQuery>().map_err(bad_request)?;
+        let aliases = match get_parameter(params, "aliases") {
+            Some(v) => v
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>(),
+            None => return Ok(Response::with((status::BadRequest, "Missing parameters"))),
+        };
+        if aliases.len() > EpgSqlServer::MAX_RESOLVE_ALIASES {
+            return Ok(Response::with((status::BadRequest, "Too many aliases")));
+        }
+        let with_unknown = get_parameter(params, "unknown").map_or(false, |v| v == "1");
+        #[derive(Serialize)]
+        struct Data {
+            data: HashMap<String, Option<i64>>,
+        }
+        let mut timing = ServerTiming::new();
+        let mut resolved = timing
+            .measure("db", || data.resolve_aliases(&aliases))
+            .map_err(server_error)?;
+        if !with_unknown {
+            resolved.retain(|_, id| id.is_some());
+        }
+        let out = timing
+            .measure("serialize", || {
+                serde_json::to_string(&Data { data: resolved })
+            })
+            .unwrap();
+        Ok(json_response(&data, out, &timing))
+    }
+
+    fn get_channel_full(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let params = req.get_ref::<UrlEncodedQuery>().map_err(bad_request)?;
+        let id = match get_parameter(params, "id") {
+            Some(id) => id.parse::<i64>().map_err(bad_request)?,
+            None => return Ok(Response::with((status::BadRequest, "Missing parameters"))),
+        };
+        let mut timing = ServerTiming::new();
+        let channel = timing
+            .measure("db", || data.db.get_channel_full(id))
+            .map_err(|e| server_error(e.into()))?;
+        match channel {
+            Some(channel) => {
+                let out = timing
+                    .measure("serialize", || serde_json::to_string(&channel))
+                    .map_err(|e| error_with_status(e, status::InternalServerError))?;
+                Ok(epg_response(&data, out, &timing))
+            }
+            None => Ok(Response::with((status::NotFound, "Not found"))),
+        }
+    }
+
+    /// Program by the id listed with `/epg_day?with_ids=1`
+    fn get_program(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let languages = request_languages(req, &data.settings());
+        let pid: i64 = Query::new(req.get_ref::<UrlEncodedQuery>().ok()).required("pid")?;
+        let mut timing = ServerTiming::new();
+        let program = timing
+            .measure("db", || data.db.get_program_by_id(pid))
+            .map_err(|e| server_error(e.into()))?;
+        let (channel_id, mut program) = match program {
+            Some(program) => program,
+            None => return Ok(Response::with((status::NotFound, "Not found"))),
+        };
+        program.localize(&languages);
+        #[derive(Serialize)]
+        struct Item {
+            channel_id: i64,
+            #[serde(flatten)]
+            program: ProgramWithId,
+        }
+        #[derive(Serialize)]
+        struct Data {
+            data: Item,
+        }
+        let out = timing
+            .measure("serialize", || {
+                serde_json::to_string(&Data {
+                    data: Item {
+                        channel_id,
+                        program: ProgramWithId { id: pid, program },
+                    },
+                })
+            })
+            .map_err(|e| error_with_status(e, status::InternalServerError))?;
+        Ok(epg_response(&data, out, &timing))
+    }
+
+    fn get_stats(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let mut timing = ServerTiming::new();
+        let stats = timing
+            .measure("db", || data.db.get_db_stats())
+            .map_err(|e| server_error(e.into()))?;
+        let out = timing
+            .measure("serialize", || serde_json::to_string(&stats))
+            .unwrap();
+        Ok(json_response(&data, out, &timing))
+    }
+
+    fn search_programs(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let ndjson = wants_ndjson(req);
+        let params = req.get_ref::<UrlEncodedQuery>().map_err(bad_request)?;
+        let text = get_parameter(params, "q").map(String::as_str);
+        let country = get_parameter(params, "country").map(String::as_str);
+        let language = get_parameter(params, "language").map(String::as_str);
+        if text.is_none() && country.is_none() && language.is_none() {
+            return Ok(Response::with((status::BadRequest, "Missing parameters")));
+        }
+        let limit = match get_parameter(params, "limit") {
+            Some(v) => v.parse::<i64>().map_err(bad_request)?,
+            None => 50,
+        };
+        let limit = limit.max(0).min(EpgSqlServer::MAX_SEARCH_PROGRAMS);
+
+        #[derive(Serialize)]
+        struct Item {
+            channel_id: i64,
+            #[serde(flatten)]
+            program: Program,
+        }
+        #[derive(Serialize)]
+        struct Data {
+            data: Vec<Item>,
+        }
+        let mut timing = ServerTiming::new();
+        let found = timing
+            .measure("db", || {
+                data.db.search_programs(text, country, language, limit)
+            })
+            .map_err(|e| server_error(e.into()))?;
+        let items = found
+            .into_iter()
+            .map(|(channel_id, program)| Item {
+                channel_id,
+                program,
+            })
+            .collect();
+        if ndjson {
+            return Ok(json_response(&data, ndjson_body(items), &timing));
+        }
+        let response = Data { data: items };
+        let out = timing
+            .measure("serialize", || serde_json::to_string(&response))
+            .unwrap();
+        Ok(json_response(&data, out, &timing))
+    }
+
+    fn get_histogram(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let params = req.get_ref::<UrlEncodedQuery>().map_err(bad_request)?;
+        let id = match get_parameter(params, "id") {
+            Some(id) => id.parse::<i64>().map_err(bad_request)?,
+            None => return Ok(Response::with((status::BadRequest, "Missing parameters"))),
+        };
+        let from = match get_parameter(params, "from") {
+            Some(v) => v.parse::<i64>().map_err(bad_request)?,
+            None => i64::min_value(),
+        };
+        let to = match get_parameter(params, "to") {
+            Some(v) => v.parse::<i64>().map_err(bad_request)?,
+            None => i64::max_value(),
+        };
+        let tz = match get_parameter(params, "tz") {
+            Some(v) => match v.parse::<chrono_tz::Tz>() {
+                Ok(tz) => tz,
+                Err(e) => return Ok(Response::with((status::BadRequest, e))),
+            },
+            None => data.settings().display_tz,
+        };
+        #[derive(Serialize)]
+        struct Data {
+            data: [i64; 24],
+        }
+        let mut timing = ServerTiming::new();
+        let histogram = timing
+            .measure("db", || data.db.get_hourly_histogram(id, from, to, &tz))
+            .map_err(|e| server_error(e.into()))?;
+        let out = timing
+            .measure("serialize", || {
+                serde_json::to_string(&Data { data: histogram })
+            })
+            .unwrap();
+        Ok(json_response(&data, out, &timing))
     }
 
     fn get_channel_names(req: &mut Request) -> IronResult<Response> {
         let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let (active, name_contains) = channel_filter(req);
         #[derive(Serialize)]
         struct Data {
             data: HashMap<String, i64>,
         }
-        let out = serde_json::to_string(&Data {
-            data: data.get_channels_name().map_err(server_error)?,
-        })
-        .map_err(|e| error_with_status(e, status::InternalServerError))?;
+        let mut timing = ServerTiming::new();
+        let channels = timing
+            .measure("db", || {
+                data.get_channels_name(active, name_contains.as_deref())
+            })
+            .map_err(server_error)?;
+        let out = timing
+            .measure("serialize", || {
+                serde_json::to_string(&Data { data: channels })
+            })
+            .map_err(|e| error_with_status(e, status::InternalServerError))?;
+        Ok(json_response(&data, out, &timing))
+    }
+
+    fn get_xmltv(req: &mut Request) -> IronResult<Response> {
+        use iron::mime::Mime;
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let params = req.get_ref::<UrlEncodedQuery>().ok();
+        let with_aliases = params
+            .and_then(|params| get_parameter(params, "aliases"))
+            .map_or(false, |v| v == "1");
+
+        let out = data.export_xmltv(with_aliases).map_err(server_error)?;
         Ok(Response::with((
             status::Ok,
-            "application/json".parse::<Mime>().unwrap(),
+            "application/xml".parse::<Mime>().unwrap(),
             out,
         )))
     }
 
+    fn get_channels_csv(req: &mut Request) -> IronResult<Response> {
+        use iron::mime::Mime;
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let (active, name_contains) = channel_filter(req);
+        let channels = data
+            .get_channels(active, name_contains.as_deref())
+            .map_err(server_error)?;
+
+        let mut out = String::new();
+        write_csv_record(&mut out, &["id", "alias", "name", "icon_url"]);
+        for (id, channel) in &channels {
+            write_csv_record(
+                &mut out,
+                &[
+                    id.to_string().as_str(),
+                    channel.alias.as_str(),
+                    channel.name.as_str(),
+                    channel.icon_url.as_str(),
+                ],
+            );
+        }
+        let mut response = Response::with((
+            status::Ok,
+            "text/csv; charset=utf-8".parse::<Mime>().unwrap(),
+            out,
+        ));
+        response.headers.set_raw(
+            "Content-Disposition",
+            vec![b"attachment; filename=\"channels.csv\"".to_vec()],
+        );
+        Ok(response)
+    }
+
+    fn search_channels(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        let params = req.get_ref::<UrlEncodedQuery>().map_err(bad_request)?;
+
+        let query = match get_parameter(&params, "q") {
+            Some(q) => q,
+            None => return Ok(Response::with((status::BadRequest, "Missing parameters"))),
+        };
+        let limit = match get_parameter(&params, "limit") {
+            Some(v) => Some(v.parse::<usize>().map_err(bad_request)?),
+            None => None,
+        };
+        let limit = playlist::search_limit(limit, data.settings().search_limit);
+
+        #[derive(Serialize)]
+        struct Item {
+            id: i64,
+            alias: String,
+            name: String,
+            sim: f32,
+        }
+        #[derive(Serialize)]
+        struct Data {
+            data: Vec<Item>,
+        }
+        let mut timing = ServerTiming::new();
+        let found = timing
+            .measure("db", || data.search_channels(query, limit))
+            .map_err(server_error)?;
+        let response = Data {
+            data: found
+                .into_iter()
+                .map(|(id, channel, sim)| Item {
+                    id,
+                    alias: channel.alias,
+                    name: channel.name,
+                    sim,
+                })
+                .collect(),
+        };
+        let out = timing
+            .measure("serialize", || serde_json::to_string(&response))
+            .map_err(|e| error_with_status(e, status::InternalServerError))?;
+        Ok(json_response(&data, out, &timing))
+    }
+
     fn get_channels_html(req: &mut Request) -> IronResult<Response> {
         let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
 
@@ -517,7 +2213,11 @@ fn create_router() -> Router {
             update: &'a Option<UpdateStatus>,
             today: &'a str,
             channels: &'a [(i64, ChannelInfo)],
+            /// Number of pinned channels at the top
+            pinned: usize,
         }
+        let channels = data.get_channels(false, None).map_err(server_error)?;
+        let pinned = data.settings().pinned_channels.clone();
         Ok(Response::with((
             status::Ok,
             ChannelsTemplate {
@@ -526,36 +2226,162 @@ fn create_router() -> Router {
                     .get_last_update()
                     .map_err(|e| server_error(Box::new(e)))?,
                 today: &format!("{}", Utc::today().format("%Y.%m.%d")),
-                channels: &data.get_channels().map_err(server_error)?,
+                channels: &channels,
+                pinned: channels
+                    .iter()
+                    .take_while(|(_, c)| pinned.contains(&c.alias))
+                    .count(),
             },
         )))
     }
 
-    fn redirect_to_channels_html(req: &mut Request) -> IronResult<Response> {
-        Ok(Response::with((
-            status::Found,
-            iron::modifiers::Redirect(router::url_for!(req, "get_channels_html")),
-        )))
+    fn get_root(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+        match data.settings().root_mode {
+            RootMode::Redirect => Ok(Response::with((
+                status::Found,
+                iron::modifiers::Redirect(url_for_route(req, "get_channels_html")),
+            ))),
+            RootMode::Json => {
+                const ENDPOINTS: &[&str] = &[
+                    "/epg_day",
+                    "/epg_list",
+                    "/now_next",
+                    "/snapshot",
+                    "/grid",
+                    "/channels",
+                    "/channels_now",
+                    "/channels/search",
+                    "/channels.csv",
+                    "/channel_full",
+                    "/program",
+                    "/channels_names",
+                    "/search",
+                    "/histogram",
+                    "/resolve",
+                    "/stats",
+                    "/xmltv",
+                    "/version",
+                ];
+                #[derive(Serialize)]
+                struct Descriptor {
+                    name: &'static str,
+                    version: &'static str,
+                    endpoints: Vec<String>,
+                    /// Time of the last successful feed update
+                    last_update: Option<i64>,
+                }
+                let mut timing = ServerTiming::new();
+                let last_update = timing
+                    .measure("db", || data.db.get_last_update())
+                    .map_err(|e| server_error(e.into()))?
+                    .filter(|st| st.succeed)
+                    .map(|st| st.time.timestamp());
+                let descriptor = Descriptor {
+                    name: env!("CARGO_PKG_NAME"),
+                    version: env!("CARGO_PKG_VERSION"),
+                    endpoints: ENDPOINTS
+                        .iter()
+                        .map(|e| format!("{}{}", data.settings().base_path, e))
+                        .collect(),
+                    last_update,
+                };
+                let out = timing
+                    .measure("serialize", || serde_json::to_string(&descriptor))
+                    .unwrap();
+                Ok(json_response(&data, out, &timing))
+            }
+            RootMode::Healthz => match data.db.get_last_update() {
+                Ok(_) => Ok(Response::with((status::Ok, "ok"))),
+                Err(e) => Ok(Response::with((status::ServiceUnavailable, e.to_string()))),
+            },
+        }
+    }
+
+    fn get_version(req: &mut Request) -> IronResult<Response> {
+        let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+
+        #[derive(Serialize)]
+        struct Version {
+            version: &'static str,
+            /// Commit the server was built from, unknown when built outside of git
+            commit: Option<&'static str>,
+            build_time: String,
+        }
+        let build_time = env!("BUILD_TIMESTAMP")
+            .parse::<i64>()
+            .map(|t| Utc.timestamp(t, 0).to_rfc3339())
+            .unwrap_or_default();
+        let version = Version {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: option_env!("GIT_COMMIT"),
+            build_time,
+        };
+        let timing = ServerTiming::new();
+        let out = serde_json::to_string(&version).map_err(|e| server_error(Box::new(e)))?;
+        Ok(json_response(&data, out, &timing))
+    }
+
+    /// Url of a named route, `Mount` strips the base path from the request so it is restored
+    fn url_for_route(req: &mut Request, route: &str) -> iron::Url {
+        let base = {
+            let data = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
+            data.settings().base_path.clone()
+        };
+        let mut url = router::url_for(req, route, HashMap::new());
+        let path = format!("{}{}", base, url.as_ref().path());
+        url.as_mut().set_path(&path);
+        url
     }
 
     let mut router = Router::new();
-    router.get("/epg_day", get_epg_day, "get_epg_day");
+    router.get(
+        "/epg_day",
+        vary("Accept, Accept-Language", get_epg_day),
+        "get_epg_day",
+    );
     router.get("/epg_list", get_epg_list, "get_epg_list");
+    router.get("/now_next", get_now_next, "get_now_next");
+    router.get("/snapshot", get_snapshot, "get_snapshot");
+    router.get("/grid", get_grid, "get_grid");
     router.get("/programs.html", get_epg_html, "get_epg_html");
     router.get("/channels", get_channel_ids, "get_channel_ids");
+    router.get("/channels_now", get_channels_now, "get_channels_now");
+    router.get("/channels/search", search_channels, "search_channels");
+    router.get("/channels.csv", get_channels_csv, "get_channels_csv");
+    router.get("/stats", get_stats, "get_stats");
+    router.get(
+        "/search",
+        vary("Accept", search_programs),
+        "search_programs",
+    );
+    router.get("/histogram", get_histogram, "get_histogram");
+    router.get("/resolve", resolve_aliases, "resolve_aliases");
+    router.get("/channel_full", get_channel_full, "get_channel_full");
+    router.get("/program", get_program, "get_program");
+    router.get("/xmltv", get_xmltv, "get_xmltv");
     router.get("/channels.html", get_channels_html, "get_channels_html");
     router.get("/channels_names", get_channel_names, "get_channel_names");
-    router.get("/", redirect_to_channels_html, "home");
+    router.get("/version", get_version, "get_version");
+    router.get("/", get_root, "home");
     router
 }
 
-fn main() {
-    const VERSION: &str = env!("CARGO_PKG_VERSION");
-
-    let args = clap::App::new("epg server")
-        .version(VERSION)
+/// Command line interface, options may also come from the environment
+fn cli() -> clap::App<'static, 'static> {
+    clap::App::new("epg server")
+        .version(env!("CARGO_PKG_VERSION"))
         .author("technic93")
         .about("Serves xmltv in json format")
+        // Options of the config file are followed by the ones of the command line
+        .setting(clap::AppSettings::AllArgsOverrideSelf)
+        .arg(
+            clap::Arg::with_name("config")
+                .long("config")
+                .env("APP_CONFIG")
+                .takes_value(true)
+                .help("file with APP_*=value lines, POST /admin/reload re-reads it"),
+        )
         .arg(
             clap::Arg::with_name("port")
                 .long("port")
@@ -564,28 +2390,838 @@ fn main() {
                 .default_value("3000")
                 .help("The port to listen to"),
         )
+        .arg(
+            clap::Arg::with_name("base_path")
+                .long("base-path")
+                .env("APP_BASE_PATH")
+                .takes_value(true)
+                .help("path prefix of all routes when served behind a proxy, like /epg"),
+        )
+        .arg(
+            clap::Arg::with_name("root_mode")
+                .long("root-mode")
+                .env("APP_ROOT_MODE")
+                .takes_value(true)
+                .possible_values(&["redirect", "json", "healthz"])
+                .default_value("redirect")
+                .help(
+                    "response of /: redirect to channels.html, service descriptor or health check",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("ws_port")
+                .long("ws-port")
+                .env("APP_WS_PORT")
+                .takes_value(true)
+                .help("port of the websocket pushing now and next programs on change"),
+        )
         .arg(
             clap::Arg::with_name("url")
                 .long("url")
                 .env("APP_URL")
                 .takes_value(true)
-                .help("xmltv download url"),
+                .help("xmltv download url, file:// url or path of a local feed file"),
         )
         .arg(
-            clap::Arg::with_name("db_path")
-                .long("db")
-                .env("APP_DB")
+            clap::Arg::with_name("fallback_url")
+                .long("fallback-url")
+                .env("APP_FALLBACK_URL")
                 .takes_value(true)
-                .default_value("./epg.db")
-                .help("path to sqlite database"),
+                .help("feed url or path loaded when the primary url fails"),
         )
-        .get_matches();
-
-    let port = {
-        let s = args.value_of("port").unwrap();
-        s.parse::<i32>().unwrap_or_else(|e| {
-            eprintln!("Bad port argument '{}', {}.", s, e);
-            std::process::exit(1);
+        .arg(
+            clap::Arg::with_name("user_agent")
+                .long("user-agent")
+                .env("APP_USER_AGENT")
+                .takes_value(true)
+                .help("User-Agent header of feed requests"),
+        )
+        .arg(
+            clap::Arg::with_name("feed_auth")
+                .long("feed-auth")
+                .env("APP_FEED_AUTH")
+                .takes_value(true)
+                .help("feed credentials as 'user:password' or 'bearer <token>'"),
+        )
+        .arg(
+            clap::Arg::with_name("admin_token")
+                .long("admin-token")
+                .env("APP_ADMIN_TOKEN")
+                .hide_env_values(true)
+                .takes_value(true)
+                .help("bearer token required by /admin endpoints, they are disabled without it"),
+        )
+        .arg(
+            clap::Arg::with_name("db_path")
+                .long("db")
+                .env("APP_DB")
+                .takes_value(true)
+                .default_value("./epg.db")
+                .help("path to sqlite database"),
+        )
+        .arg(
+            clap::Arg::with_name("db_key")
+                .long("db-key")
+                .env("APP_DB_KEY")
+                .hide_env_values(true)
+                .takes_value(true)
+                .help("key of the database encrypted with sqlcipher"),
+        )
+        .arg(
+            clap::Arg::with_name("alias_map")
+                .long("alias-map")
+                .env("APP_ALIAS_MAP")
+                .takes_value(true)
+                .help("file with 'canonical: alias, ...' lines to merge duplicate channels"),
+        )
+        .arg(
+            clap::Arg::with_name("allowlist_playlist")
+                .long("allowlist-playlist")
+                .env("APP_ALLOWLIST_PLAYLIST")
+                .takes_value(true)
+                .help("m3u playlist whose channels are the only ones which programs are loaded"),
+        )
+        .arg(
+            clap::Arg::with_name("channels_file")
+                .long("channels-file")
+                .env("APP_CHANNELS_FILE")
+                .takes_value(true)
+                .help("file with 'alias: id, name' lines to assign stable channel ids"),
+        )
+        .arg(
+            clap::Arg::with_name("channel_groups")
+                .long("channel-groups")
+                .env("APP_CHANNEL_GROUPS")
+                .takes_value(true)
+                .help("file with 'group: alias, ...' lines selected by `group` of /epg_list"),
+        )
+        .arg(
+            clap::Arg::with_name("default_durations")
+                .long("default-durations")
+                .env("APP_DEFAULT_DURATIONS")
+                .takes_value(true)
+                .help("file with 'alias: duration' lines, e.g. 'ticker: 30m', of programs without stop time"),
+        )
+        .arg(
+            clap::Arg::with_name("category_palette")
+                .long("category-palette")
+                .env("APP_CATEGORY_PALETTE")
+                .takes_value(true)
+                .help("comma separated css colors of program categories, empty to disable"),
+        )
+        .arg(
+            clap::Arg::with_name("pinned_channels")
+                .long("pinned-channels")
+                .env("APP_PINNED_CHANNELS")
+                .takes_value(true)
+                .help("comma separated aliases of channels listed first, in this order"),
+        )
+        .arg(
+            clap::Arg::with_name("category_priority")
+                .long("category-priority")
+                .env("APP_CATEGORY_PRIORITY")
+                .takes_value(true)
+                .help(
+                    "comma separated categories preferred as the primary genre, e.g. Movie,Drama",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("title_prefix")
+                .long("title-prefix")
+                .env("APP_TITLE_PREFIX")
+                .takes_value(true)
+                .help(
+                    "regex of title prefixes stripped when its 'channel' group is the channel \
+                     name, e.g. ^(?P<channel>[^:]+):\\s*",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("default_lang")
+                .long("default-lang")
+                .env("APP_DEFAULT_LANG")
+                .takes_value(true)
+                .help("language of program titles when a request has no known preference"),
+        )
+        .arg(
+            clap::Arg::with_name("locale")
+                .long("locale")
+                .env("APP_LOCALE")
+                .takes_value(true)
+                .default_value("en")
+                .help("language of day and month names on html pages"),
+        )
+        .arg(
+            clap::Arg::with_name("broadcast_day_start")
+                .long("broadcast-day-start")
+                .env("APP_BROADCAST_DAY_START")
+                .takes_value(true)
+                .help("time after midnight when a guide day begins, e.g. 6h"),
+        )
+        .arg(
+            clap::Arg::with_name("feed_tz")
+                .long("feed-tz")
+                .env("APP_FEED_TZ")
+                .takes_value(true)
+                .help("IANA timezone of feed times without an offset, UTC by default"),
+        )
+        .arg(
+            clap::Arg::with_name("display_tz")
+                .long("display-tz")
+                .env("APP_DISPLAY_TZ")
+                .takes_value(true)
+                .default_value("UTC")
+                .help("IANA timezone of program times in the html guide, e.g. Europe/Berlin"),
+        )
+        .arg(
+            clap::Arg::with_name("max_programs_at")
+                .long("max-programs-at")
+                .env("APP_MAX_PROGRAMS_AT")
+                .takes_value(true)
+                .default_value("24")
+                .help("maximum number of programs per channel in a snapshot"),
+        )
+        .arg(
+            clap::Arg::with_name("drop_descriptions")
+                .long("drop-descriptions")
+                .help("do not store program descriptions"),
+        )
+        .arg(
+            clap::Arg::with_name("description_limit")
+                .long("description-limit")
+                .env("APP_DESCRIPTION_LIMIT")
+                .takes_value(true)
+                .conflicts_with("drop_descriptions")
+                .help("maximum number of characters of stored program descriptions"),
+        )
+        .arg(
+            clap::Arg::with_name("max_days_ahead")
+                .long("max-days-ahead")
+                .env("APP_MAX_DAYS_AHEAD")
+                .takes_value(true)
+                .help("drop programs which begin more than this number of days from now"),
+        )
+        .arg(
+            clap::Arg::with_name("program_title_max_len")
+                .long("program-title-max-len")
+                .env("APP_PROGRAM_TITLE_MAX_LEN")
+                .takes_value(true)
+                .default_value("500")
+                .help("longer program titles and sub-titles are cut with an ellipsis"),
+        )
+        .arg(
+            clap::Arg::with_name("max_match_concurrency")
+                .long("max-match-concurrency")
+                .env("APP_MAX_MATCH_CONCURRENCY")
+                .takes_value(true)
+                .default_value("2")
+                .help("number of playlist matching requests running at once"),
+        )
+        .arg(
+            clap::Arg::with_name("max_query_span_days")
+                .long("max-query-span-days")
+                .env("APP_MAX_QUERY_SPAN_DAYS")
+                .takes_value(true)
+                .default_value("31")
+                .help("longest range of programs in days which one request may read"),
+        )
+        .arg(
+            clap::Arg::with_name("search_limit")
+                .long("search-limit")
+                .env("APP_SEARCH_LIMIT")
+                .takes_value(true)
+                .default_value("10")
+                .help("number of channels found by name unless the request has `limit`"),
+        )
+        .arg(
+            clap::Arg::with_name("match_metric")
+                .long("match-metric")
+                .env("APP_MATCH_METRIC")
+                .takes_value(true)
+                .possible_values(&["cosine", "jaccard"])
+                .default_value("cosine")
+                .help("similarity of channel names, jaccard may suit very short names better"),
+        )
+        .arg(
+            clap::Arg::with_name("match_transliterate")
+                .long("match-transliterate")
+                .help("also match channel names in other scripts by their latin transliteration"),
+        )
+        .arg(
+            clap::Arg::with_name("purge_min_programs")
+                .long("purge-min-programs")
+                .env("APP_PURGE_MIN_PROGRAMS")
+                .takes_value(true)
+                .default_value("1")
+                .help("channels with fewer programs may be removed after an update"),
+        )
+        .arg(
+            clap::Arg::with_name("purge_grace")
+                .long("purge-grace")
+                .env("APP_PURGE_GRACE")
+                .takes_value(true)
+                .help("keep channels which had programs in a feed within this period, e.g. 7d"),
+        )
+        .arg(
+            clap::Arg::with_name("icon_probe_interval")
+                .long("icon-probe-interval")
+                .env("APP_ICON_PROBE_INTERVAL")
+                .takes_value(true)
+                .help("check that channel icon urls respond once within this period, at least 1h, e.g. 1d"),
+        )
+        .arg(
+            clap::Arg::with_name("archive_dir")
+                .long("archive-dir")
+                .env("APP_ARCHIVE_DIR")
+                .takes_value(true)
+                .help("directory where the xml of every loaded feed is kept"),
+        )
+        .arg(
+            clap::Arg::with_name("archive_recompress_days")
+                .long("archive-recompress-days")
+                .env("APP_ARCHIVE_RECOMPRESS_DAYS")
+                .takes_value(true)
+                .requires("archive_dir")
+                .help("gzip archived feeds older than this number of days"),
+        )
+        .arg(
+            clap::Arg::with_name("archive_keep_days")
+                .long("archive-keep-days")
+                .env("APP_ARCHIVE_KEEP_DAYS")
+                .takes_value(true)
+                .requires("archive_dir")
+                .help("remove archived feeds older than this number of days"),
+        )
+        .arg(
+            clap::Arg::with_name("db_cache_size")
+                .long("db-cache-size")
+                .env("APP_DB_CACHE_SIZE")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .default_value("10000")
+                .help("sqlite cache size in pages, or in KiB when negative"),
+        )
+        .arg(
+            clap::Arg::with_name("db_mmap_size")
+                .long("db-mmap-size")
+                .env("APP_DB_MMAP_SIZE")
+                .takes_value(true)
+                .default_value("0")
+                .help("bytes of the database file mapped into memory, 0 disables mapping"),
+        )
+        .arg(
+            clap::Arg::with_name("db_synchronous")
+                .long("db-synchronous")
+                .env("APP_DB_SYNCHRONOUS")
+                .takes_value(true)
+                .possible_values(&["off", "normal", "full", "extra"])
+                .default_value("full")
+                .help("how carefully sqlite waits for writes to reach the disk"),
+        )
+        .arg(
+            clap::Arg::with_name("once")
+                .long("once")
+                .help("update the database once and exit"),
+        )
+        .arg(
+            clap::Arg::with_name("dump_db_schema")
+                .long("dump-db-schema")
+                .help("print the schema and the applied migrations of the database and exit"),
+        )
+        .arg(
+            clap::Arg::with_name("dry_run")
+                .long("dry-run")
+                .requires("once")
+                .help("parse the feed and report counts without writing to the database"),
+        )
+        .arg(
+            clap::Arg::with_name("staging")
+                .long("staging")
+                .help("load feeds into a copy of the database and swap it in when done, needs SQLite 3.27"),
+        )
+        .arg(
+            clap::Arg::with_name("serve_stale")
+                .long("serve-stale")
+                .help("serve the old snapshot while it is refreshed after an update"),
+        )
+        .arg(
+            clap::Arg::with_name("allow_empty_alias")
+                .long("allow-empty-alias")
+                .help("derive aliases of feed channels without id from their names"),
+        )
+        .arg(
+            clap::Arg::with_name("compress_descriptions")
+                .long("compress-descriptions")
+                .help("store program descriptions compressed, stored ones are converted on start"),
+        )
+        .arg(
+            clap::Arg::with_name("coalesce_programs")
+                .long("coalesce-programs")
+                .help("merge back-to-back programs with the same title into one"),
+        )
+        .arg(
+            clap::Arg::with_name("duplicate_begins")
+                .long("duplicate-begins")
+                .env("APP_DUPLICATE_BEGINS")
+                .takes_value(true)
+                .possible_values(&["longer", "first", "last"])
+                .help("which program of a channel is kept when several begin at the same time"),
+        )
+        .arg(
+            clap::Arg::with_name("merge_clumps")
+                .long("merge-clumps")
+                .help("merge programs sharing a time slot by `clumpidx` into one"),
+        )
+        .arg(
+            clap::Arg::with_name("incremental_load")
+                .long("incremental-load")
+                .conflicts_with("staging")
+                .help("accumulate feed parts until POST /admin/finalize merges them"),
+        )
+        .arg(
+            clap::Arg::with_name("server_timing")
+                .long("server-timing")
+                .help("report db and serialization timings in Server-Timing header"),
+        )
+}
+
+/// Options of the `APP_*=value` lines of the config file as command line arguments,
+/// `APP_FOO_BAR` is the environment variable of `--foo-bar`. Flags are given as `true`
+/// or `false`, which leaves them out. Other lines are ignored.
+fn config_args(path: &str) -> Result<Vec<OsString>, String> {
+    let vars = dotenv::from_path_iter(path)
+        .map_err(|e| format!("Failed to read config '{}': {}", path, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Bad config '{}': {}", path, e))?;
+    Ok(vars
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix("APP_")?.to_lowercase().replace('_', "-");
+            match value.as_str() {
+                "true" => Some(format!("--{}", name).into()),
+                "false" => None,
+                _ => Some(format!("--{}={}", name, value).into()),
+            }
+        })
+        .collect())
+}
+
+/// Command line arguments with the options of the config file, which override
+/// the environment but not the command line. Nothing is kept from earlier reads.
+fn matches_with_config<I>(args: I, path: &str) -> Result<clap::ArgMatches<'static>, String>
+where
+    I: IntoIterator<Item = OsString>,
+{
+    let mut args = args.into_iter();
+    let program = args.next();
+    let config = config_args(path)?;
+    cli()
+        .get_matches_from_safe(program.into_iter().chain(config).chain(args))
+        .map_err(|e| e.to_string())
+}
+
+/// Server settings of the command line, environment and config file
+fn parse_settings(args: &clap::ArgMatches) -> Result<Settings, String> {
+    let positive = |name: &str| {
+        let s = args.value_of(name).unwrap();
+        match s.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!(
+                "Bad {} argument '{}', expected positive number.",
+                name.replace('_', "-"),
+                s
+            )),
+        }
+    };
+
+    let broadcast_day_start = match args.value_of("broadcast_day_start") {
+        Some(s) => match parse_duration(s) {
+            Ok(d) if d < chrono::Duration::days(1) => d,
+            Ok(_) => {
+                return Err(format!(
+                    "Bad broadcast-day-start argument '{}', expected less than a day.",
+                    s
+                ))
+            }
+            Err(e) => return Err(format!("Bad broadcast-day-start argument: {}", e)),
+        },
+        None => chrono::Duration::zero(),
+    };
+
+    let feed_tz = args
+        .value_of("feed_tz")
+        .map(|v| v.parse::<chrono_tz::Tz>())
+        .transpose()
+        .map_err(|e| format!("Bad feed-tz argument: {}", e))?;
+
+    let display_tz = args
+        .value_of("display_tz")
+        .unwrap()
+        .parse::<chrono_tz::Tz>()
+        .map_err(|e| format!("Bad display-tz argument: {}", e))?;
+
+    let user_agent = match args.value_of("user_agent") {
+        Some(s) => {
+            let s = s.trim();
+            if s.is_empty() || reqwest::header::HeaderValue::from_str(s).is_err() {
+                return Err(format!(
+                    "Bad user-agent argument '{}', expected non-empty header value.",
+                    s
+                ));
+            }
+            s.to_string()
+        }
+        None => settings::DEFAULT_USER_AGENT.to_string(),
+    };
+    println!("Feed requests use user agent '{}'", user_agent);
+
+    let feed_auth = match args.value_of("feed_auth") {
+        Some(s) => {
+            if args
+                .value_of("url")
+                .map_or(false, feed_auth::url_has_credentials)
+            {
+                return Err(
+                    "Feed credentials are given both in the url and feed-auth argument."
+                        .to_string(),
+                );
+            }
+            Some(
+                s.parse::<feed_auth::FeedAuth>()
+                    .map_err(|e| format!("Bad feed-auth argument: {}", e))?,
+            )
+        }
+        None => None,
+    };
+
+    let description_limit = if args.is_present("drop_descriptions") {
+        Some(0)
+    } else {
+        args.value_of("description_limit")
+            .map(|s| {
+                s.parse::<usize>().map_err(|_| {
+                    format!(
+                        "Bad description-limit argument '{}', expected non-negative number.",
+                        s
+                    )
+                })
+            })
+            .transpose()?
+    };
+
+    // Ten years is far beyond any feed, larger values would overflow the cutoff time
+    let max_days_ahead = args
+        .value_of("max_days_ahead")
+        .map(|s| match s.parse::<i64>() {
+            Ok(n) if n > 0 && n <= 3650 => Ok(n),
+            _ => Err(format!(
+                "Bad max-days-ahead argument '{}', expected number of days from 1 to 3650.",
+                s
+            )),
+        })
+        .transpose()?;
+
+    let purge_policy = {
+        let s = args.value_of("purge_min_programs").unwrap();
+        let min_programs = match s.parse::<i64>() {
+            Ok(n) if n >= 0 => n,
+            _ => {
+                return Err(format!(
+                    "Bad purge-min-programs argument '{}', expected non-negative number.",
+                    s
+                ))
+            }
+        };
+        let grace = match args.value_of("purge_grace") {
+            Some(s) => parse_duration(s).map_err(|e| format!("Bad purge-grace argument: {}", e))?,
+            None => chrono::Duration::zero(),
+        };
+        PurgePolicy {
+            min_programs,
+            grace,
+        }
+    };
+
+    let db_pragmas = {
+        let s = args.value_of("db_cache_size").unwrap();
+        let cache_size = s
+            .parse::<i64>()
+            .map_err(|_| format!("Bad db-cache-size argument '{}', expected a number.", s))?;
+        let s = args.value_of("db_mmap_size").unwrap();
+        let mmap_size = match s.parse::<i64>() {
+            Ok(n) if n >= 0 => n,
+            _ => {
+                return Err(format!(
+                    "Bad db-mmap-size argument '{}', expected non-negative number.",
+                    s
+                ))
+            }
+        };
+        let synchronous = args
+            .value_of("db_synchronous")
+            .unwrap()
+            .parse::<Synchronous>()
+            .map_err(|e| format!("Bad db-synchronous argument: {}", e))?;
+        Pragmas {
+            cache_size,
+            mmap_size,
+            synchronous,
+        }
+    };
+
+    let alias_mapping = match args.value_of("alias_map") {
+        Some(path) => AliasMapping::open(path)
+            .map_err(|e| format!("Failed to read alias mapping '{}': {}", path, e))?,
+        None => AliasMapping::default(),
+    };
+    println!("Loaded {} channel alias mappings", alias_mapping.len());
+
+    let allowlist_playlist = args
+        .value_of("allowlist_playlist")
+        .map(std::path::PathBuf::from);
+    if let Some(path) = &allowlist_playlist {
+        if !path.is_file() {
+            return Err(format!(
+                "Allowlist playlist '{}' is not a file",
+                path.display()
+            ));
+        }
+    }
+
+    let channel_seeds = match args.value_of("channels_file") {
+        Some(path) => ChannelSeeds::open(path)
+            .map_err(|e| format!("Failed to read channels file '{}': {}", path, e))?,
+        None => ChannelSeeds::default(),
+    };
+    println!("Loaded {} seeded channels", channel_seeds.len());
+
+    let channel_groups = match args.value_of("channel_groups") {
+        Some(path) => ChannelGroups::open(path)
+            .map_err(|e| format!("Failed to read channel groups '{}': {}", path, e))?,
+        None => ChannelGroups::default(),
+    };
+    println!("Loaded {} channel groups", channel_groups.len());
+
+    let default_durations = match args.value_of("default_durations") {
+        Some(path) => ChannelDurations::open(path)
+            .map_err(|e| format!("Failed to read default durations '{}': {}", path, e))?,
+        None => ChannelDurations::default(),
+    };
+    println!(
+        "Loaded default durations of {} channels",
+        default_durations.len()
+    );
+
+    let title_prefix = match args.value_of("title_prefix") {
+        Some(s) => {
+            let re = regex::Regex::new(s)
+                .map_err(|e| format!("Bad title-prefix argument '{}', {}.", s, e))?;
+            if !re.capture_names().any(|name| name == Some("channel")) {
+                return Err(format!(
+                    "Bad title-prefix argument '{}', expected a group named 'channel'.",
+                    s
+                ));
+            }
+            Some(re)
+        }
+        None => None,
+    };
+
+    let icon_probe_interval = args
+        .value_of("icon_probe_interval")
+        .map(|s| {
+            let interval = parse_duration(s)
+                .map_err(|e| format!("Bad icon-probe-interval argument: {}", e))?;
+            // Shorter periods would probe the same icons over and over
+            if interval < chrono::Duration::hours(1) {
+                return Err(format!(
+                    "Bad icon-probe-interval argument '{}', expected at least 1h.",
+                    s
+                ));
+            }
+            interval
+                .to_std()
+                .map_err(|e| format!("Bad icon-probe-interval argument: {}", e))
+        })
+        .transpose()?;
+
+    const DAY: u64 = 24 * 60 * 60;
+    let days = |name: &str| {
+        args.value_of(name)
+            .map(|s| {
+                let seconds = s.parse::<u64>().ok().and_then(|n| n.checked_mul(DAY));
+                match seconds {
+                    Some(seconds) if seconds > 0 => Ok(time::Duration::from_secs(seconds)),
+                    _ => Err(format!(
+                        "Bad {} argument '{}', expected positive number.",
+                        name.replace('_', "-"),
+                        s
+                    )),
+                }
+            })
+            .transpose()
+    };
+    let feed_archive = match args.value_of("archive_dir") {
+        Some(dir) => Some(ArchivePolicy {
+            dir: dir.into(),
+            recompress_after: days("archive_recompress_days")?,
+            keep: days("archive_keep_days")?,
+        }),
+        None => None,
+    };
+
+    let ws_port = args
+        .value_of("ws_port")
+        .map(|s| {
+            s.parse::<u16>()
+                .map_err(|e| format!("Bad ws-port argument '{}', {}.", s, e))
+        })
+        .transpose()?;
+
+    // The staging copy is made by `vacuum into`
+    let staging = args.is_present("staging");
+    if staging && rusqlite::version_number() < 3_027_000 {
+        return Err(format!(
+            "Staging needs SQLite 3.27 or newer, linked is {}.",
+            rusqlite::version()
+        ));
+    }
+
+    Ok(Settings {
+        server_timing: args.is_present("server_timing"),
+        staging,
+        serve_stale: args.is_present("serve_stale"),
+        incremental_load: args.is_present("incremental_load"),
+        coalesce_programs: args.is_present("coalesce_programs"),
+        duplicate_begins: args
+            .value_of("duplicate_begins")
+            .map(str::parse)
+            .transpose()
+            .map_err(|e| format!("Bad duplicate-begins argument: {}", e))?,
+        merge_clumps: args.is_present("merge_clumps"),
+        compress_descriptions: args.is_present("compress_descriptions"),
+        max_programs_at: positive("max_programs_at")? as i64,
+        alias_mapping,
+        allowlist_playlist,
+        purge_policy,
+        db_pragmas,
+        description_limit,
+        max_days_ahead,
+        channel_seeds,
+        channel_groups,
+        default_durations,
+        category_palette: args.value_of("category_palette").map_or_else(
+            || Settings::default().category_palette,
+            |s| {
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            },
+        ),
+        program_title_max_len: positive("program_title_max_len")?,
+        title_prefix,
+        allow_empty_alias: args.is_present("allow_empty_alias"),
+        pinned_channels: args
+            .value_of("pinned_channels")
+            .map(|s| {
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        category_priority: args
+            .value_of("category_priority")
+            .map(|s| {
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        default_lang: args.value_of("default_lang").map(str::to_string),
+        locale: args
+            .value_of("locale")
+            .unwrap()
+            .parse()
+            .map_err(|e| format!("Bad locale argument: {}", e))?,
+        display_tz,
+        feed_tz,
+        broadcast_day_start,
+        user_agent,
+        feed_auth,
+        max_match_concurrency: positive("max_match_concurrency")?,
+        search_limit: positive("search_limit")?.min(playlist::MAX_SEARCH_LIMIT),
+        max_query_span_days: positive("max_query_span_days")? as i64,
+        match_options: MatchOptions {
+            metric: args
+                .value_of("match_metric")
+                .unwrap()
+                .parse::<Metric>()
+                .map_err(|e| format!("Bad match-metric argument: {}", e))?,
+            transliterate: args.is_present("match_transliterate"),
+        },
+        admin_token: args
+            .value_of("admin_token")
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string),
+        base_path: normalize_base_path(args.value_of("base_path").unwrap_or("")),
+        ws_port,
+        icon_probe_interval,
+        feed_archive,
+        root_mode: match args.value_of("root_mode").unwrap() {
+            "json" => RootMode::Json,
+            "healthz" => RootMode::Healthz,
+            _ => RootMode::Redirect,
+        },
+        config_file: args.value_of("config").map(str::to_string),
+    })
+}
+
+fn create_chain(app: Arc<EpgSqlServer>) -> Chain {
+    let base = app.settings().base_path.clone();
+    let mut mount = Mount::new();
+    mount.mount(&format!("{}/", base), create_router());
+    mount.mount(&format!("{}/static/", base), Assets::new("static/"));
+    mount.mount(&format!("{}/m3u", base), PlaylistModel::new());
+    mount.mount(&format!("{}/admin", base), AdminModel::new());
+    mount.mount(&format!("{}/m3u/static/", base), Assets::new("static/"));
+    let mut chain = Chain::new(mount);
+    chain.link_before(persistent::Read::<EpgSqlServer>::one(app));
+    chain
+}
+
+fn main() {
+    let mut args = cli().get_matches();
+    if let Some(path) = args.value_of("config").map(str::to_string) {
+        args = matches_with_config(std::env::args_os(), &path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    }
+
+    if args.is_present("dump_db_schema") {
+        // The database is only read, it is neither created nor migrated
+        let path = args.value_of("db_path").unwrap();
+        match db::read_schema(path, args.value_of("db_key")) {
+            Ok(schema) => print!("{}", schema),
+            Err(e) => {
+                eprintln!("Failed to read schema of '{}': {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let port = {
+        let s = args.value_of("port").unwrap();
+        s.parse::<i32>().unwrap_or_else(|e| {
+            eprintln!("Bad port argument '{}', {}.", s, e);
+            std::process::exit(1);
         })
     };
 
@@ -618,23 +3254,779 @@ fn main() {
             .unwrap_or_else(terminate)
     };
 
+    let db_key = args.value_of("db_key");
+    if db_key.is_some() && !cfg!(feature = "sqlcipher") {
+        eprintln!("Database key is given but the server is built without sqlcipher feature.");
+        std::process::exit(1);
+    }
+
+    let settings = parse_settings(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if settings.admin_token.is_none() {
+        println!("Admin endpoints are disabled, set admin-token to enable them");
+    }
+
     println!("epg server starting");
 
-    let app = Arc::new(EpgSqlServer::new(&db_path));
+    let app = Arc::new(EpgSqlServer::new(&db_path, db_key, settings));
+    // Programs of a load which was interrupted before its merge
+    match app.configured_db(&app.settings()).recover_staged() {
+        Ok(0) => {}
+        Ok(count) => println!("Merged {} programs left by an interrupted load", count),
+        Err(e) => eprintln!("Failed to merge programs of an interrupted load: {}", e),
+    }
+    // Stored descriptions follow the configured compression
+    let t = Instant::now();
+    match app.configured_db(&app.settings()).convert_descriptions() {
+        Ok(stats) if stats.rows > 0 => println!("Converted {} in {:?}", stats, t.elapsed()),
+        Ok(_) => {}
+        Err(e) => eprintln!("Failed to convert stored descriptions: {}", e),
+    }
 
-    let worker = EpgUpdaterWorker::new(app.clone(), url);
+    let worker = EpgUpdaterWorker::new(app.clone(), url)
+        .with_fallback_url(args.value_of("fallback_url").map(str::to_owned));
+    if args.is_present("once") {
+        if args.is_present("dry_run") {
+            match worker.validate() {
+                // A parser error or a feed without channels would fail the real load
+                Ok(summary) if summary.error.is_some() || summary.channels == 0 => {
+                    eprintln!("Dry run failed: {}", summary);
+                    std::process::exit(1);
+                }
+                Ok(summary) => println!("Dry run: {}", summary),
+                Err(e) => {
+                    eprintln!("Dry run failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            let mut worker = worker;
+            worker.update();
+        }
+        return;
+    }
     let _child = worker.run();
+    if let Some(ws_port) = app.settings().ws_port {
+        now_push::spawn(app.clone(), ws_port).unwrap_or_else(|e| {
+            eprintln!("Failed to listen for websocket clients: {}", e);
+            std::process::exit(1);
+        });
+    }
+    archive::spawn(app.clone());
+    if let Some(interval) = app.settings().icon_probe_interval {
+        icon_probe::spawn(app.clone(), interval).unwrap_or_else(|e| {
+            eprintln!("Failed to start probing channel icons: {}", e);
+            std::process::exit(1);
+        });
+    }
 
-    let mut mount = Mount::new();
-    mount.mount("/", create_router());
-    mount.mount("static/", Static::new(Path::new("static/")));
-    mount.mount("/m3u", PlaylistModel::new());
-    mount.mount("/m3u/static/", Static::new(Path::new("static/")));
-    let mut chain = Chain::new(mount);
-    chain.link_before(persistent::Read::<EpgSqlServer>::one(app));
-    chain.link_before(Intercept::default());
-
-    Iron::new(chain)
+    Iron::new(create_chain(app))
         .http(format!("localhost:{}", port))
         .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xmltv::test_feed;
+
+    /// Serves the app on a free port until the tests end, returns its url
+    fn serve(app: Arc<EpgSqlServer>) -> String {
+        let listening = Iron::new(create_chain(app)).http("localhost:0").unwrap();
+        let url = format!("http://{}", listening.socket);
+        // Dropping the listener would wait for the server to stop
+        std::mem::forget(listening);
+        url
+    }
+
+    /// Serialization of the snapshot before it was streamed
+    fn buffered_json(cache: &LiveCache, ids: Option<&[i64]>) -> String {
+        serde_json::to_string(&match ids {
+            Some(ids) => serde_json::json!({
+                "data": IteratorAdapter::new(ids.iter().filter_map(|id| cache.data.get(id)))
+            }),
+            None => serde_json::json!({ "data": IteratorAdapter::new(cache.data.values()) }),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn epg_list_streamed() {
+        let mut data = HashMap::new();
+        for id in 1..4 {
+            let programs = (0..2)
+                .map(|i| Program {
+                    begin: i * 60,
+                    end: (i + 1) * 60,
+                    title: format!("\"Show\" {}", i),
+                    sub_title: if i == 0 {
+                        Some("Pilot".to_string())
+                    } else {
+                        None
+                    },
+                    ..Program::new()
+                })
+                .collect();
+            data.insert(
+                id,
+                EpgNow {
+                    channel_id: id,
+                    programs,
+                },
+            );
+        }
+        let mut cache = LiveCache::new();
+        cache.set_data(data, 0);
+
+        for ids in &[None, Some(vec![3, 1, 7])] {
+            let ids = ids.as_ref().map(Vec::as_slice);
+            let mut streamed = Vec::new();
+            cache.snapshot(ids).write_json(&mut streamed).unwrap();
+            assert_eq!(
+                String::from_utf8(streamed).unwrap(),
+                buffered_json(&cache, ids)
+            );
+        }
+    }
+
+    #[test]
+    fn retry_after_minimum() {
+        for value in &["0", "Thu, 01 Jan 1970 00:00:00 GMT"] {
+            let error = FeedHttpError::new(StatusCode::TOO_MANY_REQUESTS, Some(value));
+            assert_eq!(error.retry_after, Some(FeedHttpError::MIN_RETRY_DELAY));
+        }
+        for value in &["31536000", "Fri, 01 Jan 2100 00:00:00 GMT"] {
+            let error = FeedHttpError::new(StatusCode::TOO_MANY_REQUESTS, Some(value));
+            assert_eq!(error.retry_after, Some(FeedHttpError::MAX_RETRY_DELAY));
+        }
+    }
+
+    #[test]
+    fn stale_cache_during_refresh() {
+        let file = "test_stale_cache.db";
+        if Path::new(file).exists() {
+            std::fs::remove_file(file).unwrap();
+        }
+        let settings = Settings {
+            serve_stale: true,
+            ..Settings::default()
+        };
+        let server = Arc::new(EpgSqlServer::new(file, None, settings));
+        let now = Utc::now();
+        let feed = test_feed(
+            &["ch1"],
+            now - chrono::Duration::hours(1),
+            now + chrono::Duration::hours(1),
+        );
+        server
+            .update_data(XmltvReader::new(feed.as_bytes()), false)
+            .unwrap();
+
+        let reader = {
+            let server = server.clone();
+            thread::spawn(move || {
+                for _ in 0..200 {
+                    let body = server
+                        .get_epg_list(Utc::now(), None, &mut ServerTiming::new())
+                        .unwrap();
+                    assert!(!body.data.is_empty());
+                }
+            })
+        };
+        for _ in 0..5 {
+            server
+                .update_data(XmltvReader::new(feed.as_bytes()), false)
+                .unwrap();
+        }
+        reader.join().unwrap();
+
+        // The update returns before the snapshot is read again, the old one is served
+        server
+            .update_data(
+                XmltvReader::new(feed.replace("News", "Weather").as_bytes()),
+                false,
+            )
+            .unwrap();
+        assert!(server.cache.read().unwrap().contains_time(now.timestamp()));
+        let refreshed = (0..100).any(|_| {
+            let body = server
+                .get_epg_list(now, None, &mut ServerTiming::new())
+                .unwrap();
+            let mut programs = body.data.values().flat_map(|e| e.programs.iter());
+            if programs.any(|p| p.title == "Weather") {
+                return true;
+            }
+            thread::sleep(time::Duration::from_millis(50));
+            false
+        });
+        assert!(refreshed);
+        std::fs::remove_file(file).ok();
+    }
+
+    #[test]
+    fn name_contains() {
+        let mut channels = [
+            "Eurosport 1",
+            "SPORT Plus",
+            "Sky Sports",
+            "News",
+            "Матч! Спорт",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            (
+                i as i64,
+                ChannelInfo {
+                    name: name.to_string(),
+                    ..ChannelInfo::new()
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+        let mut latin = channels.clone();
+        retain_name_contains(&mut latin, "sPort");
+        assert_eq!(
+            latin.iter().map(|&(id, _)| id).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        retain_name_contains(&mut channels, "СПОРТ");
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].1.name, "Матч! Спорт");
+    }
+
+    #[test]
+    fn channel_group_ids() {
+        let file = "test_channel_groups.db";
+        if Path::new(file).exists() {
+            std::fs::remove_file(file).unwrap();
+        }
+        let feed = ["espn", "cnn", "eurosport"]
+            .iter()
+            .fold("<tv>".to_string(), |feed, alias| {
+                feed + &format!(
+                    r#"<channel id="{0}"><display-name>{0}</display-name></channel>"#,
+                    alias
+                )
+            })
+            + "</tv>";
+        let settings = Settings {
+            channel_groups: ChannelGroups::parse(
+                "sports: eurosport, espn, sky-sports\nnews: cnn".as_bytes(),
+            )
+            .unwrap(),
+            ..Settings::default()
+        };
+        let server = EpgSqlServer::new(file, None, settings);
+        server
+            .update_data(XmltvReader::new(feed.as_bytes()), false)
+            .unwrap();
+        let aliases = server.get_channels_alias(false, None).unwrap();
+
+        // Channels missing from the database are left out, the group order is kept
+        assert_eq!(
+            server.find_ids_by_group("Sports").unwrap(),
+            Some(vec![aliases["eurosport"], aliases["espn"]])
+        );
+        assert_eq!(
+            server.find_ids_by_group("news").unwrap(),
+            Some(vec![aliases["cnn"]])
+        );
+        assert_eq!(server.find_ids_by_group("kids").unwrap(), None);
+        std::fs::remove_file(file).ok();
+    }
+
+    #[test]
+    fn config_file_options() {
+        let path = "test_config_options.env";
+        let args = || ["epg", "--port", "4000"].iter().map(OsString::from);
+        std::fs::write(path, "APP_PORT=5000\nAPP_BASE_PATH=/epg\nOTHER=1\n").unwrap();
+        let matches = matches_with_config(args(), path).unwrap();
+        assert_eq!(matches.value_of("port"), Some("4000"));
+        assert_eq!(matches.value_of("base_path"), Some("/epg"));
+        assert!(std::env::var("APP_BASE_PATH").is_err());
+
+        // An option removed from the file is not remembered
+        std::fs::write(path, "APP_PORT=5000\n").unwrap();
+        let matches = matches_with_config(args(), path).unwrap();
+        assert_eq!(matches.value_of("base_path"), None);
+
+        std::fs::write(path, "APP_STAGING=true\nAPP_SERVE_STALE=false\n").unwrap();
+        let matches = matches_with_config(args(), path).unwrap();
+        assert!(matches.is_present("staging"));
+        assert!(!matches.is_present("serve_stale"));
+
+        std::fs::write(path, "APP_BAD_OPTION=1\n").unwrap();
+        assert!(matches_with_config(args(), path).is_err());
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn channels_now_localized() {
+        let file = "test_channels_now.db";
+        if Path::new(file).exists() {
+            std::fs::remove_file(file).unwrap();
+        }
+        let now = Utc::now();
+        let feed = test_feed(
+            &["ch1"],
+            now - chrono::Duration::hours(1),
+            now + chrono::Duration::hours(1),
+        )
+        .replace(
+            "<title>News</title>",
+            r#"<title>News</title><title lang="de">Nachrichten</title>"#,
+        );
+        let server = EpgSqlServer::new(file, None, Settings::default());
+        server
+            .update_data(XmltvReader::new(feed.as_bytes()), false)
+            .unwrap();
+        let title = |languages: &[&str]| {
+            let languages = languages.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+            let channels = server
+                .get_channels_with_now(true, None, &languages)
+                .unwrap();
+            channels[0].2.clone()
+        };
+        assert_eq!(title(&["de-AT", "en"]), Some("Nachrichten".to_string()));
+        assert_eq!(title(&["fr"]), Some("News".to_string()));
+        std::fs::remove_file(file).ok();
+    }
+
+    #[test]
+    fn epg_day_not_modified_after_reload() {
+        let file = "test_not_modified.db";
+        if Path::new(file).exists() {
+            std::fs::remove_file(file).unwrap();
+        }
+        let now = Utc::now();
+        let feed = test_feed(&["ch1"], now, now + chrono::Duration::hours(1));
+        let server = Arc::new(EpgSqlServer::new(file, None, Settings::default()));
+        server
+            .update_data(XmltvReader::new(feed.as_bytes()), false)
+            .unwrap();
+        let id = server.db.get_channel_by_alias("ch1").unwrap().unwrap().0;
+        let updated = server.db.get_programs_updated(id).unwrap().unwrap();
+        let url = format!(
+            "{}/epg_day?id={}&day={}",
+            serve(server.clone()),
+            id,
+            now.format("%Y.%m.%d")
+        );
+        let get_since = |since: i64| {
+            reqwest::blocking::Client::new()
+                .get(&url)
+                .header("If-Modified-Since", http_date(since))
+                .send()
+                .unwrap()
+        };
+        let get = || get_since(updated).status();
+        assert_eq!(get(), StatusCode::NOT_MODIFIED);
+
+        // Programs are localized, both the full and the empty response depend on the language
+        for response in &[get_since(updated), get_since(0)] {
+            assert_eq!(response.headers()["vary"], "Accept, Accept-Language");
+        }
+
+        // Loading the same feed again leaves the programs unchanged
+        server
+            .update_data(XmltvReader::new(feed.as_bytes()), false)
+            .unwrap();
+        assert_eq!(server.db.get_programs_updated(id).unwrap(), Some(updated));
+        assert_eq!(get(), StatusCode::NOT_MODIFIED);
+        std::fs::remove_file(file).ok();
+    }
+
+    #[test]
+    fn admin_disabled_without_token() {
+        let file = "target/test_admin_disabled.db";
+        if Path::new(file).exists() {
+            std::fs::remove_file(file).unwrap();
+        }
+        let server = Arc::new(EpgSqlServer::new(file, None, Settings::default()));
+        let url = serve(server);
+        let client = reqwest::blocking::Client::new();
+        let response = client
+            .get(&format!("{}/admin/pragmas", url))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = client
+            .get(&format!("{}/admin/pragmas", url))
+            .bearer_auth("")
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn import_feeds() {
+        let file = "test_import_feeds.db";
+        if Path::new(file).exists() {
+            std::fs::remove_file(file).unwrap();
+        }
+        let feed = |alias: &str, title: &str| {
+            let now = Utc::now().timestamp();
+            format!(
+                r#"<tv><channel id="{0}"><display-name>{0}</display-name></channel>
+                <programme start="{1}" stop="{2}" channel="{0}"><title>{3}</title></programme>
+                </tv>"#,
+                alias,
+                Utc.timestamp(now, 0).format("%Y%m%d%H%M%S +0000"),
+                Utc.timestamp(now + 3600, 0).format("%Y%m%d%H%M%S +0000"),
+                title
+            )
+        };
+        let (north, south) = (feed("north", "News"), feed("south", "Sport"));
+        // Mismatched end tag
+        let broken = "<tv><channel id=\"west\"></tv>";
+        let body = [
+            ("north.xml", north.as_str()),
+            ("south.xml", south.as_str()),
+            ("west.xml", broken),
+        ]
+        .iter()
+        .fold(String::new(), |body, (name, xml)| {
+            body + &format!(
+                "--boundary\r\nContent-Disposition: form-data; name=\"feed\"; filename=\"{}\"\r\n\
+                 Content-Type: application/xml\r\n\r\n{}\r\n",
+                name, xml
+            )
+        }) + "--boundary--\r\n";
+        let settings = Settings {
+            admin_token: Some("secret".to_string()),
+            ..Settings::default()
+        };
+        let server = Arc::new(EpgSqlServer::new(file, None, settings));
+        let url = format!("{}/admin/import", serve(server.clone()));
+        let post = |token: &str| {
+            reqwest::blocking::Client::new()
+                .post(&url)
+                .bearer_auth(token)
+                .header(CONTENT_TYPE, "multipart/form-data; boundary=boundary")
+                .body(body.clone())
+                .send()
+                .unwrap()
+        };
+        assert_eq!(post("guess").status(), StatusCode::UNAUTHORIZED);
+        assert!(!server.is_initialized());
+
+        let response = post("secret");
+        assert_eq!(response.status(), StatusCode::OK);
+        let import: serde_json::Value = serde_json::from_str(&response.text().unwrap()).unwrap();
+        let import = &import["data"];
+        assert_eq!(import["merged"], 2);
+        assert_eq!(
+            import["files"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|f| (
+                    f["name"].as_str().unwrap(),
+                    f["channels"].as_u64().unwrap(),
+                    f["programs"].as_u64().unwrap(),
+                    f["error"].is_null()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("north.xml", 1, 1, true),
+                ("south.xml", 1, 1, true),
+                ("west.xml", 0, 0, false)
+            ]
+        );
+        assert!(server.is_initialized());
+        let channels = server.get_channels_alias(false, None).unwrap();
+        assert!(channels.contains_key("north") && channels.contains_key("south"));
+        std::fs::remove_file(file).ok();
+    }
+
+    #[test]
+    fn broadcast_day_bounds() {
+        let file = "test_broadcast_day.db";
+        if Path::new(file).exists() {
+            std::fs::remove_file(file).unwrap();
+        }
+        let day = Utc::today();
+        let midnight = day.and_hms(0, 0, 0).timestamp();
+        assert_eq!(
+            broadcast_day(&day, chrono::Duration::zero()),
+            (midnight, midnight + 24 * 3600)
+        );
+        let start = chrono::Duration::hours(6);
+        assert_eq!(
+            broadcast_day(&day, start),
+            (midnight + 6 * 3600, midnight + 30 * 3600)
+        );
+        // Clocks of Sao Paulo went from 00:00 to 01:00 on 2018-11-04, the day starts at 01:00
+        let sao_paulo = chrono_tz::America::Sao_Paulo;
+        let utc = |d: u32, h: u32| Utc.ymd(2018, 11, d).and_hms(h, 0, 0).timestamp();
+        assert_eq!(
+            broadcast_day(&sao_paulo.ymd(2018, 11, 3), start),
+            (utc(3, 9), utc(4, 9))
+        );
+        assert_eq!(
+            broadcast_day(&sao_paulo.ymd(2018, 11, 4), start),
+            (utc(4, 9), utc(5, 8))
+        );
+
+        let programs = [
+            ("Morning show", 5, 6),
+            ("Breakfast", 6, 9),
+            ("Late movie", 23, 26),
+            ("Night news", 29, 30),
+            ("Next morning", 30, 32),
+        ];
+        let feed = programs.iter().fold(
+            r#"<tv><channel id="ch1"><display-name>Channel 1</display-name></channel>"#.to_string(),
+            |feed, (title, begin, end)| {
+                let time = |h: i64| {
+                    Utc.timestamp(midnight + h * 3600, 0)
+                        .format("%Y%m%d%H%M%S +0000")
+                };
+                feed + &format!(
+                    r#"<programme start="{}" stop="{}" channel="ch1"><title>{}</title></programme>"#,
+                    time(*begin),
+                    time(*end),
+                    title
+                )
+            },
+        ) + "</tv>";
+        let settings = Settings {
+            broadcast_day_start: start,
+            ..Settings::default()
+        };
+        let server = EpgSqlServer::new(file, None, settings);
+        server
+            .update_data(XmltvReader::new(feed.as_bytes()), false)
+            .unwrap();
+        let id = server.get_channels(false, None).unwrap()[0].0;
+        let titles = server
+            .get_epg_day(id, day)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.title)
+            .collect::<Vec<_>>();
+        // The late-night programmes belong to the day which they follow
+        assert_eq!(titles, vec!["Breakfast", "Late movie", "Night news"]);
+        std::fs::remove_file(file).ok();
+    }
+
+    #[test]
+    fn ndjson_lines() {
+        use iron::response::WriteBody;
+        let programs = vec![
+            Program {
+                begin: 0,
+                end: 3600,
+                title: "News\nat noon".to_string(),
+                description: "{\"quoted\": [1, 2]}".to_string(),
+                ..Program::new()
+            },
+            Program {
+                begin: 3600,
+                end: 7200,
+                title: "Weather".to_string(),
+                ..Program::new()
+            },
+        ];
+        let mut out = Vec::new();
+        NdjsonBody(programs).write_body(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with('\n'));
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        for (line, title) in lines.iter().zip(&["News\nat noon", "Weather"]) {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert!(value.is_object());
+            assert_eq!(value["title"], *title);
+        }
+    }
+
+    #[test]
+    fn not_modified() {
+        let updated = 1622540000;
+        let date = http_date(updated);
+        assert_eq!(date, "Tue, 01 Jun 2021 09:33:20 GMT");
+        assert!(is_not_modified(Some(&date), updated));
+        assert!(is_not_modified(Some(&http_date(updated + 60)), updated));
+        // The channel changed after the copy of the client
+        assert!(!is_not_modified(Some(&date), updated + 1));
+        assert!(!is_not_modified(Some("yesterday"), updated));
+        assert!(!is_not_modified(None, updated));
+    }
+
+    #[test]
+    fn pinned_channels() {
+        let mut channels = [("a", "Zoo"), ("b", "Movies"), ("c", "Art"), ("d", "News")]
+            .iter()
+            .enumerate()
+            .map(|(i, (alias, name))| {
+                (
+                    i as i64,
+                    ChannelInfo {
+                        alias: alias.to_string(),
+                        name: name.to_string(),
+                        ..ChannelInfo::new()
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let names = |channels: &[(i64, ChannelInfo)]| {
+            channels
+                .iter()
+                .map(|(_, c)| c.name.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        sort_channels(&mut channels, &[]);
+        assert_eq!(names(&channels), "Art,Movies,News,Zoo");
+        let pinned = ["d", "unknown", "a"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>();
+        sort_channels(&mut channels, &pinned);
+        assert_eq!(names(&channels), "News,Zoo,Art,Movies");
+    }
+
+    #[test]
+    fn epg_ready() {
+        let file = "test_epg_ready.db";
+        if Path::new(file).exists() {
+            std::fs::remove_file(file).unwrap();
+        }
+        let ready = |server: &EpgSqlServer| {
+            let response = epg_response(server, "{}", &ServerTiming::new());
+            response.headers.get_raw("X-Epg-Ready").unwrap()[0].clone()
+        };
+        let server = EpgSqlServer::new(file, None, Settings::default());
+        assert!(!server.is_initialized());
+        assert_eq!(ready(&server), b"false");
+
+        // A feed without programs still makes the server ready
+        let feed = r#"<tv><channel id="ch1"><display-name>Channel 1</display-name></channel></tv>"#;
+        server
+            .update_data(XmltvReader::new(feed.as_bytes()), false)
+            .unwrap();
+        assert_eq!(ready(&server), b"true");
+        let body = server
+            .get_epg_list(Utc::now(), Some(&[1][..]), &mut ServerTiming::new())
+            .unwrap();
+        assert!(body.data.is_empty());
+        std::fs::remove_file(file).ok();
+    }
+
+    #[test]
+    fn local_feed() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        assert_eq!(
+            local_feed_path("file:///srv/epg.xml"),
+            Some(Path::new("/srv/epg.xml"))
+        );
+        assert_eq!(local_feed_path("epg.xml.gz"), Some(Path::new("epg.xml.gz")));
+        assert_eq!(local_feed_path("http://epg.org/epg.xml"), None);
+
+        let (file, feed_file) = ("test_local_feed.db", "test_local_feed.xml.gz");
+        if Path::new(file).exists() {
+            std::fs::remove_file(file).unwrap();
+        }
+        let now = Utc::now();
+        let feed = test_feed(&["ch1"], now, now + chrono::Duration::hours(1));
+        let mut encoder = GzEncoder::new(
+            std::fs::File::create(feed_file).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(feed.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let server = Arc::new(EpgSqlServer::new(file, None, Settings::default()));
+        let mut worker = EpgUpdaterWorker::new(server, format!("file://{}", feed_file));
+        let (_, _, summary) = worker.perform_update().unwrap();
+        let summary = summary.unwrap();
+        assert_eq!((summary.channels, summary.programs), (1, 1));
+
+        // Unchanged file is not loaded again
+        worker.last_modified =
+            HttpDate::from(std::fs::metadata(feed_file).unwrap().modified().unwrap());
+        let (_, _, summary) = worker.perform_update().unwrap();
+        assert!(summary.is_none());
+
+        std::fs::remove_file(feed_file).ok();
+        std::fs::remove_file(file).ok();
+    }
+
+    #[test]
+    fn fallback_feed() {
+        let (file, feed_file) = ("test_fallback_feed.db", "test_fallback_feed.xml");
+        if Path::new(file).exists() {
+            std::fs::remove_file(file).unwrap();
+        }
+        let now = Utc::now();
+        std::fs::write(
+            feed_file,
+            test_feed(&["ch1"], now, now + chrono::Duration::hours(1)),
+        )
+        .unwrap();
+
+        let server = Arc::new(EpgSqlServer::new(file, None, Settings::default()));
+        let worker = EpgUpdaterWorker::new(server.clone(), "test_missing_feed.xml".to_string());
+        assert!(worker.perform_update().is_err());
+
+        // The fallback is loaded only after repeated failures of the primary
+        let mut worker = worker.with_fallback_url(Some(format!("file://{}", feed_file)));
+        for failures in 1..EpgUpdaterWorker::FALLBACK_AFTER_FAILURES {
+            assert_eq!(worker.update(), None);
+            assert!(!server.db.get_last_update().unwrap().unwrap().succeed);
+            assert_eq!(worker.primary_failures, failures);
+        }
+        let (source, _, summary) = worker.perform_update().unwrap();
+        assert_eq!(source, FeedSource::Fallback);
+        assert_eq!(summary.unwrap().programs, 1);
+        assert_eq!(worker.update(), None);
+        let st = server.db.get_last_update().unwrap().unwrap();
+        assert!(st.succeed);
+        assert_eq!(st.source, "fallback");
+
+        // Only the modification time of the fallback is remembered
+        let secs = |t: SystemTime| DateTime::<Utc>::from(t).timestamp();
+        let worker = EpgUpdaterWorker::new(server, "test_missing_feed.xml".to_string());
+        assert_eq!(secs(worker.last_modified.into()), 0);
+        assert_eq!(
+            secs(worker.fallback_modified.into()),
+            secs(std::fs::metadata(feed_file).unwrap().modified().unwrap())
+        );
+
+        std::fs::remove_file(feed_file).ok();
+        std::fs::remove_file(file).ok();
+    }
+
+    #[test]
+    fn empty_feed() {
+        let (file, feed_file) = ("test_empty_feed.db", "test_empty_feed.xml");
+        if Path::new(file).exists() {
+            std::fs::remove_file(file).unwrap();
+        }
+        let now = Utc::now();
+        let feed = test_feed(&["ch1"], now, now + chrono::Duration::hours(1));
+        let server = Arc::new(EpgSqlServer::new(file, None, Settings::default()));
+        server
+            .update_data(XmltvReader::new(feed.as_bytes()), false)
+            .unwrap();
+
+        // The load of an empty feed fails and keeps what was loaded before
+        std::fs::write(feed_file, "<tv></tv>").unwrap();
+        let mut worker = EpgUpdaterWorker::new(server.clone(), format!("file://{}", feed_file));
+        worker.update();
+        let st = server.db.get_last_update().unwrap().unwrap();
+        assert!(!st.succeed);
+        assert!(st.message.contains("no channels"));
+        assert_eq!(server.db.get_channels().unwrap().len(), 1);
+        assert_eq!(server.db.get_db_stats().unwrap().programs, 1);
+
+        std::fs::remove_file(feed_file).ok();
+        std::fs::remove_file(file).ok();
+    }
+}
@@ -0,0 +1,374 @@
+use crate::epg::{ChannelInfo, Program};
+use crate::utils::parse_duration;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+/// Operator maintained mapping which merges several feed channels into a canonical one.
+///
+/// Each line of the mapping file has the form `canonical: alias1, alias2, ...`,
+/// the aliases are listed in the order of priority used to resolve overlapping programs.
+/// Empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Default, Clone)]
+pub struct AliasMapping {
+    /// Feed alias to canonical alias and its priority, lower value wins
+    aliases: HashMap<String, (String, usize)>,
+}
+
+impl AliasMapping {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Self::parse(BufReader::new(File::open(path)?))
+    }
+
+    pub fn parse<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut aliases = HashMap::new();
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid alias mapping at line {}", n + 1),
+                )
+            };
+            let mut parts = line.splitn(2, ':');
+            let canonical = parts.next().unwrap_or("").trim();
+            let sources = parts.next().ok_or_else(invalid)?;
+            if canonical.is_empty() {
+                return Err(invalid());
+            }
+            let mut count = 0;
+            for alias in sources.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                aliases.insert(alias.to_string(), (canonical.to_string(), count));
+                count += 1;
+            }
+            // Canonical channel itself has the lowest priority unless it is listed
+            aliases
+                .entry(canonical.to_string())
+                .or_insert_with(|| (canonical.to_string(), count));
+        }
+        Ok(Self { aliases })
+    }
+
+    pub fn len(&self) -> usize {
+        self.aliases.len()
+    }
+
+    /// Alias of the channel which receives data of the given feed channel
+    pub fn canonical<'a>(&'a self, alias: &'a str) -> &'a str {
+        self.aliases.get(alias).map_or(alias, |(c, _)| c.as_str())
+    }
+
+    /// Priority of the feed channel, `None` when it does not take part in merging
+    pub fn priority(&self, alias: &str) -> Option<usize> {
+        self.aliases.get(alias).map(|&(_, p)| p)
+    }
+}
+
+/// Operator defined groups of channels like sports or kids channels.
+///
+/// Each line of the file has the form `group: alias1, alias2, ...`, group names are
+/// case insensitive and lines of the same group add up.
+/// Empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Default, Clone)]
+pub struct ChannelGroups {
+    /// Lowercase group name to aliases of its channels
+    groups: HashMap<String, Vec<String>>,
+}
+
+impl ChannelGroups {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Self::parse(BufReader::new(File::open(path)?))
+    }
+
+    pub fn parse<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid channel group at line {}", n + 1),
+                )
+            };
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next().unwrap_or("").trim();
+            let aliases = parts.next().ok_or_else(invalid)?;
+            if name.is_empty() {
+                return Err(invalid());
+            }
+            let group = groups.entry(name.to_lowercase()).or_insert_with(Vec::new);
+            for alias in aliases.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                if !group.iter().any(|a| a == alias) {
+                    group.push(alias.to_string());
+                }
+            }
+        }
+        Ok(Self { groups })
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// Aliases of the group's channels in the listed order, `None` for an unknown group
+    pub fn get(&self, name: &str) -> Option<&[String]> {
+        self.groups.get(&name.to_lowercase()).map(Vec::as_slice)
+    }
+}
+
+/// Operator maintained channel ids which must not depend on the feed contents.
+///
+/// Each line of the file has the form `alias: id, name`, the name is optional.
+/// Empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Default, Clone)]
+pub struct ChannelSeeds {
+    channels: Vec<(i64, ChannelInfo)>,
+}
+
+impl ChannelSeeds {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Self::parse(BufReader::new(File::open(path)?))
+    }
+
+    pub fn parse<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut channels: Vec<(i64, ChannelInfo)> = Vec::new();
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid channel seed at line {}: {}", n + 1, reason),
+                )
+            };
+            let mut parts = line.splitn(2, ':');
+            let alias = parts.next().unwrap_or("").trim();
+            let rest = parts.next().ok_or_else(|| invalid("missing ':'"))?;
+            let mut parts = rest.splitn(2, ',');
+            let id = parts
+                .next()
+                .unwrap_or("")
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| invalid("bad id"))?;
+            let name = parts.next().unwrap_or("").trim();
+            if alias.is_empty() {
+                return Err(invalid("empty alias"));
+            }
+            if channels.iter().any(|(i, c)| *i == id || c.alias == alias) {
+                return Err(invalid("duplicate alias or id"));
+            }
+            channels.push((
+                id,
+                ChannelInfo {
+                    alias: alias.to_string(),
+                    name: name.to_string(),
+                    ..ChannelInfo::new()
+                },
+            ));
+        }
+        Ok(Self { channels })
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(i64, ChannelInfo)> {
+        self.channels.iter()
+    }
+}
+
+/// Operator maintained durations of programs without `stop` time by channel,
+/// for channels like news tickers which get far too short programs otherwise.
+///
+/// Each line of the file has the form `alias: duration` with durations like `30m` or `2h`.
+/// Empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Default, Clone)]
+pub struct ChannelDurations {
+    durations: HashMap<String, chrono::Duration>,
+}
+
+impl ChannelDurations {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Self::parse(BufReader::new(File::open(path)?))
+    }
+
+    pub fn parse<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut durations = HashMap::new();
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid default duration at line {}: {}", n + 1, reason),
+                )
+            };
+            let mut parts = line.splitn(2, ':');
+            let alias = parts.next().unwrap_or("").trim();
+            let duration = parts.next().ok_or_else(|| invalid("missing ':'"))?;
+            let duration = parse_duration(duration).map_err(|e| invalid(&e.to_string()))?;
+            if alias.is_empty() {
+                return Err(invalid("empty alias"));
+            }
+            durations.insert(alias.to_string(), duration);
+        }
+        Ok(Self { durations })
+    }
+
+    pub fn len(&self) -> usize {
+        self.durations.len()
+    }
+
+    /// Duration of the channel's programs without `stop`, `None` when it is not configured
+    pub fn get(&self, alias: &str) -> Option<chrono::Duration> {
+        self.durations.get(alias).copied()
+    }
+}
+
+/// Drop programs overlapping with programs from higher priority sources,
+/// the result is ordered by begin time
+pub fn merge_by_priority(mut programs: Vec<(usize, Program)>) -> Vec<Program> {
+    programs.sort_by_key(|(priority, p)| (*priority, p.begin));
+    let mut accepted: Vec<Program> = Vec::with_capacity(programs.len());
+    for (_, program) in programs {
+        let index = accepted
+            .binary_search_by_key(&program.begin, |p| p.begin)
+            .unwrap_or_else(|i| i);
+        let overlaps_prev = index > 0 && accepted[index - 1].end > program.begin;
+        let overlaps_next = index < accepted.len() && accepted[index].begin < program.end;
+        if !overlaps_prev && !overlaps_next {
+            accepted.insert(index, program);
+        }
+    }
+    accepted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn parse_seeds() {
+        let data = indoc!(
+            r#"
+            # alias: id, name
+            cnn: 10, CNN
+            bbc: 20
+            "#
+        );
+        let seeds = ChannelSeeds::parse(data.as_bytes()).unwrap();
+        let seeds = seeds
+            .iter()
+            .map(|(id, c)| (*id, c.alias.as_str(), c.name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(seeds, vec![(10, "cnn", "CNN"), (20, "bbc", "")]);
+
+        assert!(ChannelSeeds::parse("cnn: ten".as_bytes()).is_err());
+        assert!(ChannelSeeds::parse("cnn: 1\nbbc: 1".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_durations() {
+        let data = indoc!(
+            r#"
+            # alias: duration
+            ticker: 30m
+            loop: 2h
+            "#
+        );
+        let durations = ChannelDurations::parse(data.as_bytes()).unwrap();
+        assert_eq!(durations.len(), 2);
+        assert_eq!(durations.get("ticker"), Some(chrono::Duration::minutes(30)));
+        assert_eq!(durations.get("loop"), Some(chrono::Duration::hours(2)));
+        assert_eq!(durations.get("news"), None);
+
+        assert!(ChannelDurations::parse("ticker: 30".as_bytes()).is_err());
+        assert!(ChannelDurations::parse("ticker 30m".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_groups() {
+        let data = indoc!(
+            r#"
+            # group: aliases
+            Sports: espn, eurosport
+            kids: cartoon
+            sports: eurosport, sky-sports
+            "#
+        );
+        let groups = ChannelGroups::parse(data.as_bytes()).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(
+            groups.get("SPORTS").unwrap(),
+            &["espn", "eurosport", "sky-sports"]
+        );
+        assert_eq!(groups.get("kids").unwrap(), &["cartoon"]);
+        assert_eq!(groups.get("news"), None);
+
+        assert!(ChannelGroups::parse("sports espn".as_bytes()).is_err());
+        assert!(ChannelGroups::parse(": espn".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse() {
+        let data = indoc!(
+            r#"
+            # canonical: aliases in priority order
+            cnn: cnn-intl, cnn
+
+            bbc: bbc-world
+            "#
+        );
+        let mapping = AliasMapping::parse(data.as_bytes()).unwrap();
+        assert_eq!(mapping.canonical("cnn-intl"), "cnn");
+        assert_eq!(mapping.canonical("cnn"), "cnn");
+        assert_eq!(mapping.canonical("bbc-world"), "bbc");
+        assert_eq!(mapping.canonical("other"), "other");
+        assert_eq!(mapping.priority("cnn-intl"), Some(0));
+        assert_eq!(mapping.priority("cnn"), Some(1));
+        assert_eq!(mapping.priority("bbc"), Some(1));
+        assert_eq!(mapping.priority("other"), None);
+
+        assert!(AliasMapping::parse("cnn cnn-intl".as_bytes()).is_err());
+        assert!(AliasMapping::parse(": cnn-intl".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn merge() {
+        let program = |begin, end, title: &str| Program {
+            begin,
+            end,
+            title: title.to_string(),
+            description: String::new(),
+            ..Program::new()
+        };
+        let merged = merge_by_priority(vec![
+            (1, program(0, 10, "x")),
+            (0, program(5, 15, "a")),
+            (1, program(15, 20, "y")),
+            (0, program(20, 30, "b")),
+            (1, program(25, 40, "z")),
+        ]);
+        assert_eq!(
+            merged.iter().map(|p| p.title.as_str()).collect::<Vec<_>>(),
+            vec!["a", "y", "b"]
+        );
+    }
+}
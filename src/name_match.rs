@@ -1,20 +1,96 @@
+use deunicode::deunicode;
+use serde_derive::Serialize;
 use sprs::*;
+use std::cmp::Ordering;
 use std::collections::vec_deque::VecDeque;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
 use vtext::tokenize::Tokenizer;
 use vtext::vectorize::{CountVectorizer, CountVectorizerParams};
 
+/// Size of the matcher model, helps to tune arity and thresholds
+#[derive(Serialize, Debug, PartialEq)]
+pub struct MatcherStats {
+    /// Number of indexed texts
+    pub texts: usize,
+    /// Length of ngrams
+    pub arity: usize,
+    /// Number of distinct ngrams
+    pub vocabulary: usize,
+    /// Number of nonzero elements of the texts by ngrams matrix
+    pub nnz: usize,
+    /// Mean euclidean norm of text vectors
+    pub average_norm: f32,
+}
+
+/// Similarity of a query to the indexed texts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    /// Cosine of ngram count vectors
+    Cosine,
+    /// Shared distinct ngrams divided by all distinct ngrams of both texts,
+    /// repeated ngrams do not weigh more which suits very short names
+    Jaccard,
+}
+
+impl Default for Metric {
+    fn default() -> Self {
+        Metric::Cosine
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "cosine" => Ok(Metric::Cosine),
+            "jaccard" => Ok(Metric::Jaccard),
+            _ => Err(format!("unknown similarity metric {}", s)),
+        }
+    }
+}
+
+/// How texts are indexed and compared
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MatchOptions {
+    pub metric: Metric,
+    /// Also index a latin transliteration of texts in other scripts,
+    /// so `Rossiya` finds `Россия`
+    pub transliterate: bool,
+}
+
 pub struct VecMatcher {
     vectorizer: CountVectorizer<Ngram>,
     ngram: Ngram,
+    metric: Metric,
     mat: CsMat<f32>,
+    /// Index of the text of each matrix row, transliterated forms follow the texts
+    owners: Vec<usize>,
     row_norms: Vec<f32>,
+    /// Number of distinct ngrams of each text
+    row_sizes: Vec<f32>,
     workspace: Vec<i32>,
     texts: Vec<String>,
 }
 
 impl VecMatcher {
     pub fn new(texts: &[String], arity: usize) -> Self {
+        Self::with_metric(texts, arity, Metric::default())
+    }
+
+    pub fn with_metric(texts: &[String], arity: usize, metric: Metric) -> Self {
+        Self::with_options(
+            texts,
+            arity,
+            MatchOptions {
+                metric,
+                ..MatchOptions::default()
+            },
+        )
+    }
+
+    pub fn with_options(texts: &[String], arity: usize, options: MatchOptions) -> Self {
+        let metric = options.metric;
         // Pad texts
         let arity = arity.max(1);
         let ngram = Ngram::new(arity);
@@ -22,6 +98,16 @@ impl VecMatcher {
         for text in texts {
             storage.push(ngram.pad_str(text));
         }
+        let mut owners = (0..texts.len()).collect::<Vec<_>>();
+        if options.transliterate {
+            for (i, text) in texts.iter().enumerate() {
+                let latin = deunicode(text);
+                if &latin != text {
+                    storage.push(ngram.pad_str(&latin));
+                    owners.push(i);
+                }
+            }
+        }
 
         // Build model
         let mut vectorizer = CountVectorizerParams::default()
@@ -38,8 +124,14 @@ impl VecMatcher {
                 tmp.sqrt()
             })
             .collect();
+        let row_sizes = mat.outer_iterator().map(|vec| vec.nnz() as f32).collect();
+        // Jaccard compares sets, so every present ngram counts once
+        let cmat = match metric {
+            Metric::Cosine => mat.map(|&x| x as f32),
+            Metric::Jaccard => mat.map(|&x| if x > 0 { 1. } else { 0. }),
+        };
         // IDF
-        let mut cmat = mat.map(|&x| x as f32).to_csc();
+        let mut cmat = cmat.to_csc();
         // for mut col in cmat.outer_iterator_mut() {
         //     if col.nnz() > 0 {
         //         let idf = (col.dim() as f32 / col.nnz() as f32);
@@ -53,8 +145,26 @@ impl VecMatcher {
             workspace: vec![0i32; mat.rows()],
             mat: cmat,
             row_norms: norms,
+            row_sizes,
             ngram: ngram,
-            texts: storage,
+            metric,
+            owners,
+            texts: storage.into_iter().take(texts.len()).collect(),
+        }
+    }
+
+    pub fn stats(&self) -> MatcherStats {
+        let average_norm = if self.row_norms.is_empty() {
+            0.
+        } else {
+            self.row_norms.iter().sum::<f32>() / self.row_norms.len() as f32
+        };
+        MatcherStats {
+            texts: self.texts.len(),
+            arity: self.ngram.window,
+            vocabulary: self.mat.cols(),
+            nnz: self.mat.nnz(),
+            average_norm,
         }
     }
 
@@ -62,14 +172,34 @@ impl VecMatcher {
         return self.ngram.unpad_str(&self.texts[index]);
     }
 
+    /// Compare candidates so that the better one is greater.
+    /// Equal scores are resolved in favour of the shorter text,
+    /// then the lexicographically smaller one, so that results are reproducible.
+    fn compare_candidates(&self, (i, x): (usize, f32), (j, y): (usize, f32)) -> Ordering {
+        // NaN is less than any score and equal to another NaN, which keeps the order total
+        let by_score = x
+            .partial_cmp(&y)
+            .unwrap_or_else(|| y.is_nan().cmp(&x.is_nan()));
+        let (a, b) = (self.get_text(i), self.get_text(j));
+        by_score
+            .then_with(|| b.chars().count().cmp(&a.chars().count()))
+            .then_with(|| b.cmp(a))
+            .then_with(|| j.cmp(&i))
+    }
+
     #[inline]
     fn compute_prob<'a>(&mut self, padded_text: String) -> CsMat<f32> {
-        let mat = self.vectorizer.transform(&[padded_text]).map(|&x| x as f32);
+        let mat = self.vectorizer.transform(&[padded_text]);
+        let mat = match self.metric {
+            Metric::Cosine => mat.map(|&x| x as f32),
+            Metric::Jaccard => mat.map(|&x| if x > 0 { 1. } else { 0. }),
+        };
         assert!(self.mat.is_csc());
         assert!(mat.transpose_view().is_csc());
         &self.mat * &mat.transpose_view()
     }
 
+    /// Euclidean norm of the query for cosine, the number of its distinct ngrams for jaccard
     fn compute_norm(&self, padded_text: &str) -> f32 {
         let mut token_hash = HashMap::new();
         let padded_text = padded_text.to_ascii_lowercase();
@@ -77,9 +207,24 @@ impl VecMatcher {
             let count = token_hash.entry(tok).or_insert(0);
             *count += 1;
         }
-        // Because we have padded text norm is positive
-        let tmp: f32 = token_hash.values().map(|&c| (c * c) as f32).sum();
-        tmp.sqrt()
+        match self.metric {
+            Metric::Cosine => {
+                // Because we have padded text norm is positive
+                let tmp: f32 = token_hash.values().map(|&c| (c * c) as f32).sum();
+                tmp.sqrt()
+            }
+            Metric::Jaccard => token_hash.len() as f32,
+        }
+    }
+
+    /// Text of the row `i` and its similarity to the query from their dot product
+    #[inline]
+    fn score(&self, i: usize, dot: f32, norm: f32) -> (usize, f32) {
+        let sim = match self.metric {
+            Metric::Cosine => dot / norm / self.row_norms[i],
+            Metric::Jaccard => dot / (norm + self.row_sizes[i] - dot),
+        };
+        (self.owners[i], sim)
     }
 
     pub fn search_best(&mut self, text: &str, threshold: f32) -> Option<(usize, f32)> {
@@ -90,18 +235,10 @@ impl VecMatcher {
         let prob = m.outer_view(0).unwrap();
         assert_eq!(prob.dim(), self.mat.rows());
 
-        use std::cmp::Ordering;
         if let Some((i, val)) = prob
             .iter()
-            .map(|(i, &val)| (i, val as f32 / norm / self.row_norms[i]))
-            .max_by(|(_, x), (_, y)| {
-                // Ignore NaN by makeing it always less
-                x.partial_cmp(y).unwrap_or(if x.is_nan() {
-                    Ordering::Less
-                } else {
-                    Ordering::Greater
-                })
-            })
+            .map(|(i, &val)| self.score(i, val, norm))
+            .max_by(|&a, &b| self.compare_candidates(a, b))
         {
             if val >= threshold {
                 Some((i, val))
@@ -124,19 +261,17 @@ impl VecMatcher {
         // TODO: find top n can be done faster than sorting all
         let mut v = prob
             .iter()
-            .map(|(i, &val)| (i, val as f32 / norm / self.row_norms[i]))
-            .filter(|&(_, val)| val > threshold)
+            .map(|(i, &val)| self.score(i, val, norm))
+            .filter(|&(_, val)| val >= threshold)
             .collect::<Vec<_>>();
-        use std::cmp::Ordering;
-        v.sort_by(|(_, x), (_, y)| {
-            // Ignore NaN by makeing it always less
-            x.partial_cmp(y).unwrap_or(if x.is_nan() {
-                Ordering::Less
-            } else {
-                Ordering::Greater
-            })
-        });
-        v.into_iter().rev().take(nbest).collect()
+        v.sort_by(|&a, &b| self.compare_candidates(a, b));
+        // A text and its transliteration may both match, the better one is kept
+        let mut seen = HashSet::new();
+        v.into_iter()
+            .rev()
+            .filter(|&(i, _)| seen.insert(i))
+            .take(nbest)
+            .collect()
     }
 }
 
@@ -262,4 +397,83 @@ mod tests {
         assert_eq!(i, 0);
         assert_approx_eq!(sim, 1., 1e-3);
     }
+
+    #[test]
+    fn stats() {
+        let dataset = vec!["ab".to_owned(), "ba".to_owned()];
+        let stats = VecMatcher::new(&dataset, 2).stats();
+        assert_eq!(
+            (stats.texts, stats.arity, stats.vocabulary, stats.nnz),
+            (2, 2, 6, 6)
+        );
+        assert_approx_eq!(stats.average_norm, 3f32.sqrt(), 1e-6);
+    }
+
+    #[test]
+    fn search_tie_break() {
+        // Both names share exactly one padded bigram with the query
+        let dataset = vec!["ba".to_owned(), "ab".to_owned(), "abc".to_owned()];
+        let mut corpus = VecMatcher::new(&dataset, 2);
+        let (i, _) = corpus.search_best("a", 0.1).unwrap();
+        assert_eq!(i, 1);
+
+        let found = corpus.search("a", 0.1, 10);
+        assert_approx_eq!(found[0].1, found[1].1, 1e-6);
+        assert_eq!(
+            found.iter().map(|&(i, _)| i).collect::<Vec<_>>(),
+            vec![1, 0, 2]
+        );
+    }
+
+    #[test]
+    fn metrics() {
+        assert_eq!("Jaccard".parse::<Metric>(), Ok(Metric::Jaccard));
+        assert!("dice".parse::<Metric>().is_err());
+
+        // The first name repeats the bigram "aa" which cosine rewards,
+        // jaccard prefers the second one with fewer distinct bigrams
+        let dataset = vec!["aaaba".to_owned(), "ababa".to_owned()];
+        let mut cosine = VecMatcher::with_metric(&dataset, 2, Metric::Cosine);
+        let found = cosine.search("a", 0.1, 10);
+        assert_eq!(found[0].0, 0);
+        assert_approx_eq!(found[0].1, 0.5, 1e-6);
+        assert_approx_eq!(found[1].1, 0.2f32.sqrt(), 1e-6);
+
+        let mut jaccard = VecMatcher::with_metric(&dataset, 2, Metric::Jaccard);
+        let found = jaccard.search("a", 0.1, 10);
+        assert_eq!(found[0].0, 1);
+        assert_approx_eq!(found[0].1, 0.5, 1e-6);
+        assert_approx_eq!(found[1].1, 0.4, 1e-6);
+    }
+
+    #[test]
+    fn transliteration() {
+        let dataset = vec!["Россия".to_owned(), "Rossiia 24".to_owned()];
+        let mut plain = VecMatcher::new(&dataset, 2);
+        assert_eq!(plain.search_best("Rossiya", 0.7), None);
+        assert_eq!(plain.stats().texts, 2);
+
+        let options = MatchOptions {
+            transliterate: true,
+            ..MatchOptions::default()
+        };
+        let mut corpus = VecMatcher::with_options(&dataset, 2, options);
+        let (i, sim) = corpus.search_best("Rossiya", 0.7).unwrap();
+        assert_eq!((i, corpus.get_text(i)), (0, "Россия"));
+        assert_approx_eq!(sim, 0.75, 1e-6);
+        assert_eq!(corpus.stats().texts, 2);
+
+        // Both forms of the name match, it is found once
+        let mut corpus = VecMatcher::with_options(&["Россия 1".to_owned()], 2, options);
+        assert_eq!(corpus.search("1", 0.1, 10).len(), 1);
+    }
+
+    #[test]
+    fn jaccard_exact() {
+        let dataset = vec!["TV3".to_owned(), "TV 3".to_owned()];
+        let mut corpus = VecMatcher::with_metric(&dataset, 2, Metric::Jaccard);
+        let (i, sim) = corpus.search_best("tv 3", 0.9).unwrap();
+        assert_eq!(i, 1);
+        assert_approx_eq!(sim, 1., 1e-6);
+    }
 }
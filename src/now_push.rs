@@ -0,0 +1,317 @@
+use crate::epg::{EpgNow, NowNext};
+use crate::throttle::Semaphore;
+use crate::utils::ServerTiming;
+use crate::EpgSqlServer;
+use chrono::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::{http, Message};
+
+/// Number of messages waiting for a client before it is dropped as too slow
+const QUEUE_LEN: usize = 8;
+/// Upper limit for the sleep of the timer, so feed updates are noticed too
+const MAX_DELAY: Duration = Duration::from_secs(60);
+/// How often a client connection checks for messages to send
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Upper limit for connected clients, each of them takes a thread
+const MAX_CLIENTS: usize = 1000;
+/// Time for a client to complete the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time for a client to take a message before it is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Client waiting for changes of the channels
+struct Subscriber {
+    ids: HashSet<i64>,
+    sender: SyncSender<String>,
+}
+
+/// Notifies clients when the program on air changes on one of their channels
+pub struct Broadcaster {
+    subscribers: Mutex<Vec<Subscriber>>,
+    /// Begin of the program on air by channel as of the last publish
+    current: Mutex<HashMap<i64, Option<i64>>>,
+}
+
+impl Broadcaster {
+    pub fn new() -> Self {
+        Self {
+            subscribers: Mutex::new(Vec::new()),
+            current: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Messages for the client, the channel disconnects when the client is dropped
+    pub fn subscribe(&self, ids: &[i64]) -> Receiver<String> {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+        self.subscribers.lock().unwrap().push(Subscriber {
+            ids: ids.iter().cloned().collect(),
+            sender,
+        });
+        receiver
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+
+    /// Send now and next programs to the clients of channels where the program on air
+    /// changed since the last call, returns the time of the next program boundary
+    pub fn publish(&self, data: &HashMap<i64, EpgNow>, time: i64) -> Option<i64> {
+        let mut changed = HashSet::new();
+        let mut next = None;
+        {
+            let mut current = self.current.lock().unwrap();
+            for (&id, epg) in data {
+                let now_next = NowNext::at(epg, time);
+                let begin = now_next.now.as_ref().map(|p| p.begin);
+                if current.insert(id, begin) != Some(begin) {
+                    changed.insert(id);
+                }
+                let boundary = match (&now_next.now, &now_next.next) {
+                    (Some(now), _) => Some(now.end),
+                    (None, Some(next)) => Some(next.begin),
+                    (None, None) => None,
+                };
+                next = next.into_iter().chain(boundary).min();
+            }
+        }
+        self.subscribers.lock().unwrap().retain(|s| {
+            let ids = s
+                .ids
+                .iter()
+                .cloned()
+                .filter(|id| changed.contains(id))
+                .collect::<Vec<_>>();
+            if ids.is_empty() {
+                return true;
+            }
+            match s.sender.try_send(message(data, &ids, time)) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    eprintln!("Dropping now playing client which does not keep up");
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+        next
+    }
+}
+
+/// Json with now and next programs of the channels in the `/now_next` format
+pub fn message(data: &HashMap<i64, EpgNow>, ids: &[i64], time: i64) -> String {
+    let list = ids
+        .iter()
+        .filter_map(|id| data.get(id))
+        .map(|epg| NowNext::at(epg, time))
+        .collect::<Vec<_>>();
+    serde_json::json!({ "data": list }).to_string()
+}
+
+/// Channel ids of the `ids=1,2,3` query parameter
+fn parse_ids(query: Option<&str>) -> Option<Vec<i64>> {
+    query?
+        .split('&')
+        .find_map(|param| param.strip_prefix("ids="))?
+        .split(',')
+        .map(|id| id.parse::<i64>().ok())
+        .collect()
+}
+
+/// Accept websocket clients on the port and push now and next programs
+/// of the channels requested as `ws://host:port/?ids=1,2,3`
+pub fn spawn(app: Arc<EpgSqlServer>, port: u16) -> io::Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(format!("localhost:{}", port))?;
+    let broadcaster = Arc::new(Broadcaster::new());
+    {
+        let app = app.clone();
+        let broadcaster = broadcaster.clone();
+        thread::spawn(move || loop {
+            let now = Utc::now();
+            let next = match app.get_epg_list(now, None, &mut ServerTiming::new()) {
+                Ok(body) => broadcaster.publish(&body.data, now.timestamp()),
+                Err(e) => {
+                    eprintln!("Failed to read programs on air: {}", e);
+                    None
+                }
+            };
+            let delay = next.map_or(MAX_DELAY, |t| {
+                Duration::from_secs((t - now.timestamp()).max(1) as u64).min(MAX_DELAY)
+            });
+            thread::sleep(delay);
+        });
+    }
+    let clients = Arc::new(Semaphore::new(MAX_CLIENTS));
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    // The slot is taken before the thread is spawned, so refused clients
+                    // cost none, the connection is closed when the stream is dropped
+                    let permit = match clients.clone().try_acquire_owned() {
+                        Some(permit) => permit,
+                        None => {
+                            eprintln!("Refusing websocket client, {} connected", MAX_CLIENTS);
+                            continue;
+                        }
+                    };
+                    let app = app.clone();
+                    let broadcaster = broadcaster.clone();
+                    thread::spawn(move || {
+                        let _permit = permit;
+                        serve_client(&app, &broadcaster, stream)
+                    });
+                }
+                Err(e) => eprintln!("Failed to accept websocket client: {}", e),
+            }
+        }
+    }))
+}
+
+fn serve_client(app: &EpgSqlServer, broadcaster: &Broadcaster, stream: TcpStream) {
+    // Clients which stall the handshake or stop reading do not hold the thread
+    let timeouts = stream
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(WRITE_TIMEOUT)));
+    if let Err(e) = timeouts {
+        eprintln!("Failed to set websocket timeout: {}", e);
+        return;
+    }
+    let mut ids = Vec::new();
+    let callback = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+        match parse_ids(request.uri().query()) {
+            Some(parsed) => {
+                ids = parsed;
+                Ok(response)
+            }
+            None => Err(http::Response::builder()
+                .status(http::StatusCode::BAD_REQUEST)
+                .body(Some("Missing or bad ids parameter".to_string()))
+                .unwrap()),
+        }
+    };
+    let mut ws = match tungstenite::accept_hdr(stream, callback) {
+        Ok(ws) => ws,
+        Err(e) => {
+            eprintln!("Websocket handshake failed: {}", e);
+            return;
+        }
+    };
+    // Reads time out, so queued messages are sent in between
+    if let Err(e) = ws.get_mut().set_read_timeout(Some(POLL_INTERVAL)) {
+        eprintln!("Failed to set websocket timeout: {}", e);
+        return;
+    }
+    let receiver = broadcaster.subscribe(&ids);
+    println!("Now playing client joined, {} clients", broadcaster.len());
+
+    // Programs on air right away, changes are pushed by the timer
+    let now = Utc::now();
+    let initial = match app.get_epg_list(now, None, &mut ServerTiming::new()) {
+        Ok(body) => message(&body.data, &ids, now.timestamp()),
+        Err(e) => {
+            eprintln!("Failed to read programs on air: {}", e);
+            return;
+        }
+    };
+    if ws.write_message(Message::Text(initial)).is_err() {
+        return;
+    }
+    loop {
+        // Pings are answered and close is confirmed while reading
+        match ws.read_message() {
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(ref e))
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+            }
+            Err(_) => break,
+        }
+        let text = match receiver.try_recv() {
+            Ok(text) => text,
+            Err(TryRecvError::Empty) => continue,
+            // Dropped by the broadcaster as too slow
+            Err(TryRecvError::Disconnected) => break,
+        };
+        if ws.write_message(Message::Text(text)).is_err() {
+            break;
+        }
+    }
+    ws.close(None).ok();
+    ws.write_pending().ok();
+    println!("Now playing client left");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epg::Program;
+
+    fn epg(channel_id: i64, times: &[(i64, i64)]) -> (i64, EpgNow) {
+        let programs = times
+            .iter()
+            .map(|&(begin, end)| Program {
+                begin,
+                end,
+                title: format!("{}-{}", channel_id, begin),
+                ..Program::new()
+            })
+            .collect();
+        (
+            channel_id,
+            EpgNow {
+                channel_id,
+                programs,
+            },
+        )
+    }
+
+    #[test]
+    fn publish_changes() {
+        let data = vec![epg(1, &[(0, 10), (10, 20)]), epg(2, &[(0, 15), (15, 30)])]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let broadcaster = Broadcaster::new();
+        assert_eq!(broadcaster.publish(&data, 5), Some(10));
+
+        let first = broadcaster.subscribe(&[1]);
+        let second = broadcaster.subscribe(&[2]);
+        // Nothing changed on air
+        assert_eq!(broadcaster.publish(&data, 6), Some(10));
+        assert!(first.try_recv().is_err());
+
+        assert_eq!(broadcaster.publish(&data, 10), Some(15));
+        assert!(first.try_recv().unwrap().contains(r#""title":"1-10""#));
+        assert!(second.try_recv().is_err());
+
+        drop(second);
+        broadcaster.publish(&data, 16);
+        assert_eq!(broadcaster.len(), 1);
+    }
+
+    #[test]
+    fn slow_client() {
+        let broadcaster = Broadcaster::new();
+        let _receiver = broadcaster.subscribe(&[1]);
+        for t in 0..=QUEUE_LEN as i64 {
+            let data = vec![epg(1, &[(t, t + 1)])].into_iter().collect();
+            broadcaster.publish(&data, t);
+        }
+        assert_eq!(broadcaster.len(), 0);
+    }
+
+    #[test]
+    fn ids() {
+        assert_eq!(parse_ids(Some("ids=1,2")), Some(vec![1, 2]));
+        assert_eq!(parse_ids(Some("x=1&ids=3")), Some(vec![3]));
+        assert_eq!(parse_ids(Some("ids=a")), None);
+        assert_eq!(parse_ids(None), None);
+    }
+}
@@ -2,7 +2,7 @@ use crate::epg::ChannelInfo;
 use crate::m3u;
 use crate::m3u::Playlist;
 use crate::m3u::PlaylistWriter;
-use crate::name_match::VecMatcher;
+use crate::name_match::{MatchOptions, VecMatcher};
 use crate::utils::{bad_request, server_error};
 use crate::EpgSqlServer;
 use askama::Template;
@@ -10,11 +10,13 @@ use io::Read;
 use iron::prelude::*;
 use iron::status;
 use lazy_static::lazy_static;
+use multipart::server::iron::Intercept;
 use multipart::server::save::DataReader;
 use multipart::server::Entries;
 use router::Router;
 use serde_derive::Serialize;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::iter::FromIterator;
 use std::time::Instant;
@@ -24,7 +26,22 @@ use urlencoded::UrlEncodedBody;
 pub struct PlaylistModel {}
 
 const SIM_GOOD: f32 = 0.7;
-const SIM_POSSIBLE: f32 = 0.45;
+pub const SIM_POSSIBLE: f32 = 0.45;
+/// Number of channels found by name unless configured
+pub const SEARCH_LIMIT: usize = 10;
+/// Upper limit for the number of channels found by name
+pub const MAX_SEARCH_LIMIT: usize = 100;
+
+/// Upper limit for the size of the changes map of a playlist download in bytes
+const MAX_CHANGES_SIZE: u64 = 1 << 20;
+/// Upper limit for the number of entries of the changes map
+const MAX_CHANGES: usize = 10_000;
+/// Name of the downloaded playlist when the upload had no usable name
+const DEFAULT_PLAYLIST_NAME: &str = "playlist.m3u";
+/// Content type of m3u playlists which players recognize
+const PLAYLIST_CONTENT_TYPE: &str = "audio/x-mpegurl";
+/// Upper limit for the length of the channel list in the `Warning` header of a download
+const MAX_WARNING_LENGTH: usize = 512;
 
 struct ProcessedItem {
     entry: m3u::Entry,
@@ -41,15 +58,22 @@ struct SearchResultItem {
 fn process<R: io::BufRead>(
     buf: R,
     channels: &[ChannelInfo],
+    options: MatchOptions,
 ) -> Result<Vec<ProcessedItem>, m3u::Error> {
     let t = Instant::now();
 
     let mut result = Vec::new();
     let dataset = channels.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
-    let mut corpus = VecMatcher::new(&dataset, 2);
+    let mut corpus = VecMatcher::with_options(&dataset, 2, options);
     for elem in Playlist::open(buf) {
         let mut elem = elem?;
-        let ret = corpus.search_best(elem.name(), SIM_GOOD);
+        // Guide name is closer to the channel names in the database
+        let query = if elem.tvg_name().is_empty() {
+            elem.name()
+        } else {
+            elem.tvg_name()
+        };
+        let ret = corpus.search_best(query, SIM_GOOD);
         if let Some((index, mut sim)) = ret {
             if (sim - 1.0).abs() < 1e-5 {
                 sim = 1.0
@@ -74,11 +98,53 @@ fn process<R: io::BufRead>(
     Ok(result)
 }
 
+/// Items with their playlist positions in the order of review, weakest matches first.
+/// The sort is stable, so items with equal similarity stay in playlist order.
+fn review_order(items: &[ProcessedItem]) -> Vec<(usize, &ProcessedItem)> {
+    let mut rows = items.iter().enumerate().collect::<Vec<_>>();
+    rows.sort_by(|(_, a), (_, b)| a.sim.partial_cmp(&b.sim).unwrap_or(Ordering::Equal));
+    rows
+}
+
+/// Aliases of the channels of the playlist: tvg-ids of its entries along with the channels
+/// found by name, so entries without a tvg-id and unknown tvg-ids contribute as well
+pub fn playlist_aliases<R: io::BufRead>(
+    mut buf: R,
+    channels: &[ChannelInfo],
+    options: MatchOptions,
+) -> Result<HashSet<String>, m3u::Error> {
+    let mut data = Vec::new();
+    buf.read_to_end(&mut data)?;
+    let mut aliases = HashSet::new();
+    for entry in Playlist::open(data.as_slice()) {
+        let entry = entry?;
+        if !entry.tvg_id().is_empty() {
+            aliases.insert(entry.tvg_id().to_string());
+        }
+    }
+    for item in process(data.as_slice(), channels, options)? {
+        if !item.entry.tvg_id().is_empty() {
+            aliases.insert(item.entry.tvg_id().to_string());
+        }
+    }
+    Ok(aliases)
+}
+
+/// Number of channels found by name, the requested one or the default, at most `MAX_SEARCH_LIMIT`
+pub fn search_limit(requested: Option<usize>, default: usize) -> usize {
+    requested.unwrap_or(default).min(MAX_SEARCH_LIMIT)
+}
+
 /// Searches channels with similar name in the database
-fn find(name: &str, channels: &[ChannelInfo]) -> Vec<SearchResultItem> {
+fn find(
+    name: &str,
+    channels: &[ChannelInfo],
+    options: MatchOptions,
+    limit: usize,
+) -> Vec<SearchResultItem> {
     let dataset = channels.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
-    let mut corpus = VecMatcher::new(&dataset, 2);
-    let ret = corpus.search(name, SIM_POSSIBLE, 10);
+    let mut corpus = VecMatcher::with_options(&dataset, 2, options);
+    let ret = corpus.search(name, SIM_POSSIBLE, limit);
     ret.iter()
         .map(|(index, _sim)| {
             let c = &channels[*index];
@@ -98,10 +164,11 @@ fn replace_tvg<R: io::BufRead>(
     let aliases = HashMap::<&str, &str>::from_iter(
         channels.iter().map(|c| (c.name.as_str(), c.alias.as_str())),
     );
-    let mut result = PlaylistWriter::new();
-    let playlist = Playlist::open(buf);
-    for entry in playlist {
-        let mut entry = entry?;
+    let mut playlist = Playlist::open(buf);
+    let entries = playlist.by_ref().collect::<Result<Vec<_>, _>>()?;
+    // Keep the guide url and notes of the original header
+    let mut result = PlaylistWriter::with_header(playlist.header(), playlist.comments());
+    for mut entry in entries {
         if let Some(name) = replace.get(entry.name()) {
             if name.is_empty() {
                 entry.set_tvg_id("");
@@ -114,6 +181,104 @@ fn replace_tvg<R: io::BufRead>(
     Ok(result.into())
 }
 
+/// Read the json map of playlist names to channel names, returns it with the channel
+/// names which are not known, an empty name resets the guide id
+fn parse_changes<R: io::Read>(
+    reader: R,
+    channels: &[ChannelInfo],
+) -> Result<(HashMap<String, String>, Vec<String>), ErrorMessage> {
+    let mut text = String::new();
+    reader
+        .take(MAX_CHANGES_SIZE + 1)
+        .read_to_string(&mut text)
+        .map_err(|e| ErrorMessage(format!("Failed to read changes: {}", e)))?;
+    if text.len() as u64 > MAX_CHANGES_SIZE {
+        return Err(ErrorMessage(format!(
+            "Changes are larger than {} bytes",
+            MAX_CHANGES_SIZE
+        )));
+    }
+    let changes: HashMap<String, String> = serde_json::from_str(&text)
+        .map_err(|e| ErrorMessage(format!("Invalid changes json: {}", e)))?;
+    if changes.len() > MAX_CHANGES {
+        return Err(ErrorMessage(format!(
+            "Too many changes, at most {} are allowed",
+            MAX_CHANGES
+        )));
+    }
+    let known = channels
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect::<HashSet<_>>();
+    let mut unknown = changes
+        .values()
+        .filter(|name| !name.is_empty() && !known.contains(name.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    unknown.sort();
+    unknown.dedup();
+    Ok((changes, unknown))
+}
+
+/// `Warning` listing the unknown channels of the changes. The names are submitted by the user,
+/// so they are reduced to printable ascii and the list is cut at `MAX_WARNING_LENGTH`.
+fn unknown_channels_warning(unknown: &[String]) -> String {
+    let mut names = unknown
+        .join(", ")
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c == ' ' || c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    if names.len() > MAX_WARNING_LENGTH {
+        names.truncate(MAX_WARNING_LENGTH);
+        names.push_str("...");
+    }
+    format!("199 - \"Unknown channels: {}\"", names)
+}
+
+/// `Content-Disposition` which saves the playlist under the name of the upload.
+/// Directories are dropped, a missing m3u extension is added and names which are
+/// not plain ascii are also given percent encoded as `filename*`.
+fn attachment_disposition(name: Option<&str>) -> String {
+    let name = name
+        .map(|s| s.rsplit(&['/', '\\'][..]).next().unwrap_or("").trim())
+        .filter(|s| !s.is_empty())
+        .unwrap_or(DEFAULT_PLAYLIST_NAME);
+    let lower = name.to_lowercase();
+    let name = if lower.ends_with(".m3u") || lower.ends_with(".m3u8") {
+        name.to_string()
+    } else {
+        format!("{}.m3u", name)
+    };
+    let ascii = name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c == ' ' || c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    if ascii == name {
+        return format!("attachment; filename=\"{}\"", name);
+    }
+    let encoded = name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect::<String>();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        ascii, encoded
+    )
+}
+
 #[derive(Debug)]
 struct ErrorMessage(String);
 
@@ -139,7 +304,7 @@ lazy_static! {
 }
 
 impl PlaylistModel {
-    pub fn new() -> Router {
+    pub fn new() -> Chain {
         let mut router = Router::new();
         router.get("/index.html", PlaylistModel::welcome_page, "welcome_page");
         router.post(
@@ -153,7 +318,9 @@ impl PlaylistModel {
             PlaylistModel::download_playlist,
             "download_playlist",
         );
-        router
+        let mut chain = Chain::new(router);
+        chain.link_before(Intercept::default());
+        chain
     }
 
     fn get_entry<'a>(entries: &'a Entries, key: &str) -> IronResult<DataReader<'a>> {
@@ -166,6 +333,22 @@ impl PlaylistModel {
         entry.data.readable().map_err(bad_request)
     }
 
+    /// Text of an optional form field
+    fn get_text(entries: &Entries, key: &str) -> IronResult<Option<String>> {
+        let entry = match entries.fields.get(key).and_then(|v| v.first()) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let mut text = String::new();
+        entry
+            .data
+            .readable()
+            .map_err(bad_request)?
+            .read_to_string(&mut text)
+            .map_err(bad_request)?;
+        Ok(Some(text))
+    }
+
     fn welcome_page(_req: &mut Request) -> IronResult<Response> {
         #[derive(Template)]
         #[template(path = "playlist.html")]
@@ -200,13 +383,21 @@ impl PlaylistModel {
         }
 
         let file = Self::get_entry(&entries, "playlistFile")?;
+        let filename = entries
+            .fields
+            .get("playlistFile")
+            .and_then(|v| v.first())
+            .and_then(|entry| entry.headers.filename.clone())
+            .unwrap_or_default();
+        let _permit = data.acquire_match_permit()?;
         let channels = data
-            .get_channels()
+            .get_channels(false, None)
             .map_err(server_error)?
             .into_iter()
             .map(|(_, c)| c)
             .collect::<Vec<_>>();
-        let channels = process(file, &channels).map_err(bad_request)?;
+        let channels =
+            process(file, &channels, data.settings().match_options).map_err(bad_request)?;
         let mut playlist = PlaylistWriter::new();
         for c in channels.iter() {
             playlist.push(&c.entry)
@@ -217,14 +408,17 @@ impl PlaylistModel {
         struct PlaylistTemplate<'a> {
             sim_good: f32,
             playlist: &'a str,
-            channels: &'a [ProcessedItem],
+            filename: &'a str,
+            /// Playlist positions and items, the downloaded playlist keeps its own order
+            rows: Vec<(usize, &'a ProcessedItem)>,
         }
         Ok(Response::with((
             status::Ok,
             PlaylistTemplate {
                 sim_good: SIM_GOOD,
                 playlist: &buf,
-                channels: &channels,
+                filename: &filename,
+                rows: review_order(&channels),
             },
         )))
     }
@@ -232,26 +426,34 @@ impl PlaylistModel {
     fn find_matches(req: &mut Request) -> IronResult<Response> {
         use iron::mime::Mime;
         let server = req.get::<persistent::Read<EpgSqlServer>>().unwrap();
-        let name = req
-            .get_ref::<UrlEncodedBody>()
-            .ok()
+        let params = req.get_ref::<UrlEncodedBody>().ok();
+        let name = params
             .and_then(|params| params.get("name"))
             .and_then(|l| l.last())
             .ok_or_else(|| ErrorMessage::from("Invalid parameters"))
             .map_err(bad_request)?;
+        let limit = params
+            .and_then(|params| params.get("limit"))
+            .and_then(|l| l.last())
+            .map(|v| v.parse::<usize>())
+            .transpose()
+            .map_err(bad_request)?;
+        let settings = server.settings();
+        let limit = search_limit(limit, settings.search_limit);
 
         #[derive(Serialize)]
         struct Json {
             data: Vec<SearchResultItem>,
         }
+        let _permit = server.acquire_match_permit()?;
         let channels = server
-            .get_channels()
+            .get_channels(false, None)
             .map_err(server_error)?
             .into_iter()
             .map(|(_, c)| c)
             .collect::<Vec<_>>();
         let out = serde_json::to_string(&Json {
-            data: dbg!(find(name, &channels)),
+            data: find(name, &channels, settings.match_options, limit),
         })
         .map_err(bad_request)?;
         Ok(Response::with((
@@ -283,20 +485,262 @@ impl PlaylistModel {
 
         let file = Self::get_entry(&entries, "playlistFile")?;
         let changes = Self::get_entry(&entries, "changes")?;
+        let filename = Self::get_text(&entries, "filename")?;
 
-        let replace: HashMap<String, String> =
-            serde_json::from_reader(changes).map_err(bad_request)?;
         let channels = server
-            .get_channels()
+            .get_channels(false, None)
             .map_err(server_error)?
             .into_iter()
             .map(|(_, c)| c)
             .collect::<Vec<_>>();
+        let (replace, unknown) = parse_changes(changes, &channels).map_err(bad_request)?;
         let out = replace_tvg(file, replace, &channels).map_err(bad_request)?;
-        Ok(Response::with((
+        let mut response = Response::with((
             status::Ok,
-            "application/mpegurl".parse::<Mime>().unwrap(),
+            PLAYLIST_CONTENT_TYPE.parse::<Mime>().unwrap(),
             out,
-        )))
+        ));
+        response.headers.set_raw(
+            "Content-Disposition",
+            vec![attachment_disposition(filename.as_deref()).into_bytes()],
+        );
+        if !unknown.is_empty() {
+            // Entries changed to unknown channels keep their guide id
+            eprintln!("Playlist changes with unknown channels: {:?}", unknown);
+            let warning = unknown_channels_warning(&unknown);
+            response
+                .headers
+                .set_raw("Warning", vec![warning.into_bytes()]);
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn process_prefers_tvg_name() {
+        let channel = |alias: &str, name: &str| ChannelInfo {
+            alias: alias.to_string(),
+            name: name.to_string(),
+            ..ChannelInfo::new()
+        };
+        let channels = vec![
+            channel("disc", "Discovery Channel"),
+            channel("es", "Eurosport 1"),
+        ];
+        let data = indoc!(
+            r#"#EXTM3U
+        #EXTINF:0 tvg-name="Discovery Channel",DSC HD+ (backup)
+        http://iptv.com/1.m3u8
+        #EXTINF:0,DSC HD+ (backup)
+        http://iptv.com/2.m3u8
+        "#
+        );
+        let items = process(data.as_bytes(), &channels, MatchOptions::default()).unwrap();
+        assert_eq!(items[0].entry.tvg_id(), "disc");
+        assert_eq!(items[0].name, "Discovery Channel");
+        assert_eq!(items[1].entry.tvg_id(), "");
+    }
+
+    #[test]
+    fn review_weakest_first() {
+        let data = indoc!(
+            r#"#EXTM3U
+        #EXTINF:0,Discovery Channel
+        http://iptv.com/1.m3u8
+        #EXTINF:0,Weather Forecast
+        http://iptv.com/2.m3u8
+        #EXTINF:0,Eurosport
+        http://iptv.com/3.m3u8
+        #EXTINF:0,Cooking
+        http://iptv.com/4.m3u8
+        "#
+        );
+        let mut items = process(data.as_bytes(), &[], MatchOptions::default()).unwrap();
+        for (item, &sim) in items.iter_mut().zip(&[1.0, 0.0, 0.8, 0.0]) {
+            item.sim = sim;
+        }
+        let order = review_order(&items)
+            .into_iter()
+            .map(|(i, item)| (i, item.entry.name()))
+            .collect::<Vec<_>>();
+        // Unmatched entries keep their playlist order
+        assert_eq!(
+            order,
+            vec![
+                (1, "Weather Forecast"),
+                (3, "Cooking"),
+                (2, "Eurosport"),
+                (0, "Discovery Channel")
+            ]
+        );
+    }
+
+    #[test]
+    fn aliases_of_playlist() {
+        let channels = vec![
+            ChannelInfo {
+                alias: "disc".to_string(),
+                name: "Discovery Channel".to_string(),
+                ..ChannelInfo::new()
+            },
+            ChannelInfo {
+                alias: "es".to_string(),
+                name: "Eurosport 1".to_string(),
+                ..ChannelInfo::new()
+            },
+        ];
+        let data = indoc!(
+            r#"#EXTM3U
+        #EXTINF:0 tvg-id="bbc1",BBC One
+        http://iptv.com/1.m3u8
+        #EXTINF:0,Discovery Channel
+        http://iptv.com/2.m3u8
+        #EXTINF:0,Weather Forecast
+        http://iptv.com/3.m3u8
+        "#
+        );
+        let aliases =
+            playlist_aliases(data.as_bytes(), &channels, MatchOptions::default()).unwrap();
+        let mut aliases = aliases.into_iter().collect::<Vec<_>>();
+        aliases.sort();
+        // The tvg-id of an unmatched entry is kept, the matched name adds its channel
+        assert_eq!(aliases, vec!["bbc1", "disc"]);
+    }
+
+    #[test]
+    fn find_limit() {
+        let channels = (1..=20)
+            .map(|i| ChannelInfo {
+                alias: format!("sport{}", i),
+                name: format!("Sport {}", i),
+                ..ChannelInfo::new()
+            })
+            .collect::<Vec<_>>();
+        let found = |limit| find("Sport", &channels, MatchOptions::default(), limit).len();
+        assert_eq!(found(search_limit(None, SEARCH_LIMIT)), 10);
+        assert_eq!(found(search_limit(Some(3), SEARCH_LIMIT)), 3);
+        assert_eq!(found(search_limit(Some(15), SEARCH_LIMIT)), 15);
+        assert_eq!(search_limit(Some(100_000), SEARCH_LIMIT), MAX_SEARCH_LIMIT);
+        assert_eq!(search_limit(None, 500), MAX_SEARCH_LIMIT);
+    }
+
+    #[test]
+    fn process_transliterated() {
+        let channels = vec![ChannelInfo {
+            alias: "russia".to_string(),
+            name: "Россия".to_string(),
+            ..ChannelInfo::new()
+        }];
+        let data = indoc!(
+            r#"#EXTM3U
+        #EXTINF:0,Rossiya
+        http://iptv.com/1.m3u8
+        "#
+        );
+        let items = process(data.as_bytes(), &channels, MatchOptions::default()).unwrap();
+        assert_eq!(items[0].entry.tvg_id(), "");
+
+        let options = MatchOptions {
+            transliterate: true,
+            ..MatchOptions::default()
+        };
+        let items = process(data.as_bytes(), &channels, options).unwrap();
+        assert_eq!(items[0].entry.tvg_id(), "russia");
+        assert_eq!(items[0].name, "Россия");
+    }
+
+    #[test]
+    fn changes() {
+        let channels = vec![ChannelInfo {
+            name: "Discovery Channel".to_string(),
+            ..ChannelInfo::new()
+        }];
+        let data = r#"{"DSC": "Discovery Channel", "ES": "Eurosport", "Foo": ""}"#;
+        let (changes, unknown) = parse_changes(data.as_bytes(), &channels).unwrap();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(unknown, vec!["Eurosport"]);
+
+        let e = parse_changes(r#"{"DSC": 1}"#.as_bytes(), &channels).unwrap_err();
+        assert!(e.0.starts_with("Invalid changes json"));
+        assert!(e.0.contains("line 1 column"));
+
+        let big = format!(r#"{{"a": "{}"}}"#, "x".repeat(MAX_CHANGES_SIZE as usize));
+        let e = parse_changes(big.as_bytes(), &channels).unwrap_err();
+        assert!(e.0.contains("larger than"));
+
+        let many = (0..=MAX_CHANGES)
+            .map(|i| format!("\"{}\": \"\"", i))
+            .collect::<Vec<_>>()
+            .join(",");
+        let e = parse_changes(format!("{{{}}}", many).as_bytes(), &channels).unwrap_err();
+        assert!(e.0.starts_with("Too many changes"));
+    }
+
+    #[test]
+    fn disposition() {
+        assert_eq!(
+            attachment_disposition(None),
+            r#"attachment; filename="playlist.m3u""#
+        );
+        assert_eq!(
+            attachment_disposition(Some("C:\\Users\\me\\tv.M3U8")),
+            r#"attachment; filename="tv.M3U8""#
+        );
+        assert_eq!(
+            attachment_disposition(Some("my \"tv\"")),
+            r#"attachment; filename="my _tv_.m3u"; filename*=UTF-8''my%20%22tv%22.m3u"#
+        );
+        assert_eq!(
+            attachment_disposition(Some("тв.m3u")),
+            r#"attachment; filename="__.m3u"; filename*=UTF-8''%D1%82%D0%B2.m3u"#
+        );
+    }
+
+    #[test]
+    fn unknown_warning() {
+        assert_eq!(
+            unknown_channels_warning(&["Eurosport".to_string(), "Россия \"1\"".to_string()]),
+            r#"199 - "Unknown channels: Eurosport, ______ _1_""#
+        );
+        let split = unknown_channels_warning(&["a\r\nSet-Cookie: x=1".to_string()]);
+        assert_eq!(split, r#"199 - "Unknown channels: a__Set-Cookie: x=1""#);
+        let long = unknown_channels_warning(&["x".repeat(MAX_WARNING_LENGTH * 2)]);
+        assert!(long.len() < MAX_WARNING_LENGTH + 40);
+        assert!(long.ends_with("...\""));
+    }
+
+    #[test]
+    fn replace_tvg_keeps_header() {
+        let channels = vec![ChannelInfo {
+            alias: "disc".to_string(),
+            name: "Discovery Channel".to_string(),
+            ..ChannelInfo::new()
+        }];
+        let data = indoc!(
+            r#"#EXTM3U url-tvg="http://epg.org/guide.xml" x-tvg-url="http://epg.org/guide.xml"
+        # Updated daily
+        #EXTINF:0,DSC
+        http://iptv.com/1.m3u8
+        "#
+        );
+        let replace = vec![("DSC".to_string(), "Discovery Channel".to_string())]
+            .into_iter()
+            .collect();
+        let out = replace_tvg(data.as_bytes(), replace, &channels).unwrap();
+        assert_eq!(
+            out,
+            indoc!(
+                r#"#EXTM3U url-tvg="http://epg.org/guide.xml" x-tvg-url="http://epg.org/guide.xml"
+        # Updated daily
+        #EXTINF:0 tvg-id="disc",DSC
+        http://iptv.com/1.m3u8
+        "#
+            )
+        );
     }
 }
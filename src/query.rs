@@ -0,0 +1,521 @@
+use crate::epg::ProgramFields;
+use crate::utils::{get_parameter, parse_duration};
+use chrono::prelude::*;
+use iron::mime::Mime;
+use iron::prelude::*;
+use iron::status;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+
+/// Invalid query parameter, responded with `400` and `{"error": ..., "field": ...}`
+#[derive(Debug, PartialEq)]
+pub struct QueryError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl QueryError {
+    pub fn new<M: ToString>(field: &'static str, message: M) -> Self {
+        Self {
+            field,
+            message: message.to_string(),
+        }
+    }
+
+    fn body(&self) -> String {
+        serde_json::json!({ "error": self.message, "field": self.field }).to_string()
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid parameter {}: {}", self.field, self.message)
+    }
+}
+
+impl StdError for QueryError {}
+
+impl From<QueryError> for IronError {
+    fn from(error: QueryError) -> Self {
+        let response = Response::with((
+            status::BadRequest,
+            "application/json".parse::<Mime>().unwrap(),
+            error.body(),
+        ));
+        IronError {
+            error: Box::new(error),
+            response,
+        }
+    }
+}
+
+/// Typed access to query parameters, the last value of a repeated parameter wins
+#[derive(Clone, Copy)]
+pub struct Query<'a>(Option<&'a HashMap<String, Vec<String>>>);
+
+impl<'a> Query<'a> {
+    /// Parameters of a request, `None` when it has no query string
+    pub fn new(params: Option<&'a HashMap<String, Vec<String>>>) -> Self {
+        Self(params)
+    }
+
+    pub fn get(&self, field: &str) -> Option<&'a str> {
+        self.0
+            .and_then(|params| get_parameter(params, field))
+            .map(String::as_str)
+    }
+
+    /// `1` enables an option, anything else or nothing leaves it disabled
+    pub fn flag(&self, field: &str) -> bool {
+        self.get(field) == Some("1")
+    }
+
+    /// Value converted by the function, its error names the field
+    pub fn parse_with<T, E, F>(&self, field: &'static str, f: F) -> Result<Option<T>, QueryError>
+    where
+        E: fmt::Display,
+        F: FnOnce(&'a str) -> Result<T, E>,
+    {
+        self.get(field)
+            .map(f)
+            .transpose()
+            .map_err(|e| QueryError::new(field, e))
+    }
+
+    pub fn parse<T>(&self, field: &'static str) -> Result<Option<T>, QueryError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.parse_with(field, |s| s.parse::<T>())
+    }
+
+    pub fn required<T>(&self, field: &'static str) -> Result<T, QueryError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.parse(field)?
+            .ok_or_else(|| QueryError::new(field, "missing"))
+    }
+
+    /// Comma separated values like `ids=1,2,3`
+    pub fn list<T>(&self, field: &'static str) -> Result<Option<Vec<T>>, QueryError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.parse_with(field, |s| {
+            s.split(',')
+                .map(|v| v.parse::<T>())
+                .collect::<Result<Vec<_>, _>>()
+        })
+    }
+
+    /// Unix time of the field, now when it is absent
+    pub fn time(&self, field: &'static str) -> Result<DateTime<Utc>, QueryError> {
+        match self.parse::<i64>(field)? {
+            Some(ts) => Utc
+                .timestamp_opt(ts, 0)
+                .single()
+                .ok_or_else(|| QueryError::new(field, "out of range")),
+            None => Ok(Utc::now()),
+        }
+    }
+
+    /// Program fields of the `fields` parameter, the stable format always has all of them
+    pub fn fields(&self, stable: bool) -> Result<Option<ProgramFields>, QueryError> {
+        if stable && self.get("fields").is_some() {
+            return Err(QueryError::new(
+                "fields",
+                "can not be selected in the stable format",
+            ));
+        }
+        self.parse("fields")
+    }
+}
+
+/// Default for the longest range of programs one request may read
+pub const MAX_QUERY_SPAN_DAYS: i64 = 31;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// Reject a range longer than `max_days`, the error names the field which sets its end
+pub fn check_span(field: &'static str, seconds: i64, max_days: i64) -> Result<(), QueryError> {
+    if seconds > max_days.saturating_mul(DAY) {
+        return Err(QueryError::new(
+            field,
+            format!("span is limited to {} days", max_days),
+        ));
+    }
+    Ok(())
+}
+
+/// Parameters of `/epg_day`
+#[derive(Debug)]
+pub struct EpgDayQuery {
+    pub id: i64,
+    pub day: Date<Utc>,
+    pub clamp: bool,
+    pub stable: bool,
+    pub fields: Option<ProgramFields>,
+    /// Programs carry their ids for `/program?pid=`
+    pub with_ids: bool,
+}
+
+impl EpgDayQuery {
+    pub fn parse(query: Query) -> Result<Self, QueryError> {
+        let id = query.required("id")?;
+        let day = query
+            .parse_with("day", |s| NaiveDate::parse_from_str(s, "%Y.%m.%d"))?
+            .ok_or_else(|| QueryError::new("day", "missing"))?;
+        let stable = query.flag("stable");
+        let fields = query.fields(stable)?;
+        let with_ids = query.flag("with_ids");
+        if with_ids && (stable || fields.is_some()) {
+            return Err(QueryError::new(
+                "with_ids",
+                "can not be combined with stable or fields",
+            ));
+        }
+        Ok(Self {
+            id,
+            day: Utc.from_utc_date(&day),
+            clamp: query.flag("clamp"),
+            stable,
+            fields,
+            with_ids,
+        })
+    }
+}
+
+/// Parameters of `/epg_list`
+#[derive(Debug)]
+pub struct EpgListQuery<'a> {
+    pub time: DateTime<Utc>,
+    pub ids: Option<Vec<i64>>,
+    pub alias_prefix: Option<&'a str>,
+    /// Name of a configured channel group
+    pub group: Option<&'a str>,
+    pub window: Option<chrono::Duration>,
+    /// Clamping needs the window, snapshot programs are never cut
+    pub clamp: bool,
+    pub stable: bool,
+    pub fields: Option<ProgramFields>,
+    /// Programs which already ended, the snapshot has none of them
+    pub lookback: i64,
+}
+
+impl<'a> EpgListQuery<'a> {
+    pub fn parse(query: Query<'a>, max_span_days: i64) -> Result<Self, QueryError> {
+        let window = query.parse_with("window", parse_duration)?;
+        if let Some(window) = window {
+            check_span("window", window.num_seconds(), max_span_days)?;
+        }
+        let lookback = query.parse::<i64>("lookback")?.unwrap_or(0);
+        if lookback < 0 {
+            return Err(QueryError::new("lookback", "must be non-negative"));
+        }
+        if lookback > 0 && window.is_some() {
+            return Err(QueryError::new(
+                "lookback",
+                "can not be combined with window",
+            ));
+        }
+        let stable = query.flag("stable");
+        Ok(Self {
+            time: query.time("time")?,
+            ids: query.list("ids")?,
+            alias_prefix: query.get("alias_prefix"),
+            group: query.get("group"),
+            window,
+            clamp: query.flag("clamp"),
+            stable,
+            fields: query.fields(stable)?,
+            lookback,
+        })
+    }
+}
+
+/// Upper limit for the number of channels times the window of `/grid` in seconds,
+/// a day of 200 channels
+pub const MAX_GRID_SPAN: i64 = 200 * 24 * 60 * 60;
+
+/// Parameters of `/grid`
+#[derive(Debug)]
+pub struct GridQuery {
+    pub ids: Vec<i64>,
+    pub from: i64,
+    pub to: i64,
+}
+
+impl GridQuery {
+    pub fn parse(query: Query, max_span_days: i64) -> Result<Self, QueryError> {
+        let ids = query
+            .list("ids")?
+            .ok_or_else(|| QueryError::new("ids", "missing"))?;
+        let from = query.required("from")?;
+        let to = query.required("to")?;
+        if to <= from {
+            return Err(QueryError::new("to", "must be after from"));
+        }
+        let span = to
+            .checked_sub(from)
+            .ok_or_else(|| QueryError::new("to", "is too far after from"))?;
+        if span.saturating_mul(ids.len() as i64) > MAX_GRID_SPAN {
+            return Err(QueryError::new(
+                "ids",
+                format!(
+                    "{} channels over {} seconds exceed {} channel seconds",
+                    ids.len(),
+                    span,
+                    MAX_GRID_SPAN
+                ),
+            ));
+        }
+        check_span("to", span, max_span_days)?;
+        Ok(Self { ids, from, to })
+    }
+}
+
+/// Parameters of the admin `/gaps`, the day from today's midnight by default
+#[derive(Debug)]
+pub struct GapsQuery {
+    pub id: i64,
+    pub from: i64,
+    pub to: i64,
+}
+
+impl GapsQuery {
+    pub fn parse(query: Query, max_span_days: i64) -> Result<Self, QueryError> {
+        let id = query.required("id")?;
+        let from = query
+            .parse("from")?
+            .unwrap_or_else(|| Utc::today().and_hms(0, 0, 0).timestamp());
+        let to = query
+            .parse("to")?
+            .or_else(|| from.checked_add(DAY))
+            .ok_or_else(|| QueryError::new("from", "is out of range"))?;
+        if to <= from {
+            return Err(QueryError::new("to", "must be after from"));
+        }
+        let span = to
+            .checked_sub(from)
+            .ok_or_else(|| QueryError::new("to", "is too far after from"))?;
+        check_span("to", span, max_span_days)?;
+        Ok(Self { id, from, to })
+    }
+}
+
+/// Parameters of `/now_next` and `/snapshot`
+#[derive(Debug)]
+pub struct NowNextQuery {
+    pub time: DateTime<Utc>,
+    pub ids: Option<Vec<i64>>,
+}
+
+impl NowNextQuery {
+    pub fn parse(query: Query) -> Result<Self, QueryError> {
+        Ok(Self {
+            time: query.time("time")?,
+            ids: query.list("ids")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(query: &str) -> HashMap<String, Vec<String>> {
+        let mut params = HashMap::new();
+        for pair in query.split('&') {
+            let (key, value) = pair.split_at(pair.find('=').unwrap());
+            params
+                .entry(key.to_string())
+                .or_insert_with(Vec::new)
+                .push(value[1..].to_string());
+        }
+        params
+    }
+
+    #[test]
+    fn epg_day() {
+        let p = params("id=5&day=2021.05.30&clamp=1");
+        let q = EpgDayQuery::parse(Query::new(Some(&p))).unwrap();
+        assert_eq!(q.id, 5);
+        assert_eq!(q.day, Utc.ymd(2021, 5, 30));
+        assert!(q.clamp && !q.stable);
+
+        assert_eq!(
+            EpgDayQuery::parse(Query::new(None)).unwrap_err(),
+            QueryError::new("id", "missing")
+        );
+        let p = params("id=x&day=2021.05.30");
+        assert_eq!(
+            EpgDayQuery::parse(Query::new(Some(&p))).unwrap_err().field,
+            "id"
+        );
+        let p = params("id=5&day=30.05.2021");
+        assert_eq!(
+            EpgDayQuery::parse(Query::new(Some(&p))).unwrap_err().field,
+            "day"
+        );
+        let p = params("id=5&day=2021.05.30&stable=1&fields=title");
+        assert_eq!(
+            EpgDayQuery::parse(Query::new(Some(&p))).unwrap_err().field,
+            "fields"
+        );
+        let p = params("id=5&day=2021.05.30&with_ids=1");
+        assert!(EpgDayQuery::parse(Query::new(Some(&p))).unwrap().with_ids);
+        let p = params("id=5&day=2021.05.30&with_ids=1&stable=1");
+        assert_eq!(
+            EpgDayQuery::parse(Query::new(Some(&p))).unwrap_err().field,
+            "with_ids"
+        );
+    }
+
+    #[test]
+    fn epg_list() {
+        let q = EpgListQuery::parse(Query::new(None), MAX_QUERY_SPAN_DAYS).unwrap();
+        assert_eq!(q.ids, None);
+        assert_eq!(q.lookback, 0);
+
+        let p = params("time=1622000000&ids=1,2&window=2h&fields=title");
+        let q = EpgListQuery::parse(Query::new(Some(&p)), MAX_QUERY_SPAN_DAYS).unwrap();
+        assert_eq!(q.time, Utc.timestamp(1622000000, 0));
+        assert_eq!(q.ids, Some(vec![1, 2]));
+        assert_eq!(q.window, Some(chrono::Duration::hours(2)));
+        assert!(q.fields.is_some());
+
+        for (query, field) in &[
+            ("ids=1,x", "ids"),
+            ("time=now", "time"),
+            ("time=9223372036854775807", "time"),
+            ("window=2y", "window"),
+            ("lookback=-1", "lookback"),
+            ("lookback=2&window=2h", "lookback"),
+            ("fields=rating", "fields"),
+        ] {
+            let p = params(query);
+            assert_eq!(
+                EpgListQuery::parse(Query::new(Some(&p)), MAX_QUERY_SPAN_DAYS)
+                    .unwrap_err()
+                    .field,
+                *field,
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn grid() {
+        let p = params("ids=1,2&from=1622000000&to=1622010800");
+        let q = GridQuery::parse(Query::new(Some(&p)), MAX_QUERY_SPAN_DAYS).unwrap();
+        assert_eq!(q.ids, vec![1, 2]);
+        assert_eq!((q.from, q.to), (1622000000, 1622010800));
+
+        for (query, field) in &[
+            ("from=0&to=3600", "ids"),
+            ("ids=1&to=3600", "from"),
+            ("ids=1&from=3600&to=3600", "to"),
+            (
+                "ids=1&from=-9223372036854775808&to=9223372036854775807",
+                "to",
+            ),
+            ("ids=1,2,3&from=0&to=6048000", "ids"),
+        ] {
+            let p = params(query);
+            assert_eq!(
+                GridQuery::parse(Query::new(Some(&p)), MAX_QUERY_SPAN_DAYS)
+                    .unwrap_err()
+                    .field,
+                *field,
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn gaps() {
+        let p = params("id=5&from=1622000000&to=1622010800");
+        let q = GapsQuery::parse(Query::new(Some(&p)), MAX_QUERY_SPAN_DAYS).unwrap();
+        assert_eq!((q.id, q.from, q.to), (5, 1622000000, 1622010800));
+        let p = params("id=5&from=1622000000");
+        let q = GapsQuery::parse(Query::new(Some(&p)), MAX_QUERY_SPAN_DAYS).unwrap();
+        assert_eq!(q.to, 1622000000 + DAY);
+        let p = params("id=5");
+        let q = GapsQuery::parse(Query::new(Some(&p)), MAX_QUERY_SPAN_DAYS).unwrap();
+        assert_eq!(q.to - q.from, DAY);
+
+        for (query, field) in &[
+            ("from=0", "id"),
+            ("id=5&from=x", "from"),
+            ("id=5&from=9223372036854775807", "from"),
+            ("id=5&from=3600&to=3600", "to"),
+            ("id=5&from=3600&to=0", "to"),
+            (
+                "id=5&from=-9223372036854775808&to=9223372036854775807",
+                "to",
+            ),
+        ] {
+            let p = params(query);
+            assert_eq!(
+                GapsQuery::parse(Query::new(Some(&p)), MAX_QUERY_SPAN_DAYS)
+                    .unwrap_err()
+                    .field,
+                *field,
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn span_limit() {
+        let error = |field| QueryError::new(field, "span is limited to 2 days");
+
+        let p = params("window=48h");
+        assert!(EpgListQuery::parse(Query::new(Some(&p)), 2).is_ok());
+        let p = params("window=49h");
+        assert_eq!(
+            EpgListQuery::parse(Query::new(Some(&p)), 2).unwrap_err(),
+            error("window")
+        );
+
+        let p = params("ids=1&from=0&to=172800");
+        assert!(GridQuery::parse(Query::new(Some(&p)), 2).is_ok());
+        let p = params("ids=1&from=0&to=172801");
+        assert_eq!(
+            GridQuery::parse(Query::new(Some(&p)), 2).unwrap_err(),
+            error("to")
+        );
+
+        let p = params("id=1&from=0&to=172800");
+        let q = GapsQuery::parse(Query::new(Some(&p)), 2).unwrap();
+        assert_eq!((q.id, q.from, q.to), (1, 0, 172800));
+        let p = params("id=1&from=0&to=172801");
+        assert_eq!(
+            GapsQuery::parse(Query::new(Some(&p)), 2).unwrap_err(),
+            error("to")
+        );
+        let p = params("id=1&from=0");
+        assert_eq!(GapsQuery::parse(Query::new(Some(&p)), 2).unwrap().to, DAY);
+    }
+
+    #[test]
+    fn error_body() {
+        let error = QueryError::new("ids", "invalid digit found in string");
+        let body: serde_json::Value = serde_json::from_str(&error.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"error": "invalid digit found in string", "field": "ids"})
+        );
+        let error = IronError::from(error);
+        assert_eq!(error.response.status, Some(status::BadRequest));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::archive::ArchivePolicy;
+use crate::db::{DuplicateBegins, Pragmas, PurgePolicy, MAX_PROGRAMS_AT};
+use crate::feed_auth::FeedAuth;
+use crate::locale::Locale;
+use crate::mapping::{AliasMapping, ChannelDurations, ChannelGroups, ChannelSeeds};
+use crate::name_match::MatchOptions;
+use crate::playlist::SEARCH_LIMIT;
+use crate::query::MAX_QUERY_SPAN_DAYS;
+use crate::xmltv::MAX_TITLE_LEN;
+use chrono_tz::Tz;
+use regex::Regex;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Response of the root path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RootMode {
+    /// Redirect to the channels page
+    Redirect,
+    /// Json descriptor of the service for headless deployments
+    Json,
+    /// Plain `ok` while the database is readable
+    Healthz,
+}
+
+/// Server options configured from the command line, environment or config file.
+///
+/// `POST /admin/reload` re-reads the config file and replaces all settings except
+/// `base_path`, `ws_port`, `icon_probe_interval`, `max_match_concurrency`, `max_programs_at`
+/// and `db_pragmas`, which need a restart like the port, feed urls and database path.
+/// Database options apply from the next feed update.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Report processing stages in the `Server-Timing` header of json responses
+    pub server_timing: bool,
+    /// Load feeds into a staging copy of the database which replaces the live file
+    pub staging: bool,
+    /// Keep serving the old `/epg_list` snapshot until the updated one is read
+    pub serve_stale: bool,
+    /// Feed loads only stage programs, they are merged by the admin `/finalize` request
+    pub incremental_load: bool,
+    /// Feeds which split a show into contiguous parts with the same title get one program
+    pub coalesce_programs: bool,
+    /// Which program is kept when several of a channel begin at the same time, all without it
+    pub duplicate_begins: Option<DuplicateBegins>,
+    /// Programs sharing a time slot, told by their `clumpidx`, are loaded as one program
+    pub merge_clumps: bool,
+    /// Descriptions are stored deflated, which mostly pays off for long ones
+    pub compress_descriptions: bool,
+    /// Upper limit for the number of programs per channel in the `/epg_list` snapshot
+    pub max_programs_at: i64,
+    /// Merge duplicate feed channels into canonical ones
+    pub alias_mapping: AliasMapping,
+    /// Playlist whose channels are the only ones which programs are loaded
+    pub allowlist_playlist: Option<PathBuf>,
+    /// When channels missing from feeds are removed
+    pub purge_policy: PurgePolicy,
+    /// Sqlite cache, memory mapping and sync options of database connections
+    pub db_pragmas: Pragmas,
+    /// Maximum number of characters of stored descriptions, zero drops them
+    pub description_limit: Option<usize>,
+    /// Programs which begin later than this number of days from now are dropped
+    pub max_days_ahead: Option<i64>,
+    /// Channels with ids which stay the same whatever the feed contains
+    pub channel_seeds: ChannelSeeds,
+    /// Named channel groups selected by the `group` parameter of `/epg_list`
+    pub channel_groups: ChannelGroups,
+    /// Durations of programs without `stop` time by channel, a minute for the others
+    pub default_durations: ChannelDurations,
+    /// Css colors of program rows in `programs.html` picked by category
+    pub category_palette: Vec<String>,
+    /// Aliases of channels listed before the others, the rest is sorted by name
+    pub pinned_channels: Vec<String>,
+    /// Categories preferred as the primary category of a program, most wanted first
+    pub category_priority: Vec<String>,
+    /// Maximum number of characters of program titles and sub-titles
+    pub program_title_max_len: usize,
+    /// Title prefixes with a `channel` group naming the program's channel are stripped
+    pub title_prefix: Option<Regex>,
+    /// Feed channels without id get an alias derived from the name
+    pub allow_empty_alias: bool,
+    /// Language of program titles when the request does not specify a known one
+    pub default_lang: Option<String>,
+    /// Day and month names on html pages when no language of the request has them
+    pub locale: Locale,
+    /// Timezone of times and days in `programs.html` unless the request has `tz`
+    pub display_tz: Tz,
+    /// Timezone of feed times without an offset, they are UTC when it is not set
+    pub feed_tz: Option<Tz>,
+    /// Time after midnight when the day of `/epg_day` and `programs.html` begins
+    pub broadcast_day_start: chrono::Duration,
+    /// `User-Agent` header of feed requests
+    pub user_agent: String,
+    /// Credentials of feed requests
+    pub feed_auth: Option<FeedAuth>,
+    /// Number of playlist matching requests which may run at once
+    pub max_match_concurrency: usize,
+    /// Number of channels found by name when the request has no `limit`
+    pub search_limit: usize,
+    /// Longest range of programs in days which `/epg_list`, `/grid` and `/admin/gaps` read
+    pub max_query_span_days: i64,
+    /// Similarity of playlist and channel names and whether names are also
+    /// compared transliterated
+    pub match_options: MatchOptions,
+    /// Bearer token required by `/admin` endpoints, they refuse every request when it is not set
+    pub admin_token: Option<String>,
+    /// Path prefix of all routes like `/epg`, empty when served from the root
+    pub base_path: String,
+    /// What `/` responds with
+    pub root_mode: RootMode,
+    /// Port of the websocket which pushes programs on air, disabled when not set
+    pub ws_port: Option<u16>,
+    /// How often channel icon urls are checked, disabled when not set
+    pub icon_probe_interval: Option<Duration>,
+    /// Directory and lifetime of archived feeds, feeds are not archived when not set
+    pub feed_archive: Option<ArchivePolicy>,
+    /// File with `APP_*=value` lines read on start and on reload
+    pub config_file: Option<String>,
+}
+
+/// User agent of the feed fetcher unless configured
+pub const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Light background colors which keep the text readable
+pub const DEFAULT_CATEGORY_PALETTE: &[&str] = &[
+    "#fde2e2", "#fff1c1", "#e2f0cb", "#d4eefa", "#e8e0f7", "#fbe3f0",
+];
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            server_timing: false,
+            staging: false,
+            serve_stale: false,
+            incremental_load: false,
+            coalesce_programs: false,
+            duplicate_begins: None,
+            merge_clumps: false,
+            compress_descriptions: false,
+            max_programs_at: MAX_PROGRAMS_AT,
+            alias_mapping: AliasMapping::default(),
+            allowlist_playlist: None,
+            purge_policy: PurgePolicy::default(),
+            db_pragmas: Pragmas::default(),
+            description_limit: None,
+            max_days_ahead: None,
+            channel_seeds: ChannelSeeds::default(),
+            channel_groups: ChannelGroups::default(),
+            default_durations: ChannelDurations::default(),
+            category_palette: DEFAULT_CATEGORY_PALETTE
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            pinned_channels: Vec::new(),
+            category_priority: Vec::new(),
+            program_title_max_len: MAX_TITLE_LEN,
+            title_prefix: None,
+            allow_empty_alias: false,
+            default_lang: None,
+            locale: Locale::default(),
+            display_tz: Tz::UTC,
+            feed_tz: None,
+            broadcast_day_start: chrono::Duration::zero(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            feed_auth: None,
+            max_match_concurrency: 2,
+            search_limit: SEARCH_LIMIT,
+            max_query_span_days: MAX_QUERY_SPAN_DAYS,
+            match_options: MatchOptions::default(),
+            admin_token: None,
+            base_path: String::new(),
+            root_mode: RootMode::Redirect,
+            ws_port: None,
+            icon_probe_interval: None,
+            feed_archive: None,
+            config_file: None,
+        }
+    }
+}
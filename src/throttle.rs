@@ -0,0 +1,115 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Counting semaphore limiting the number of concurrently running heavy tasks
+pub struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Occupied slot of the semaphore which is freed on drop
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+/// Occupied slot which keeps the semaphore alive, so it can move to another thread
+pub struct OwnedPermit {
+    semaphore: Arc<Semaphore>,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait up to `timeout` for a free slot, `None` when all of them stay busy
+    pub fn acquire_timeout(&self, timeout: Duration) -> Option<Permit> {
+        let deadline = Instant::now() + timeout;
+        let mut permits = self.permits.lock().unwrap();
+        while *permits == 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            permits = self
+                .released
+                .wait_timeout(permits, deadline - now)
+                .unwrap()
+                .0;
+        }
+        *permits -= 1;
+        Some(Permit { semaphore: self })
+    }
+
+    /// Take a free slot without waiting, `None` when all of them are busy
+    pub fn try_acquire_owned(self: Arc<Self>) -> Option<OwnedPermit> {
+        {
+            let mut permits = self.permits.lock().unwrap();
+            if *permits == 0 {
+                return None;
+            }
+            *permits -= 1;
+        }
+        Some(OwnedPermit { semaphore: self })
+    }
+
+    fn release(&self) {
+        *self.permits.lock().unwrap() += 1;
+        self.released.notify_one();
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
+
+impl Drop for OwnedPermit {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn throttle() {
+        let semaphore = Semaphore::new(2);
+        let timeout = Duration::from_millis(20);
+        let first = semaphore.acquire_timeout(timeout).unwrap();
+        let _second = semaphore.acquire_timeout(timeout).unwrap();
+        assert!(semaphore.acquire_timeout(timeout).is_none());
+        drop(first);
+        assert!(semaphore.acquire_timeout(timeout).is_some());
+    }
+
+    #[test]
+    fn queued() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        let permit = semaphore.acquire_timeout(Duration::from_secs(1)).unwrap();
+        let waiting = {
+            let semaphore = semaphore.clone();
+            thread::spawn(move || semaphore.acquire_timeout(Duration::from_secs(10)).is_some())
+        };
+        thread::sleep(Duration::from_millis(50));
+        drop(permit);
+        assert!(waiting.join().unwrap());
+    }
+
+    #[test]
+    fn owned() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        let permit = semaphore.clone().try_acquire_owned().unwrap();
+        assert!(semaphore.clone().try_acquire_owned().is_none());
+        // The slot is freed by the thread which took it over
+        thread::spawn(move || drop(permit)).join().unwrap();
+        assert!(semaphore.try_acquire_owned().is_some());
+    }
+}
@@ -1,12 +1,36 @@
+use crate::xmltv::FeedInfo;
 use chrono::prelude::*;
 use std::time::UNIX_EPOCH;
 
+/// Feed url an update was loaded from
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FeedSource {
+    Primary,
+    /// Tried only when the primary url fails
+    Fallback,
+}
+
+impl FeedSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FeedSource::Primary => "primary",
+            FeedSource::Fallback => "fallback",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct UpdateStatus {
     pub message: String,
     pub succeed: bool,
     pub time: DateTime<Utc>,
     pub last_modified: DateTime<Utc>,
+    /// Provenance of the loaded feed
+    pub feed: FeedInfo,
+    /// Programs of the loaded feed which begin before the previous one of the channel ends
+    pub overlaps: i64,
+    /// `primary` or `fallback` url of a succeeded update
+    pub source: String,
 }
 
 impl UpdateStatus {
@@ -16,6 +40,9 @@ impl UpdateStatus {
             succeed: true,
             time,
             last_modified,
+            feed: FeedInfo::default(),
+            overlaps: 0,
+            source: FeedSource::Primary.as_str().to_string(),
         }
     }
 
@@ -25,6 +52,9 @@ impl UpdateStatus {
             succeed: false,
             time,
             last_modified: UNIX_EPOCH.into(),
+            feed: FeedInfo::default(),
+            overlaps: 0,
+            source: String::new(),
         }
     }
 
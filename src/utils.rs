@@ -2,6 +2,9 @@ use iron::prelude::*;
 use iron::status;
 use std::collections::HashMap;
 use std::error::Error as StdError;
+use std::fmt;
+use std::fmt::Write;
+use std::time::{Duration, Instant, SystemTime};
 
 pub fn bad_request<E: StdError + Send + 'static>(error: E) -> IronError {
     error_with_status(error, status::BadRequest)
@@ -33,3 +36,266 @@ pub fn get_parameter<'a>(
 ) -> Option<&'a String> {
     params.get(key).and_then(|l| l.last())
 }
+
+#[derive(Debug, PartialEq)]
+pub struct ParseDurationError(String);
+
+impl fmt::Display for ParseDurationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid duration '{}', expected e.g. 90m, 6h or 2d",
+            self.0
+        )
+    }
+}
+
+impl StdError for ParseDurationError {}
+
+/// Parse positive human readable duration like `30s`, `90m`, `6h` or `2d`
+pub fn parse_duration(s: &str) -> Result<chrono::Duration, ParseDurationError> {
+    let error = || ParseDurationError(s.to_string());
+    let s = s.trim();
+    if s.len() < 2 || !s.is_char_boundary(s.len() - 1) {
+        return Err(error());
+    }
+    let (value, unit) = s.split_at(s.len() - 1);
+    let value = value.parse::<i64>().map_err(|_| error())?;
+    if value <= 0 {
+        return Err(error());
+    }
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(error()),
+    };
+    // Constructors of `chrono::Duration` panic beyond its range
+    value
+        .checked_mul(unit)
+        .filter(|&seconds| seconds <= chrono::Duration::max_value().num_seconds())
+        .map(chrono::Duration::seconds)
+        .ok_or_else(error)
+}
+
+/// Path prefix with a leading slash and without a trailing one, `/` becomes empty
+pub fn normalize_base_path(path: &str) -> String {
+    let path = path.trim().trim_matches('/');
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", path)
+    }
+}
+
+/// Languages of `Accept-Language` header ordered by preference, lowercase and without `*`
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut languages = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let lang = parts.next().filter(|l| !l.is_empty() && *l != "*")?;
+            let quality = parts
+                .find_map(|p| p.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            if quality > 0.0 {
+                Some((lang.to_lowercase(), quality))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    // Stable sort keeps the header order of equally weighted languages
+    languages.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    languages.into_iter().map(|(lang, _)| lang).collect()
+}
+
+/// Whether a media range of `Accept` header names the media type with a non-zero quality,
+/// wildcards do not count
+pub fn accepts_media_type(header: &str, media_type: &str) -> bool {
+    header.split(',').any(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        let name = parts.next().unwrap_or("");
+        let quality = parts
+            .find_map(|p| p.strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.parse::<f32>().ok());
+        name.eq_ignore_ascii_case(media_type) && quality.map_or(false, |q| q > 0.0)
+    })
+}
+
+/// Stable position of the key in a palette of `len` entries, case insensitive
+pub fn palette_index(key: &str, len: usize) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+    // FNV-1a, unlike `DefaultHasher` it is guaranteed to stay the same between releases
+    let hash = key
+        .to_lowercase()
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |h, b| {
+            (h ^ u64::from(b)).wrapping_mul(0x100000001b3)
+        });
+    Some((hash % len as u64) as usize)
+}
+
+/// Append a CSV record, quoting fields that contain separators, quotes or line breaks
+pub fn write_csv_record<I, S>(out: &mut String, fields: I)
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let field = field.as_ref();
+        if field.contains(&[',', '"', '\r', '\n'][..]) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(field);
+        }
+    }
+    out.push_str("\r\n");
+}
+
+/// Parse `Retry-After` header value, which is either a number of seconds or an http date
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date: SystemTime = value.parse::<hyperx::header::HttpDate>().ok()?.into();
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+/// Durations of the request processing stages reported in the `Server-Timing` header
+#[derive(Debug, Default)]
+pub struct ServerTiming {
+    entries: Vec<(&'static str, Duration)>,
+}
+
+impl ServerTiming {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `f` and record how long it took under the `name` metric
+    pub fn measure<T, F: FnOnce() -> T>(&mut self, name: &'static str, f: F) -> T {
+        let t = Instant::now();
+        let result = f();
+        self.entries.push((name, t.elapsed()));
+        result
+    }
+
+    pub fn header_value(&self) -> String {
+        let mut value = String::new();
+        for (name, duration) in self.entries.iter() {
+            if !value.is_empty() {
+                value.push_str(", ");
+            }
+            write!(value, "{};dur={:.3}", name, duration.as_secs_f64() * 1000.0).unwrap();
+        }
+        value
+    }
+
+    pub fn apply(&self, response: &mut Response) {
+        if !self.entries.is_empty() {
+            response
+                .headers
+                .set_raw("Server-Timing", vec![self.header_value().into_bytes()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_path() {
+        assert_eq!(normalize_base_path(""), "");
+        assert_eq!(normalize_base_path("/"), "");
+        assert_eq!(normalize_base_path("epg/"), "/epg");
+        assert_eq!(normalize_base_path("/tv/epg"), "/tv/epg");
+    }
+
+    #[test]
+    fn accept_language() {
+        assert_eq!(
+            parse_accept_language("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.9, *;q=0.5, ru;q=0"),
+            vec!["fr-ch", "fr", "de", "en"]
+        );
+        assert_eq!(parse_accept_language(""), Vec::<String>::new());
+        assert_eq!(parse_accept_language("en;q=x, de"), vec!["de"]);
+    }
+
+    #[test]
+    fn accept_media_type() {
+        let ndjson = "application/x-ndjson";
+        assert!(accepts_media_type("application/x-ndjson", ndjson));
+        assert!(accepts_media_type(
+            "application/json;q=0.5, application/x-ndjson;q=0.9",
+            ndjson
+        ));
+        assert!(!accepts_media_type("application/x-ndjson;q=0", ndjson));
+        assert!(!accepts_media_type(
+            "application/x-ndjson;q=0.0, */*",
+            ndjson
+        ));
+        assert!(!accepts_media_type("application/x-ndjson-seq", ndjson));
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(palette_index("News", 0), None);
+        assert_eq!(palette_index("News", 1), Some(0));
+        assert_eq!(palette_index("News", 6), palette_index("news", 6));
+        let sport = palette_index("Sport", 6).unwrap();
+        assert!(sport < 6);
+        assert_eq!(palette_index("Sport", 6), Some(sport));
+    }
+
+    #[test]
+    fn csv_record() {
+        let mut out = String::new();
+        write_csv_record(&mut out, &["1", "plain", ""]);
+        write_csv_record(&mut out, &["a,b", "say \"hi\"", "two\nlines"]);
+        assert_eq!(
+            out,
+            "1,plain,\r\n\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\"\r\n"
+        );
+    }
+
+    #[test]
+    fn retry_after() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Sun, 09 Sep 2001 01:48:40 GMT", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Sun, 09 Sep 2001 01:40:00 GMT", now),
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(parse_duration("90m"), Ok(chrono::Duration::minutes(90)));
+        assert_eq!(parse_duration("6h"), Ok(chrono::Duration::hours(6)));
+        assert_eq!(parse_duration("2d"), Ok(chrono::Duration::days(2)));
+        assert_eq!(parse_duration("45s"), Ok(chrono::Duration::seconds(45)));
+        for s in &["", "h", "6", "0h", "-1h", "6w", "1.5h", "6ч"] {
+            assert!(parse_duration(s).is_err(), "{}", s);
+        }
+        assert!(parse_duration("9999999999999d").is_err());
+    }
+}
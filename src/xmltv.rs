@@ -1,32 +1,108 @@
-use crate::epg::{ChannelInfo, Program};
-use chrono::{prelude::*, ParseResult};
+use crate::epg::{Audio, ChannelInfo, Program, Video};
+use crate::mapping::ChannelDurations;
+use chrono::{prelude::*, LocalResult, ParseResult};
+use chrono_tz::Tz;
+use deunicode::deunicode;
 use quick_xml::events::attributes::Attributes;
-use quick_xml::events::Event;
-use quick_xml::Reader;
-use std::io::BufRead;
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, Write};
 use std::ops::Deref;
 use std::str;
 
+/// Default limit for the number of characters of program titles and sub-titles
+pub const MAX_TITLE_LEN: usize = 500;
+
+/// Duration in seconds of programs without `stop` time unless configured for their channel
+const DEFAULT_DURATION: i64 = 60;
+
 struct ProgramParser {
     channel_alias: String,
     program: Program,
     field: Option<ProgramField>,
+    /// Element with nested fields the parser is in
+    block: Option<ProgramBlock>,
+    /// Language of the current field
+    lang: Option<String>,
+    /// Categories preferred as the primary one, most wanted first
+    category_priority: Vec<String>,
+    /// Longer titles and sub-titles are cut
+    title_max_len: usize,
+    /// Timezone of times without an offset, UTC when it is not set
+    timezone: Option<Tz>,
+    /// Durations of programs without `stop` time by channel alias
+    default_durations: ChannelDurations,
+    /// `clumpidx` of the last parsed program, it is kept after `finish` for the reader
+    clump: Option<Clump>,
+    /// Number of programs with cut titles since the last report
+    truncated: usize,
 }
 
 #[derive(PartialEq)]
 enum ProgramField {
     Title,
+    SubTitle,
     Category,
     Description,
+    Country,
+    Language,
+    /// Child of `<video>` or `<audio>`
+    Media(MediaField),
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum ProgramBlock {
+    Video,
+    Audio,
+}
+
+impl ProgramBlock {
+    fn from_tag(tag: &[u8]) -> Option<Self> {
+        match tag {
+            b"video" => Some(ProgramBlock::Video),
+            b"audio" => Some(ProgramBlock::Audio),
+            _ => None,
+        }
+    }
+
+    /// Field of a child element, the same name has different meaning in each block
+    fn field(self, name: &str) -> Option<ProgramField> {
+        let field = match (self, name) {
+            (ProgramBlock::Video, "present") => MediaField::VideoPresent,
+            (ProgramBlock::Video, "colour") => MediaField::VideoColour,
+            (ProgramBlock::Video, "aspect") => MediaField::VideoAspect,
+            (ProgramBlock::Video, "quality") => MediaField::VideoQuality,
+            (ProgramBlock::Audio, "present") => MediaField::AudioPresent,
+            (ProgramBlock::Audio, "stereo") => MediaField::AudioStereo,
+            _ => return None,
+        };
+        Some(ProgramField::Media(field))
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum MediaField {
+    VideoPresent,
+    VideoColour,
+    VideoAspect,
+    VideoQuality,
+    AudioPresent,
+    AudioStereo,
 }
 
 impl str::FromStr for ProgramField {
     type Err = ();
     fn from_str(s: &str) -> Result<ProgramField, ()> {
         match s {
+            // Exact match, so `sub-title` never ends up in the title
             "title" => Ok(ProgramField::Title),
+            "sub-title" => Ok(ProgramField::SubTitle),
             "category" => Ok(ProgramField::Category),
             "desc" => Ok(ProgramField::Description),
+            "country" => Ok(ProgramField::Country),
+            "language" => Ok(ProgramField::Language),
             _ => Err(()),
         }
     }
@@ -40,6 +116,14 @@ impl ProgramParser {
             channel_alias: String::new(),
             program: Program::new(),
             field: None,
+            block: None,
+            lang: None,
+            category_priority: Vec::new(),
+            title_max_len: MAX_TITLE_LEN,
+            timezone: None,
+            default_durations: ChannelDurations::default(),
+            clump: None,
+            truncated: 0,
         }
     }
 
@@ -53,29 +137,82 @@ impl ProgramParser {
             Event::Start(element) => {
                 if element.local_name() == Self::TAG {
                     self.parse_attributes(element.attributes());
+                } else if let Some(block) = ProgramBlock::from_tag(element.local_name()) {
+                    self.block = Some(block);
+                    self.field = None;
+                    match block {
+                        ProgramBlock::Video => {
+                            self.program.video.get_or_insert_with(Video::default);
+                        }
+                        ProgramBlock::Audio => {
+                            self.program.audio.get_or_insert_with(Audio::default);
+                        }
+                    }
                 } else {
-                    self.field = str::from_utf8(element.local_name())
-                        .ok()
-                        .and_then(|s| s.parse().ok());
+                    let name = str::from_utf8(element.local_name()).ok();
+                    self.field = match self.block {
+                        Some(block) => name.and_then(|s| block.field(s)),
+                        None => name.and_then(|s| s.parse().ok()),
+                    };
+                    self.lang = element
+                        .attributes()
+                        .filter_map(|a| a.ok())
+                        .find(|a| a.key == b"lang")
+                        .and_then(|a| str::from_utf8(a.value.deref()).ok().map(str::to_lowercase));
                 }
             }
             Event::Text(s) => match self.field {
                 Some(ProgramField::Title) => {
                     if let Ok(s) = s.unescape_and_decode(reader) {
+                        if let Some(lang) = &self.lang {
+                            let translation = self.program.translations.entry(lang.clone());
+                            translation.or_default().title = s.clone();
+                        }
                         self.program.title = s;
                     }
                 }
+                Some(ProgramField::SubTitle) => {
+                    if let Ok(s) = s.unescape_and_decode(reader) {
+                        self.program.sub_title = Some(s);
+                    }
+                }
                 Some(ProgramField::Description) => {
                     if let Ok(s) = s.unescape_and_decode(reader) {
+                        if let Some(lang) = &self.lang {
+                            let translation = self.program.translations.entry(lang.clone());
+                            translation.or_default().description = s.clone();
+                        }
                         self.program.description = s;
                     }
                 }
+                Some(ProgramField::Category) => {
+                    if let Ok(s) = s.unescape_and_decode(reader) {
+                        self.program.categories.push(s);
+                    }
+                }
+                Some(ProgramField::Country) => {
+                    if let Ok(s) = s.unescape_and_decode(reader) {
+                        self.program.countries.push(s);
+                    }
+                }
+                Some(ProgramField::Language) => {
+                    if let Ok(s) = s.unescape_and_decode(reader) {
+                        self.program.language = Some(s);
+                    }
+                }
+                Some(ProgramField::Media(field)) => {
+                    if let Ok(s) = s.unescape_and_decode(reader) {
+                        self.set_media_field(field, s);
+                    }
+                }
                 _ => {}
             },
             Event::End(element) => {
                 if element.local_name() == Self::TAG {
-                    result = Some((self.channel_alias.clone(), self.program.clone()));
-                    self.reset();
+                    result = Some(self.finish());
+                } else if ProgramBlock::from_tag(element.local_name()).is_some() {
+                    self.block = None;
+                    self.field = None;
                 }
             }
             // Both Start and End
@@ -89,8 +226,7 @@ impl ProgramParser {
                         .and_then(|s| s.parse().ok());
                 }
                 if element.local_name() == Self::TAG {
-                    result = Some((self.channel_alias.clone(), self.program.clone()));
-                    self.reset();
+                    result = Some(self.finish());
                 }
             }
             _ => {
@@ -101,33 +237,150 @@ impl ProgramParser {
     }
 
     fn parse_attributes(&mut self, attributes: Attributes) {
+        self.clump = None;
+        let mut end = None;
         for a in attributes.filter_map(|a| a.ok()) {
             match a.key {
                 b"start" => {
                     self.program.begin =
-                        to_timestamp(str::from_utf8(a.value.deref()).unwrap_or("")).unwrap_or(0)
+                        to_timestamp(str::from_utf8(a.value.deref()).unwrap_or(""), self.timezone)
+                            .unwrap_or(0)
                 }
                 b"stop" => {
-                    self.program.end = to_timestamp(str::from_utf8(a.value.deref()).unwrap_or(""))
-                        .unwrap_or(self.program.begin + 60)
+                    end = to_timestamp(str::from_utf8(a.value.deref()).unwrap_or(""), self.timezone)
+                        .ok()
                 }
                 b"channel" => {
                     self.channel_alias = str::from_utf8(a.value.deref()).unwrap_or("").to_string();
                 }
+                b"clumpidx" => {
+                    self.clump = str::from_utf8(a.value.deref()).ok().and_then(Clump::parse);
+                }
                 _ => {
-                    panic!(
+                    eprintln!(
                         "unknown attribute {}",
                         str::from_utf8(a.key).unwrap_or("???")
                     );
                 }
             }
         }
+        // Known only once all attributes are read, they may come in any order
+        self.program.end = end.unwrap_or_else(|| {
+            let duration = self
+                .default_durations
+                .get(&self.channel_alias)
+                .map_or(DEFAULT_DURATION, |d| d.num_seconds());
+            self.program.begin + duration
+        });
+    }
+
+    fn set_media_field(&mut self, field: MediaField, s: String) {
+        // `yes` or `no`, anything else is unknown
+        let flag = match s.as_str() {
+            "yes" => Some(true),
+            "no" => Some(false),
+            _ => None,
+        };
+        match field {
+            MediaField::VideoPresent => {
+                self.program
+                    .video
+                    .get_or_insert_with(Video::default)
+                    .present = flag
+            }
+            MediaField::VideoColour => {
+                self.program.video.get_or_insert_with(Video::default).colour = flag
+            }
+            MediaField::VideoAspect => {
+                self.program.video.get_or_insert_with(Video::default).aspect = Some(s)
+            }
+            MediaField::VideoQuality => {
+                self.program
+                    .video
+                    .get_or_insert_with(Video::default)
+                    .quality = Some(s)
+            }
+            MediaField::AudioPresent => {
+                self.program
+                    .audio
+                    .get_or_insert_with(Audio::default)
+                    .present = flag
+            }
+            MediaField::AudioStereo => {
+                self.program.audio.get_or_insert_with(Audio::default).stereo = Some(s)
+            }
+        }
+    }
+
+    /// Complete the current program and get ready for the next one
+    fn finish(&mut self) -> (String, Program) {
+        self.program
+            .select_primary_category(&self.category_priority);
+        let limit = self.title_max_len;
+        let mut truncated = truncate_with_ellipsis(&mut self.program.title, limit);
+        if let Some(sub_title) = &mut self.program.sub_title {
+            truncated |= truncate_with_ellipsis(sub_title, limit);
+        }
+        for translation in self.program.translations.values_mut() {
+            truncated |= truncate_with_ellipsis(&mut translation.title, limit);
+        }
+        if truncated {
+            self.truncated += 1;
+        }
+        let result = (self.channel_alias.clone(), self.program.clone());
+        self.reset();
+        result
     }
 
     fn reset(&mut self) {
         self.channel_alias = String::new();
         self.program = Program::new();
         self.field = None;
+        self.block = None;
+        self.lang = None;
+    }
+}
+
+/// Position of a program among the ones sharing its time slot, like `clumpidx="0/2"`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Clump {
+    index: usize,
+    count: usize,
+}
+
+impl Clump {
+    /// Clump of an `index/count` value, a single program is not a clump
+    fn parse(s: &str) -> Option<Self> {
+        let slash = s.find('/')?;
+        let clump = Self {
+            index: s[..slash].trim().parse().ok()?,
+            count: s[slash + 1..].trim().parse().ok()?,
+        };
+        Some(clump).filter(|c| c.count > 1 && c.index < c.count)
+    }
+}
+
+/// Add a part of the program's time slot to the program, titles and descriptions are joined
+fn merge_clump_part(program: &mut Program, part: Program) {
+    let join = |text: &mut String, part: String, separator: &str| {
+        if text.is_empty() {
+            *text = part;
+        } else if !part.is_empty() {
+            text.push_str(separator);
+            text.push_str(&part);
+        }
+    };
+    program.begin = program.begin.min(part.begin);
+    program.end = program.end.max(part.end);
+    join(&mut program.title, part.title, " / ");
+    join(&mut program.description, part.description, "\n");
+    for category in part.categories {
+        if !program.categories.contains(&category) {
+            program.categories.push(category);
+        }
+    }
+    if program.primary_category.is_none() {
+        program.primary_category = part.primary_category;
     }
 }
 
@@ -237,14 +490,68 @@ impl ChannelParser {
     }
 }
 
-fn to_timestamp(s: &str) -> ParseResult<i64> {
+/// Cut the text to `limit` characters with the last one replaced by an ellipsis,
+/// returns whether it was too long
+fn truncate_with_ellipsis(s: &mut String, limit: usize) -> bool {
+    if s.chars().nth(limit).is_none() {
+        return false;
+    }
+    let index = s
+        .char_indices()
+        .nth(limit.saturating_sub(1))
+        .map_or(0, |(i, _)| i);
+    s.truncate(index);
+    s.push('…');
+    true
+}
+
+/// Unix time of an xmltv date, dates without an offset are local times of the timezone
+/// or UTC without one
+fn to_timestamp(s: &str, timezone: Option<Tz>) -> ParseResult<i64> {
     if s.find(' ').is_some() {
         DateTime::parse_from_str(s, "%Y%m%d%H%M%S %z").map(|dt| std::cmp::max(dt.timestamp(), 0))
     } else {
-        NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M%S").map(|dt| std::cmp::max(dt.timestamp(), 0))
+        NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M%S").map(|dt| {
+            let t = match timezone {
+                Some(tz) => local_timestamp(tz, &dt),
+                None => dt.timestamp(),
+            };
+            std::cmp::max(t, 0)
+        })
+    }
+}
+
+/// Unix time of the local time. Times repeated when clocks go back are the earlier ones,
+/// times skipped when clocks go forward keep the offset from before the change.
+fn local_timestamp(tz: Tz, dt: &NaiveDateTime) -> i64 {
+    match tz.from_local_datetime(dt) {
+        LocalResult::Single(t) => t.timestamp(),
+        LocalResult::Ambiguous(a, b) => a.timestamp().min(b.timestamp()),
+        LocalResult::None => {
+            let before = tz.offset_from_utc_datetime(&(*dt - chrono::Duration::days(1)));
+            dt.timestamp() - i64::from(before.fix().local_minus_utc())
+        }
     }
 }
 
+fn from_timestamp(t: i64) -> String {
+    Utc.timestamp(t, 0).format("%Y%m%d%H%M%S +0000").to_string()
+}
+
+/// Feed with the given channels, each airing the same program
+#[cfg(test)]
+pub fn test_feed(aliases: &[&str], start: DateTime<Utc>, stop: DateTime<Utc>) -> String {
+    aliases.iter().fold("<tv>".to_string(), |xml, alias| {
+        xml + &format!(
+            r#"<channel id="{0}"><display-name>{0}</display-name></channel>
+            <programme start="{1}" stop="{2}" channel="{0}"><title>News</title></programme>"#,
+            alias,
+            from_timestamp(start.timestamp()),
+            from_timestamp(stop.timestamp()),
+        )
+    }) + "</tv>"
+}
+
 fn get_attribute(name: &str, attributes: Attributes) -> Option<String> {
     let mut result = None;
     for a in attributes.filter_map(|a| a.ok()) {
@@ -255,6 +562,32 @@ fn get_attribute(name: &str, attributes: Attributes) -> Option<String> {
     result
 }
 
+/// Provenance attributes of the root `<tv>` element
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FeedInfo {
+    pub generator_name: String,
+    pub generator_url: String,
+    pub date: String,
+}
+
+impl FeedInfo {
+    const TAG: &'static [u8] = b"tv";
+
+    fn from_attributes(element: &quick_xml::events::BytesStart) -> Self {
+        Self {
+            generator_name: get_attribute("generator-info-name", element.attributes())
+                .unwrap_or_default(),
+            generator_url: get_attribute("generator-info-url", element.attributes())
+                .unwrap_or_default(),
+            date: get_attribute("date", element.attributes()).unwrap_or_default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.generator_name.is_empty() && self.generator_url.is_empty() && self.date.is_empty()
+    }
+}
+
 #[derive(Debug)]
 enum Level {
     Top,
@@ -268,6 +601,23 @@ pub struct XmltvReader<R: BufRead> {
     buf: Vec<u8>,
     channel_parser: ChannelParser,
     program_parser: ProgramParser,
+    /// Channels without id get an alias derived from their name
+    synthesize_aliases: bool,
+    /// Aliases of channels which have an id
+    aliases: HashSet<String>,
+    /// Aliases derived from channel names
+    synthesized: HashSet<String>,
+    /// Derived alias by channel name
+    synthesized_names: HashMap<String, String>,
+    /// Prefix of titles with a `channel` group which is stripped when it names the channel
+    title_prefix: Option<Regex>,
+    /// Programs sharing a time slot are merged into one
+    merge_clumps: bool,
+    /// Merged parts of clumped programs by channel until their last part is read,
+    /// ordered so incomplete ones come out in the same order on each read
+    clumps: BTreeMap<String, (usize, Program)>,
+    /// Names of the channels by alias, known while stripping title prefixes
+    channel_names: HashMap<String, String>,
 }
 
 impl<R: BufRead> XmltvReader<R> {
@@ -280,12 +630,323 @@ impl<R: BufRead> XmltvReader<R> {
             buf: Vec::with_capacity(2048),
             channel_parser: ChannelParser::new(),
             program_parser: ProgramParser::new(),
+            synthesize_aliases: false,
+            aliases: HashSet::new(),
+            synthesized: HashSet::new(),
+            synthesized_names: HashMap::new(),
+            title_prefix: None,
+            merge_clumps: false,
+            clumps: BTreeMap::new(),
+            channel_names: HashMap::new(),
+        }
+    }
+
+    /// Categories preferred as the primary category of programs, most wanted first
+    pub fn with_category_priority(mut self, priority: Vec<String>) -> Self {
+        self.program_parser.category_priority = priority;
+        self
+    }
+
+    /// Maximum number of characters of program titles and sub-titles, longer ones are cut
+    pub fn with_title_max_len(mut self, limit: usize) -> Self {
+        self.program_parser.title_max_len = limit;
+        self
+    }
+
+    /// Timezone of program times without an offset, they are UTC when it is not given
+    pub fn with_timezone(mut self, timezone: Option<Tz>) -> Self {
+        self.program_parser.timezone = timezone;
+        self
+    }
+
+    /// Durations of programs without `stop` time by channel alias of the feed,
+    /// the others get a minute
+    pub fn with_default_durations(mut self, durations: ChannelDurations) -> Self {
+        self.program_parser.default_durations = durations;
+        self
+    }
+
+    /// Merge programs which share a time slot, told by their `clumpidx`, into one program,
+    /// otherwise each of them is a separate program
+    pub fn with_merged_clumps(mut self, enabled: bool) -> Self {
+        self.merge_clumps = enabled;
+        self
+    }
+
+    /// Collect the part of a clumped program, the merged program is returned with its last part.
+    /// A clump which is not complete when the next one of the channel begins is returned as it is,
+    /// a part without the first one of its clump is a program of its own.
+    fn merge_clump(
+        &mut self,
+        (alias, program): (String, Program),
+        clump: Clump,
+    ) -> Option<(String, Program)> {
+        let previous = match self.clumps.remove(&alias) {
+            Some((remaining, mut merged)) if clump.index > 0 => {
+                merge_clump_part(&mut merged, program);
+                if remaining > 1 {
+                    self.clumps.insert(alias, (remaining - 1, merged));
+                    return None;
+                }
+                return Some((alias, merged));
+            }
+            None if clump.index > 0 => return Some((alias, program)),
+            previous => previous,
+        };
+        self.clumps
+            .insert(alias.clone(), (clump.count - 1, program));
+        previous.map(|(_, merged)| (alias, merged))
+    }
+
+    /// Give channels without id an alias made of their name, like `bbc-one` for "BBC One",
+    /// programs may refer to such channels by the name or by the derived alias
+    pub fn with_synthesized_aliases(mut self, enabled: bool) -> Self {
+        self.synthesize_aliases = enabled;
+        self
+    }
+
+    /// Strip title prefixes matching the pattern, like "BBC One: " of "BBC One: Doctor Who",
+    /// when its `channel` group is the name of the program's channel
+    pub fn with_title_prefix(mut self, pattern: Option<Regex>) -> Self {
+        self.title_prefix = pattern;
+        self
+    }
+
+    /// Strip the prefix from the title and each translated one, `localize` would bring it back
+    fn strip_title_prefix(&self, alias: &str, program: &mut Program) {
+        let (pattern, name) = match (&self.title_prefix, self.channel_names.get(alias)) {
+            (Some(pattern), Some(name)) => (pattern, name),
+            _ => return,
+        };
+        let titles = std::iter::once(&mut program.title).chain(
+            program
+                .translations
+                .values_mut()
+                .map(|translation| &mut translation.title),
+        );
+        for title in titles {
+            let end = match pattern.captures(title) {
+                Some(captures) if captures.get(0).map_or(false, |m| m.start() == 0) => {
+                    let channel = captures.name("channel").map_or("", |m| m.as_str().trim());
+                    if channel.to_lowercase() != name.trim().to_lowercase() {
+                        continue;
+                    }
+                    captures.get(0).unwrap().end()
+                }
+                _ => continue,
+            };
+            // A title which is only the channel name is kept
+            let rest = title[end..].trim();
+            if !rest.is_empty() {
+                *title = rest.to_string();
+            }
+        }
+    }
+
+    fn synthesize_alias(&mut self, channel: &mut ChannelInfo) {
+        if !channel.alias.is_empty() {
+            self.aliases.insert(channel.alias.clone());
+            return;
+        }
+        if let Some(alias) = self.synthesized_names.get(&channel.name) {
+            channel.alias = alias.clone();
+            return;
+        }
+        let slug = slugify(&channel.name);
+        if slug.is_empty() {
+            return;
+        }
+        // Names which differ only in punctuation get numbered aliases
+        let mut alias = slug.clone();
+        let mut n = 1;
+        while self.aliases.contains(&alias) || self.synthesized.contains(&alias) {
+            n += 1;
+            alias = format!("{}-{}", slug, n);
+        }
+        println!(
+            "Channel '{}' without id is known as '{}'",
+            channel.name, alias
+        );
+        channel.alias = alias.clone();
+        self.synthesized.insert(alias.clone());
+        self.synthesized_names.insert(channel.name.clone(), alias);
+    }
+
+    fn resolve_program_alias(&self, alias: &mut String) {
+        if self.aliases.contains(alias.as_str()) {
+            return;
+        }
+        if let Some(derived) = self.synthesized_names.get(alias.as_str()) {
+            *alias = derived.clone();
+            return;
+        }
+        let slug = slugify(alias);
+        if self.synthesized.contains(&slug) {
+            *alias = slug;
+        }
+    }
+}
+
+/// Lowercase ascii letters and digits of the text transliterated to latin,
+/// other characters collapsed into single dashes
+pub fn slugify(text: &str) -> String {
+    let text = deunicode(text);
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
         }
     }
+    let len = slug.trim_end_matches('-').len();
+    slug.truncate(len);
+    slug
+}
+
+/// Serializes channels and programs into XMLTV document
+pub struct XmltvWriter<W: Write> {
+    writer: Writer<W>,
+}
+
+impl<W: Write> XmltvWriter<W> {
+    pub fn new(inner: W, info: &FeedInfo) -> quick_xml::Result<Self> {
+        let mut writer = Writer::new(inner);
+        writer.write(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n")?;
+        let mut tv = BytesStart::borrowed_name(FeedInfo::TAG);
+        for (key, value) in [
+            ("generator-info-name", &info.generator_name),
+            ("generator-info-url", &info.generator_url),
+            ("date", &info.date),
+        ]
+        .iter()
+        {
+            if !value.is_empty() {
+                tv.push_attribute((*key, value.as_str()));
+            }
+        }
+        writer.write_event(Event::Start(tv))?;
+        Ok(Self { writer })
+    }
+
+    /// Write channel, `with_alias` adds alias as an alternative display name
+    ///
+    /// The alias goes first since the reader keeps the last display name.
+    pub fn write_channel(
+        &mut self,
+        channel: &ChannelInfo,
+        with_alias: bool,
+    ) -> quick_xml::Result<()> {
+        let tag = BytesStart::borrowed_name(ChannelParser::TAG)
+            .with_attributes(vec![("id", channel.alias.as_str())]);
+        self.writer.write_event(Event::Start(tag))?;
+        if with_alias && channel.alias != channel.name {
+            self.write_text(b"display-name", &channel.alias)?;
+        }
+        self.write_text(b"display-name", &channel.name)?;
+        if !channel.icon_url.is_empty() {
+            let icon = BytesStart::borrowed_name(b"icon")
+                .with_attributes(vec![("src", channel.icon_url.as_str())]);
+            self.writer.write_event(Event::Empty(icon))?;
+        }
+        self.writer
+            .write_event(Event::End(BytesEnd::borrowed(ChannelParser::TAG)))
+    }
+
+    pub fn write_program(&mut self, alias: &str, program: &Program) -> quick_xml::Result<()> {
+        let begin = from_timestamp(program.begin);
+        let end = from_timestamp(program.end);
+        let tag = BytesStart::borrowed_name(ProgramParser::TAG).with_attributes(vec![
+            ("start", begin.as_str()),
+            ("stop", end.as_str()),
+            ("channel", alias),
+        ]);
+        self.writer.write_event(Event::Start(tag))?;
+        self.write_text(b"title", &program.title)?;
+        if let Some(sub_title) = &program.sub_title {
+            self.write_text(b"sub-title", sub_title)?;
+        }
+        if !program.description.is_empty() {
+            self.write_text(b"desc", &program.description)?;
+        }
+        for category in &program.categories {
+            self.write_text(b"category", category)?;
+        }
+        if let Some(language) = &program.language {
+            self.write_text(b"language", language)?;
+        }
+        for country in &program.countries {
+            self.write_text(b"country", country)?;
+        }
+        if let Some(video) = &program.video {
+            self.write_block(b"video", |w| {
+                if let Some(present) = video.present {
+                    w.write_text(b"present", yes_no(present))?;
+                }
+                if let Some(colour) = video.colour {
+                    w.write_text(b"colour", yes_no(colour))?;
+                }
+                if let Some(aspect) = &video.aspect {
+                    w.write_text(b"aspect", aspect)?;
+                }
+                if let Some(quality) = &video.quality {
+                    w.write_text(b"quality", quality)?;
+                }
+                Ok(())
+            })?;
+        }
+        if let Some(audio) = &program.audio {
+            self.write_block(b"audio", |w| {
+                if let Some(present) = audio.present {
+                    w.write_text(b"present", yes_no(present))?;
+                }
+                if let Some(stereo) = &audio.stereo {
+                    w.write_text(b"stereo", stereo)?;
+                }
+                Ok(())
+            })?;
+        }
+        self.writer
+            .write_event(Event::End(BytesEnd::borrowed(ProgramParser::TAG)))
+    }
+
+    /// Close the document and return the underlying writer
+    pub fn finish(mut self) -> quick_xml::Result<W> {
+        self.writer
+            .write_event(Event::End(BytesEnd::borrowed(FeedInfo::TAG)))?;
+        Ok(self.writer.into_inner())
+    }
+
+    fn write_text(&mut self, tag: &[u8], text: &str) -> quick_xml::Result<()> {
+        self.writer
+            .write_event(Event::Start(BytesStart::borrowed_name(tag)))?;
+        self.writer
+            .write_event(Event::Text(BytesText::from_plain_str(text)))?;
+        self.writer.write_event(Event::End(BytesEnd::borrowed(tag)))
+    }
+
+    fn write_block<F>(&mut self, tag: &[u8], children: F) -> quick_xml::Result<()>
+    where
+        F: FnOnce(&mut Self) -> quick_xml::Result<()>,
+    {
+        self.writer
+            .write_event(Event::Start(BytesStart::borrowed_name(tag)))?;
+        children(self)?;
+        self.writer.write_event(Event::End(BytesEnd::borrowed(tag)))
+    }
+}
+
+fn yes_no(flag: bool) -> &'static str {
+    if flag {
+        "yes"
+    } else {
+        "no"
+    }
 }
 
 #[derive(Debug)]
 pub enum XmltvItem {
+    Header(FeedInfo),
     Channel(ChannelInfo),
     Program((String, Program)),
 }
@@ -299,7 +960,18 @@ impl<R: BufRead> Iterator for XmltvReader<R> {
         self.buf.clear();
         loop {
             let ev = match self.parser.read_event(&mut self.buf) {
-                Ok(Event::Eof) => return None,
+                Ok(Event::Eof) => {
+                    // Clumps missing some of their parts
+                    if let Some(alias) = self.clumps.keys().next().cloned() {
+                        let (_, program) = self.clumps.remove(&alias).unwrap();
+                        return Some(Ok(XmltvItem::Program((alias, program))));
+                    }
+                    let truncated = std::mem::take(&mut self.program_parser.truncated);
+                    if truncated > 0 {
+                        eprintln!("Truncated overlong titles of {} programs", truncated);
+                    }
+                    return None;
+                }
                 Ok(ev) => ev,
                 Err(e) => {
                     println!("Xml parser error: {}", e);
@@ -318,6 +990,10 @@ impl<R: BufRead> Iterator for XmltvReader<R> {
                                 self.level = Level::Channel;
                                 self.channel_parser.handle_event(&ev, &self.parser);
                             }
+                            FeedInfo::TAG => {
+                                let info = FeedInfo::from_attributes(element);
+                                return Some(Ok(XmltvItem::Header(info)));
+                            }
                             _ => {
                                 if let Ok(tag) = str::from_utf8(element.local_name()) {
                                     eprintln!("unknown tag {}", tag);
@@ -331,15 +1007,37 @@ impl<R: BufRead> Iterator for XmltvReader<R> {
                 },
                 Level::Channel => {
                     let result = self.channel_parser.handle_event(&ev, &self.parser);
-                    if let Some(channel) = result {
+                    if let Some(mut channel) = result {
                         self.level = Level::Top;
+                        if self.synthesize_aliases {
+                            self.synthesize_alias(&mut channel);
+                        }
+                        if self.title_prefix.is_some() {
+                            self.channel_names
+                                .insert(channel.alias.clone(), channel.name.clone());
+                        }
                         return Some(Ok(XmltvItem::Channel(channel)));
                     }
                 }
                 Level::Program => {
                     let result = self.program_parser.handle_event(&ev, &self.parser);
-                    if let Some(pair) = result {
+                    if let Some(mut pair) = result {
                         self.level = Level::Top;
+                        if self.synthesize_aliases {
+                            self.resolve_program_alias(&mut pair.0);
+                        }
+                        self.strip_title_prefix(&pair.0, &mut pair.1);
+                        let clump = if self.merge_clumps {
+                            self.program_parser.clump
+                        } else {
+                            None
+                        };
+                        if let Some(clump) = clump {
+                            match self.merge_clump(pair, clump) {
+                                Some(pair) => return Some(Ok(XmltvItem::Program(pair))),
+                                None => continue,
+                            }
+                        }
                         return Some(Ok(XmltvItem::Program(pair)));
                     }
                 }
@@ -358,15 +1056,496 @@ mod test {
     fn test_date() {
         let hour = 3600;
         assert_eq!(
-            to_timestamp("20200530181000 +0200").unwrap(),
+            to_timestamp("20200530181000 +0200", None).unwrap(),
             FixedOffset::east(2 * hour)
                 .ymd(2020, 05, 30)
                 .and_hms(18, 10, 00)
                 .timestamp()
         );
         assert_eq!(
-            to_timestamp("20200530164500").unwrap(),
+            to_timestamp("20200530164500", None).unwrap(),
             Utc.ymd(2020, 05, 30).and_hms(16, 45, 00).timestamp()
         );
+        // The offset wins over the timezone
+        assert_eq!(
+            to_timestamp("20200530181000 +0200", Some(chrono_tz::Asia::Tokyo)).unwrap(),
+            to_timestamp("20200530181000 +0200", None).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_date_timezone() {
+        let berlin = Some(chrono_tz::Europe::Berlin);
+        let utc = |d, h, m| Utc.ymd(2021, 3, d).and_hms(h, m, 0).timestamp();
+        assert_eq!(
+            to_timestamp("20210328013000", berlin).unwrap(),
+            utc(28, 0, 30)
+        );
+        // Clocks go forward from 02:00 to 03:00, the skipped time keeps the winter offset
+        assert_eq!(
+            to_timestamp("20210328023000", berlin).unwrap(),
+            utc(28, 1, 30)
+        );
+        assert_eq!(
+            to_timestamp("20210328033000", berlin).unwrap(),
+            utc(28, 1, 30)
+        );
+        assert_eq!(
+            to_timestamp("20210328043000", berlin).unwrap(),
+            utc(28, 2, 30)
+        );
+        // The repeated hour when clocks go back is taken as the summer time one
+        assert_eq!(
+            to_timestamp("20211031023000", berlin).unwrap(),
+            Utc.ymd(2021, 10, 31).and_hms(0, 30, 0).timestamp()
+        );
+
+        let data = r#"<tv><channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <programme start="20210328010000" stop="20210328040000" channel="ch1">
+            <title>Night movie</title></programme></tv>"#;
+        let programs = XmltvReader::new(data.as_bytes())
+            .with_timezone(berlin)
+            .filter_map(|item| match item {
+                Ok(XmltvItem::Program((_, program))) => Some(program),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(programs.len(), 1);
+        assert_eq!(programs[0].begin, utc(28, 0, 0));
+        // Three hours on the wall clock are two hours long
+        assert_eq!(programs[0].end - programs[0].begin, 2 * 3600);
+    }
+
+    #[test]
+    fn test_writer_round_trip() {
+        let channel = ChannelInfo {
+            alias: "ch1".to_string(),
+            name: "Channel <1>".to_string(),
+            icon_url: "http://icons.org/1.png".to_string(),
+        };
+        let program = Program {
+            begin: 1622498400,
+            end: 1622502000,
+            title: "News & Weather".to_string(),
+            description: "Daily news".to_string(),
+            sub_title: Some("Evening edition".to_string()),
+            categories: vec!["News".to_string()],
+            primary_category: Some("News".to_string()),
+            video: Some(Video {
+                aspect: Some("16:9".to_string()),
+                quality: Some("HDTV".to_string()),
+                ..Video::default()
+            }),
+            audio: Some(Audio {
+                stereo: Some("stereo".to_string()),
+                ..Audio::default()
+            }),
+            ..Program::new()
+        };
+        let info = FeedInfo {
+            generator_name: "epg-server".to_string(),
+            ..FeedInfo::default()
+        };
+        let mut writer = XmltvWriter::new(Vec::new(), &info).unwrap();
+        writer.write_channel(&channel, true).unwrap();
+        writer.write_program(&channel.alias, &program).unwrap();
+        let data = writer.finish().unwrap();
+
+        let text = str::from_utf8(&data).unwrap();
+        assert!(text.contains("<display-name>ch1</display-name>"));
+
+        let items = XmltvReader::new(data.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(items.len(), 3);
+        match &items[0] {
+            XmltvItem::Header(h) => assert_eq!(h, &info),
+            item => panic!("unexpected item {:?}", item),
+        }
+        match &items[1] {
+            XmltvItem::Channel(c) => {
+                assert_eq!(c.alias, channel.alias);
+                assert_eq!(c.name, channel.name);
+                assert_eq!(c.icon_url, channel.icon_url);
+            }
+            item => panic!("unexpected item {:?}", item),
+        }
+        match &items[2] {
+            XmltvItem::Program((alias, p)) => {
+                assert_eq!(alias, &channel.alias);
+                assert_eq!(p, &program);
+            }
+            item => panic!("unexpected item {:?}", item),
+        }
+    }
+
+    #[test]
+    fn test_sub_title() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <tv>
+            <programme start="20210316180000 +0000" stop="20210316190000 +0000" channel="ch1">
+              <sub-title>Pilot</sub-title>
+              <title>Series</title>
+              <category>Drama</category>
+              <category>Comedy</category>
+            </programme>
+            <programme start="20210316190000 +0000" stop="20210316200000 +0000" channel="ch1">
+              <title>Movie</title>
+            </programme>
+            </tv>"#;
+        let programs = XmltvReader::new(data.as_bytes())
+            .filter_map(|item| match item.unwrap() {
+                XmltvItem::Program((_, program)) => Some(program),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(programs.len(), 2);
+        assert_eq!(programs[0].title, "Series");
+        assert_eq!(programs[0].sub_title.as_deref(), Some("Pilot"));
+        assert_eq!(programs[0].categories, vec!["Drama", "Comedy"]);
+        assert_eq!(programs[1].title, "Movie");
+        assert_eq!(programs[1].sub_title, None);
+    }
+
+    #[test]
+    fn test_primary_category() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <tv>
+            <programme start="20210316180000 +0000" stop="20210316200000 +0000" channel="ch1">
+              <title>Movie</title>
+              <category lang="en">Drama</category>
+              <category lang="en">Movie</category>
+            </programme>
+            </tv>"#;
+        let program = XmltvReader::new(data.as_bytes())
+            .with_category_priority(vec!["movie".to_string(), "drama".to_string()])
+            .find_map(|item| match item.unwrap() {
+                XmltvItem::Program((_, program)) => Some(program),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(program.categories, vec!["Drama", "Movie"]);
+        assert_eq!(program.primary_category.as_deref(), Some("Movie"));
+    }
+
+    #[test]
+    fn test_title_max_len() {
+        let data = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <tv>
+            <programme start="20210316180000 +0000" stop="20210316200000 +0000" channel="ch1">
+              <title>{}</title>
+              <sub-title>Pilot</sub-title>
+            </programme>
+            </tv>"#,
+            "é".repeat(20)
+        );
+        let mut reader = XmltvReader::new(data.as_bytes()).with_title_max_len(10);
+        let program = reader
+            .find_map(|item| match item.unwrap() {
+                XmltvItem::Program((_, program)) => Some(program),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(program.title, format!("{}…", "é".repeat(9)));
+        assert_eq!(program.sub_title.as_deref(), Some("Pilot"));
+        assert_eq!(reader.program_parser.truncated, 1);
+    }
+
+    #[test]
+    fn test_default_durations() {
+        let data = r#"<tv>
+            <programme start="20210316180000 +0000" channel="ticker"><title>Headlines</title></programme>
+            <programme channel="news" start="20210316180000 +0000"><title>News</title></programme>
+            <programme stop="bad" start="20210316180000 +0000" channel="ticker"><title>Stocks</title></programme>
+            <programme start="20210316180000 +0000" stop="20210316183000 +0000" channel="ticker">
+            <title>Weather</title></programme>
+            </tv>"#;
+        let durations = ChannelDurations::parse("ticker: 2h".as_bytes()).unwrap();
+        let durations = XmltvReader::new(data.as_bytes())
+            .with_default_durations(durations)
+            .filter_map(|item| match item.unwrap() {
+                XmltvItem::Program((alias, program)) => Some((alias, program.end - program.begin)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // The configured channel gets its duration, others the global one, stop times win
+        assert_eq!(
+            durations,
+            vec![
+                ("ticker".to_string(), 2 * 3600),
+                ("news".to_string(), DEFAULT_DURATION),
+                ("ticker".to_string(), 2 * 3600),
+                ("ticker".to_string(), 30 * 60),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_attributes() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <tv>
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <programme start="20210316180000 +0000" stop="20210316190000 +0000" channel="ch1"
+              pdc-start="20210316180000 +0000" showview="12345">
+              <title>News</title>
+            </programme>
+            </tv>"#;
+        let programs = XmltvReader::new(data.as_bytes())
+            .filter_map(|item| match item.unwrap() {
+                XmltvItem::Program(pair) => Some(pair),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(programs.len(), 1);
+        let (alias, program) = &programs[0];
+        assert_eq!(alias, "ch1");
+        assert_eq!(program.title, "News");
+        assert_eq!(program.end - program.begin, 3600);
+    }
+
+    #[test]
+    fn test_title_prefix() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <tv>
+            <channel id="bbc1"><display-name>BBC One</display-name></channel>
+            <channel id="cnn"><display-name>CNN</display-name></channel>
+            <programme start="20210316180000 +0000" stop="20210316190000 +0000" channel="bbc1">
+              <title>bbc one: Doctor Who</title>
+            </programme>
+            <programme start="20210316190000 +0000" stop="20210316200000 +0000" channel="bbc1">
+              <title>Star Trek: Discovery</title>
+            </programme>
+            <programme start="20210316200000 +0000" stop="20210316210000 +0000" channel="bbc1">
+              <title>BBC One: </title>
+            </programme>
+            <programme start="20210316180000 +0000" stop="20210316190000 +0000" channel="cnn">
+              <title>BBC One: Doctor Who</title>
+            </programme>
+            <programme start="20210316210000 +0000" stop="20210316220000 +0000" channel="bbc1">
+              <title lang="de">BBC One: Nachrichten</title><title lang="en">BBC One: News</title>
+            </programme>
+            </tv>"#;
+        let pattern = Regex::new(r"^(?P<channel>[^:]+):\s*").unwrap();
+        let mut programs = XmltvReader::new(data.as_bytes())
+            .with_title_prefix(Some(pattern))
+            .filter_map(|item| match item.unwrap() {
+                XmltvItem::Program((_, program)) => Some(program),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut localized = programs.pop().unwrap();
+        assert_eq!(
+            programs
+                .iter()
+                .map(|p| p.title.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "Doctor Who",
+                "Star Trek: Discovery",
+                "BBC One:",
+                "BBC One: Doctor Who"
+            ]
+        );
+        assert_eq!(localized.title, "News");
+        localized.localize(&["de".to_string()]);
+        assert_eq!(localized.title, "Nachrichten");
+    }
+
+    #[test]
+    fn test_clumps() {
+        assert_eq!(Clump::parse("1/2"), Some(Clump { index: 1, count: 2 }));
+        assert_eq!(Clump::parse("0/1"), None);
+        assert_eq!(Clump::parse("2/2"), None);
+        assert_eq!(Clump::parse("x/2"), None);
+
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <tv>
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            <programme start="20210316170000 +0000" stop="20210316180000 +0000" channel="ch1" clumpidx="1/2">
+              <title>Teaser</title>
+            </programme>
+            <programme start="20210316180000 +0000" stop="20210316190000 +0000" channel="ch1" clumpidx="0/2">
+              <title>News</title><desc>Headlines</desc><category>news</category>
+            </programme>
+            <programme start="20210316180000 +0000" stop="20210316190000 +0000" channel="ch1" clumpidx="1/2">
+              <title>Weather</title><category>weather</category>
+            </programme>
+            <programme start="20210316190000 +0000" stop="20210316200000 +0000" channel="ch1">
+              <title>Movie</title>
+            </programme>
+            <programme start="20210316200000 +0000" stop="20210316210000 +0000" channel="ch2" clumpidx="0/2">
+              <title>Sport</title>
+            </programme>
+            <programme start="20210316200000 +0000" stop="20210316210000 +0000" channel="ch1" clumpidx="0/3">
+              <title>Cartoon</title>
+            </programme>
+            <programme start="20210316200000 +0000" stop="20210316210000 +0000" channel="ch1" clumpidx="1/3">
+              <title>Quiz</title>
+            </programme>
+            </tv>"#;
+        let programs = |merge| {
+            XmltvReader::new(data.as_bytes())
+                .with_merged_clumps(merge)
+                .filter_map(|item| match item.unwrap() {
+                    XmltvItem::Program((_, program)) => Some(program),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let titles =
+            |programs: &[Program]| programs.iter().map(|p| p.title.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            titles(&programs(false)),
+            vec!["Teaser", "News", "Weather", "Movie", "Sport", "Cartoon", "Quiz"]
+        );
+
+        let merged = programs(true);
+        // The part without its first one stays as it is,
+        // incomplete clumps come at the end of the feed by channel
+        assert_eq!(
+            titles(&merged),
+            vec![
+                "Teaser",
+                "News / Weather",
+                "Movie",
+                "Cartoon / Quiz",
+                "Sport"
+            ]
+        );
+        assert_eq!(merged[1].end - merged[1].begin, 3600);
+        assert_eq!(merged[1].description, "Headlines");
+        assert_eq!(merged[1].categories, vec!["news", "weather"]);
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("BBC One"), "bbc-one");
+        assert_eq!(slugify(" Canal+ (HD) "), "canal-hd");
+        assert_eq!(slugify("Первый"), "pervyi");
+        assert_eq!(slugify("日本"), "ri-ben");
+        assert_eq!(slugify("★"), "");
+    }
+
+    #[test]
+    fn test_title_languages() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <tv>
+            <programme start="20210316180000 +0000" stop="20210316190000 +0000" channel="ch1">
+              <title lang="en">News</title>
+              <title lang="DE">Nachrichten</title>
+              <desc lang="de">Aktuelles</desc>
+            </programme>
+            </tv>"#;
+        let program = XmltvReader::new(data.as_bytes())
+            .find_map(|item| match item.unwrap() {
+                XmltvItem::Program((_, program)) => Some(program),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(program.translations["en"].title, "News");
+        assert_eq!(program.translations["de"].title, "Nachrichten");
+        assert_eq!(program.translations["de"].description, "Aktuelles");
+    }
+
+    #[test]
+    fn test_country_language() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <tv>
+            <programme start="20210316180000 +0000" stop="20210316200000 +0000" channel="ch1">
+              <title>Movie</title>
+              <language>fr</language>
+              <country>FR</country>
+              <country>BE</country>
+            </programme>
+            <programme start="20210316200000 +0000" stop="20210316210000 +0000" channel="ch1">
+              <title>News</title>
+            </programme>
+            </tv>"#;
+        let programs = XmltvReader::new(data.as_bytes())
+            .filter_map(|item| match item.unwrap() {
+                XmltvItem::Program((_, program)) => Some(program),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(programs[0].countries, vec!["FR", "BE"]);
+        assert_eq!(programs[0].language.as_deref(), Some("fr"));
+        assert!(programs[1].countries.is_empty());
+        assert_eq!(programs[1].language, None);
+    }
+
+    #[test]
+    fn test_audio_video() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <tv>
+            <programme start="20210316180000 +0000" stop="20210316200000 +0000" channel="ch1">
+              <title>Movie</title>
+              <video>
+                <present>yes</present>
+                <colour>no</colour>
+                <aspect>4:3</aspect>
+                <quality>SDTV</quality>
+              </video>
+              <audio>
+                <present>yes</present>
+                <stereo>dolby digital</stereo>
+              </audio>
+            </programme>
+            <programme start="20210316200000 +0000" stop="20210316210000 +0000" channel="ch1">
+              <title>News</title>
+              <audio><stereo>mono</stereo></audio>
+            </programme>
+            </tv>"#;
+        let programs = XmltvReader::new(data.as_bytes())
+            .filter_map(|item| match item.unwrap() {
+                XmltvItem::Program((_, program)) => Some(program),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(programs[0].title, "Movie");
+        assert_eq!(
+            programs[0].video,
+            Some(Video {
+                present: Some(true),
+                colour: Some(false),
+                aspect: Some("4:3".to_string()),
+                quality: Some("SDTV".to_string()),
+            })
+        );
+        assert_eq!(
+            programs[0].audio,
+            Some(Audio {
+                present: Some(true),
+                stereo: Some("dolby digital".to_string()),
+            })
+        );
+        assert_eq!(programs[1].video, None);
+        assert_eq!(
+            programs[1].audio.as_ref().and_then(|a| a.stereo.as_deref()),
+            Some("mono")
+        );
+    }
+
+    #[test]
+    fn test_feed_info() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <tv generator-info-name="epg-gen" generator-info-url="http://epg.org/" date="20210316">
+            <channel id="ch1"><display-name>Channel 1</display-name></channel>
+            </tv>"#;
+        let items = XmltvReader::new(data.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(items.len(), 2);
+        match &items[0] {
+            XmltvItem::Header(info) => assert_eq!(
+                info,
+                &FeedInfo {
+                    generator_name: "epg-gen".to_string(),
+                    generator_url: "http://epg.org/".to_string(),
+                    date: "20210316".to_string(),
+                }
+            ),
+            item => panic!("unexpected item {:?}", item),
+        }
     }
 }
@@ -78,6 +78,7 @@ $(function() {
       $('#tableContainer').html(reply);
       $('#tableContainer').find('.btn.btn-primary').on('click', edit);
       $('#tableContainer').find('.btn.btn-secondary').on('click', markOk);
+      $('#tableContainer').find('th.sortable').on('click', sortBy);
     } catch (error) {
       alert(error)
     } finally {
@@ -112,6 +113,28 @@ $(function() {
     modal.modal('show');
   };
 
+  // Rows come weakest match first, a header click sorts by its column and a second one reverses
+  function sortBy() {
+    const th = $(this);
+    const column = th.index();
+    const numeric = th.data('sort') === 'number';
+    const descending = th.data('order') === 'asc';
+    th.siblings().data('order', '');
+    th.data('order', descending ? 'desc' : 'asc');
+    const value = (tr) => {
+      const td = $(tr).children('td').eq(column);
+      return numeric ? parseFloat(td.data('value')) : td.text().trim().toLowerCase();
+    };
+    const tbody = $('#playlistTable');
+    const rows = tbody.children('tr').get();
+    rows.sort((a, b) => {
+      const [x, y] = [value(a), value(b)];
+      const order = x < y ? -1 : (x > y ? 1 : 0);
+      return descending ? -order : order;
+    });
+    tbody.append(rows);
+  }
+
   function markOk() {
     $(this).closest('tr').removeClass('table-warning');
   }
@@ -148,17 +171,24 @@ $(function() {
       const formData = new FormData(document.getElementById('downloadForm'));
       formData.set('changes', JSON.stringify(changes));
       formData.set('g-recaptcha-response', token);
-      const playlist = await $.ajax({
-        type: 'POST',
-        url: './get_m3u',
-        data: formData,
-        processData: false,
-        contentType: false,
+      const response = await fetch('./get_m3u', {
+        method: 'POST',
+        body: formData,
       });
-      const blob = new Blob([playlist], {type: 'application/mpegurl'});
+      checkResponse(response);
+      const blob = await response.blob();
+      const disposition = response.headers.get('Content-Disposition') || '';
+      const encoded = /filename\*=UTF-8''([^;]*)/.exec(disposition);
+      const plain = /filename="([^"]*)"/.exec(disposition);
+      let name = 'playlist.m3u';
+      if (encoded) {
+        name = decodeURIComponent(encoded[1]);
+      } else if (plain) {
+        name = plain[1];
+      }
       const url = URL.createObjectURL(blob);
       const a = document.createElement('a');
-      a.href = url; a.download = 'playlist.m3u'; a.click();
+      a.href = url; a.download = name; a.click();
     } catch (error) {
       alert(error);
     } finally {
@@ -1,13 +1,4 @@
 import $ from 'jquery';
-import fromUnixTime from 'date-fns/fromUnixTime';
-import format from 'date-fns/format';
-
-$(function() {
-  $('span.date').each(function() {
-    const t = $(this).data('timestamp');
-    $(this).text(format(fromUnixTime(t), 'HH:mm'));
-  });
-});
 
 $(function() {
   $('.filterable .btn-filter').on('click', function() {